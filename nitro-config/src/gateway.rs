@@ -0,0 +1,151 @@
+use anyhow::{Result, bail};
+use clap::Parser;
+use log::LevelFilter;
+use nitro::{LogFormat, LoggingOptions};
+use serde::{Deserialize, Serialize};
+
+use std::net::SocketAddr;
+
+use crate::{
+    CommonArgs,
+    Config,
+    ConfigArgs,
+    DEFAULT_LOG_LEVEL,
+    DEFAULT_MAX_FRAME_SIZE,
+    DEFAULT_TIMEOUT_SECS,
+    check_frame_size,
+    check_port,
+    check_positive,
+    set,
+    set_opt,
+};
+
+/// Settings for `nitro-cvv-gateway`, the TCP front end on the parent instance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatewayConfig {
+    pub log_level: LevelFilter,
+    #[serde(with = "crate::display_fromstr")]
+    pub log_format: LogFormat,
+    pub listen_port: u16,
+    pub max_frame_size: usize,
+    /// Seconds allowed for each write and for the enclave's response.
+    pub timeout_secs: u64,
+    /// Address of the HTTP `/metrics` endpoint; disabled when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_addr: Option<SocketAddr>,
+    pub enclave: EnclaveConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnclaveConfig {
+    pub cid: u32,
+    pub port: u32,
+    /// The host's `metrics_port`; its metrics are appended to the gateway's when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_port: Option<u32>,
+}
+
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        Self {
+            log_level: DEFAULT_LOG_LEVEL,
+            log_format: LogFormat::Text,
+            listen_port: 3200,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            timeout_secs: DEFAULT_TIMEOUT_SECS,
+            metrics_addr: None,
+            enclave: EnclaveConfig::default(),
+        }
+    }
+}
+
+impl Default for EnclaveConfig {
+    fn default() -> Self {
+        Self { cid: 16, port: 3100, metrics_port: None }
+    }
+}
+
+impl GatewayConfig {
+    pub fn logging(&self) -> LoggingOptions {
+        LoggingOptions { level: self.log_level, format: self.log_format, shipping: None }
+    }
+}
+
+impl Config for GatewayConfig {
+    fn validate(&self) -> Result<()> {
+        check_port("listen_port", self.listen_port as u32)?;
+        check_frame_size("max_frame_size", self.max_frame_size)?;
+        check_positive("timeout_secs", self.timeout_secs)?;
+
+        // CIDs 0-2 are reserved for the hypervisor, local and host
+        if self.enclave.cid <= 2 {
+            bail!("enclave.cid must be greater than 2, got {}", self.enclave.cid);
+        }
+        check_port("enclave.port", self.enclave.port)?;
+
+        if let Some(port) = self.enclave.metrics_port {
+            check_port("enclave.metrics_port", port)?;
+            if self.metrics_addr.is_none() {
+                bail!("enclave.metrics_port requires metrics_addr");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "nitro-cvv-gateway", about = "TCP gateway forwarding requests to the enclave")]
+pub struct GatewayArgs {
+    #[command(flatten)]
+    pub common: CommonArgs,
+
+    /// TCP port to listen on
+    #[arg(long, env = "GATEWAY_PORT")]
+    pub port: Option<u16>,
+
+    /// Largest accepted frame after the length prefix
+    #[arg(long, env = "GATEWAY_MAX_FRAME_SIZE")]
+    pub max_frame_size: Option<usize>,
+
+    /// Seconds allowed for each write and for the enclave's response
+    #[arg(long, env = "GATEWAY_TIMEOUT")]
+    pub timeout: Option<u64>,
+
+    /// vsock CID of the enclave
+    #[arg(long, env = "ENCLAVE_CID")]
+    pub enclave_cid: Option<u32>,
+
+    /// vsock port of the enclave
+    #[arg(long, env = "ENCLAVE_PORT")]
+    pub enclave_port: Option<u32>,
+
+    /// Address for the HTTP `/metrics` endpoint, e.g. 0.0.0.0:9100
+    #[arg(long, env = "GATEWAY_METRICS_ADDR", value_name = "ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// vsock port of the enclave's metrics, relayed on `/metrics`
+    #[arg(long, env = "ENCLAVE_METRICS_PORT")]
+    pub enclave_metrics_port: Option<u32>,
+}
+
+impl ConfigArgs for GatewayArgs {
+    type Config = GatewayConfig;
+
+    fn common(&self) -> &CommonArgs {
+        &self.common
+    }
+
+    fn apply(&self, config: &mut GatewayConfig) {
+        set(&mut config.log_level, &self.common.log_level);
+        set(&mut config.log_format, &self.common.log_format);
+        set(&mut config.listen_port, &self.port);
+        set(&mut config.max_frame_size, &self.max_frame_size);
+        set(&mut config.timeout_secs, &self.timeout);
+        set(&mut config.enclave.cid, &self.enclave_cid);
+        set(&mut config.enclave.port, &self.enclave_port);
+        set_opt(&mut config.metrics_addr, &self.metrics_addr);
+        set_opt(&mut config.enclave.metrics_port, &self.enclave_metrics_port);
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::{Result, bail};
+use clap::Parser;
+use log::LevelFilter;
+use nitro::logging::DEFAULT_SHIPPING_BUFFER_RECORDS;
+use nitro::{LogFormat, LogShipping, LoggingOptions};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::{
+    Backend,
+    CommonArgs,
+    Config,
+    ConfigArgs,
+    LocalConfig,
+    DEFAULT_LOG_LEVEL,
+    DEFAULT_MAX_FRAME_SIZE,
+    DEFAULT_TIMEOUT_SECS,
+    check_frame_size,
+    check_port,
+    check_positive,
+    set,
+    set_opt,
+};
+
+/// `VMADDR_CID_HOST`: the parent instance as seen from the enclave.
+const DEFAULT_SECRET_CID: u32 = 3;
+
+/// Settings for `nitro-cvv-host`, the CVV service inside the enclave.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostConfig {
+    pub log_level: LevelFilter,
+    #[serde(with = "crate::display_fromstr")]
+    pub log_format: LogFormat,
+    pub backend: Backend,
+    pub listen_port: u32,
+    pub max_frame_size: usize,
+    pub write_timeout_secs: u64,
+    /// AWS region for KMS; the SDK default chain is used when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// vsock port serving metrics for the parent to relay; disabled when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_port: Option<u32>,
+    pub secret_server: SecretServerConfig,
+    pub key_cache: KeyCacheConfig,
+    /// IBM 3624 decimalization tables by name, as 16 digits for hex 0-F;
+    /// offset requests reference them instead of carrying a table.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub decimalization_tables: BTreeMap<String, String>,
+    /// Usage and algorithm of every key the enclave may load, by key name;
+    /// requests naming any other key are refused.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, KeySpec>,
+    /// Ships JSON log lines to a receiver on the parent when set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_shipping: Option<LogShippingConfig>,
+    pub local: LocalConfig,
+}
+
+/// Where and how the enclave reaches `nitro-cvv-secret`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretServerConfig {
+    pub cid: u32,
+    pub port: u32,
+    pub pool_size: usize,
+    pub request_timeout_secs: u64,
+}
+
+/// A size or TTL of 0 disables the cache.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyCacheConfig {
+    pub ttl_secs: u64,
+    pub size: usize,
+}
+
+/// What a key may be used for; each registered key serves exactly one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyUsage {
+    Cvk,
+    Pvk,
+    PinEncryption,
+    Bdk,
+}
+
+impl fmt::Display for KeyUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyUsage::Cvk           => write!(f, "CVK"),
+            KeyUsage::Pvk           => write!(f, "PVK"),
+            KeyUsage::PinEncryption => write!(f, "PIN encryption"),
+            KeyUsage::Bdk           => write!(f, "BDK"),
+        }
+    }
+}
+
+/// Cipher and length of a key's material.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAlgorithm {
+    Des,
+    Tdes2,
+    Tdes3,
+    Aes128,
+    Aes192,
+    Aes256,
+}
+
+impl KeyAlgorithm {
+    /// Length of the key material in bytes.
+    pub fn key_len(&self) -> usize {
+        match self {
+            KeyAlgorithm::Des    => 8,
+            KeyAlgorithm::Tdes2  => 16,
+            KeyAlgorithm::Tdes3  => 24,
+            KeyAlgorithm::Aes128 => 16,
+            KeyAlgorithm::Aes192 => 24,
+            KeyAlgorithm::Aes256 => 32,
+        }
+    }
+
+    pub fn is_aes(&self) -> bool {
+        matches!(self, KeyAlgorithm::Aes128 | KeyAlgorithm::Aes192 | KeyAlgorithm::Aes256)
+    }
+
+    pub fn is_tdes(&self) -> bool {
+        matches!(self, KeyAlgorithm::Tdes2 | KeyAlgorithm::Tdes3)
+    }
+}
+
+impl fmt::Display for KeyAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyAlgorithm::Des    => write!(f, "single-length DES"),
+            KeyAlgorithm::Tdes2  => write!(f, "double-length TDES"),
+            KeyAlgorithm::Tdes3  => write!(f, "triple-length TDES"),
+            KeyAlgorithm::Aes128 => write!(f, "AES-128"),
+            KeyAlgorithm::Aes192 => write!(f, "AES-192"),
+            KeyAlgorithm::Aes256 => write!(f, "AES-256"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeySpec {
+    pub usage: KeyUsage,
+    pub algorithm: KeyAlgorithm,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogShippingConfig {
+    pub cid: u32,
+    pub port: u32,
+    /// Records queued while the receiver is slow or down; further records are dropped.
+    pub buffer_records: usize,
+}
+
+impl Default for HostConfig {
+    fn default() -> Self {
+        Self {
+            log_level: DEFAULT_LOG_LEVEL,
+            log_format: LogFormat::Text,
+            backend: Backend::Aws,
+            listen_port: 3100,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            write_timeout_secs: DEFAULT_TIMEOUT_SECS,
+            region: None,
+            metrics_port: None,
+            secret_server: SecretServerConfig::default(),
+            key_cache: KeyCacheConfig::default(),
+            decimalization_tables: BTreeMap::new(),
+            keys: BTreeMap::new(),
+            log_shipping: None,
+            local: LocalConfig::default(),
+        }
+    }
+}
+
+impl Default for SecretServerConfig {
+    fn default() -> Self {
+        Self {
+            cid: DEFAULT_SECRET_CID,
+            port: 3000,
+            pool_size: 2,
+            request_timeout_secs: DEFAULT_TIMEOUT_SECS,
+        }
+    }
+}
+
+impl Default for KeyCacheConfig {
+    fn default() -> Self {
+        Self { ttl_secs: 300, size: 100 }
+    }
+}
+
+impl Default for LogShippingConfig {
+    fn default() -> Self {
+        Self {
+            cid: DEFAULT_SECRET_CID,
+            port: crate::secret::DEFAULT_LOG_RECEIVER_PORT,
+            buffer_records: DEFAULT_SHIPPING_BUFFER_RECORDS,
+        }
+    }
+}
+
+impl HostConfig {
+    pub fn logging(&self) -> LoggingOptions {
+        LoggingOptions {
+            level: self.log_level,
+            format: self.log_format,
+            shipping: self.log_shipping.as_ref().map(|s| LogShipping {
+                cid: s.cid,
+                port: s.port,
+                buffer_records: s.buffer_records,
+            }),
+        }
+    }
+}
+
+impl Config for HostConfig {
+    fn validate(&self) -> Result<()> {
+        check_port("listen_port", self.listen_port)?;
+        check_frame_size("max_frame_size", self.max_frame_size)?;
+        check_positive("write_timeout_secs", self.write_timeout_secs)?;
+
+        check_port("secret_server.port", self.secret_server.port)?;
+        check_positive("secret_server.request_timeout_secs", self.secret_server.request_timeout_secs)?;
+        if self.secret_server.pool_size == 0 {
+            bail!("secret_server.pool_size must be greater than 0");
+        }
+
+        if let Some(port) = self.metrics_port {
+            check_port("metrics_port", port)?;
+        }
+
+        for (name, table) in &self.decimalization_tables {
+            check_decimalization_table(name, table)?;
+        }
+
+        for (name, spec) in &self.keys {
+            check_key_spec(name, spec)?;
+        }
+
+        if let Some(shipping) = &self.log_shipping {
+            check_port("log_shipping.port", shipping.port)?;
+            if shipping.buffer_records == 0 {
+                bail!("log_shipping.buffer_records must be greater than 0");
+            }
+        }
+
+        self.local.validate()
+    }
+}
+
+/// Names are sent as 16-byte key references; a table that lacks some digit
+/// cannot produce every PIN and usually means a typo.
+fn check_decimalization_table(name: &str, table: &str) -> Result<()> {
+    if name.is_empty() || name.len() > 16 {
+        bail!("decimalization_tables: name '{}' must be 1 to 16 bytes", name);
+    }
+    if table.len() != 16 || !table.chars().all(|c| c.is_ascii_digit()) {
+        bail!("decimalization_tables.{} must be 16 decimal digits", name);
+    }
+    if let Some(missing) = ('0'..='9').find(|digit| !table.contains(*digit)) {
+        bail!("decimalization_tables.{} never maps to {}", name, missing);
+    }
+    Ok(())
+}
+
+/// CVKs and PVV key halves are single-length by construction; PIN keys,
+/// IBM 3624 PVKs and BDKs must be at least double-length, and TDES DUKPT
+/// only defines double-length BDKs.
+fn check_key_spec(name: &str, spec: &KeySpec) -> Result<()> {
+    if name.is_empty() || name.len() > 16 {
+        bail!("keys: name '{}' must be 1 to 16 bytes", name);
+    }
+
+    let allowed = match spec.usage {
+        KeyUsage::Cvk           => spec.algorithm == KeyAlgorithm::Des,
+        KeyUsage::Pvk           => spec.algorithm == KeyAlgorithm::Des || spec.algorithm.is_tdes(),
+        KeyUsage::PinEncryption => spec.algorithm.is_tdes() || spec.algorithm.is_aes(),
+        KeyUsage::Bdk           => spec.algorithm == KeyAlgorithm::Tdes2 || spec.algorithm.is_aes(),
+    };
+    if !allowed {
+        bail!("keys.{}: a {} key cannot be {}", name, spec.usage, spec.algorithm);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "nitro-cvv-host", about = "CVV service running inside the Nitro Enclave")]
+pub struct HostArgs {
+    #[command(flatten)]
+    pub common: CommonArgs,
+
+    /// Key backend (aws, local)
+    #[arg(long, env = "NITRO_BACKEND")]
+    pub backend: Option<Backend>,
+
+    /// vsock port to listen on
+    #[arg(long, env = "HOST_PORT")]
+    pub port: Option<u32>,
+
+    /// Largest accepted frame after the length prefix
+    #[arg(long, env = "HOST_MAX_FRAME_SIZE")]
+    pub max_frame_size: Option<usize>,
+
+    /// Seconds allowed for writing a response
+    #[arg(long, env = "HOST_WRITE_TIMEOUT")]
+    pub write_timeout: Option<u64>,
+
+    /// AWS region for KMS
+    #[arg(long, env = "AWS_REGION")]
+    pub region: Option<String>,
+
+    /// vsock CID of the secret server
+    #[arg(long, env = "SECRET_CID")]
+    pub secret_cid: Option<u32>,
+
+    /// vsock port of the secret server
+    #[arg(long, env = "SECRET_PORT")]
+    pub secret_port: Option<u32>,
+
+    /// Persistent connections to the secret server
+    #[arg(long, env = "SECRET_POOL_SIZE")]
+    pub secret_pool_size: Option<usize>,
+
+    /// Seconds allowed for one secret server request, including reconnects
+    #[arg(long, env = "SECRET_REQUEST_TIMEOUT")]
+    pub secret_request_timeout: Option<u64>,
+
+    /// Seconds a decrypted key stays cached
+    #[arg(long, env = "HOST_KEY_CACHE_TTL")]
+    pub key_cache_ttl: Option<u64>,
+
+    /// Maximum number of cached keys
+    #[arg(long, env = "HOST_KEY_CACHE_SIZE")]
+    pub key_cache_size: Option<usize>,
+
+    /// vsock port serving metrics to the parent
+    #[arg(long, env = "HOST_METRICS_PORT")]
+    pub metrics_port: Option<u32>,
+
+    /// vsock port of the parent's log receiver; enables log shipping
+    #[arg(long, env = "HOST_LOG_PORT")]
+    pub log_port: Option<u32>,
+
+    /// vsock CID of the parent's log receiver
+    #[arg(long, env = "HOST_LOG_CID", requires = "log_port")]
+    pub log_cid: Option<u32>,
+
+    /// Log records buffered for shipping before new ones are dropped
+    #[arg(long, env = "HOST_LOG_BUFFER", requires = "log_port")]
+    pub log_buffer: Option<usize>,
+
+    /// Hex master key for the local KMS emulator; only read from `LOCAL_KMS_KEY`
+    /// so it never shows up in the process list
+    #[arg(skip = std::env::var("LOCAL_KMS_KEY").ok())]
+    pub local_kms_key: Option<String>,
+}
+
+impl ConfigArgs for HostArgs {
+    type Config = HostConfig;
+
+    fn common(&self) -> &CommonArgs {
+        &self.common
+    }
+
+    fn apply(&self, config: &mut HostConfig) {
+        set(&mut config.log_level, &self.common.log_level);
+        set(&mut config.log_format, &self.common.log_format);
+        set(&mut config.backend, &self.backend);
+        set(&mut config.listen_port, &self.port);
+        set(&mut config.max_frame_size, &self.max_frame_size);
+        set(&mut config.write_timeout_secs, &self.write_timeout);
+        set_opt(&mut config.region, &self.region);
+
+        set(&mut config.secret_server.cid, &self.secret_cid);
+        set(&mut config.secret_server.port, &self.secret_port);
+        set(&mut config.secret_server.pool_size, &self.secret_pool_size);
+        set(&mut config.secret_server.request_timeout_secs, &self.secret_request_timeout);
+
+        set(&mut config.key_cache.ttl_secs, &self.key_cache_ttl);
+        set(&mut config.key_cache.size, &self.key_cache_size);
+
+        set_opt(&mut config.metrics_port, &self.metrics_port);
+
+        if let Some(port) = self.log_port {
+            let shipping = config.log_shipping.get_or_insert_with(LogShippingConfig::default);
+            shipping.port = port;
+            set(&mut shipping.cid, &self.log_cid);
+            set(&mut shipping.buffer_records, &self.log_buffer);
+        }
+
+        set_opt(&mut config.local.kms_key, &self.local_kms_key);
+    }
+}
@@ -0,0 +1,368 @@
+//! Configuration shared by the nitro binaries.
+//!
+//! Values are layered: built-in defaults, then an optional TOML file
+//! (`--config` or `NITRO_CONFIG`), then environment variables and command
+//! line flags. The merged result is validated before it is returned.
+
+use anyhow::{Result, Context, bail};
+use clap::Args;
+use log::LevelFilter;
+use nitro::LogFormat;
+use nitro::message::{MSGHDR_CMD_SIZE, MSGHDR_HDR_SIZE, MSGHDR_MAX_LEN};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize, Serializer};
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub mod gateway;
+pub mod host;
+pub mod secret;
+
+pub use gateway::{GatewayArgs, GatewayConfig};
+pub use host::{HostArgs, HostConfig, KeyAlgorithm, KeySpec, KeyUsage};
+pub use secret::{ReceiveLogsArgs, SecretArgs, SecretCommand, SecretConfig};
+
+/// Default for the 2-byte standard length prefix; larger frames must be enabled on both ends.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = u16::MAX as usize;
+/// Smallest useful frame: `hdr` and `cmd` with no payload.
+pub const MIN_FRAME_SIZE: usize = MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE;
+/// Largest frame the extended 4-byte length prefix can describe.
+pub const MAX_FRAME_SIZE_LIMIT: usize = MSGHDR_MAX_LEN;
+
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+pub const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// Selects real AWS services or the in-process stand-ins (`local` feature).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Aws,
+    Local,
+}
+
+impl FromStr for Backend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "aws"   => Ok(Backend::Aws),
+            "local" => Ok(Backend::Local),
+            other   => bail!("unknown backend '{}', expected 'aws' or 'local'", other),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Aws   => write!(f, "aws"),
+            Backend::Local => write!(f, "local"),
+        }
+    }
+}
+
+/// Settings for the `local` backend.
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LocalConfig {
+    /// Hex master key for the KMS emulator; must match on both sides.
+    #[serde(skip_serializing_if = "Option::is_none", serialize_with = "redact")]
+    pub kms_key: Option<String>,
+    /// JSON object of `name -> value` (secret server only).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets_file: Option<PathBuf>,
+}
+
+impl fmt::Debug for LocalConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalConfig")
+            .field("kms_key", &self.kms_key.as_ref().map(|_| "<redacted>"))
+            .field("secrets_file", &self.secrets_file)
+            .finish()
+    }
+}
+
+impl LocalConfig {
+    fn validate(&self) -> Result<()> {
+        if let Some(key) = &self.kms_key {
+            let bytes = hex::decode(key.trim()).context("local.kms_key is not valid hex")?;
+            if bytes.len() != 32 {
+                bail!("local.kms_key must be 32 bytes, got {}", bytes.len());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Flags accepted by every binary.
+#[derive(Debug, Clone, Args)]
+pub struct CommonArgs {
+    /// TOML configuration file
+    #[arg(short, long, env = "NITRO_CONFIG", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Print the effective configuration and exit
+    #[arg(long)]
+    pub print_config: bool,
+
+    /// Log level (off, error, warn, info, debug, trace); `RUST_LOG` takes precedence
+    #[arg(long, env = "NITRO_LOG_LEVEL", value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Log output format (text, json)
+    #[arg(long, env = "NITRO_LOG_FORMAT", value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+}
+
+/// A binary's configuration: serializable, with defaults and checks.
+pub trait Config: Default + Serialize + DeserializeOwned {
+    fn validate(&self) -> Result<()>;
+}
+
+/// Parsed command line (including environment) for one binary.
+pub trait ConfigArgs {
+    type Config: Config;
+
+    fn common(&self) -> &CommonArgs;
+
+    /// Overrides file values with anything given on the command line or in the environment.
+    fn apply(&self, config: &mut Self::Config);
+}
+
+/// Builds the validated configuration for `args`.
+pub fn load<A: ConfigArgs>(args: &A) -> Result<A::Config> {
+    let mut config = match &args.common().config {
+        Some(path) => from_file(path)?,
+        None => A::Config::default(),
+    };
+
+    args.apply(&mut config);
+    config.validate().context("Invalid configuration")?;
+
+    Ok(config)
+}
+
+pub fn from_file<C: Config>(path: &Path) -> Result<C> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file '{}'", path.display()))?;
+
+    toml::from_str(&content).with_context(|| format!("Failed to parse config file '{}'", path.display()))
+}
+
+/// Renders the configuration as TOML with secrets redacted.
+pub fn to_toml<C: Config>(config: &C) -> Result<String> {
+    toml::to_string_pretty(config).context("Failed to serialize configuration")
+}
+
+/// Serde for types that round-trip through `Display` and `FromStr`.
+mod display_fromstr {
+    use serde::{Deserialize, Deserializer, Serializer, de};
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    pub fn serialize<T: Display, S: Serializer>(value: &T, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+fn redact<S: Serializer>(value: &Option<String>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match value {
+        Some(_) => serializer.serialize_str("<redacted>"),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn set<T>(target: &mut T, value: &Option<T>)
+where
+    T: Clone,
+{
+    if let Some(value) = value {
+        *target = value.clone();
+    }
+}
+
+fn set_opt<T>(target: &mut Option<T>, value: &Option<T>)
+where
+    T: Clone,
+{
+    if value.is_some() {
+        *target = value.clone();
+    }
+}
+
+fn check_port(name: &str, port: u32) -> Result<()> {
+    if port == 0 {
+        bail!("{} must not be 0", name);
+    }
+    Ok(())
+}
+
+fn check_positive(name: &str, value: u64) -> Result<()> {
+    if value == 0 {
+        bail!("{} must be greater than 0", name);
+    }
+    Ok(())
+}
+
+fn check_frame_size(name: &str, max_frame_size: usize) -> Result<()> {
+    if !(MIN_FRAME_SIZE..=MAX_FRAME_SIZE_LIMIT).contains(&max_frame_size) {
+        bail!("{} must be between {} and {}, got {}", name, MIN_FRAME_SIZE, MAX_FRAME_SIZE_LIMIT, max_frame_size);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::sync::Mutex;
+
+    // clap reads the environment while parsing, so tests that parse or set
+    // variables take turns
+    static ENV: Mutex<()> = Mutex::new(());
+
+    const KMS_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+    fn write_config(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("nitro-config-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn file_then_env_then_flags_override_defaults() {
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let path = write_config("layers", "
+            listen_port = 3101
+            write_timeout_secs = 5
+
+            [key_cache]
+            size = 7
+        ");
+
+        // SAFETY: every test touching the environment holds `ENV`
+        unsafe {
+            std::env::set_var("HOST_PORT", "3102");
+            std::env::set_var("HOST_WRITE_TIMEOUT", "6");
+        }
+        let args = HostArgs::try_parse_from(["nitro-cvv-host", "--config", path.to_str().unwrap(), "--port", "3103"]);
+        unsafe {
+            std::env::remove_var("HOST_PORT");
+            std::env::remove_var("HOST_WRITE_TIMEOUT");
+        }
+        let config = load(&args.unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.listen_port, 3103);
+        assert_eq!(config.write_timeout_secs, 6);
+        assert_eq!(config.key_cache.size, 7);
+        assert_eq!(config.key_cache.ttl_secs, host::KeyCacheConfig::default().ttl_secs);
+        assert_eq!(config.max_frame_size, DEFAULT_MAX_FRAME_SIZE);
+    }
+
+    #[test]
+    fn local_kms_key_is_only_read_from_env_or_file() {
+        let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        assert!(HostArgs::try_parse_from(["nitro-cvv-host", "--local-kms-key", KMS_KEY]).is_err());
+        assert!(SecretArgs::try_parse_from(["nitro-cvv-secret", "--local-kms-key", KMS_KEY]).is_err());
+
+        // SAFETY: every test touching the environment holds `ENV`
+        unsafe { std::env::set_var("LOCAL_KMS_KEY", KMS_KEY) };
+        let args = HostArgs::try_parse_from(["nitro-cvv-host"]);
+        unsafe { std::env::remove_var("LOCAL_KMS_KEY") };
+        assert_eq!(load(&args.unwrap()).unwrap().local.kms_key.as_deref(), Some(KMS_KEY));
+
+        let path = write_config("kms-key", &format!("[local]\nkms_key = \"{}\"\n", KMS_KEY));
+        let config: HostConfig = from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(config.local.kms_key.as_deref(), Some(KMS_KEY));
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        HostConfig::default().validate().unwrap();
+        GatewayConfig::default().validate().unwrap();
+        SecretConfig::default().validate().unwrap();
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        let rejected = |change: fn(&mut HostConfig), message: &str| {
+            let mut config = HostConfig::default();
+            change(&mut config);
+            let error = config.validate().unwrap_err().to_string();
+            assert!(error.contains(message), "{} does not mention {}", error, message);
+        };
+
+        rejected(|c| c.max_frame_size = MIN_FRAME_SIZE - 1, "max_frame_size must be between");
+        rejected(|c| c.max_frame_size = MAX_FRAME_SIZE_LIMIT + 1, "max_frame_size must be between");
+        rejected(|c| c.write_timeout_secs = 0, "write_timeout_secs must be greater than 0");
+        rejected(|c| c.secret_server.request_timeout_secs = 0, "request_timeout_secs must be greater than 0");
+        rejected(|c| c.secret_server.pool_size = 0, "pool_size must be greater than 0");
+        rejected(|c| c.listen_port = 0, "listen_port must not be 0");
+        rejected(|c| c.local.kms_key = Some("0011".to_string()), "must be 32 bytes");
+        rejected(|c| { c.decimalization_tables.insert("ibm".to_string(), "0123466789012346".to_string()); }, "never maps to 5");
+        rejected(
+            |c| { c.keys.insert("cvk".to_string(), KeySpec { usage: KeyUsage::Cvk, algorithm: KeyAlgorithm::Tdes2 }); },
+            "a CVK key cannot be double-length TDES",
+        );
+
+        let gateway = GatewayConfig { timeout_secs: 0, ..Default::default() };
+        assert!(gateway.validate().is_err());
+
+        let secret = SecretConfig { backend: Backend::Local, ..Default::default() };
+        assert!(secret.validate().unwrap_err().to_string().contains("local.secrets_file is required"));
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        assert!(toml::from_str::<HostConfig>("listen_prot = 3100").is_err());
+        assert!(toml::from_str::<HostConfig>("[keys.cvk]\nusage = \"cvk\"\nalgorithm = \"des\"\nlength = 8").is_err());
+    }
+
+    #[test]
+    fn to_toml_round_trips() {
+        let mut config = HostConfig {
+            metrics_port: Some(3400),
+            region: Some("eu-west-1".to_string()),
+            log_shipping: Some(host::LogShippingConfig::default()),
+            ..Default::default()
+        };
+        config.decimalization_tables.insert("ibm".to_string(), "0123456789012345".to_string());
+        config.keys.insert("zpk".to_string(), KeySpec { usage: KeyUsage::PinEncryption, algorithm: KeyAlgorithm::Aes128 });
+
+        let rendered = to_toml(&config).unwrap();
+        let parsed: HostConfig = toml::from_str(&rendered).unwrap();
+        parsed.validate().unwrap();
+        assert_eq!(to_toml(&parsed).unwrap(), rendered);
+        assert_eq!(parsed.keys, config.keys);
+
+        let rendered = to_toml(&GatewayConfig::default()).unwrap();
+        assert_eq!(to_toml(&toml::from_str::<GatewayConfig>(&rendered).unwrap()).unwrap(), rendered);
+
+        let rendered = to_toml(&SecretConfig::default()).unwrap();
+        assert_eq!(to_toml(&toml::from_str::<SecretConfig>(&rendered).unwrap()).unwrap(), rendered);
+    }
+
+    #[test]
+    fn to_toml_redacts_kms_key() {
+        let mut config = HostConfig::default();
+        config.local.kms_key = Some(KMS_KEY.to_string());
+
+        let rendered = to_toml(&config).unwrap();
+        assert!(rendered.contains("kms_key = \"<redacted>\""));
+        assert!(!rendered.contains(KMS_KEY));
+    }
+}
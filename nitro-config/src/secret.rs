@@ -0,0 +1,206 @@
+use anyhow::{Result, bail};
+use clap::{Args, Parser, Subcommand};
+use log::LevelFilter;
+use nitro::{LogFormat, LoggingOptions};
+use serde::{Deserialize, Serialize};
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use crate::{
+    Backend,
+    CommonArgs,
+    Config,
+    ConfigArgs,
+    LocalConfig,
+    DEFAULT_LOG_LEVEL,
+    DEFAULT_MAX_FRAME_SIZE,
+    DEFAULT_TIMEOUT_SECS,
+    check_frame_size,
+    check_port,
+    check_positive,
+    set,
+    set_opt,
+};
+
+/// Default vsock port the enclave ships its logs to.
+pub const DEFAULT_LOG_RECEIVER_PORT: u32 = 3300;
+
+/// Settings for `nitro-cvv-secret`, the key server on the parent instance.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretConfig {
+    pub log_level: LevelFilter,
+    #[serde(with = "crate::display_fromstr")]
+    pub log_format: LogFormat,
+    pub backend: Backend,
+    pub listen_port: u32,
+    pub max_frame_size: usize,
+    pub write_timeout_secs: u64,
+    /// AWS region; the SDK default chain is used when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    /// KMS key id or alias the secrets are encrypted under.
+    pub kms_key_id: String,
+    pub secret_cache: SecretCacheConfig,
+    /// Address of the HTTP `/metrics` endpoint; disabled when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_addr: Option<SocketAddr>,
+    pub local: LocalConfig,
+}
+
+/// Secrets Manager client-side cache.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretCacheConfig {
+    pub ttl_secs: u64,
+    pub size: usize,
+}
+
+impl Default for SecretConfig {
+    fn default() -> Self {
+        Self {
+            log_level: DEFAULT_LOG_LEVEL,
+            log_format: LogFormat::Text,
+            backend: Backend::Aws,
+            listen_port: 3000,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            write_timeout_secs: DEFAULT_TIMEOUT_SECS,
+            region: None,
+            kms_key_id: "alias/nitro-kms-key".to_string(),
+            secret_cache: SecretCacheConfig::default(),
+            metrics_addr: None,
+            local: LocalConfig::default(),
+        }
+    }
+}
+
+impl Default for SecretCacheConfig {
+    fn default() -> Self {
+        Self { ttl_secs: 300, size: 1000 }
+    }
+}
+
+impl SecretConfig {
+    pub fn logging(&self) -> LoggingOptions {
+        LoggingOptions { level: self.log_level, format: self.log_format, shipping: None }
+    }
+}
+
+impl Config for SecretConfig {
+    fn validate(&self) -> Result<()> {
+        check_port("listen_port", self.listen_port)?;
+        check_frame_size("max_frame_size", self.max_frame_size)?;
+        check_positive("write_timeout_secs", self.write_timeout_secs)?;
+
+        if self.kms_key_id.trim().is_empty() {
+            bail!("kms_key_id must not be empty");
+        }
+        if self.secret_cache.size == 0 {
+            bail!("secret_cache.size must be greater than 0");
+        }
+        if self.backend == Backend::Local && self.local.secrets_file.is_none() {
+            bail!("local.secrets_file is required for the local backend");
+        }
+
+        self.local.validate()
+    }
+}
+
+#[derive(Debug, Clone, Parser)]
+#[command(name = "nitro-cvv-secret", about = "Key server for the enclave, running on the parent instance")]
+pub struct SecretArgs {
+    #[command(flatten)]
+    pub common: CommonArgs,
+
+    #[command(subcommand)]
+    pub command: Option<SecretCommand>,
+
+    /// Secret and KMS backend (aws, local)
+    #[arg(long, env = "NITRO_BACKEND")]
+    pub backend: Option<Backend>,
+
+    /// vsock port to listen on
+    #[arg(long, env = "SECRET_PORT")]
+    pub port: Option<u32>,
+
+    /// Largest accepted frame after the length prefix
+    #[arg(long, env = "SECRET_MAX_FRAME_SIZE")]
+    pub max_frame_size: Option<usize>,
+
+    /// Seconds allowed for writing a response
+    #[arg(long, env = "SECRET_WRITE_TIMEOUT")]
+    pub write_timeout: Option<u64>,
+
+    /// AWS region
+    #[arg(long, env = "AWS_REGION")]
+    pub region: Option<String>,
+
+    /// KMS key id or alias used to encrypt secrets for the enclave
+    #[arg(long, env = "KMS_KEY_ID")]
+    pub kms_key_id: Option<String>,
+
+    /// Seconds a secret stays in the Secrets Manager cache
+    #[arg(long, env = "SECRET_CACHE_TTL")]
+    pub secret_cache_ttl: Option<u64>,
+
+    /// Maximum number of cached secrets
+    #[arg(long, env = "SECRET_CACHE_SIZE")]
+    pub secret_cache_size: Option<usize>,
+
+    /// Address for the HTTP `/metrics` endpoint, e.g. 127.0.0.1:9101
+    #[arg(long, env = "SECRET_METRICS_ADDR", value_name = "ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Hex master key for the local KMS emulator; only read from `LOCAL_KMS_KEY`
+    /// so it never shows up in the process list
+    #[arg(skip = std::env::var("LOCAL_KMS_KEY").ok())]
+    pub local_kms_key: Option<String>,
+
+    /// JSON secrets file for the local backend
+    #[arg(long, env = "LOCAL_SECRETS_FILE", value_name = "PATH")]
+    pub local_secrets_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum SecretCommand {
+    /// Receive logs shipped from the enclave instead of serving keys
+    ReceiveLogs(ReceiveLogsArgs),
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ReceiveLogsArgs {
+    /// vsock port to listen on for shipped logs
+    #[arg(long, env = "LOG_RECEIVER_PORT", default_value_t = DEFAULT_LOG_RECEIVER_PORT)]
+    pub port: u32,
+
+    /// File to append log lines to; stdout when omitted
+    #[arg(short, long, env = "LOG_RECEIVER_OUTPUT", value_name = "PATH")]
+    pub output: Option<PathBuf>,
+}
+
+impl ConfigArgs for SecretArgs {
+    type Config = SecretConfig;
+
+    fn common(&self) -> &CommonArgs {
+        &self.common
+    }
+
+    fn apply(&self, config: &mut SecretConfig) {
+        set(&mut config.log_level, &self.common.log_level);
+        set(&mut config.log_format, &self.common.log_format);
+        set(&mut config.backend, &self.backend);
+        set(&mut config.listen_port, &self.port);
+        set(&mut config.max_frame_size, &self.max_frame_size);
+        set(&mut config.write_timeout_secs, &self.write_timeout);
+        set_opt(&mut config.region, &self.region);
+        set(&mut config.kms_key_id, &self.kms_key_id);
+
+        set(&mut config.secret_cache.ttl_secs, &self.secret_cache_ttl);
+        set(&mut config.secret_cache.size, &self.secret_cache_size);
+        set_opt(&mut config.metrics_addr, &self.metrics_addr);
+
+        set_opt(&mut config.local.kms_key, &self.local_kms_key);
+        set_opt(&mut config.local.secrets_file, &self.local_secrets_file);
+    }
+}
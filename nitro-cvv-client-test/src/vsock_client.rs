@@ -1,14 +1,15 @@
 use anyhow::{Result, Context};
+use futures::{SinkExt, StreamExt};
 use tokio_vsock::{
     VsockAddr,
     VsockStream
 };
 use tokio::time::Duration;
-use tokio_util::sync::CancellationToken;
+use tokio_util::codec::Framed;
 
 use nitro::hexdump;
-use nitro::message::{GetKeyRequest};
-use nitro_tokio::message_utils::{write_message, read_message};
+use nitro::message::{GetKeyRequest, HeaderIdAllocator, Message};
+use nitro_tokio::NitroCodec;
 
 pub async fn test(cid: u32, port: u32) -> Result<()> {
 
@@ -17,41 +18,41 @@ pub async fn test(cid: u32, port: u32) -> Result<()> {
     let host_addr = VsockAddr::new(cid, port);
     println!("Connecting to vsock CID {} port {}/{}...", cid, port, host_addr);
 
-    let mut stream = VsockStream::connect(host_addr)
+    let stream = VsockStream::connect(host_addr)
         .await
         .context(format!("Failed to connect to CID {} port {}", cid, port))?;
 
     println!("✓ Connected!");
 
-    let request = GetKeyRequest::new(*b"0000", key_id.as_bytes().to_vec());    
+    let mut framed = Framed::new(stream, NitroCodec::new());
+
+    let header_ids = HeaderIdAllocator::new();
+    let header_id  = header_ids.allocate().context("No free header ids")?;
+
+    let request = GetKeyRequest::new(header_id.hdr(), key_id.as_bytes().to_vec())?;    
     println!("→ Sending Key Request:");
     hexdump(&request.to_bytes());
 
-    let shutdown_token = CancellationToken::new();
-    let timeout = Some(Duration::from_secs(60));
+    let timeout = Duration::from_secs(60);
 
-    let written = write_message(
-        &mut stream,
-        &request.to_bytes(),
-        timeout,
-        &shutdown_token
-    ).await?;
-    
-    if written == 0 {
-        anyhow::bail!("Failed to send request (connection closed or timeout)");
-    }
+    tokio::time::timeout(timeout, framed.send(Message::GetKeyRequest(request)))
+        .await
+        .context("Timeout sending request")??;
     
-    println!("✓ Sent {} bytes", written);
-
-    let message_bytes: Vec<u8> = match read_message(&mut stream, timeout, &shutdown_token).await {
-        Ok(bytes) => bytes,
-        Err(e)  => {
-            println!("read message error: {}", e);
-            Vec::<u8>::new()
+    println!("✓ Sent");
+
+    match tokio::time::timeout(timeout, framed.next()).await {
+        Ok(Some(Ok(message))) => {
+            if message.header().hdr != header_id.hdr() {
+                println!("response hdr does not match request {:?}", header_id);
+            }
+            println!("→ Recv Key Response:");
+            hexdump(&message.to_bytes());
         }
-    };
-    println!("→ Recv Key Response:");
-    hexdump(&message_bytes);
+        Ok(Some(Err(e))) => println!("read message error: {}", e),
+        Ok(None)         => println!("connection closed by server"),
+        Err(_)           => println!("timeout waiting for response"),
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,3 +1,135 @@
-fn main() {
-    println!("Hello, world!");
+use anyhow::{Result, Context};
+use anyhow::anyhow;
+use clap::Parser;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::signal;
+use tracing::Instrument;
+use tokio_util::sync::CancellationToken;
+use tokio_vsock::VsockAddr;
+
+use nitro_config::{GatewayArgs, GatewayConfig};
+use nitro_metrics::{Metrics, Relay};
+
+mod session;
+
+/// Kept well under Prometheus' default scrape timeout.
+const ENCLAVE_METRICS_TIMEOUT: Duration = Duration::from_secs(2);
+
+async fn run_server(config: &GatewayConfig, metrics: Arc<Metrics>) -> Result<()> {
+
+    let listen_port    = config.listen_port;
+    let enclave_cid    = config.enclave.cid;
+    let enclave_port   = config.enclave.port;
+    let max_frame_size = config.max_frame_size;
+    let timeout        = Duration::from_secs(config.timeout_secs);
+
+    let shutdown_token = CancellationToken::new();
+    let server_token   = shutdown_token.clone();
+
+    tokio::spawn(async move {
+        match signal::ctrl_c().await {
+            Ok(()) => {
+                println!();
+                log::info!("shutdown signal received ...");
+                server_token.cancel();
+            }
+            Err(e) => {
+                log::error!("failed to listen for shutdown signal: {}", e);
+            }
+        }    
+    });    
+
+    if let Some(metrics_addr) = config.metrics_addr {
+        let relay = match config.enclave.metrics_port {
+            Some(port) => Some(Relay::new(&metrics, VsockAddr::new(enclave_cid, port), ENCLAVE_METRICS_TIMEOUT)?),
+            None => None,
+        };
+
+        let metrics = Arc::clone(&metrics);
+        let token   = shutdown_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = nitro_metrics::serve_http(metrics_addr, metrics, relay, token).await {
+                log::error!("metrics server: {:?}", e);
+            }
+        });
+    }
+
+    let listener = TcpListener::bind(("0.0.0.0", listen_port))
+        .await
+        .context(format!("failed to bind to tcp port: {}", listen_port))?;
+
+    log::info!("listening on tcp port: {}, forwarding to cid: {} port: {}", listen_port, enclave_cid, enclave_port);
+    loop {
+        tokio::select! {
+            accept_result = listener.accept() => {
+                match accept_result {
+                    Ok((client_stream, client_addr)) => {
+                        log::info!("accept connection from {}", client_addr);
+
+                        let handler_metrics = Arc::clone(&metrics);
+                        let handler_token   = shutdown_token.child_token();
+
+                        tokio::spawn(async move {
+                            if let Err(e) = session::handle_client(
+                                client_stream,
+                                enclave_cid,
+                                enclave_port,
+                                handler_metrics,
+                                max_frame_size,
+                                timeout,
+                                handler_token).await {
+                                log::error!("error handling client from {}: {}", client_addr, e);
+                            }
+                            else {
+                                log::info!("client {} disconnected cleanly", client_addr);
+                            }
+                        }.instrument(tracing::info_span!("connection", peer = %client_addr)));
+                    },
+                    Err(e) => {
+                        log::error!("error accepting client connection: {}", e);
+                    }
+                }
+            },
+            _ = shutdown_token.cancelled() => {
+                log::info!("shutdown initiated ...");
+                break;            
+            }
+        }
+    }
+
+    log::info!("server stopped accepting new connections");
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+
+    let args   = GatewayArgs::parse();
+    let config = nitro_config::load(&args)?;
+
+    if args.common.print_config {
+        print!("{}", nitro_config::to_toml(&config)?);
+        return Ok(());
+    }
+
+    let _logging = nitro::init_tracing(&config.logging()).map_err(|e| anyhow!("Failed to initialize logging: {}", e))?;
+
+    log::info!("starting ...");
+    log::debug!("configuration: {:?}", config);
+
+    let metrics = Arc::new(Metrics::new("gateway")?);
+
+    match run_server(&config, metrics).await {
+        Ok(()) => {
+            log::info!("server exited gracefully");
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("server: {:?}", e);
+            Err(e)
+        }
+    }
 }
@@ -0,0 +1,160 @@
+use anyhow::{Result, Context, bail};
+use std::sync::Arc;
+use std::time::Instant;
+
+use tokio::net::TcpStream;
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tokio_vsock::{VsockAddr, VsockStream};
+use tracing::Instrument;
+
+use nitro_tokio::IoResult;
+use nitro_tokio::message_utils::{read_message, write_message};
+
+use nitro::logging;
+use nitro::utils;
+use nitro::message::Message;
+use nitro_metrics::Metrics;
+
+pub async fn handle_client(
+    mut stream: TcpStream,
+    enclave_cid: u32,
+    enclave_port: u32,
+    metrics: Arc<Metrics>,
+    max_frame_size: usize,
+    timeout: Duration,
+    shutdown_token: CancellationToken,
+) -> Result<()> {
+    log::info!("Client connected, started");
+    let _connection = metrics.connection_opened();
+
+    let addr = VsockAddr::new(enclave_cid, enclave_port);
+    log::debug!("Connecting to enclave at CID {} port {}...", enclave_cid, enclave_port);
+
+    let mut enclave = VsockStream::connect(addr)
+        .await
+        .context(format!("Failed to connect to enclave CID {} port {}", enclave_cid, enclave_port))?;
+
+    log::debug!("Connected to enclave");
+
+    let timeout = Some(timeout);
+
+    loop {
+        let message_bytes: Vec<u8> = match read_message(&mut stream, max_frame_size, None, &shutdown_token).await {
+            Ok(IoResult::Success(bytes)) => bytes,
+            Ok(IoResult::Closed) => {
+                log::info!("Connection closed by peer");
+                break;
+            }
+            Ok(IoResult::Timeout) => {
+                log::debug!("Idle timeout waiting for request");
+                continue;
+            }
+            Err(e)  => {
+                log::error!("read message error: {}", e);
+                break;
+            }
+        };
+
+        let span = logging::request_span(&message_bytes);
+        let started = Instant::now();
+        metrics.request_received(&message_bytes);
+
+        let response = forward(&mut enclave, &message_bytes, max_frame_size, timeout, &shutdown_token)
+            .instrument(span.clone())
+            .await;
+
+        let response_bytes = match response {
+            Ok(Some(bytes)) => {
+                logging::finish_request(&span, Some(&bytes), started);
+                metrics.request_completed(&message_bytes, Some(&bytes), started.elapsed());
+                bytes
+            }
+            Ok(None) => {
+                logging::finish_request(&span, None, started);
+                metrics.request_completed(&message_bytes, None, started.elapsed());
+                continue;
+            }
+            Err(e) => {
+                logging::finish_request(&span, None, started);
+                metrics.request_completed(&message_bytes, None, started.elapsed());
+                log::error!("{}", e);
+                break;
+            }
+        };
+
+        match write_message(&mut stream, &response_bytes, timeout, &shutdown_token).await? {
+            IoResult::Success(written) => {
+                let dump = utils::hexdump_frame(&response_bytes);
+                log::info!("sent message {} bytes\n\n{}", written, dump);
+            }
+            IoResult::Closed => {
+                log::error!("Failed to send response, connection closed by peer");
+                break;
+            }
+            IoResult::Timeout => {
+                log::error!("Failed to send response, write timed out");
+                break;
+            }
+        }
+    }
+
+    log::info!("Connection closed");
+    Ok(())
+}
+
+/// Sends one client frame to the enclave and returns its response, or `None` if the frame is dropped.
+/// Errors mean the enclave connection is no longer usable.
+async fn forward(
+    enclave: &mut VsockStream,
+    message_bytes: &[u8],
+    max_frame_size: usize,
+    timeout: Option<Duration>,
+    shutdown_token: &CancellationToken,
+) -> Result<Option<Vec<u8>>> {
+
+    let dump = utils::hexdump_frame(message_bytes);
+    log::info!("recieve message {} bytes\n\n{}", message_bytes.len(), dump);
+
+    let message = match Message::parse(message_bytes) {
+        Ok(msg) => msg,
+        Err(e) => {
+            log::error!("Failed to parse message: {}", e);
+            return Ok(None);
+        }
+    };
+
+    if !is_host_request(&message) {
+        log::warn!("Dropping {} message, the enclave does not serve it", message.cmd());
+        return Ok(None);
+    }
+
+    log::info!("Forwarding {} request to enclave", message.cmd());
+
+    match write_message(enclave, message_bytes, timeout, shutdown_token).await? {
+        IoResult::Success(_) => {}
+        IoResult::Closed => bail!("Failed to forward request, enclave closed the connection"),
+        IoResult::Timeout => bail!("Failed to forward request, write to enclave timed out"),
+    }
+
+    match read_message(enclave, max_frame_size, timeout, shutdown_token).await? {
+        IoResult::Success(bytes) => Ok(Some(bytes)),
+        IoResult::Closed => bail!("Enclave closed the connection before responding"),
+        IoResult::Timeout => bail!("Timeout waiting for enclave response"),
+    }
+}
+
+/// Requests the host answers; anything else would leave the enclave connection waiting for a response.
+fn is_host_request(message: &Message) -> bool {
+    matches!(
+        message,
+        Message::VerifyCVVRequest(_)
+            | Message::GenerateCVVRequest(_)
+            | Message::VerifyPvvRequest(_)
+            | Message::GenerateOffsetRequest(_)
+            | Message::VerifyOffsetRequest(_)
+            | Message::TranslatePinRequest(_)
+            | Message::DukptTranslatePinRequest(_)
+            | Message::DukptDecryptRequest(_)
+    )
+}
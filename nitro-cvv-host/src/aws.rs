@@ -1,9 +1,7 @@
 
 use anyhow::{Result, anyhow, Context};
-
-use tokio::time::Duration;
-use tokio_vsock::{VsockAddr, VsockStream, VMADDR_CID_HOST};
-use tokio_util::sync::CancellationToken;
+use async_trait::async_trait;
+use std::sync::Arc;
 
 use aws_nitro_enclaves_nsm_api::api::{Request, Response};
 use aws_nitro_enclaves_nsm_api::driver as nsm_driver;
@@ -14,8 +12,8 @@ use aws_sdk_kms::types::RecipientInfo;
 
 use serde_bytes::ByteBuf;
 
-use nitro::message::{Message, GetKeyRequest};
-use nitro_tokio::message_utils::{read_message, write_message};
+use crate::backend::{AttestationProvider, KeyDecryptor};
+use crate::recipient::RecipientKey;
 
 pub fn get_attestation_document(
     user_data: Option<Vec<u8>>,
@@ -52,113 +50,74 @@ pub fn get_attestation_document(
     }
 }
 
-pub async fn decrypt_with_attestation(
-    kms_client: &KmsClient,
-    ciphertext: &[u8],
-) -> Result<Vec<u8>> {
-    log::debug!("Decrypting with KMS attestation ({} bytes)", ciphertext.len());
-
-    let attestation_doc = get_attestation_document(None, None, None)
-        .context("Failed to get attestation from NSM")?;
-    
-    log::debug!("Attestation: {} bytes", attestation_doc.len());
-
-    let recipient = RecipientInfo::builder()
-        .attestation_document(Blob::new(attestation_doc))
-        .key_encryption_algorithm(
-            aws_sdk_kms::types::KeyEncryptionMechanism::RsaesOaepSha256
-        )
-        .build();
-
-    log::debug!("Calling KMS Decrypt API (via vsock-proxy)");
-    
-    let response = kms_client
-        .decrypt()
-        .ciphertext_blob(Blob::new(ciphertext.to_vec()))
-        .recipient(recipient)
-        .send()
-        .await
-        .context("KMS Decrypt API failed")?;
-
-    let plaintext = response
-        .plaintext()
-        .ok_or_else(|| anyhow!("KMS response contains no plaintext"))?;
-    
-    log::debug!("✓ Decrypted successfully ({} bytes)", plaintext.as_ref().len());
-    
-    Ok(plaintext.as_ref().to_vec())
+/// Attestation from the enclave's `/dev/nsm` device.
+pub struct NsmAttestation;
+
+impl AttestationProvider for NsmAttestation {
+    fn attestation_document(
+        &self,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+        public_key: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        get_attestation_document(user_data, nonce, public_key)
+    }
 }
 
-pub async fn get_secret_key(
-    key_id: &str,
-    shutdown_token: &CancellationToken,
-) -> Result<Vec<u8>> {
-
-    let secret_port = 3000;
-    let addr = VsockAddr::new(VMADDR_CID_HOST, secret_port);
-    
-    log::debug!("Connecting to secret server at CID {} port {}...", VMADDR_CID_HOST, secret_port);
-    
-    let mut stream = VsockStream::connect(addr)
-        .await
-        .context(format!("Failed to connect to secret server on port {}", secret_port))?;
-    
-    log::debug!("Connected to secret server");
-
-    let request = match GetKeyRequest::new(*b"0000", key_id.as_bytes().to_vec()) {
-        Ok(req) => req,
-        Err(e)  => {
-            log::error!("GetKeyRequest error: {}", e);
-            return Err(e);
-        }
-    };
+/// KMS `Decrypt` with the enclave attestation document attached as recipient.
+/// KMS wraps the plaintext to the recipient key instead of returning it.
+pub struct KmsDecryptor {
+    kms_client: KmsClient,
+    attestation: Arc<dyn AttestationProvider>,
+    recipient: RecipientKey,
+}
 
-    let timeout = Some(Duration::from_secs(60));
-    
-    log::debug!("Requesting key: '{}'", key_id);
-
-    let written = write_message(
-        &mut stream,
-        &request.to_bytes(),
-        timeout,
-        shutdown_token
-    ).await?;
-    
-    if written == 0 {
-        return Err(anyhow!("Failed to send request to secret server"));
-    }
-    
-    log::debug!("Request sent ({} bytes)", written);
-
-    let response_bytes = read_message(&mut stream, timeout, shutdown_token)
-        .await?;
-    
-    if response_bytes.is_empty() {
-        return Err(anyhow!("No response from secret server"));
-    }
-    
-    log::debug!("Response received ({} bytes)", response_bytes.len());
-
-    let message = Message::parse(&response_bytes)
-        .context("Failed to parse secret server response")?;
-    
-    match message {
-        Message::GetKeyResponse(response) => {
-            if !response.is_success() {
-                return Err(anyhow!(
-                    "Secret server returned error: {}",
-                    response.response_code_str()
-                ));
-            }
-            
-            let encrypted_key = response.encrypted_key
-                .ok_or_else(|| anyhow!("Response indicated success but no encrypted key"))?;
-            
-            log::debug!("✓ Got encrypted key from secret server ({} bytes)", encrypted_key.len());
-            
-            Ok(encrypted_key)
-        }
-        _ => Err(anyhow!("Unexpected message type from secret server")),
+impl KmsDecryptor {
+    pub fn new(kms_client: KmsClient, attestation: Arc<dyn AttestationProvider>) -> Result<Self> {
+        let recipient = RecipientKey::generate()?;
+        Ok(Self { kms_client, attestation, recipient })
     }
 }
 
+#[async_trait]
+impl KeyDecryptor for KmsDecryptor {
+    async fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        log::debug!("Decrypting with KMS attestation ({} bytes)", ciphertext.len());
+
+        let public_key = self.recipient.public_key_der().to_vec();
+        let attestation_doc = self.attestation.attestation_document(None, None, Some(public_key))
+            .context("Failed to get attestation from NSM")?;
+        
+        log::debug!("Attestation: {} bytes", attestation_doc.len());
+
+        let recipient = RecipientInfo::builder()
+            .attestation_document(Blob::new(attestation_doc))
+            .key_encryption_algorithm(
+                aws_sdk_kms::types::KeyEncryptionMechanism::RsaesOaepSha256
+            )
+            .build();
+
+        log::debug!("Calling KMS Decrypt API (via vsock-proxy)");
+        
+        let response = self.kms_client
+            .decrypt()
+            .ciphertext_blob(Blob::new(ciphertext.to_vec()))
+            .recipient(recipient)
+            .send()
+            .await
+            .context("KMS Decrypt API failed")?;
+
+        let enveloped = response
+            .ciphertext_for_recipient()
+            .ok_or_else(|| anyhow!("KMS response contains no CiphertextForRecipient"))?;
+
+        log::debug!("CiphertextForRecipient received ({} bytes)", enveloped.as_ref().len());
+
+        let plaintext = self.recipient.decrypt_enveloped(enveloped.as_ref())
+            .context("Failed to decrypt CiphertextForRecipient")?;
+        
+        log::debug!("✓ Decrypted successfully ({} bytes)", plaintext.len());
+        
+        Ok(plaintext.to_vec())
+    }
+}
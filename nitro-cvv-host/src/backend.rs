@@ -0,0 +1,18 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// Source of NSM attestation documents.
+pub trait AttestationProvider: Send + Sync {
+    fn attestation_document(
+        &self,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+        public_key: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>>;
+}
+
+/// Decrypts key material fetched from the secret server.
+#[async_trait]
+pub trait KeyDecryptor: Send + Sync {
+    async fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>>;
+}
@@ -1,33 +1,37 @@
+use aes::{Aes128, Aes192, Aes256};
 use des::Des;
 use des::TdesEde2;
 use des::TdesEde3;
 use des::cipher::{BlockEncrypt, BlockDecrypt, KeyInit};
+use des::cipher::generic_array::GenericArray;
+use zeroize::{Zeroize, Zeroizing};
 
+/// Single, double or triple length DES key; shared by the CVV and PIN code.
 #[derive(Clone)]
-enum DesKey {
+pub(crate) enum DesKey {
     Single([u8; 8]),
     Double([u8; 16]),
     Triple([u8; 24]),
 }
 
 impl DesKey {
-    fn from_hex(key_hex: &str) -> Result<Self, String> {
-        let key_bytes = hex::decode(key_hex)
-            .map_err(|e| format!("Failed to decode key: {}", e))?;
+    pub(crate) fn from_hex(key_hex: &str) -> Result<Self, String> {
+        let key_bytes = Zeroizing::new(hex::decode(key_hex)
+            .map_err(|e| format!("Failed to decode key: {}", e))?);
         
         match key_bytes.len() {
             8 => {
-                let k: [u8; 8] = key_bytes.try_into()
+                let k: [u8; 8] = key_bytes.as_slice().try_into()
                     .map_err(|_| "Failed to convert key to 8-byte array".to_string())?;
                 Ok(DesKey::Single(k))
             }
             16 => {
-                let k: [u8; 16] = key_bytes.try_into()
+                let k: [u8; 16] = key_bytes.as_slice().try_into()
                     .map_err(|_| "Failed to convert key to 16-byte array".to_string())?;
                 Ok(DesKey::Double(k))
             }
             24 => {
-                let k: [u8; 24] = key_bytes.try_into()
+                let k: [u8; 24] = key_bytes.as_slice().try_into()
                     .map_err(|_| "Failed to convert key to 24-byte array".to_string())?;
                 Ok(DesKey::Triple(k))
             }
@@ -40,7 +44,7 @@ impl DesKey {
         }
     }
 
-    fn encrypt(&self, block: &[u8; 8]) -> [u8; 8] {
+    pub(crate) fn encrypt(&self, block: &[u8; 8]) -> [u8; 8] {
         match self {
             DesKey::Single(key) => {
                 let cipher = Des::new(key.into());
@@ -69,7 +73,7 @@ impl DesKey {
         }
     }
 
-    fn decrypt(&self, block: &[u8; 8]) -> [u8; 8] {
+    pub(crate) fn decrypt(&self, block: &[u8; 8]) -> [u8; 8] {
         match self {
             DesKey::Single(key) => {
                 let cipher = Des::new(key.into());
@@ -99,6 +103,76 @@ impl DesKey {
     }
 }
 
+impl Drop for DesKey {
+    fn drop(&mut self) {
+        match self {
+            DesKey::Single(key) => key.zeroize(),
+            DesKey::Double(key) => key.zeroize(),
+            DesKey::Triple(key) => key.zeroize(),
+        }
+    }
+}
+
+/// AES key of 128, 192 or 256 bits; used where ISO 9564 format 4 needs AES.
+#[derive(Clone)]
+pub(crate) enum AesKey {
+    Aes128([u8; 16]),
+    Aes192([u8; 24]),
+    Aes256([u8; 32]),
+}
+
+impl AesKey {
+    pub(crate) fn from_hex(key_hex: &str) -> Result<Self, String> {
+        let key_bytes = Zeroizing::new(hex::decode(key_hex)
+            .map_err(|e| format!("Failed to decode key: {}", e))?);
+
+        match key_bytes.len() {
+            16 => Ok(AesKey::Aes128(key_bytes.as_slice().try_into()
+                .map_err(|_| "Failed to convert key to 16-byte array".to_string())?)),
+            24 => Ok(AesKey::Aes192(key_bytes.as_slice().try_into()
+                .map_err(|_| "Failed to convert key to 24-byte array".to_string())?)),
+            32 => Ok(AesKey::Aes256(key_bytes.as_slice().try_into()
+                .map_err(|_| "Failed to convert key to 32-byte array".to_string())?)),
+            _ => {
+                Err(format!(
+                    "Invalid key length: {} bytes. Expected 16, 24, or 32 bytes (32, 48, or 64 hex characters)",
+                    key_bytes.len()
+                ))
+            }
+        }
+    }
+
+    pub(crate) fn encrypt(&self, block: &[u8; 16]) -> [u8; 16] {
+        let mut block_array = GenericArray::clone_from_slice(block);
+        match self {
+            AesKey::Aes128(key) => Aes128::new(key.into()).encrypt_block(&mut block_array),
+            AesKey::Aes192(key) => Aes192::new(key.into()).encrypt_block(&mut block_array),
+            AesKey::Aes256(key) => Aes256::new(key.into()).encrypt_block(&mut block_array),
+        }
+        block_array.into()
+    }
+
+    pub(crate) fn decrypt(&self, block: &[u8; 16]) -> [u8; 16] {
+        let mut block_array = GenericArray::clone_from_slice(block);
+        match self {
+            AesKey::Aes128(key) => Aes128::new(key.into()).decrypt_block(&mut block_array),
+            AesKey::Aes192(key) => Aes192::new(key.into()).decrypt_block(&mut block_array),
+            AesKey::Aes256(key) => Aes256::new(key.into()).decrypt_block(&mut block_array),
+        }
+        block_array.into()
+    }
+}
+
+impl Drop for AesKey {
+    fn drop(&mut self) {
+        match self {
+            AesKey::Aes128(key) => key.zeroize(),
+            AesKey::Aes192(key) => key.zeroize(),
+            AesKey::Aes256(key) => key.zeroize(),
+        }
+    }
+}
+
 pub struct Cvv {
     cvk_a: DesKey,
     cvk_b: DesKey
@@ -185,8 +259,12 @@ impl Cvv {
         let cvv_calc = self.calculate(pan, expired_date, service_code)
             .map_err(|e| format!("Failed to calculate cvv: {}", e))?;
 
-        let result   = cvv_calc == cvv;
-        Ok(result)
+        Ok(constant_time_eq(cvv_calc.as_bytes(), cvv.as_bytes()))
     }
 
 }
+
+/// Compares secret values without returning early on the first difference.
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,374 @@
+//! DUKPT transaction keys derived from a base derivation key (BDK).
+//!
+//! TDES DUKPT follows ANSI X9.24-1 (10-byte KSN, 21-bit counter) and AES
+//! DUKPT follows ANSI X9.24-3 (12-byte KSN, 32-bit counter); the KSN length
+//! selects the variant.
+//!
+//! The tests check the X9.24-1 and X9.24-3 published sample vectors.
+
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::cvv::{AesKey, DesKey};
+use crate::pin_block::PinKey;
+
+const TDES_KSN_LEN: usize = 10;
+const AES_KSN_LEN: usize = 12;
+
+/// The TDES counter is the rightmost 21 bits of the KSN.
+const TDES_COUNTER_MASK: u32 = 0x1F_FFFF;
+
+/// Terminals never use counters with more bits set.
+const TDES_MAX_COUNTER_BITS: u32 = 10;
+const AES_MAX_COUNTER_BITS: u32 = 16;
+
+const TDES_KEY_MASK: [u8; 16] = [
+    0xC0, 0xC0, 0xC0, 0xC0, 0x00, 0x00, 0x00, 0x00,
+    0xC0, 0xC0, 0xC0, 0xC0, 0x00, 0x00, 0x00, 0x00,
+];
+const TDES_PIN_VARIANT: [u8; 16] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF,
+];
+const TDES_DATA_VARIANT: [u8; 16] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00,
+];
+
+/// X9.24-3 key usage indicators.
+const AES_USAGE_KEY_DERIVATION: u16 = 0x8000;
+const AES_USAGE_INITIAL_KEY:    u16 = 0x8001;
+const AES_USAGE_PIN_ENCRYPTION: u16 = 0x1000;
+const AES_USAGE_DATA_ENCRYPT:   u16 = 0x3000;
+
+/// Key serial number; its length tells TDES and AES DUKPT apart.
+pub enum Ksn {
+    Tdes([u8; TDES_KSN_LEN]),
+    Aes([u8; AES_KSN_LEN]),
+}
+
+impl Ksn {
+    pub fn from_hex(ksn_hex: &str) -> Result<Self, String> {
+        let bytes = hex::decode(ksn_hex).map_err(|e| format!("Failed to decode KSN: {}", e))?;
+
+        let ksn = match bytes.len() {
+            TDES_KSN_LEN => Ksn::Tdes(bytes.try_into().map_err(|_| "KSN must be 10 bytes".to_string())?),
+            AES_KSN_LEN  => Ksn::Aes(bytes.try_into().map_err(|_| "KSN must be 12 bytes".to_string())?),
+            len => return Err(format!("KSN must be 10 (TDES) or 12 (AES) bytes, got {}", len)),
+        };
+
+        let counter  = ksn.counter();
+        let max_bits = if ksn.is_aes() { AES_MAX_COUNTER_BITS } else { TDES_MAX_COUNTER_BITS };
+        if counter == 0 || counter.count_ones() > max_bits {
+            return Err(format!("KSN counter {:X} is not a valid transaction counter", counter));
+        }
+
+        Ok(ksn)
+    }
+
+    pub fn is_aes(&self) -> bool {
+        matches!(self, Ksn::Aes(_))
+    }
+
+    fn counter(&self) -> u32 {
+        match self {
+            Ksn::Tdes(ksn) => u32::from_be_bytes([0, ksn[7], ksn[8], ksn[9]]) & TDES_COUNTER_MASK,
+            Ksn::Aes(ksn)  => u32::from_be_bytes([ksn[8], ksn[9], ksn[10], ksn[11]]),
+        }
+    }
+}
+
+/// PIN encryption key for the transaction identified by `ksn`.
+pub fn pin_key(bdk_hex: &str, ksn: &Ksn) -> Result<PinKey, String> {
+    let bdk = Zeroizing::new(hex::decode(bdk_hex).map_err(|e| format!("Failed to decode BDK: {}", e))?);
+
+    match ksn {
+        Ksn::Tdes(ksn) => {
+            let mut key = tdes_transaction_key(&bdk, ksn)?;
+            xor(key.as_mut_slice(), &TDES_PIN_VARIANT);
+            Ok(PinKey::Des(DesKey::Double(*key)))
+        }
+        Ksn::Aes(ksn) => {
+            let key = aes_working_key(&bdk, ksn, AES_USAGE_PIN_ENCRYPTION)?;
+            Ok(PinKey::Aes(aes_key(&key)?))
+        }
+    }
+}
+
+/// Decrypts data the terminal encrypted with its data encryption key, in
+/// CBC mode with a zero IV.
+pub fn decrypt_data(bdk_hex: &str, ksn: &Ksn, data: &[u8]) -> Result<Zeroizing<Vec<u8>>, String> {
+    let bdk = Zeroizing::new(hex::decode(bdk_hex).map_err(|e| format!("Failed to decode BDK: {}", e))?);
+
+    match ksn {
+        Ksn::Tdes(ksn) => {
+            let mut variant = tdes_transaction_key(&bdk, ksn)?;
+            xor(variant.as_mut_slice(), &TDES_DATA_VARIANT);
+
+            // X9.24-1 only defines the data variant; terminals (IDTech readers,
+            // BP-Tools) encrypt each half of it under the whole variant and use
+            // the result as the data key.
+            let variant_key = DesKey::Double(*variant);
+            let mut half = Zeroizing::new([0u8; 8]);
+            let mut data_key = Zeroizing::new([0u8; 16]);
+
+            half.copy_from_slice(&variant[..8]);
+            data_key[..8].copy_from_slice(&variant_key.encrypt(&half));
+            half.copy_from_slice(&variant[8..]);
+            data_key[8..].copy_from_slice(&variant_key.encrypt(&half));
+
+            let key = DesKey::Double(*data_key);
+            cbc_decrypt::<8>(data, |block| key.decrypt(block))
+        }
+        Ksn::Aes(ksn) => {
+            let key = aes_key(&aes_working_key(&bdk, ksn, AES_USAGE_DATA_ENCRYPT)?)?;
+            cbc_decrypt::<16>(data, |block| key.decrypt(block))
+        }
+    }
+}
+
+/// Initial PIN encryption key: the BDK encrypts the KSN with its counter
+/// cleared, once as is and once XORed with the key mask.
+fn tdes_ipek(bdk: &[u8], ksn: &[u8; TDES_KSN_LEN]) -> Result<Zeroizing<[u8; 16]>, String> {
+    let bdk: Zeroizing<[u8; 16]> = Zeroizing::new(bdk.try_into()
+        .map_err(|_| "TDES BDK must be a double-length key (32 hex characters)".to_string())?);
+
+    let mut base: [u8; 8] = ksn[..8].try_into().map_err(|_| "KSN must be 10 bytes".to_string())?;
+    base[7] &= 0xE0;
+
+    let mut masked = bdk.clone();
+    xor(masked.as_mut_slice(), &TDES_KEY_MASK);
+
+    let mut ipek = Zeroizing::new([0u8; 16]);
+    ipek[..8].copy_from_slice(&DesKey::Double(*bdk).encrypt(&base));
+    ipek[8..].copy_from_slice(&DesKey::Double(*masked).encrypt(&base));
+
+    Ok(ipek)
+}
+
+/// Walks the counter bits from the most significant one, applying the
+/// non-reversible key generation process for each bit set.
+fn tdes_transaction_key(bdk: &[u8], ksn: &[u8; TDES_KSN_LEN]) -> Result<Zeroizing<[u8; 16]>, String> {
+    let mut key = tdes_ipek(bdk, ksn)?;
+
+    let counter = Ksn::Tdes(*ksn).counter();
+    let mut register: [u8; 8] = ksn[2..].try_into().map_err(|_| "KSN must be 10 bytes".to_string())?;
+    register[5] &= 0xE0;
+    register[6] = 0;
+    register[7] = 0;
+
+    for bit in (0..21).rev().map(|shift| 1u32 << shift) {
+        if counter & bit == 0 {
+            continue;
+        }
+
+        let bytes = bit.to_be_bytes();
+        register[5] |= bytes[1];
+        register[6] |= bytes[2];
+        register[7] |= bytes[3];
+
+        key = non_reversible_key(&key, &register);
+    }
+
+    Ok(key)
+}
+
+fn non_reversible_key(key: &[u8; 16], register: &[u8; 8]) -> Zeroizing<[u8; 16]> {
+    // Right half XORed in, encrypted under the left half, right half XORed out
+    let half = |key: &[u8; 16]| {
+        let mut left = [0u8; 8];
+        left.copy_from_slice(&key[..8]);
+        let mut block = *register;
+        xor(&mut block, &key[8..]);
+        let mut out = DesKey::Single(left).encrypt(&block);
+        xor(&mut out, &key[8..]);
+        out
+    };
+
+    let mut next = Zeroizing::new([0u8; 16]);
+    next[8..].copy_from_slice(&half(key));
+
+    let mut masked = Zeroizing::new(*key);
+    xor(masked.as_mut_slice(), &TDES_KEY_MASK);
+    next[..8].copy_from_slice(&half(&masked));
+
+    next
+}
+
+/// Derives the working key for `usage`, of the same AES size as the BDK.
+fn aes_working_key(bdk: &[u8], ksn: &[u8; AES_KSN_LEN], usage: u16) -> Result<Zeroizing<Vec<u8>>, String> {
+    let bdk_key = aes_key(bdk)?;
+    let initial_key_id: [u8; 8] = ksn[..8].try_into().map_err(|_| "KSN must be 12 bytes".to_string())?;
+
+    let mut derivation_key = aes_derive(&bdk_key, AES_USAGE_INITIAL_KEY, bdk.len(), &initial_key_id);
+
+    let counter = Ksn::Aes(*ksn).counter();
+    let mut working_counter = 0u32;
+
+    for bit in (0..32).rev().map(|shift| 1u32 << shift) {
+        if counter & bit == 0 {
+            continue;
+        }
+        working_counter |= bit;
+
+        let key = aes_key(&derivation_key)?;
+        derivation_key = aes_derive(&key, AES_USAGE_KEY_DERIVATION, bdk.len(), &derivation_id(ksn, working_counter));
+    }
+
+    let key = aes_key(&derivation_key)?;
+    Ok(aes_derive(&key, usage, bdk.len(), &derivation_id(ksn, counter)))
+}
+
+/// Rightmost half of the initial key ID followed by the counter.
+fn derivation_id(ksn: &[u8; AES_KSN_LEN], counter: u32) -> [u8; 8] {
+    let mut id = [0u8; 8];
+    id[..4].copy_from_slice(&ksn[4..8]);
+    id[4..].copy_from_slice(&counter.to_be_bytes());
+    id
+}
+
+/// Encrypts one derivation data block per 128 bits of output.
+fn aes_derive(key: &AesKey, usage: u16, key_len: usize, id: &[u8; 8]) -> Zeroizing<Vec<u8>> {
+    let algorithm: u16 = match key_len {
+        16 => 0x0002,
+        24 => 0x0003,
+        _  => 0x0004,
+    };
+    let length_bits = (key_len * 8) as u16;
+
+    let mut derived = Zeroizing::new(Vec::with_capacity(key_len.next_multiple_of(16)));
+    for block_counter in 1..=key_len.div_ceil(16) as u8 {
+        let mut data = [0u8; 16];
+        data[0] = 0x01;
+        data[1] = block_counter;
+        data[2..4].copy_from_slice(&usage.to_be_bytes());
+        data[4..6].copy_from_slice(&algorithm.to_be_bytes());
+        data[6..8].copy_from_slice(&length_bits.to_be_bytes());
+        data[8..].copy_from_slice(id);
+
+        derived.extend_from_slice(&key.encrypt(&data));
+    }
+
+    derived.truncate(key_len);
+    derived
+}
+
+fn aes_key(key: &[u8]) -> Result<AesKey, String> {
+    AesKey::from_hex(&Zeroizing::new(hex::encode(key)))
+}
+
+fn cbc_decrypt<const N: usize>(data: &[u8], decrypt: impl Fn(&[u8; N]) -> [u8; N]) -> Result<Zeroizing<Vec<u8>>, String> {
+    if data.is_empty() || !data.len().is_multiple_of(N) {
+        return Err(format!("Data must be a non-empty multiple of {} bytes", N));
+    }
+
+    let mut clear = Zeroizing::new(Vec::with_capacity(data.len()));
+    let mut previous = [0u8; N];
+
+    for chunk in data.chunks_exact(N) {
+        let block: [u8; N] = chunk.try_into().map_err(|_| "Invalid data block".to_string())?;
+        let mut plain = decrypt(&block);
+        xor(&mut plain, &previous);
+        clear.extend_from_slice(&plain);
+        plain.zeroize();
+        previous = block;
+    }
+
+    Ok(clear)
+}
+
+fn xor(block: &mut [u8], mask: &[u8]) {
+    for (byte, mask_byte) in block.iter_mut().zip(mask) {
+        *byte ^= mask_byte;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TDES_BDK: &str = "0123456789ABCDEFFEDCBA9876543210";
+    const AES_BDK: &str = "FEDCBA9876543210F1F1F1F1F1F1F1F1";
+
+    /// "4111111111111111=2512101" padded with zeros to 32 bytes.
+    const CLEAR_DATA: &str = "343131313131313131313131313131313D323531323130310000000000000000";
+
+    fn tdes_ksn(ksn_hex: &str) -> [u8; TDES_KSN_LEN] {
+        hex::decode(ksn_hex).unwrap().try_into().unwrap()
+    }
+
+    fn decrypt(bdk: &str, ksn_hex: &str, data_hex: &str) -> String {
+        let ksn = Ksn::from_hex(ksn_hex).unwrap();
+        hex::encode_upper(decrypt_data(bdk, &ksn, &hex::decode(data_hex).unwrap()).unwrap())
+    }
+
+    #[test]
+    fn tdes_ipek_matches_published_vector() {
+        let bdk = hex::decode(TDES_BDK).unwrap();
+        let ipek = tdes_ipek(&bdk, &tdes_ksn("FFFF9876543210E00000")).unwrap();
+        assert_eq!(hex::encode_upper(*ipek), "6AC292FAA1315B4D858AB3A3D7D5933A");
+    }
+
+    #[test]
+    fn tdes_transaction_key_matches_published_vector() {
+        let bdk = hex::decode(TDES_BDK).unwrap();
+        let key = tdes_transaction_key(&bdk, &tdes_ksn("FFFF9876543210E00001")).unwrap();
+        assert_eq!(hex::encode_upper(*key), "042666B49184CFA368DE9628D0397BC9");
+    }
+
+    #[test]
+    fn tdes_pin_key_matches_published_vector() {
+        let ksn = Ksn::from_hex("FFFF9876543210E00001").unwrap();
+        let PinKey::Des(DesKey::Double(key)) = pin_key(TDES_BDK, &ksn).unwrap() else { panic!("expected a double-length DES key") };
+        assert_eq!(hex::encode_upper(key), "042666B49184CF5C68DE9628D0397B36");
+    }
+
+    #[test]
+    fn tdes_data_key_matches_reference() {
+        // Data key 448D3F076D8304036A55A3D7E0055A78
+        let data = "7B779F3C0277D9733FEF2488997A7FB1E860985AC8B3608E40E2F1DBD046BE18";
+        assert_eq!(decrypt(TDES_BDK, "FFFF9876543210E00001", data), CLEAR_DATA);
+    }
+
+    #[test]
+    fn aes_initial_key_matches_published_vector() {
+        let bdk = aes_key(&hex::decode(AES_BDK).unwrap()).unwrap();
+        let id: [u8; 8] = hex::decode("1234567890123456").unwrap().try_into().unwrap();
+        let key = aes_derive(&bdk, AES_USAGE_INITIAL_KEY, 16, &id);
+        assert_eq!(hex::encode_upper(&*key), "1273671EA26AC29AFA4D1084127652A1");
+    }
+
+    #[test]
+    fn aes_data_decrypts_across_the_counter_range() {
+        let vectors = [
+            ("123456789012345600000001", "87D45DC64A786FCC6B44187CFD2DFA795394DAC4A8EBE616A814F964D36B7993"),
+            ("123456789012345680000000", "70156DEB1B3B791C574A3D0E6C7BAF12221B73A81A187D6F2EEA34D2D1A34BA5"),
+            ("1234567890123456FFFF0000", "C7806B03AC45D600B80977DDB827F7EC5F5E171E0D923918BCDB5E1F8097E985"),
+        ];
+
+        for (ksn, data) in vectors {
+            assert_eq!(decrypt(AES_BDK, ksn, data), CLEAR_DATA, "KSN {}", ksn);
+        }
+    }
+
+    #[test]
+    fn zero_counter_is_rejected() {
+        assert!(Ksn::from_hex("FFFF9876543210E00000").is_err());
+        assert!(Ksn::from_hex("123456789012345600000000").is_err());
+    }
+
+    #[test]
+    fn tdes_counter_is_limited_to_ten_bits() {
+        assert!(Ksn::from_hex("FFFF9876543210FFF800").is_ok());
+        assert!(Ksn::from_hex("FFFF9876543210E003FF").is_ok());
+        assert!(Ksn::from_hex("FFFF9876543210FFFC00").is_err());
+        assert!(Ksn::from_hex("FFFF9876543210FFFFFF").is_err());
+    }
+
+    #[test]
+    fn aes_counter_is_limited_to_sixteen_bits() {
+        assert!(Ksn::from_hex("1234567890123456FFFF0000").is_ok());
+        assert!(Ksn::from_hex("12345678901234560000FFFF").is_ok());
+        assert!(Ksn::from_hex("1234567890123456FFFF8000").is_err());
+        assert!(Ksn::from_hex("1234567890123456FFFFFFFF").is_err());
+    }
+}
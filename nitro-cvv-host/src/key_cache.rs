@@ -0,0 +1,178 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use zeroize::Zeroizing;
+
+struct Entry {
+    key: Zeroizing<Vec<u8>>,
+    expires_at: Instant,
+    last_used: Instant,
+}
+
+/// Decrypted key material by key name.
+///
+/// Entries expire `ttl` after insertion; when full, the least recently used
+/// entry is dropped. Removed entries are zeroized. A `max_entries` of 0
+/// disables caching.
+pub struct KeyCache {
+    entries: Mutex<HashMap<String, Entry>>,
+    ttl: Duration,
+    max_entries: usize,
+}
+
+impl KeyCache {
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::with_capacity(max_entries)),
+            ttl,
+            max_entries,
+        }
+    }
+
+    pub fn get(&self, key_name: &str) -> Option<Zeroizing<Vec<u8>>> {
+        let mut entries = self.lock();
+        let now = Instant::now();
+
+        match entries.get_mut(key_name) {
+            Some(entry) if entry.expires_at > now => {
+                entry.last_used = now;
+                Some(entry.key.clone())
+            }
+            Some(_) => {
+                log::debug!("Key '{}' expired from cache", key_name);
+                entries.remove(key_name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key_name: &str, key: Zeroizing<Vec<u8>>) {
+        if self.max_entries == 0 || self.ttl.is_zero() {
+            return;
+        }
+
+        let mut entries = self.lock();
+        let now = Instant::now();
+
+        entries.retain(|_, entry| entry.expires_at > now);
+
+        if !entries.contains_key(key_name) && entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(name, _)| name.clone());
+
+            if let Some(name) = oldest {
+                log::debug!("Key cache full, evicting '{}'", name);
+                entries.remove(&name);
+            }
+        }
+
+        entries.insert(key_name.to_string(), Entry {
+            key,
+            expires_at: now + self.ttl,
+            last_used: now,
+        });
+    }
+
+    /// Drops one key, returning whether it was cached.
+    pub fn evict(&self, key_name: &str) -> bool {
+        self.lock().remove(key_name).is_some()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Drops expired entries, returning how many were removed.
+    pub fn purge_expired(&self) -> usize {
+        let mut entries = self.lock();
+        let before = entries.len();
+        let now = Instant::now();
+
+        entries.retain(|_, entry| entry.expires_at > now);
+        before - entries.len()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        // Entries are always left consistent, so a poisoned lock is still usable
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    fn key(value: &str) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn entries_expire_after_ttl() {
+        let cache = KeyCache::new(Duration::from_millis(50), 4);
+        cache.insert("cvk-a", key("0123456789ABCDEF"));
+        cache.insert("cvk-b", key("FEDCBA9876543210"));
+
+        assert_eq!(cache.get("cvk-a").as_deref().map(Vec::as_slice), Some(b"0123456789ABCDEF".as_slice()));
+
+        sleep(Duration::from_millis(60));
+        assert!(cache.get("cvk-a").is_none());
+
+        // The other entry is still held until it is looked up or purged
+        assert_eq!(cache.purge_expired(), 1);
+        assert!(cache.get("cvk-b").is_none());
+    }
+
+    #[test]
+    fn least_recently_used_entry_is_evicted_when_full() {
+        let cache = KeyCache::new(Duration::from_secs(60), 2);
+        cache.insert("cvk-a", key("a"));
+        sleep(Duration::from_millis(2));
+        cache.insert("cvk-b", key("b"));
+        sleep(Duration::from_millis(2));
+
+        // Using cvk-a makes cvk-b the least recently used
+        assert!(cache.get("cvk-a").is_some());
+        sleep(Duration::from_millis(2));
+        cache.insert("cvk-c", key("c"));
+
+        assert!(cache.get("cvk-a").is_some());
+        assert!(cache.get("cvk-b").is_none());
+        assert!(cache.get("cvk-c").is_some());
+
+        // Replacing an existing entry evicts nothing
+        cache.insert("cvk-c", key("c2"));
+        assert_eq!(cache.get("cvk-c").as_deref().map(Vec::as_slice), Some(b"c2".as_slice()));
+        assert!(cache.get("cvk-a").is_some());
+    }
+
+    #[test]
+    fn zero_size_or_ttl_disables_caching() {
+        for cache in [KeyCache::new(Duration::from_secs(60), 0), KeyCache::new(Duration::ZERO, 4)] {
+            cache.insert("cvk-a", key("a"));
+            assert!(cache.get("cvk-a").is_none());
+        }
+    }
+
+    #[test]
+    fn clear_and_evict_drop_entries() {
+        let cache = KeyCache::new(Duration::from_secs(60), 4);
+        cache.insert("cvk-a", key("a"));
+        cache.insert("cvk-b", key("b"));
+        cache.insert("cvk-c", key("c"));
+
+        assert!(cache.evict("cvk-a"));
+        assert!(!cache.evict("cvk-a"));
+        assert!(cache.get("cvk-a").is_none());
+
+        cache.clear();
+        assert!(cache.get("cvk-b").is_none());
+        assert!(cache.get("cvk-c").is_none());
+        assert_eq!(cache.purge_expired(), 0);
+    }
+}
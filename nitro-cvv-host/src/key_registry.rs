@@ -0,0 +1,117 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+pub use nitro_config::{KeyAlgorithm, KeySpec, KeyUsage};
+
+/// Cipher family an operation needs from its key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    /// Single-length DES, as CVK and PVV key halves are.
+    Des,
+    Tdes,
+    Aes,
+}
+
+impl Cipher {
+    fn accepts(&self, algorithm: KeyAlgorithm) -> bool {
+        match self {
+            Cipher::Des  => algorithm == KeyAlgorithm::Des,
+            Cipher::Tdes => algorithm.is_tdes(),
+            Cipher::Aes  => algorithm.is_aes(),
+        }
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Cipher::Des  => write!(f, "single-length DES"),
+            Cipher::Tdes => write!(f, "TDES"),
+            Cipher::Aes  => write!(f, "AES"),
+        }
+    }
+}
+
+/// A request named a key for something its registration does not allow.
+#[derive(Debug)]
+pub struct KeyNotPermitted {
+    pub key_name: String,
+    pub reason: String,
+}
+
+impl fmt::Display for KeyNotPermitted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Key '{}' {}", self.key_name, self.reason)
+    }
+}
+
+impl std::error::Error for KeyNotPermitted {}
+
+impl KeyNotPermitted {
+    pub fn new(key_name: &str, reason: impl Into<String>) -> Self {
+        Self { key_name: key_name.to_string(), reason: reason.into() }
+    }
+}
+
+/// Usage and algorithm of each key the enclave may load, from the `keys`
+/// configuration table. Nothing a request does can change them.
+pub struct KeyRegistry(HashMap<String, KeySpec>);
+
+impl KeyRegistry {
+
+    pub fn from_config(keys: &BTreeMap<String, KeySpec>) -> Self {
+        KeyRegistry(keys.iter().map(|(name, spec)| (name.clone(), *spec)).collect())
+    }
+
+    /// Returns the algorithm of `key_name` if it is registered for `usage`
+    /// with a key of the `cipher` family.
+    pub fn check(&self, key_name: &str, usage: KeyUsage, cipher: Cipher) -> Result<KeyAlgorithm, KeyNotPermitted> {
+        let Some(spec) = self.0.get(key_name) else {
+            return Err(KeyNotPermitted::new(key_name, "is not registered"));
+        };
+
+        if spec.usage != usage {
+            return Err(KeyNotPermitted::new(key_name, format!("is a {} key, not a {} key", spec.usage, usage)));
+        }
+        if !cipher.accepts(spec.algorithm) {
+            return Err(KeyNotPermitted::new(key_name, format!("is {}, not {}", spec.algorithm, cipher)));
+        }
+
+        Ok(spec.algorithm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registry() -> KeyRegistry {
+        KeyRegistry::from_config(&BTreeMap::from([
+            ("zpk".to_string(), KeySpec { usage: KeyUsage::PinEncryption, algorithm: KeyAlgorithm::Tdes2 }),
+            ("zpk-aes".to_string(), KeySpec { usage: KeyUsage::PinEncryption, algorithm: KeyAlgorithm::Aes128 }),
+        ]))
+    }
+
+    #[test]
+    fn registered_usage_and_cipher_are_accepted() {
+        assert_eq!(registry().check("zpk", KeyUsage::PinEncryption, Cipher::Tdes).unwrap(), KeyAlgorithm::Tdes2);
+        assert_eq!(registry().check("zpk-aes", KeyUsage::PinEncryption, Cipher::Aes).unwrap(), KeyAlgorithm::Aes128);
+    }
+
+    #[test]
+    fn other_usage_cipher_or_name_is_refused() {
+        let registry = registry();
+
+        let refused = registry.check("zpk", KeyUsage::Cvk, Cipher::Des).unwrap_err();
+        assert_eq!(refused.to_string(), "Key 'zpk' is a PIN encryption key, not a CVK key");
+
+        let refused = registry.check("zpk", KeyUsage::PinEncryption, Cipher::Aes).unwrap_err();
+        assert_eq!(refused.to_string(), "Key 'zpk' is double-length TDES, not AES");
+
+        let refused = registry.check("zpk-aes", KeyUsage::PinEncryption, Cipher::Tdes).unwrap_err();
+        assert_eq!(refused.to_string(), "Key 'zpk-aes' is AES-128, not TDES");
+
+        let refused = registry.check("cvk", KeyUsage::Cvk, Cipher::Des).unwrap_err();
+        assert_eq!(refused.to_string(), "Key 'cvk' is not registered");
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::{Result, Context};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use nitro_config::LocalConfig;
+use nitro_local::{LocalKms, MockNsm};
+
+use crate::backend::{AttestationProvider, KeyDecryptor};
+use crate::recipient::RecipientKey;
+
+impl AttestationProvider for MockNsm {
+    fn attestation_document(
+        &self,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+        public_key: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        MockNsm::attestation_document(self, user_data, nonce, public_key)
+    }
+}
+
+/// Decrypts through the in-process KMS emulator, attaching a mock NSM attestation.
+pub struct LocalKmsDecryptor {
+    kms: LocalKms,
+    attestation: Arc<dyn AttestationProvider>,
+    recipient: RecipientKey,
+}
+
+#[async_trait]
+impl KeyDecryptor for LocalKmsDecryptor {
+    async fn decrypt(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        log::debug!("Decrypting with local KMS ({} bytes)", ciphertext.len());
+
+        let public_key = self.recipient.public_key_der().to_vec();
+        let attestation_doc = self.attestation.attestation_document(None, None, Some(public_key))
+            .context("Failed to get attestation from mock NSM")?;
+
+        let enveloped = self.kms.decrypt_for_recipient(ciphertext, &attestation_doc)?;
+        let plaintext = self.recipient.decrypt_enveloped(&enveloped)
+            .context("Failed to decrypt CiphertextForRecipient")?;
+
+        Ok(plaintext.to_vec())
+    }
+}
+
+/// Builds the local decryptor; `local.kms_key` must match the secret server's.
+pub fn create_decryptor(config: &LocalConfig) -> Result<Arc<dyn KeyDecryptor>> {
+    let nsm = MockNsm::new().context("Failed to create mock NSM")?;
+
+    let kms = match &config.kms_key {
+        Some(key) => LocalKms::from_hex(key).context("Invalid local.kms_key")?,
+        None => {
+            log::warn!("local.kms_key not set, using the development master key");
+            LocalKms::development()
+        }
+    };
+    let kms = kms.with_trusted_certificate(nsm.certificate().to_vec());
+
+    let recipient = RecipientKey::generate()?;
+
+    Ok(Arc::new(LocalKmsDecryptor { kms, attestation: Arc::new(nsm), recipient }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use tokio_util::sync::CancellationToken;
+    use zeroize::Zeroizing;
+
+    use nitro::message::{ResponseCode, VerifyCVVRequest, VerifyCVVResponse};
+    use nitro_local::verify_attestation_document;
+    use nitro_metrics::Metrics;
+
+    use crate::key_cache::KeyCache;
+    use crate::key_registry::{KeyAlgorithm, KeyRegistry, KeySpec, KeyUsage};
+    use crate::pin::DecimalizationTables;
+    use crate::secret_client::{SecretClient, SecretClientConfig};
+    use crate::session::process_request;
+
+    const CVKA: &str = "0123456789ABCDEF";
+    const CVKB: &str = "FEDCBA9876543210";
+
+    #[test]
+    fn recipient_unwraps_key_released_against_attestation() {
+        let nsm = MockNsm::new().unwrap();
+        let recipient = RecipientKey::generate().unwrap();
+
+        let attestation_doc = nsm
+            .attestation_document(None, None, Some(recipient.public_key_der().to_vec()))
+            .unwrap();
+        let document = verify_attestation_document(&attestation_doc, nsm.certificate()).unwrap();
+        assert_eq!(document.public_key.as_ref().map(|key| key.as_slice()), Some(recipient.public_key_der()));
+
+        let kms = LocalKms::development().with_trusted_certificate(nsm.certificate().to_vec());
+        let ciphertext = kms.encrypt("cvk-a", CVKA.as_bytes()).unwrap();
+
+        let enveloped = kms.decrypt_for_recipient(&ciphertext, &attestation_doc).unwrap();
+        assert_eq!(recipient.decrypt_enveloped(&enveloped).unwrap().as_slice(), CVKA.as_bytes());
+
+        // A document from another NSM is not trusted
+        let other = MockNsm::new().unwrap()
+            .attestation_document(None, None, Some(recipient.public_key_der().to_vec()))
+            .unwrap();
+        assert!(kms.decrypt_for_recipient(&ciphertext, &other).is_err());
+    }
+
+    #[tokio::test]
+    async fn verify_cvv_with_keys_from_local_kms() {
+        let decryptor = create_decryptor(&LocalConfig::default()).unwrap();
+        let kms = LocalKms::development();
+
+        let key_cache = KeyCache::new(Duration::from_secs(60), 8);
+        for (key_id, key) in [("cvk-a", CVKA), ("cvk-b", CVKB)] {
+            let ciphertext = kms.encrypt(key_id, key.as_bytes()).unwrap();
+            let plaintext = decryptor.decrypt(&ciphertext).await.unwrap();
+            assert_eq!(plaintext, key.as_bytes());
+
+            key_cache.insert(key_id, Zeroizing::new(plaintext));
+        }
+
+        // Every key is cached, so the secret server is never contacted
+        let secret_client = SecretClient::new(SecretClientConfig {
+            cid: 1,
+            port: 0,
+            pool_size: 1,
+            request_timeout: Duration::from_millis(10),
+            max_frame_size: 1024,
+        }, CancellationToken::new());
+        let keys = KeyRegistry::from_config(&[("cvk-a", CVKA), ("cvk-b", CVKB)]
+            .map(|(key_id, _)| (key_id.to_string(), KeySpec { usage: KeyUsage::Cvk, algorithm: KeyAlgorithm::Des }))
+            .into());
+        let dectabs = DecimalizationTables::from_config(&Default::default()).unwrap();
+        let metrics = Metrics::new("nitro-cvv-host-test").unwrap();
+
+        for (cvv, expected) in [("561", ResponseCode::Success), ("562", ResponseCode::CvvMismatch)] {
+            let request = VerifyCVVRequest::new(*b"0001", "cvk-a", "cvk-b", cvv, "4123456789012345", "8701", "101")
+                .unwrap()
+                .to_bytes();
+
+            let response = process_request(&request, decryptor.as_ref(), &key_cache, &keys, &secret_client, &dectabs, &metrics)
+                .await
+                .unwrap();
+            let response = VerifyCVVResponse::parse(&response).unwrap();
+
+            assert_eq!(response.header.hdr, *b"0001");
+            assert_eq!(response.code(), Some(expected));
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use anyhow::{Result, Context};
 use anyhow::anyhow;
-use std::env;
+use clap::Parser;
+use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::signal;
+use tracing::Instrument;
 use tokio_util::sync::CancellationToken;
 use tokio_vsock::{
     VMADDR_CID_ANY,
@@ -11,16 +14,40 @@ use tokio_vsock::{
 };
 
 use aws_config::meta::region::RegionProviderChain;
-use aws_config::BehaviorVersion;
+use aws_config::{BehaviorVersion, Region};
 use aws_sdk_kms::Client as KmsClient;
 
-use nitro;
+use nitro_config::{Backend, HostArgs, HostConfig};
+use nitro_metrics::Metrics;
 
 mod aws;
+mod backend;
+mod cvv;
+mod dukpt;
+mod key_cache;
+mod key_registry;
+#[cfg(any(test, feature = "local"))]
+mod local;
+mod pin;
+mod pin_block;
+mod recipient;
+mod secret_client;
 mod session;
 
-const DEFAULT_PORT: u32 = 3100;
-async fn run_server(listen_port: u32, kms_client: KmsClient) -> Result<()> {
+use backend::KeyDecryptor;
+use key_cache::KeyCache;
+use key_registry::KeyRegistry;
+use pin::DecimalizationTables;
+use secret_client::{SecretClient, SecretClientConfig};
+
+const KEY_CACHE_PURGE_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_REGION: &str = "us-east-1";
+
+async fn run_server(config: &HostConfig, decryptor: Arc<dyn KeyDecryptor>, metrics: Arc<Metrics>) -> Result<()> {
+
+    let listen_port    = config.listen_port;
+    let max_frame_size = config.max_frame_size;
+    let write_timeout  = Duration::from_secs(config.write_timeout_secs);
 
     let shutdown_token = CancellationToken::new();
     let server_token   = shutdown_token.clone();
@@ -28,7 +55,7 @@ async fn run_server(listen_port: u32, kms_client: KmsClient) -> Result<()> {
     tokio::spawn(async move {
         match signal::ctrl_c().await {
             Ok(()) => {
-                println!("");
+                println!();
                 log::info!("shutdown signal received ...");
                 server_token.cancel();
             }
@@ -38,10 +65,64 @@ async fn run_server(listen_port: u32, kms_client: KmsClient) -> Result<()> {
         }    
     });    
 
+    let secret_config = SecretClientConfig {
+        cid: config.secret_server.cid,
+        port: config.secret_server.port,
+        pool_size: config.secret_server.pool_size,
+        request_timeout: Duration::from_secs(config.secret_server.request_timeout_secs),
+        max_frame_size,
+    };
+    let secret_client = Arc::new(SecretClient::new(secret_config, shutdown_token.child_token()));
+
+    let key_cache = Arc::new(KeyCache::new(
+        Duration::from_secs(config.key_cache.ttl_secs),
+        config.key_cache.size,
+    ));
+
+    let keys = Arc::new(KeyRegistry::from_config(&config.keys));
+    if config.keys.is_empty() {
+        log::warn!("no keys registered, every key request will be refused");
+    }
+
+    let dectabs = DecimalizationTables::from_config(&config.decimalization_tables)
+        .map_err(|e| anyhow!("invalid decimalization table {}", e))?;
+    let dectabs = Arc::new(dectabs);
+
+    // Expired keys are zeroized even when no requests arrive
+    let purge_cache = Arc::clone(&key_cache);
+    let purge_token = shutdown_token.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(KEY_CACHE_PURGE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    let purged = purge_cache.purge_expired();
+                    if purged > 0 {
+                        log::debug!("purged {} expired keys from cache", purged);
+                    }
+                }
+                _ = purge_token.cancelled() => {
+                    purge_cache.clear();
+                    break;
+                }
+            }
+        }
+    });
+
+    if let Some(metrics_port) = config.metrics_port {
+        let metrics = Arc::clone(&metrics);
+        let token   = shutdown_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = nitro_metrics::serve_vsock(metrics_port, metrics, token).await {
+                log::error!("metrics server: {:?}", e);
+            }
+        });
+    }
+
     let addr     = VsockAddr::new(VMADDR_CID_ANY, listen_port);
     let listener = VsockListener::bind(addr).context(format!("failed to bind to cid: ANY port: {}", listen_port))?;
 
-    log::info!("listening on cid: ANY port: {}", listen_port);
+    log::info!("listening on cid: ANY port: {} (max frame {} bytes)", listen_port, max_frame_size);
     loop {
         tokio::select! {
             accept_result = listener.accept() => {
@@ -49,20 +130,32 @@ async fn run_server(listen_port: u32, kms_client: KmsClient) -> Result<()> {
                     Ok((client_stream, client_addr)) => {
                         log::info!("accept connection from {}", client_addr);
 
-                        let handler_kms_client = kms_client.clone();
-                        let handler_token      = shutdown_token.child_token();
+                        let handler_decryptor = Arc::clone(&decryptor);
+                        let handler_key_cache = Arc::clone(&key_cache);
+                        let handler_keys      = Arc::clone(&keys);
+                        let handler_secret    = Arc::clone(&secret_client);
+                        let handler_dectabs   = Arc::clone(&dectabs);
+                        let handler_metrics   = Arc::clone(&metrics);
+                        let handler_token     = shutdown_token.child_token();
 
                         tokio::spawn(async move {
                             if let Err(e) = session::handle_client(
                                 client_stream, 
-                                handler_kms_client,
+                                handler_decryptor,
+                                handler_key_cache,
+                                handler_keys,
+                                handler_secret,
+                                handler_dectabs,
+                                handler_metrics,
+                                max_frame_size,
+                                write_timeout,
                                 handler_token).await {
                                 log::error!("error handling client from {}: {}", client_addr, e);
                             }
                             else {
                                 log::info!("client {} disconnected cleanly", client_addr);
                             }
-                        });
+                        }.instrument(tracing::info_span!("connection", peer = %client_addr)));
                     },
                     Err(e) => {
                         log::error!("error accepting client connection: {}", e);
@@ -81,39 +174,55 @@ async fn run_server(listen_port: u32, kms_client: KmsClient) -> Result<()> {
 }
 
 
+async fn create_decryptor(config: &HostConfig) -> Result<Arc<dyn KeyDecryptor>> {
+    match config.backend {
+        Backend::Aws => {
+            let region_provider = match &config.region {
+                Some(region) => RegionProviderChain::first_try(Region::new(region.clone())),
+                None => RegionProviderChain::default_provider(),
+            }.or_else(DEFAULT_REGION);
+            let config = aws_config::defaults(BehaviorVersion::latest())
+                .region(region_provider)
+                .load()
+                .await;
+
+            let kms_client = KmsClient::new(&config);
+            log::info!("AWS clients initialized");
+
+            Ok(Arc::new(aws::KmsDecryptor::new(kms_client, Arc::new(aws::NsmAttestation))?))
+        }
+        #[cfg(feature = "local")]
+        Backend::Local => {
+            log::warn!("using local KMS and mock NSM, not for production");
+            local::create_decryptor(&config.local)
+        }
+        #[cfg(not(feature = "local"))]
+        Backend::Local => {
+            Err(anyhow!("local backend requested but built without the `local` feature"))
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
 
-    nitro::init_logging().map_err(|e| anyhow!("Failed to initialize logging: {}", e))?;
+    let args   = HostArgs::parse();
+    let config = nitro_config::load(&args)?;
+
+    if args.common.print_config {
+        print!("{}", nitro_config::to_toml(&config)?);
+        return Ok(());
+    }
+
+    let _logging = nitro::init_tracing(&config.logging()).map_err(|e| anyhow!("Failed to initialize logging: {}", e))?;
 
     log::info!("starting ...");
+    log::debug!("configuration: {:?}", config);
 
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::defaults(BehaviorVersion::latest())
-        .region(region_provider)
-        .load()
-        .await;
-
-    let kms_client    = KmsClient::new(&config);
-    log::info!("AWS clients initialized");
-
-    let listen_port = env::var("HOST_PORT")
-        .ok()
-        .and_then(|v| {
-            v.parse::<u32>().map_err(|e| {
-                log::warn!(
-                    "failed to parse HOST_PORT='{}': {}, using default port {}",
-                    v, e, DEFAULT_PORT
-                );
-                e
-            }).ok()
-        })
-        .unwrap_or_else(|| {
-            log::info!("SECRET_PORT not set, using default port {}", DEFAULT_PORT);
-            DEFAULT_PORT
-        });
+    let decryptor = create_decryptor(&config).await?;
+    let metrics   = Arc::new(Metrics::new("host")?);
 
-    match run_server(listen_port, kms_client).await {
+    match run_server(&config, decryptor, metrics).await {
         Ok(()) => {
             log::info!("server exited gracefully");
             Ok(())
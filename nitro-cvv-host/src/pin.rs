@@ -0,0 +1,258 @@
+use std::collections::{BTreeMap, HashMap};
+
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::cvv::{constant_time_eq, DesKey};
+use crate::pin_block::{account_digits, to_nibbles, PIN_MAX_LEN, PIN_MIN_LEN};
+
+/// Visa PIN Verification Value under a double-length PVK pair.
+pub struct Pvv {
+    pvk: DesKey,
+}
+
+impl Pvv {
+
+    pub fn new(pvk_a: &str, pvk_b: &str) -> Result<Self, String> {
+        if pvk_a.len() != 16 || pvk_b.len() != 16 {
+            return Err("PVK A and B must each be a single-length key (16 hex characters)".to_string());
+        }
+
+        let pair = Zeroizing::new(format!("{}{}", pvk_a, pvk_b));
+        let pvk  = DesKey::from_hex(&pair).map_err(|e| format!("pvk: {}", e))?;
+
+        Ok(Pvv { pvk })
+    }
+
+    /// Encrypts the transformed security parameter (11 PAN digits, PVKI,
+    /// 4 leftmost PIN digits) and decimalizes the result to 4 digits.
+    pub fn calculate(&self, pan: &str, pvki: &str, pin: &str) -> Result<String, String> {
+        if pvki.len() != 1 || !pvki.chars().all(|c| c.is_ascii_digit()) {
+            return Err("PVKI must be a single digit".to_string());
+        }
+        if pin.len() < PIN_MIN_LEN {
+            return Err("PIN is shorter than 4 digits".to_string());
+        }
+
+        let tsp = Zeroizing::new(format!("{}{}{}", account_digits(pan, 11)?, pvki, &pin[..4]));
+
+        let mut tsp_bytes: [u8; 8] = hex::decode(tsp.as_str())
+            .map_err(|e| format!("Failed to decode TSP: {}", e))?
+            .try_into()
+            .map_err(|_| "TSP must be exactly 8 bytes".to_string())?;
+
+        let encrypted = self.pvk.encrypt(&tsp_bytes);
+        tsp_bytes.zeroize();
+
+        // Decimal digits in order first, then A-F mapped to 0-5
+        let nibbles = to_nibbles(&encrypted);
+        let pvv: String = nibbles.iter().filter(|&&n| n <= 9)
+            .chain(nibbles.iter().filter(|&&n| n > 9))
+            .take(4)
+            .map(|&n| (b'0' + n % 10) as char)
+            .collect();
+
+        Ok(pvv)
+    }
+
+    pub fn verify(&self, pan: &str, pvki: &str, pin: &str, pvv: &str) -> Result<bool, String> {
+        let pvv_calc = self.calculate(pan, pvki, pin)
+            .map_err(|e| format!("Failed to calculate pvv: {}", e))?;
+
+        Ok(constant_time_eq(pvv_calc.as_bytes(), pvv.as_bytes()))
+    }
+}
+
+/// Maps each hex digit of encrypted validation data to a decimal digit.
+pub struct DecimalizationTable([u8; 16]);
+
+impl DecimalizationTable {
+
+    /// `digits` lists the decimal value for hex digits 0 through F, e.g. `0123456789012345`.
+    pub fn from_digits(digits: &str) -> Result<Self, String> {
+        if digits.len() != 16 || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err("Decimalization table must be 16 decimal digits".to_string());
+        }
+
+        let mut table = [0u8; 16];
+        for (value, byte) in table.iter_mut().zip(digits.bytes()) {
+            *value = byte - b'0';
+        }
+
+        Ok(DecimalizationTable(table))
+    }
+}
+
+/// Configured decimalization tables, looked up by the name sent in requests.
+pub struct DecimalizationTables(HashMap<String, DecimalizationTable>);
+
+impl DecimalizationTables {
+
+    pub fn from_config(tables: &BTreeMap<String, String>) -> Result<Self, String> {
+        tables.iter()
+            .map(|(name, digits)| {
+                let table = DecimalizationTable::from_digits(digits).map_err(|e| format!("{}: {}", name, e))?;
+                Ok((name.clone(), table))
+            })
+            .collect::<Result<_, String>>()
+            .map(DecimalizationTables)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DecimalizationTable> {
+        self.0.get(name)
+    }
+}
+
+/// IBM 3624 PIN offsets under a PIN verification key.
+pub struct Ibm3624 {
+    pvk: DesKey,
+}
+
+impl Ibm3624 {
+
+    pub fn new(pvk: DesKey) -> Self {
+        Ibm3624 { pvk }
+    }
+
+    /// Encrypts the validation data, right padded with `F`, and decimalizes
+    /// every digit of the result.
+    fn natural_pin(&self, validation_data: &str, table: &DecimalizationTable) -> Result<Zeroizing<Vec<u8>>, String> {
+        if validation_data.is_empty() || validation_data.len() > 16 {
+            return Err("Validation data must be 1 to 16 hex digits".to_string());
+        }
+
+        let mut data: [u8; 8] = hex::decode(format!("{:F<16}", validation_data))
+            .map_err(|e| format!("Failed to decode validation data: {}", e))?
+            .try_into()
+            .map_err(|_| "Validation data must be exactly 8 bytes".to_string())?;
+
+        let encrypted = self.pvk.encrypt(&data);
+        data.zeroize();
+
+        let mut natural = to_nibbles(&encrypted);
+        for digit in natural.iter_mut() {
+            *digit = table.0[*digit as usize];
+        }
+
+        Ok(natural)
+    }
+
+    /// Digit-wise `pin - natural PIN` modulo 10 over the `check_length`
+    /// leftmost digits, right padded with `F` to 12 characters.
+    pub fn offset(&self, validation_data: &str, table: &DecimalizationTable, pin: &str, check_length: usize) -> Result<String, String> {
+        if !(PIN_MIN_LEN..=PIN_MAX_LEN).contains(&check_length) {
+            return Err("Check length must be between 4 and 12".to_string());
+        }
+        if pin.len() < check_length || !pin.chars().all(|c| c.is_ascii_digit()) {
+            return Err("PIN is shorter than the check length".to_string());
+        }
+
+        let natural = self.natural_pin(validation_data, table)?;
+
+        let offset: String = pin.bytes().zip(natural.iter())
+            .take(check_length)
+            .map(|(digit, &natural)| (b'0' + (digit - b'0' + 10 - natural) % 10) as char)
+            .collect();
+
+        Ok(format!("{:F<12}", offset))
+    }
+
+    pub fn verify(&self, validation_data: &str, table: &DecimalizationTable, pin: &str, check_length: usize, offset: &str) -> Result<bool, String> {
+        if pin.len() < check_length {
+            return Ok(false);
+        }
+
+        let offset_calc = self.offset(validation_data, table, pin, check_length)
+            .map_err(|e| format!("Failed to calculate offset: {}", e))?;
+
+        Ok(constant_time_eq(offset_calc.as_bytes(), offset.to_ascii_uppercase().as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAN: &str = "4123456789012345";
+
+    fn pvv() -> Pvv {
+        Pvv::new("0123456789ABCDEF", "FEDCBA9876543210").unwrap()
+    }
+
+    #[test]
+    fn pvv_matches_reference_vector() {
+        // TSP 45678901234 1 1234 encrypts to 189E41ACA69078E5
+        assert_eq!(pvv().calculate(PAN, "1", "1234").unwrap(), "1894");
+        assert!(pvv().verify(PAN, "1", "1234", "1894").unwrap());
+    }
+
+    #[test]
+    fn pvv_falls_back_to_hex_digits() {
+        // FCCFC6D56AEEDBCF has only three decimal digits
+        assert_eq!(pvv().calculate(PAN, "1", "1216").unwrap(), "6565");
+    }
+
+    #[test]
+    fn pvv_uses_four_leftmost_pin_digits() {
+        assert_eq!(pvv().calculate(PAN, "1", "123456").unwrap(), "1894");
+    }
+
+    #[test]
+    fn pvv_rejects_wrong_pin_and_pvki() {
+        assert!(!pvv().verify(PAN, "1", "1235", "1894").unwrap());
+        assert!(!pvv().verify(PAN, "2", "1234", "1894").unwrap());
+        assert!(pvv().calculate(PAN, "A", "1234").is_err());
+        assert!(pvv().calculate(PAN, "1", "123").is_err());
+    }
+
+    fn ibm3624() -> Ibm3624 {
+        Ibm3624::new(DesKey::from_hex("0123456789ABCDEFFEDCBA9876543210").unwrap())
+    }
+
+    fn table(digits: &str) -> DecimalizationTable {
+        DecimalizationTable::from_digits(digits).unwrap()
+    }
+
+    #[test]
+    fn offset_matches_reference_vectors() {
+        let standard = table("0123456789012345");
+
+        // Natural PIN 0620 537310205269
+        assert_eq!(ibm3624().offset(PAN, &standard, "1234", 4).unwrap(), "1614FFFFFFFF");
+        assert_eq!(ibm3624().offset(PAN, &standard, "987654", 6).unwrap(), "925601FFFFFF");
+
+        // Short validation data is padded with F before encryption
+        assert_eq!(ibm3624().offset("12345", &standard, "123456789012", 12).unwrap(), "001025189577");
+        assert_eq!(ibm3624().offset("ABCDEF", &table("9876543210543210"), "55551", 4).unwrap(), "3502FFFFFFFF");
+    }
+
+    #[test]
+    fn offset_checks_only_check_length_digits() {
+        let standard = table("0123456789012345");
+
+        assert!(ibm3624().verify(PAN, &standard, "1234", 4, "1614FFFFFFFF").unwrap());
+        assert!(ibm3624().verify(PAN, &standard, "12349", 4, "1614ffffffff").unwrap());
+        assert!(ibm3624().verify(PAN, &standard, "987654", 6, "925601FFFFFF").unwrap());
+        assert!(ibm3624().verify("12345", &standard, "123456789012", 12, "001025189577").unwrap());
+    }
+
+    #[test]
+    fn offset_rejects_wrong_pin() {
+        let standard = table("0123456789012345");
+
+        assert!(!ibm3624().verify(PAN, &standard, "1235", 4, "1614FFFFFFFF").unwrap());
+        assert!(!ibm3624().verify(PAN, &standard, "987655", 6, "925601FFFFFF").unwrap());
+        assert!(!ibm3624().verify(PAN, &standard, "98765", 6, "925601FFFFFF").unwrap());
+        assert!(!ibm3624().verify("12346", &standard, "123456789012", 12, "001025189577").unwrap());
+    }
+
+    #[test]
+    fn offset_rejects_bad_parameters() {
+        let standard = table("0123456789012345");
+
+        assert!(ibm3624().offset(PAN, &standard, "1234", 3).is_err());
+        assert!(ibm3624().offset(PAN, &standard, "1234", 13).is_err());
+        assert!(ibm3624().offset("", &standard, "1234", 4).is_err());
+        assert!(ibm3624().offset("41234567890123456", &standard, "1234", 4).is_err());
+        assert!(DecimalizationTable::from_digits("012345678901234A").is_err());
+    }
+}
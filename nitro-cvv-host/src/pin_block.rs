@@ -0,0 +1,423 @@
+//! ISO 9564-1 PIN blocks: formats 0, 1 and 3 under DES, format 4 under AES.
+//!
+//! Test vectors for PIN `1234` and PAN `4111111111111111`:
+//!
+//! | Format | Clear PIN field                     | PAN field                          |
+//! |--------|-------------------------------------|------------------------------------|
+//! | 0      | `041234FFFFFFFFFF`                  | `0000111111111111`                 |
+//! | 1      | `141234` + 10 random nibbles        | not used                           |
+//! | 3      | `341234` + 10 random nibbles `A-F`  | `0000111111111111`                 |
+//! | 4      | `441234AAAAAAAAAA` + 8 random bytes | `44111111111111111000000000000000` |
+//!
+//! The format 0 clear block is `041225EEEEEEEEEE`; under the double-length
+//! key `0123456789ABCDEFFEDCBA9876543210` it encrypts to `2A3D408A1977DDE9`.
+//! With AES-128 key `000102030405060708090A0B0C0D0E0F` and an all-zero random
+//! part, the format 4 block is `A1D526A17E6144F3DD647103256D19A2`.
+
+use rand_core::{OsRng, RngCore};
+use zeroize::Zeroizing;
+
+use crate::cvv::{AesKey, DesKey};
+
+pub(crate) const PIN_MIN_LEN: usize = 4;
+pub(crate) const PIN_MAX_LEN: usize = 12;
+
+/// Format 4 PAN fields hold 12 to 19 digits.
+const ISO4_PAN_MIN_LEN: usize = 12;
+const ISO4_PAN_MAX_LEN: usize = 19;
+
+/// Errors do not say which check failed, so logs reveal nothing about the clear block.
+const INVALID_PIN_BLOCK: &str = "PIN block does not decode to a valid PIN";
+
+/// PIN block formats, identified in requests by their Thales format codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinBlockFormat {
+    Iso0,
+    Iso1,
+    Iso3,
+    Iso4,
+}
+
+impl PinBlockFormat {
+    pub fn from_code(code: &str) -> Result<Self, String> {
+        match code {
+            "01" => Ok(PinBlockFormat::Iso0),
+            "05" => Ok(PinBlockFormat::Iso1),
+            "47" => Ok(PinBlockFormat::Iso3),
+            "48" => Ok(PinBlockFormat::Iso4),
+            _    => Err(format!("Unsupported PIN block format '{}'", code)),
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            PinBlockFormat::Iso0 => "01",
+            PinBlockFormat::Iso1 => "05",
+            PinBlockFormat::Iso3 => "47",
+            PinBlockFormat::Iso4 => "48",
+        }
+    }
+
+    /// Whether the PAN is part of the block, binding the PIN to the card.
+    pub fn is_pan_bound(&self) -> bool {
+        *self != PinBlockFormat::Iso1
+    }
+
+    /// The control field, first nibble of the clear PIN field.
+    fn control(&self) -> u8 {
+        match self {
+            PinBlockFormat::Iso0 => 0,
+            PinBlockFormat::Iso1 => 1,
+            PinBlockFormat::Iso3 => 3,
+            PinBlockFormat::Iso4 => 4,
+        }
+    }
+}
+
+/// Key a PIN block is encrypted under; format 4 takes AES, the others DES.
+pub enum PinKey {
+    Des(DesKey),
+    Aes(AesKey),
+}
+
+impl PinKey {
+    /// Single-length DES is too weak for PIN encryption and is refused.
+    pub fn from_hex(format: PinBlockFormat, key_hex: &str) -> Result<Self, String> {
+        match format {
+            PinBlockFormat::Iso4 => AesKey::from_hex(key_hex).map(PinKey::Aes),
+            _ => match DesKey::from_hex(key_hex)? {
+                DesKey::Single(_) => Err("PIN encryption key must be double or triple length".to_string()),
+                key               => Ok(PinKey::Des(key)),
+            },
+        }
+    }
+}
+
+/// Builds a PIN block with fresh random fill and returns it encrypted, in hex.
+pub fn encrypt_pin_block(key: &PinKey, format: PinBlockFormat, pin: &str, pan: &str) -> Result<String, String> {
+    if !(PIN_MIN_LEN..=PIN_MAX_LEN).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("PIN must be {} to {} digits", PIN_MIN_LEN, PIN_MAX_LEN));
+    }
+
+    match (key, format) {
+        (PinKey::Aes(key), PinBlockFormat::Iso4) => {
+            let pin_field = iso4_pin_field(pin);
+            let pan_field = iso4_pan_field(pan)?;
+
+            let mut intermediate = Zeroizing::new(key.encrypt(&pin_field));
+            xor(intermediate.as_mut_slice(), &pan_field);
+
+            Ok(hex::encode_upper(key.encrypt(&intermediate)))
+        }
+        (PinKey::Des(key), PinBlockFormat::Iso0 | PinBlockFormat::Iso1 | PinBlockFormat::Iso3) => {
+            let mut clear = des_pin_field(format, pin);
+            xor(clear.as_mut_slice(), &des_pan_field(format, pan)?);
+
+            Ok(hex::encode_upper(key.encrypt(&clear)))
+        }
+        _ => Err(format!("Key type does not match PIN block format {}", format.code())),
+    }
+}
+
+/// Decrypts a PIN block in hex and returns the clear PIN digits.
+///
+/// The control field, PIN length, digits and fill are all checked; the
+/// random fill of formats 1, 3 and 4 is only checked for its range.
+pub fn decrypt_pin_block(key: &PinKey, format: PinBlockFormat, pin_block_hex: &str, pan: &str) -> Result<Zeroizing<String>, String> {
+    let encrypted = hex::decode(pin_block_hex)
+        .map_err(|e| format!("Failed to decode PIN block: {}", e))?;
+
+    let nibbles = match (key, format) {
+        (PinKey::Aes(key), PinBlockFormat::Iso4) => {
+            let encrypted: [u8; 16] = encrypted.try_into()
+                .map_err(|_| "Format 4 PIN block must be exactly 16 bytes".to_string())?;
+
+            let mut pin_field = Zeroizing::new(key.decrypt(&encrypted));
+            xor(pin_field.as_mut_slice(), &iso4_pan_field(pan)?);
+            let pin_field = Zeroizing::new(key.decrypt(&pin_field));
+
+            // The random second half carries no PIN data
+            to_nibbles(&pin_field[..8])
+        }
+        (PinKey::Des(key), PinBlockFormat::Iso0 | PinBlockFormat::Iso1 | PinBlockFormat::Iso3) => {
+            let encrypted: [u8; 8] = encrypted.try_into()
+                .map_err(|_| "PIN block must be exactly 8 bytes".to_string())?;
+
+            let mut clear = Zeroizing::new(key.decrypt(&encrypted));
+            xor(clear.as_mut_slice(), &des_pan_field(format, pan)?);
+
+            to_nibbles(clear.as_slice())
+        }
+        _ => return Err(format!("Key type does not match PIN block format {}", format.code())),
+    };
+
+    let pin_len = nibbles[1] as usize;
+    if nibbles[0] != format.control() || !(PIN_MIN_LEN..=PIN_MAX_LEN).contains(&pin_len) {
+        return Err(INVALID_PIN_BLOCK.to_string());
+    }
+
+    let digits = &nibbles[2..2 + pin_len];
+    let fill   = &nibbles[2 + pin_len..];
+    let fill_ok = match format {
+        PinBlockFormat::Iso0 => fill.iter().all(|&n| n == 0xF),
+        PinBlockFormat::Iso1 => true,
+        PinBlockFormat::Iso3 => fill.iter().all(|&n| n >= 0xA),
+        PinBlockFormat::Iso4 => fill.iter().all(|&n| n == 0xA),
+    };
+    if digits.iter().any(|&n| n > 9) || !fill_ok {
+        return Err(INVALID_PIN_BLOCK.to_string());
+    }
+
+    Ok(Zeroizing::new(digits.iter().map(|&n| (b'0' + n) as char).collect()))
+}
+
+/// Control, length and PIN nibbles followed by the fill of `format`.
+fn des_pin_field(format: PinBlockFormat, pin: &str) -> Zeroizing<[u8; 8]> {
+    let mut nibbles = Zeroizing::new([0u8; 16]);
+    nibbles[0] = format.control();
+    nibbles[1] = pin.len() as u8;
+    for (nibble, digit) in nibbles[2..].iter_mut().zip(pin.bytes()) {
+        *nibble = digit - b'0';
+    }
+
+    let mut random = Zeroizing::new([0u8; 16]);
+    OsRng.fill_bytes(random.as_mut_slice());
+
+    for (nibble, &byte) in nibbles[2 + pin.len()..].iter_mut().zip(random.iter()) {
+        *nibble = match format {
+            PinBlockFormat::Iso1 => byte & 0x0F,
+            PinBlockFormat::Iso3 => 0xA + byte % 6,
+            _                    => 0xF,
+        };
+    }
+
+    Zeroizing::new(from_nibbles(nibbles.as_slice()))
+}
+
+/// `0000` followed by the 12 rightmost PAN digits excluding the check digit;
+/// format 1 does not bind the PAN and XORs with zeros.
+fn des_pan_field(format: PinBlockFormat, pan: &str) -> Result<[u8; 8], String> {
+    if format == PinBlockFormat::Iso1 {
+        return Ok([0u8; 8]);
+    }
+
+    let digits = account_digits(pan, 12)?;
+
+    hex::decode(format!("0000{}", digits))
+        .map_err(|e| format!("Failed to decode PAN block: {}", e))?
+        .try_into()
+        .map_err(|_| "PAN block must be exactly 8 bytes".to_string())
+}
+
+/// Control, length, PIN and `A` fill nibbles, then 8 random bytes.
+fn iso4_pin_field(pin: &str) -> Zeroizing<[u8; 16]> {
+    let mut nibbles = Zeroizing::new([0xAu8; 16]);
+    nibbles[0] = PinBlockFormat::Iso4.control();
+    nibbles[1] = pin.len() as u8;
+    for (nibble, digit) in nibbles[2..].iter_mut().zip(pin.bytes()) {
+        *nibble = digit - b'0';
+    }
+
+    let mut field = Zeroizing::new([0u8; 16]);
+    field[..8].copy_from_slice(&from_nibbles::<8>(nibbles.as_slice()));
+    OsRng.fill_bytes(&mut field[8..]);
+    field
+}
+
+/// PAN length minus 12, then the whole PAN right padded with zeros to 32 nibbles.
+fn iso4_pan_field(pan: &str) -> Result<[u8; 16], String> {
+    if !(ISO4_PAN_MIN_LEN..=ISO4_PAN_MAX_LEN).contains(&pan.len()) || !pan.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("PAN must be {} to {} digits", ISO4_PAN_MIN_LEN, ISO4_PAN_MAX_LEN));
+    }
+
+    let field = format!("{}{}", pan.len() - ISO4_PAN_MIN_LEN, pan);
+
+    hex::decode(format!("{:0<32}", field))
+        .map_err(|e| format!("Failed to decode PAN field: {}", e))?
+        .try_into()
+        .map_err(|_| "PAN field must be exactly 16 bytes".to_string())
+}
+
+/// The `count` PAN digits left of the check digit.
+pub(crate) fn account_digits(pan: &str, count: usize) -> Result<&str, String> {
+    if pan.len() < count + 1 || !pan.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("PAN must have at least {} digits", count + 1));
+    }
+    Ok(&pan[pan.len() - 1 - count..pan.len() - 1])
+}
+
+pub(crate) fn to_nibbles(bytes: &[u8]) -> Zeroizing<Vec<u8>> {
+    Zeroizing::new(bytes.iter().flat_map(|&byte| [byte >> 4, byte & 0x0F]).collect())
+}
+
+fn from_nibbles<const N: usize>(nibbles: &[u8]) -> [u8; N] {
+    let mut bytes = [0u8; N];
+    for (byte, pair) in bytes.iter_mut().zip(nibbles.chunks_exact(2)) {
+        *byte = (pair[0] << 4) | pair[1];
+    }
+    bytes
+}
+
+fn xor(block: &mut [u8], mask: &[u8]) {
+    for (byte, mask_byte) in block.iter_mut().zip(mask) {
+        *byte ^= mask_byte;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAN: &str = "4111111111111111";
+    const DES_KEY: &str = "0123456789ABCDEFFEDCBA9876543210";
+    const AES_KEY: &str = "000102030405060708090A0B0C0D0E0F";
+
+    fn key(format: PinBlockFormat) -> PinKey {
+        let key_hex = if format == PinBlockFormat::Iso4 { AES_KEY } else { DES_KEY };
+        PinKey::from_hex(format, key_hex).unwrap()
+    }
+
+    /// Encrypts a hand-built clear PIN field, bypassing the encoder's checks.
+    fn encrypt_field(format: PinBlockFormat, pin_field: &str, pan: &str) -> String {
+        let mut field = hex::decode(pin_field).unwrap();
+
+        match key(format) {
+            PinKey::Aes(key) => {
+                let mut intermediate = key.encrypt(&field.try_into().unwrap());
+                xor(&mut intermediate, &iso4_pan_field(pan).unwrap());
+                hex::encode_upper(key.encrypt(&intermediate))
+            }
+            PinKey::Des(key) => {
+                xor(&mut field, &des_pan_field(format, pan).unwrap());
+                hex::encode_upper(key.encrypt(&field.try_into().unwrap()))
+            }
+        }
+    }
+
+    fn decrypt(format: PinBlockFormat, pin_block: &str, pan: &str) -> Result<String, String> {
+        decrypt_pin_block(&key(format), format, pin_block, pan).map(|pin| pin.to_string())
+    }
+
+    #[test]
+    fn format0_matches_reference_vector() {
+        let pin_block = encrypt_pin_block(&key(PinBlockFormat::Iso0), PinBlockFormat::Iso0, "1234", PAN).unwrap();
+        assert_eq!(pin_block, "2A3D408A1977DDE9");
+        assert_eq!(decrypt(PinBlockFormat::Iso0, "2A3D408A1977DDE9", PAN).unwrap(), "1234");
+    }
+
+    #[test]
+    fn format4_matches_reference_vector() {
+        assert_eq!(encrypt_field(PinBlockFormat::Iso4, "441234AAAAAAAAAA0000000000000000", PAN), "A1D526A17E6144F3DD647103256D19A2");
+        assert_eq!(decrypt(PinBlockFormat::Iso4, "A1D526A17E6144F3DD647103256D19A2", PAN).unwrap(), "1234");
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let formats = [PinBlockFormat::Iso0, PinBlockFormat::Iso1, PinBlockFormat::Iso3, PinBlockFormat::Iso4];
+
+        for format in formats {
+            for pin in ["1234", "98765", "000000", "123456789012"] {
+                for pan in [PAN, "5500000000000004", "4000123456789010123"] {
+                    let pin_block = encrypt_pin_block(&key(format), format, pin, pan).unwrap();
+                    assert_eq!(decrypt(format, &pin_block, pan).unwrap(), pin, "format {}", format.code());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn random_fill_stays_in_range() {
+        for _ in 0..32 {
+            let pin_block = encrypt_pin_block(&key(PinBlockFormat::Iso3), PinBlockFormat::Iso3, "1234", PAN).unwrap();
+
+            let PinKey::Des(des) = key(PinBlockFormat::Iso3) else { unreachable!() };
+            let mut clear = des.decrypt(&hex::decode(&pin_block).unwrap().try_into().unwrap());
+            xor(&mut clear, &des_pan_field(PinBlockFormat::Iso3, PAN).unwrap());
+
+            assert!(to_nibbles(&clear)[6..].iter().all(|&n| n >= 0xA));
+        }
+    }
+
+    #[test]
+    fn rejects_bad_control_nibble() {
+        let pin_block = encrypt_field(PinBlockFormat::Iso0, "141234FFFFFFFFFF", PAN);
+        assert_eq!(decrypt(PinBlockFormat::Iso0, &pin_block, PAN), Err(INVALID_PIN_BLOCK.to_string()));
+
+        // A format 0 block is not accepted as format 3
+        let pin_block = encrypt_pin_block(&key(PinBlockFormat::Iso0), PinBlockFormat::Iso0, "1234", PAN).unwrap();
+        assert!(decrypt(PinBlockFormat::Iso3, &pin_block, PAN).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_pin_length() {
+        for pin_field in ["03123FFFFFFFFFFF", "0D1234567890123F", "00FFFFFFFFFFFFFF"] {
+            let pin_block = encrypt_field(PinBlockFormat::Iso0, pin_field, PAN);
+            assert!(decrypt(PinBlockFormat::Iso0, &pin_block, PAN).is_err(), "{}", pin_field);
+        }
+
+        let pin_block = encrypt_field(PinBlockFormat::Iso4, "43123AAAAAAAAAAA0000000000000000", PAN);
+        assert!(decrypt(PinBlockFormat::Iso4, &pin_block, PAN).is_err());
+
+        for pin in ["123", "1234567890123", "12a4"] {
+            assert!(encrypt_pin_block(&key(PinBlockFormat::Iso0), PinBlockFormat::Iso0, pin, PAN).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_digits_and_fill() {
+        let cases = [
+            (PinBlockFormat::Iso0, "04123AFFFFFFFFFF"),
+            (PinBlockFormat::Iso0, "041234FFFFFFFFFE"),
+            (PinBlockFormat::Iso3, "341234AAAAAAAAA9"),
+            (PinBlockFormat::Iso4, "441234AAAAAAAAAB0000000000000000"),
+        ];
+
+        for (format, pin_field) in cases {
+            let pin_block = encrypt_field(format, pin_field, PAN);
+            assert!(decrypt(format, &pin_block, PAN).is_err(), "{}", pin_field);
+        }
+    }
+
+    #[test]
+    fn rejects_pan_mismatch() {
+        // Format 0 notices a change anywhere in the bound digits; random format 3
+        // fill can absorb one, so change the digit under the third PIN digit
+        let cases = [
+            (PinBlockFormat::Iso0, "4111111111111121"),
+            (PinBlockFormat::Iso3, "4119111111111111"),
+            (PinBlockFormat::Iso4, "4111111111111121"),
+            (PinBlockFormat::Iso4, "4111111111111111111"),
+        ];
+
+        for (format, pan) in cases {
+            let pin_block = encrypt_pin_block(&key(format), format, "1234", PAN).unwrap();
+            assert_eq!(decrypt(format, &pin_block, pan), Err(INVALID_PIN_BLOCK.to_string()), "format {}", format.code());
+        }
+
+        // Format 1 does not bind the PAN
+        let pin_block = encrypt_pin_block(&key(PinBlockFormat::Iso1), PinBlockFormat::Iso1, "1234", PAN).unwrap();
+        assert_eq!(decrypt(PinBlockFormat::Iso1, &pin_block, "4111111111111121").unwrap(), "1234");
+    }
+
+    #[test]
+    fn format4_takes_12_to_19_digit_pans() {
+        assert_eq!(hex::encode_upper(iso4_pan_field("123456789012").unwrap()), "01234567890120000000000000000000");
+        assert_eq!(hex::encode_upper(iso4_pan_field("1234567890123456789").unwrap()), "71234567890123456789000000000000");
+
+        for pan in ["12345678901", "12345678901234567890", "41111111111111x1"] {
+            assert!(encrypt_pin_block(&key(PinBlockFormat::Iso4), PinBlockFormat::Iso4, "1234", pan).is_err(), "{}", pan);
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_key_type() {
+        assert!(encrypt_pin_block(&key(PinBlockFormat::Iso0), PinBlockFormat::Iso4, "1234", PAN).is_err());
+        assert!(decrypt_pin_block(&key(PinBlockFormat::Iso4), PinBlockFormat::Iso0, "2A3D408A1977DDE9", PAN).is_err());
+    }
+
+    #[test]
+    fn rejects_single_length_des_key() {
+        assert!(PinKey::from_hex(PinBlockFormat::Iso0, "0123456789ABCDEF").is_err());
+        assert!(PinKey::from_hex(PinBlockFormat::Iso4, "0123456789ABCDEFFEDCBA9876543210").is_ok());
+    }
+}
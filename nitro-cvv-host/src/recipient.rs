@@ -0,0 +1,359 @@
+use anyhow::{Result, anyhow, bail};
+
+use aes::Aes256;
+use cbc::cipher::{BlockDecryptMut, KeyIvInit};
+use cbc::cipher::block_padding::Pkcs7;
+use rand_core::OsRng;
+use rsa::{Oaep, RsaPrivateKey, RsaPublicKey};
+use rsa::pkcs8::EncodePublicKey;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+const RSA_KEY_BITS: usize = 2048;
+const AES_256_KEY_SIZE: usize = 32;
+const AES_BLOCK_SIZE: usize = 16;
+
+const OID_ENVELOPED_DATA: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x03];
+const OID_RSAES_OAEP: &[u8]     = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x07];
+const OID_AES_256_CBC: &[u8]    = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2A];
+
+/// Deepest indefinite-length or constructed OCTET STRING nesting accepted;
+/// EnvelopedData needs five levels.
+const MAX_NESTING: usize = 16;
+
+const TAG_INTEGER: u8                  = 0x02;
+const TAG_OCTET_STRING: u8             = 0x04;
+const TAG_OID: u8                      = 0x06;
+const TAG_OCTET_STRING_CONSTRUCTED: u8 = 0x24;
+const TAG_SEQUENCE: u8                 = 0x30;
+const TAG_SET: u8                      = 0x31;
+const TAG_IMPLICIT_0: u8               = 0x80;
+const TAG_CONTEXT_0: u8                = 0xA0;
+
+/// RSA-2048 key pair the enclave hands to KMS as the `Recipient` public key.
+///
+/// The key only ever lives in enclave memory and is replaced on restart;
+/// KMS returns the plaintext wrapped to it as CMS EnvelopedData
+/// (RSAES-OAEP-SHA256 key transport, AES-256-CBC content encryption).
+pub struct RecipientKey {
+    private_key: RsaPrivateKey,
+    public_key_der: Vec<u8>,
+}
+
+impl RecipientKey {
+    pub fn generate() -> Result<Self> {
+        let private_key = RsaPrivateKey::new(&mut OsRng, RSA_KEY_BITS)
+            .map_err(|e| anyhow!("Failed to generate recipient key: {}", e))?;
+
+        let public_key_der = RsaPublicKey::from(&private_key)
+            .to_public_key_der()
+            .map_err(|e| anyhow!("Failed to encode recipient public key: {}", e))?
+            .into_vec();
+
+        log::debug!("Generated RSA-{} recipient key", RSA_KEY_BITS);
+
+        Ok(Self { private_key, public_key_der })
+    }
+
+    /// DER SubjectPublicKeyInfo, as expected in the attestation document `public_key`.
+    pub fn public_key_der(&self) -> &[u8] {
+        &self.public_key_der
+    }
+
+    /// Unwraps a `CiphertextForRecipient` blob.
+    pub fn decrypt_enveloped(&self, enveloped: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        let envelope = EnvelopedData::parse(enveloped)?;
+
+        let content_key = Zeroizing::new(
+            self.private_key
+                .decrypt(Oaep::new::<Sha256>(), envelope.encrypted_key)
+                .map_err(|_| anyhow!("Failed to unwrap content key"))?
+        );
+        if content_key.len() != AES_256_KEY_SIZE {
+            bail!("Unexpected content key length {}", content_key.len());
+        }
+
+        let plaintext = cbc::Decryptor::<Aes256>::new_from_slices(&content_key, envelope.iv)
+            .map_err(|_| anyhow!("Invalid content encryption IV"))?
+            .decrypt_padded_vec_mut::<Pkcs7>(&envelope.encrypted_content)
+            .map_err(|_| anyhow!("Failed to decrypt enveloped content"))?;
+
+        Ok(Zeroizing::new(plaintext))
+    }
+}
+
+/// The parts of a CMS EnvelopedData needed to recover the content.
+struct EnvelopedData<'a> {
+    encrypted_key: &'a [u8],
+    iv: &'a [u8],
+    encrypted_content: Vec<u8>,
+}
+
+impl<'a> EnvelopedData<'a> {
+    fn parse(buffer: &'a [u8]) -> Result<Self> {
+        // ContentInfo ::= SEQUENCE { contentType, [0] EXPLICIT content }
+        let mut content_info = BerReader::new(BerReader::new(buffer).expect(TAG_SEQUENCE)?);
+        if content_info.expect(TAG_OID)? != OID_ENVELOPED_DATA {
+            bail!("CMS content is not EnvelopedData");
+        }
+
+        let mut content = BerReader::new(content_info.expect(TAG_CONTEXT_0)?);
+        let mut enveloped = BerReader::new(content.expect(TAG_SEQUENCE)?);
+
+        enveloped.expect(TAG_INTEGER)?;
+        if enveloped.peek_tag() == Some(TAG_CONTEXT_0) {
+            enveloped.next()?; // originatorInfo
+        }
+
+        let encrypted_key = Self::key_transport(enveloped.expect(TAG_SET)?)?;
+
+        // EncryptedContentInfo ::= SEQUENCE { contentType, contentEncryptionAlgorithm, [0] IMPLICIT encryptedContent }
+        let mut content_info = BerReader::new(enveloped.expect(TAG_SEQUENCE)?);
+        content_info.expect(TAG_OID)?;
+
+        let mut algorithm = BerReader::new(content_info.expect(TAG_SEQUENCE)?);
+        if algorithm.expect(TAG_OID)? != OID_AES_256_CBC {
+            bail!("Unsupported content encryption algorithm, expected AES-256-CBC");
+        }
+        let iv = algorithm.expect(TAG_OCTET_STRING)?;
+        if iv.len() != AES_BLOCK_SIZE {
+            bail!("Invalid AES-256-CBC IV length {}", iv.len());
+        }
+
+        let encrypted_content = match content_info.next()? {
+            Tlv { tag: TAG_IMPLICIT_0, content } => content.to_vec(),
+            Tlv { tag: TAG_CONTEXT_0, content } => octet_string_chunks(content, 0)?,
+            _ => bail!("EnvelopedData has no encrypted content"),
+        };
+
+        Ok(Self { encrypted_key, iv, encrypted_content })
+    }
+
+    /// Finds the RSAES-OAEP KeyTransRecipientInfo and returns its encrypted key.
+    fn key_transport(recipient_infos: &'a [u8]) -> Result<&'a [u8]> {
+        let mut recipients = BerReader::new(recipient_infos);
+
+        while !recipients.is_empty() {
+            let recipient = recipients.next()?;
+            if recipient.tag != TAG_SEQUENCE {
+                continue; // kari, kekri, ... are context tagged
+            }
+
+            let mut ktri = BerReader::new(recipient.content);
+            ktri.expect(TAG_INTEGER)?;
+            ktri.next()?; // rid
+
+            let mut algorithm = BerReader::new(ktri.expect(TAG_SEQUENCE)?);
+            if algorithm.expect(TAG_OID)? != OID_RSAES_OAEP {
+                bail!("Unsupported key encryption algorithm, expected RSAES-OAEP");
+            }
+
+            return ktri.expect(TAG_OCTET_STRING);
+        }
+
+        bail!("EnvelopedData has no key transport recipient")
+    }
+}
+
+/// Joins the segments of a constructed (BER) OCTET STRING.
+fn octet_string_chunks(content: &[u8], depth: usize) -> Result<Vec<u8>> {
+    if depth > MAX_NESTING {
+        bail!("ASN.1 nesting too deep");
+    }
+
+    let mut reader = BerReader::new(content);
+    let mut result = Vec::with_capacity(content.len());
+
+    while !reader.is_empty() {
+        match reader.next()? {
+            Tlv { tag: TAG_OCTET_STRING, content } => result.extend_from_slice(content),
+            Tlv { tag: TAG_OCTET_STRING_CONSTRUCTED, content } => result.extend_from_slice(&octet_string_chunks(content, depth + 1)?),
+            _ => bail!("Unexpected element in constructed OCTET STRING"),
+        }
+    }
+
+    Ok(result)
+}
+
+struct Tlv<'a> {
+    tag: u8,
+    content: &'a [u8],
+}
+
+/// Minimal BER reader: single byte tags, definite and indefinite lengths.
+/// KMS output is not guaranteed to be DER, so indefinite lengths must be accepted.
+struct BerReader<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> BerReader<'a> {
+    fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    fn peek_tag(&self) -> Option<u8> {
+        self.buffer.first().copied()
+    }
+
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8]> {
+        let tlv = self.next()?;
+        if tlv.tag != tag {
+            bail!("Unexpected ASN.1 tag 0x{:02X}, expected 0x{:02X}", tlv.tag, tag);
+        }
+        Ok(tlv.content)
+    }
+
+    fn next(&mut self) -> Result<Tlv<'a>> {
+        let (tlv, consumed) = Self::element(self.buffer, 0)?;
+        self.buffer = &self.buffer[consumed..];
+        Ok(tlv)
+    }
+
+    /// Decodes one element, returning it and the number of bytes it occupies.
+    fn element(buffer: &'a [u8], depth: usize) -> Result<(Tlv<'a>, usize)> {
+        if depth > MAX_NESTING {
+            bail!("ASN.1 nesting too deep");
+        }
+
+        let (&tag, rest) = buffer.split_first().ok_or_else(|| anyhow!("ASN.1 element truncated"))?;
+        if tag & 0x1F == 0x1F {
+            bail!("Multi-byte ASN.1 tags are not supported");
+        }
+
+        let (&first, rest) = rest.split_first().ok_or_else(|| anyhow!("ASN.1 length truncated"))?;
+
+        if first == 0x80 {
+            if tag & 0x20 == 0 {
+                bail!("Indefinite length on primitive ASN.1 element");
+            }
+
+            // Children run until the end-of-contents marker
+            let mut offset = 0;
+            loop {
+                if rest[offset..].starts_with(&[0x00, 0x00]) {
+                    let content = &rest[..offset];
+                    return Ok((Tlv { tag, content }, 2 + offset + 2));
+                }
+                let (_, consumed) = Self::element(&rest[offset..], depth + 1)?;
+                offset += consumed;
+            }
+        }
+
+        let (length, header) = if first & 0x80 == 0 {
+            (first as usize, 2)
+        } else {
+            let count = (first & 0x7F) as usize;
+            if count > std::mem::size_of::<u32>() || rest.len() < count {
+                bail!("Invalid ASN.1 length");
+            }
+            let length = rest[..count].iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+            (length, 2 + count)
+        };
+
+        let content = buffer
+            .get(header..header + length)
+            .ok_or_else(|| anyhow!("ASN.1 element truncated"))?;
+
+        Ok((Tlv { tag, content }, header + length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::pkcs8::DecodePrivateKey;
+
+    // Generated with:
+    //   openssl cms -encrypt [-stream] -aes256 -recip cert.pem -outform DER \
+    //     -keyopt rsa_padding_mode:oaep -keyopt rsa_oaep_md:sha256 -keyopt rsa_mgf1_md:sha256
+    // The streamed form uses indefinite lengths and chunked encrypted content.
+    const RECIPIENT_KEY: &[u8] = include_bytes!("../testdata/cms_recipient_key.der");
+    const ENVELOPED: &[u8]     = include_bytes!("../testdata/cms_oaep_aes256.der");
+    const ENVELOPED_BER: &[u8] = include_bytes!("../testdata/cms_oaep_aes256_stream.der");
+    const PLAINTEXT: &[u8]     = b"0123456789ABCDEFFEDCBA9876543210";
+
+    fn recipient() -> RecipientKey {
+        let private_key = RsaPrivateKey::from_pkcs8_der(RECIPIENT_KEY).unwrap();
+        let public_key_der = RsaPublicKey::from(&private_key).to_public_key_der().unwrap().into_vec();
+        RecipientKey { private_key, public_key_der }
+    }
+
+    fn replace(buffer: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+        let at = buffer.windows(from.len()).position(|window| window == from).unwrap();
+        [&buffer[..at], to, &buffer[at + from.len()..]].concat()
+    }
+
+    #[test]
+    fn openssl_envelopes_decrypt() {
+        for enveloped in [ENVELOPED, ENVELOPED_BER] {
+            assert_eq!(recipient().decrypt_enveloped(enveloped).unwrap().as_slice(), PLAINTEXT);
+        }
+    }
+
+    #[test]
+    fn streamed_envelope_has_chunked_content() {
+        let envelope = EnvelopedData::parse(ENVELOPED_BER).unwrap();
+        assert_eq!(envelope.encrypted_content.len(), 48);
+        assert_eq!(envelope.iv.len(), AES_BLOCK_SIZE);
+    }
+
+    #[test]
+    fn rejects_other_algorithms() {
+        let aes_128_cbc = [&OID_AES_256_CBC[..8], &[0x02]].concat();
+        let rsa_pkcs1   = [&OID_RSAES_OAEP[..8], &[0x01]].concat();
+
+        for enveloped in [ENVELOPED, ENVELOPED_BER] {
+            let error = EnvelopedData::parse(&replace(enveloped, OID_AES_256_CBC, &aes_128_cbc)).err().unwrap();
+            assert!(error.to_string().contains("content encryption algorithm"), "{}", error);
+
+            let error = EnvelopedData::parse(&replace(enveloped, OID_RSAES_OAEP, &rsa_pkcs1)).err().unwrap();
+            assert!(error.to_string().contains("key encryption algorithm"), "{}", error);
+
+            let error = EnvelopedData::parse(&replace(enveloped, OID_ENVELOPED_DATA, &[&OID_ENVELOPED_DATA[..8], &[0x01]].concat())).err().unwrap();
+            assert!(error.to_string().contains("not EnvelopedData"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for enveloped in [ENVELOPED, ENVELOPED_BER] {
+            for len in 0..enveloped.len() {
+                assert!(EnvelopedData::parse(&enveloped[..len]).is_err(), "accepted {} of {} bytes", len, enveloped.len());
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let depth = 100_000;
+        let nested = [[0x30, 0x80].repeat(depth), [0x00, 0x00].repeat(depth)].concat();
+        let error = BerReader::new(&nested).next().err().unwrap();
+        assert_eq!(error.to_string(), "ASN.1 nesting too deep");
+
+        let mut chunks = vec![TAG_OCTET_STRING, 0x01, 0xAA];
+        for _ in 0..=MAX_NESTING {
+            chunks = [&[TAG_OCTET_STRING_CONSTRUCTED, chunks.len() as u8][..], &chunks].concat();
+        }
+        assert!(octet_string_chunks(&chunks, 0).is_err());
+        assert_eq!(octet_string_chunks(&chunks[2..], 0).unwrap(), [0xAA]);
+    }
+
+    #[test]
+    fn rejects_malformed_elements() {
+        let cases: [&[u8]; 5] = [
+            &[0x04, 0x80, 0x00, 0x00],             // indefinite primitive
+            &[0x30, 0x80, 0x04, 0x01, 0xAA],       // no end-of-contents
+            &[0x04, 0x85, 0x01, 0x00, 0x00, 0x00, 0x00], // length of more than 4 bytes
+            &[0x04, 0x82, 0x01],                   // length cut short
+            &[0x1F, 0x81, 0x00],                   // multi-byte tag
+        ];
+
+        for case in cases {
+            assert!(BerReader::new(case).next().is_err(), "{:02X?}", case);
+        }
+    }
+}
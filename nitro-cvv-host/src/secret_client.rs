@@ -0,0 +1,365 @@
+use anyhow::{Result, Context, anyhow, bail};
+use futures::{SinkExt, StreamExt};
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tokio::time::Instant;
+use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
+use tokio_vsock::{VsockAddr, VsockStream};
+
+use nitro::message::{GetKeyRequest, HeaderId, HeaderIdAllocator, Message};
+use nitro_tokio::NitroCodec;
+
+const REQUEST_QUEUE_SIZE: usize = 64;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct SecretClientConfig {
+    pub cid: u32,
+    pub port: u32,
+    pub pool_size: usize,
+    pub request_timeout: Duration,
+    pub max_frame_size: usize,
+}
+
+/// The header id stays allocated until the response arrives or the
+/// connection closes, even if the caller has given up by then; a late
+/// response must never match a newer request that reused its `hdr`.
+struct PendingRequest {
+    header_id: HeaderId,
+    message: Message,
+    reply: oneshot::Sender<Message>,
+}
+
+/// Handle to a live connection task; requests are pipelined over one stream.
+#[derive(Clone)]
+struct Connection {
+    requests: mpsc::Sender<PendingRequest>,
+    closed: CancellationToken,
+}
+
+impl Connection {
+    fn is_open(&self) -> bool {
+        !self.closed.is_cancelled()
+    }
+}
+
+/// Client for `nitro-cvv-secret` over a small pool of persistent vsock connections.
+///
+/// Each request gets its own header id and responses are matched by header,
+/// so any number of requests can be in flight on a connection. Broken
+/// connections are re-established on next use with exponential backoff.
+pub struct SecretClient {
+    config: SecretClientConfig,
+    pool: Vec<Mutex<Option<Connection>>>,
+    next_slot: AtomicUsize,
+    header_ids: HeaderIdAllocator,
+    shutdown_token: CancellationToken,
+}
+
+impl SecretClient {
+    pub fn new(config: SecretClientConfig, shutdown_token: CancellationToken) -> Self {
+        let pool = (0..config.pool_size.max(1)).map(|_| Mutex::new(None)).collect();
+
+        Self {
+            config,
+            pool,
+            next_slot: AtomicUsize::new(0),
+            header_ids: HeaderIdAllocator::new(),
+            shutdown_token,
+        }
+    }
+
+    /// Fetches the KMS-encrypted key for `key_id`.
+    pub async fn get_key(&self, key_id: &str) -> Result<Vec<u8>> {
+        let deadline = Instant::now() + self.config.request_timeout;
+
+        let header_id = self.header_ids.allocate()
+            .ok_or_else(|| anyhow!("No free header ids for secret server request"))?;
+        let hdr = header_id.hdr();
+
+        let request = GetKeyRequest::new(hdr, key_id.as_bytes().to_vec())?;
+
+        let connection = self.connection(deadline).await?;
+
+        let (reply, response) = oneshot::channel();
+        connection.requests
+            .send(PendingRequest { header_id, message: Message::GetKeyRequest(request), reply })
+            .await
+            .map_err(|_| anyhow!("Secret server connection closed before sending request"))?;
+
+        log::debug!("Requesting key '{}' (hdr {})", key_id, String::from_utf8_lossy(&hdr));
+
+        let message = tokio::time::timeout_at(deadline, response)
+            .await
+            .map_err(|_| anyhow!("Timeout waiting for secret server response"))?
+            .map_err(|_| anyhow!("Secret server closed the connection before responding"))?;
+
+        match message {
+            Message::GetKeyResponse(response) => {
+                if !response.is_success() {
+                    return Err(match response.code() {
+                        Some(code) => anyhow!("Secret server returned error: {}", code),
+                        None => anyhow!("Secret server returned unknown error: {}", response.response_code_str()),
+                    });
+                }
+
+                let encrypted_key = response.encrypted_key
+                    .ok_or_else(|| anyhow!("Response indicated success but no encrypted key"))?;
+
+                log::debug!("✓ Got encrypted key from secret server ({} bytes)", encrypted_key.len());
+
+                Ok(encrypted_key)
+            }
+            _ => Err(anyhow!("Unexpected message type from secret server")),
+        }
+    }
+
+    /// Returns an open connection from the next pool slot, connecting it if needed.
+    async fn connection(&self, deadline: Instant) -> Result<Connection> {
+        let slot = self.next_slot.fetch_add(1, Ordering::Relaxed) % self.pool.len();
+        let mut entry = self.pool[slot].lock().await;
+
+        if let Some(connection) = entry.as_ref().filter(|c| c.is_open()) {
+            return Ok(connection.clone());
+        }
+
+        let connection = self.connect_with_backoff(deadline).await?;
+        *entry = Some(connection.clone());
+        Ok(connection)
+    }
+
+    async fn connect_with_backoff(&self, deadline: Instant) -> Result<Connection> {
+        let addr = VsockAddr::new(self.config.cid, self.config.port);
+        let mut backoff = INITIAL_BACKOFF;
+
+        loop {
+            log::debug!("Connecting to secret server at CID {} port {}...", self.config.cid, self.config.port);
+
+            match VsockStream::connect(addr).await {
+                Ok(stream) => {
+                    log::info!("Connected to secret server at CID {} port {}", self.config.cid, self.config.port);
+                    return Ok(self.spawn_connection(stream));
+                }
+                Err(e) => {
+                    if Instant::now() + backoff > deadline {
+                        return Err(e).context(format!(
+                            "Failed to connect to secret server on CID {} port {}", self.config.cid, self.config.port
+                        ));
+                    }
+                    log::warn!("Secret server connect failed: {}, retrying in {:?}", e, backoff);
+                }
+            }
+
+            tokio::select! {
+                _ = tokio::time::sleep(backoff) => {}
+                _ = self.shutdown_token.cancelled() => bail!("Shutdown requested while connecting to secret server"),
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    fn spawn_connection(&self, stream: VsockStream) -> Connection {
+        let (requests, queue) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        let closed = self.shutdown_token.child_token();
+        let framed = Framed::new(stream, NitroCodec::with_max_frame_size(self.config.max_frame_size));
+
+        let task_closed = closed.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run_connection(framed, queue, &task_closed).await {
+                log::warn!("Secret server connection closed: {}", e);
+            }
+            task_closed.cancel();
+        });
+
+        Connection { requests, closed }
+    }
+}
+
+/// Writes queued requests and routes responses back by header until the stream fails.
+async fn run_connection<T>(
+    mut framed: Framed<T, NitroCodec>,
+    mut queue: mpsc::Receiver<PendingRequest>,
+    closed: &CancellationToken,
+) -> Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut pending: HashMap<[u8; 4], (HeaderId, oneshot::Sender<Message>)> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            request = queue.recv() => {
+                let Some(PendingRequest { header_id, message, reply }) = request else {
+                    return Ok(());
+                };
+
+                let hdr = header_id.hdr();
+                if pending.contains_key(&hdr) {
+                    log::error!("Header {} already in flight, dropping request", String::from_utf8_lossy(&hdr));
+                    continue;
+                }
+
+                pending.insert(hdr, (header_id, reply));
+                framed.send(&message).await.context("Failed to send request")?;
+            }
+            frame = framed.next() => {
+                let message = match frame {
+                    Some(Ok(message)) => message,
+                    Some(Err(e)) => return Err(e).context("Failed to read response"),
+                    None => bail!("closed by peer"),
+                };
+
+                // Removing the entry releases its header id
+                let hdr = message.header().hdr;
+                match pending.remove(&hdr) {
+                    Some((_header_id, reply)) => {
+                        if reply.send(message).is_err() {
+                            log::debug!("Late response for header {}, caller gave up", String::from_utf8_lossy(&hdr));
+                        }
+                    }
+                    None => {
+                        log::warn!("Response for unknown header {}, ignoring", String::from_utf8_lossy(&hdr));
+                    }
+                }
+            }
+            _ = closed.cancelled() => {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nitro::message::GetKeyResponse;
+
+    const MAX_FRAME_SIZE: usize = 1024;
+
+    fn request(header_ids: &HeaderIdAllocator, key_id: &str) -> (PendingRequest, oneshot::Receiver<Message>) {
+        let header_id = header_ids.allocate().unwrap();
+        let message = Message::GetKeyRequest(GetKeyRequest::new(header_id.hdr(), key_id.as_bytes().to_vec()).unwrap());
+        let (reply, response) = oneshot::channel();
+        (PendingRequest { header_id, message, reply }, response)
+    }
+
+    fn key_of(message: Message) -> Vec<u8> {
+        match message {
+            Message::GetKeyResponse(response) => response.encrypted_key.unwrap(),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn pipelined_responses_are_matched_by_header() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut server = Framed::new(server, NitroCodec::with_max_frame_size(MAX_FRAME_SIZE));
+
+        let (queue, requests) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        let closed = CancellationToken::new();
+        let task_closed = closed.clone();
+        let connection = tokio::spawn(async move {
+            run_connection(Framed::new(client, NitroCodec::with_max_frame_size(MAX_FRAME_SIZE)), requests, &task_closed).await
+        });
+
+        let header_ids = HeaderIdAllocator::new();
+        let mut responses = Vec::new();
+        for key_id in ["key-a", "key-b", "key-c"] {
+            let (pending, response) = request(&header_ids, key_id);
+            queue.send(pending).await.unwrap();
+            responses.push(response);
+        }
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            match server.next().await.unwrap().unwrap() {
+                Message::GetKeyRequest(request) => received.push((request.header.hdr, request.key_id)),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(header_ids.in_flight(), 3);
+
+        // Answer in reverse order, echoing each key id as the key
+        for (hdr, key_id) in received.into_iter().rev() {
+            server.send(Message::GetKeyResponse(GetKeyResponse::success(hdr, key_id).unwrap())).await.unwrap();
+        }
+
+        for (response, key_id) in responses.into_iter().zip(["key-a", "key-b", "key-c"]) {
+            assert_eq!(key_of(response.await.unwrap()), key_id.as_bytes());
+        }
+        assert_eq!(header_ids.in_flight(), 0);
+
+        closed.cancel();
+        connection.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn late_response_keeps_header_id_until_it_arrives() {
+        let (client, server) = tokio::io::duplex(4096);
+        let mut server = Framed::new(server, NitroCodec::with_max_frame_size(MAX_FRAME_SIZE));
+
+        let (queue, requests) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        let closed = CancellationToken::new();
+        let task_closed = closed.clone();
+        let connection = tokio::spawn(async move {
+            run_connection(Framed::new(client, NitroCodec::with_max_frame_size(MAX_FRAME_SIZE)), requests, &task_closed).await
+        });
+
+        let header_ids = HeaderIdAllocator::new();
+
+        // The caller for key X times out before the response arrives
+        let (late, gave_up) = request(&header_ids, "key-x");
+        let late_hdr = late.header_id.hdr();
+        queue.send(late).await.unwrap();
+        drop(gave_up);
+        let late_request = server.next().await.unwrap().unwrap();
+        assert_eq!(late_request.header().hdr, late_hdr);
+
+        // Its id is still held, so a new request cannot be given the same hdr
+        assert_eq!(header_ids.in_flight(), 1);
+        let (current, response) = request(&header_ids, "key-y");
+        let current_hdr = current.header_id.hdr();
+        assert_ne!(current_hdr, late_hdr);
+        queue.send(current).await.unwrap();
+        server.next().await.unwrap().unwrap();
+
+        server.send(Message::GetKeyResponse(GetKeyResponse::success(late_hdr, b"key-x".to_vec()).unwrap())).await.unwrap();
+        server.send(Message::GetKeyResponse(GetKeyResponse::success(current_hdr, b"key-y".to_vec()).unwrap())).await.unwrap();
+
+        assert_eq!(key_of(response.await.unwrap()), b"key-y");
+        assert_eq!(header_ids.in_flight(), 0);
+
+        closed.cancel();
+        connection.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn closed_connection_releases_header_ids() {
+        let (client, server) = tokio::io::duplex(4096);
+        let (queue, requests) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        let closed = CancellationToken::new();
+        let task_closed = closed.clone();
+        let connection = tokio::spawn(async move {
+            run_connection(Framed::new(client, NitroCodec::with_max_frame_size(MAX_FRAME_SIZE)), requests, &task_closed).await
+        });
+
+        let header_ids = HeaderIdAllocator::new();
+        let (pending, response) = request(&header_ids, "key-x");
+        queue.send(pending).await.unwrap();
+
+        let mut server = Framed::new(server, NitroCodec::with_max_frame_size(MAX_FRAME_SIZE));
+        server.next().await.unwrap().unwrap();
+        drop(server);
+
+        assert!(connection.await.unwrap().is_err());
+        assert!(response.await.is_err());
+        assert_eq!(header_ids.in_flight(), 0);
+    }
+}
@@ -1,113 +1,1334 @@
-use anyhow::{Result};
+use anyhow::{Result, Context, anyhow};
+use std::sync::Arc;
+use std::time::Instant;
 
-use tokio_vsock::VsockStream;
+use tokio_vsock::{OwnedWriteHalf, VsockStream};
+use tokio::sync::{Semaphore, mpsc};
 use tokio::time::Duration;
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
+use zeroize::Zeroizing;
 
-use aws_sdk_kms::Client as KmsClient;
 
+use nitro_tokio::IoResult;
 use nitro_tokio::message_utils::{read_message, write_message};
 
+use nitro::logging;
 use nitro::utils;
 use nitro::message::{
     Message,
-    VerifyCVVRequest,
+    MessageHeader,
+    MessageRef,
+    VerifyCVVRequestRef,
     VerifyCVVResponse,
+    GenerateCVVRequestRef,
+    GenerateCVVResponse,
+    VerifyPvvRequestRef,
+    VerifyPvvResponse,
+    GenerateOffsetRequestRef,
+    GenerateOffsetResponse,
+    VerifyOffsetRequestRef,
+    VerifyOffsetResponse,
+    OFFSET_SIZE,
+    TranslatePinRequestRef,
+    TranslatePinResponse,
+    DukptTranslatePinRequestRef,
+    DukptTranslatePinResponse,
+    DukptDecryptRequestRef,
+    DukptDecryptResponse,
+    ResponseCode,
 };
 
-use crate::aws;
+use nitro_metrics::Metrics;
 
+use crate::backend::KeyDecryptor;
+use crate::key_cache::KeyCache;
+use crate::key_registry::{Cipher, KeyNotPermitted, KeyRegistry, KeyUsage};
+use crate::secret_client::SecretClient;
+use crate::cvv::{Cvv, DesKey};
+use crate::dukpt::{self, Ksn};
+use crate::pin::{DecimalizationTables, Ibm3624, Pvv};
+use crate::pin_block::{self, PinBlockFormat, PinKey};
+
+const FRAME_BUFFER_CAPACITY: usize = 512;
+const MAX_IN_FLIGHT_REQUESTS: usize = 32;
+
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_client(
-    mut stream: VsockStream,
-    kms_client: KmsClient,
+    stream: VsockStream,
+    decryptor: Arc<dyn KeyDecryptor>,
+    key_cache: Arc<KeyCache>,
+    keys: Arc<KeyRegistry>,
+    secret_client: Arc<SecretClient>,
+    dectabs: Arc<DecimalizationTables>,
+    metrics: Arc<Metrics>,
+    max_frame_size: usize,
+    write_timeout: Duration,
     shutdown_token: CancellationToken,
 ) -> Result<()> {
     log::info!("Client connected, started");
-    
+    let _connection = metrics.connection_opened();
+
+    let (mut reader, writer) = stream.into_split();
+
+    // Responses are written in completion order; clients match them by hdr
+    let (responses, outbound) = mpsc::channel(MAX_IN_FLIGHT_REQUESTS);
+    let writer_token = shutdown_token.child_token();
+    let writer_task  = tokio::spawn(write_responses(writer, outbound, write_timeout, writer_token.clone()).in_current_span());
+
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT_REQUESTS));
+
     loop {
-        let message_bytes: Vec<u8> = match read_message(&mut stream, None, &shutdown_token).await {
-            Ok(bytes) => bytes,
+        let message_bytes: Vec<u8> = match read_message(&mut reader, max_frame_size, None, &writer_token).await {
+            Ok(IoResult::Success(bytes)) => bytes,
+            Ok(IoResult::Closed) => {
+                log::info!("Connection closed by peer");
+                break;
+            }
+            Ok(IoResult::Timeout) => {
+                log::debug!("Idle timeout waiting for request");
+                continue;
+            }
             Err(e)  => {
                 log::error!("read message error: {}", e);
                 break;
             }
         };
 
-        if message_bytes.is_empty() {
-            log::info!("Connection closed by peer or timeout");
-            break;
-        }
+        metrics.request_received(&message_bytes);
 
-        let dump = utils::hexdump_string(&message_bytes);
-        log::info!("recieve message {} bytes\n\n{}", message_bytes.len(), dump);
+        // Stop reading once the limit is reached until a request completes
+        let permit = Arc::clone(&in_flight).acquire_owned().await?;
 
-        let message = match Message::parse(&message_bytes) {
-            Ok(msg) => msg,
-            Err(e) => {
-                log::error!("Failed to parse message: {}", e);
-                continue;
-            }
-        };
+        let decryptor     = Arc::clone(&decryptor);
+        let key_cache     = Arc::clone(&key_cache);
+        let keys          = Arc::clone(&keys);
+        let secret_client = Arc::clone(&secret_client);
+        let dectabs       = Arc::clone(&dectabs);
+        let metrics       = Arc::clone(&metrics);
+        let responses     = responses.clone();
 
-        match message {
-            Message::VerifyCVVRequest(request) => {
-                log::info!("Processing VerifyCVV request");
-
-                let response = process_verifycvv(&request, &kms_client, &shutdown_token).await;
-
-                let timeout  = Some(Duration::from_secs(60));
-                let written = write_message(
-                    &mut stream,
-                    &response.to_bytes(),
-                    timeout,
-                    &shutdown_token
-                ).await?;
-                
-                if written == 0 {
-                    log::error!("Failed to send request (connection closed or timeout)");
-                }
-                else {
-                    let dump = utils::hexdump_string(&response.to_bytes());
-                    log::info!("sent message {} bytes\n\n{}", written, dump);
-                }
-            }
-            _ => {
-                log::warn!("Server received unsupport request, ignoring");
+        let span = logging::request_span(&message_bytes);
+
+        tokio::spawn(async move {
+            let started = Instant::now();
+
+            let dump = utils::hexdump_frame(&message_bytes);
+            log::info!("recieve message {} bytes\n\n{}", message_bytes.len(), dump);
+
+            let response = process_request(&message_bytes, decryptor.as_ref(), &key_cache, &keys, &secret_client, &dectabs, &metrics).await;
+            logging::finish_request(&tracing::Span::current(), response.as_deref(), started);
+            metrics.request_completed(&message_bytes, response.as_deref(), started.elapsed());
+
+            if let Some(response) = response
+                && responses.send(response).await.is_err() {
+                log::warn!("Connection closed before response could be sent");
             }
-        }        
+            drop(permit);
+        }.instrument(span));
     }
 
+    // The writer drains outstanding responses once every request task is done
+    drop(responses);
+    writer_task.await?;
+
     log::info!("Connection closed");
     Ok(())
 }
 
+/// Returns the encoded response, or `None` when the request is dropped.
+pub(crate) async fn process_request(
+    message_bytes: &[u8],
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    dectabs: &DecimalizationTables,
+    metrics: &Metrics,
+) -> Option<Vec<u8>> {
+
+    let message = match MessageRef::parse(message_bytes) {
+        Ok(msg) => msg,
+        Err(e) => {
+            log::error!("Failed to parse message: {}", e);
+            return error_response(message_bytes);
+        }
+    };
+
+    let mut outbound: Vec<u8> = Vec::with_capacity(FRAME_BUFFER_CAPACITY);
+
+    match message {
+        MessageRef::VerifyCVVRequest(request) => {
+            log::info!("Processing VerifyCVV request (hdr {})", String::from_utf8_lossy(&request.header.hdr));
+
+            process_verifycvv(&request, decryptor, key_cache, keys, secret_client, metrics).await
+                .write_to(&mut outbound);
+        }
+        MessageRef::GenerateCVVRequest(request) => {
+            log::info!("Processing GenerateCVV request (hdr {})", String::from_utf8_lossy(&request.header.hdr));
+
+            process_generatecvv(&request, decryptor, key_cache, keys, secret_client, metrics).await
+                .write_to(&mut outbound);
+        }
+        MessageRef::VerifyPvvRequest(request) => {
+            log::info!("Processing VerifyPVV request (hdr {})", String::from_utf8_lossy(&request.header.hdr));
+
+            process_verifypvv(&request, decryptor, key_cache, keys, secret_client, metrics).await
+                .write_to(&mut outbound);
+        }
+        MessageRef::GenerateOffsetRequest(request) => {
+            log::info!("Processing GenerateOffset request (hdr {})", String::from_utf8_lossy(&request.header.hdr));
+
+            process_generateoffset(&request, decryptor, key_cache, keys, secret_client, dectabs, metrics).await
+                .write_to(&mut outbound);
+        }
+        MessageRef::VerifyOffsetRequest(request) => {
+            log::info!("Processing VerifyOffset request (hdr {})", String::from_utf8_lossy(&request.header.hdr));
+
+            process_verifyoffset(&request, decryptor, key_cache, keys, secret_client, dectabs, metrics).await
+                .write_to(&mut outbound);
+        }
+        MessageRef::TranslatePinRequest(request) => {
+            log::info!("Processing TranslatePIN request (hdr {})", String::from_utf8_lossy(&request.header.hdr));
+
+            process_translatepin(&request, decryptor, key_cache, keys, secret_client, metrics).await
+                .write_to(&mut outbound);
+        }
+        MessageRef::DukptTranslatePinRequest(request) => {
+            log::info!("Processing DUKPT TranslatePIN request (hdr {})", String::from_utf8_lossy(&request.header.hdr));
+
+            process_dukpttranslatepin(&request, decryptor, key_cache, keys, secret_client, metrics).await
+                .write_to(&mut outbound);
+        }
+        MessageRef::DukptDecryptRequest(request) => {
+            log::info!("Processing DUKPT DecryptData request (hdr {})", String::from_utf8_lossy(&request.header.hdr));
+
+            process_dukptdecrypt(&request, decryptor, key_cache, keys, secret_client, metrics).await
+                .write_to(&mut outbound);
+        }
+        _ => {
+            log::warn!("Server received unsupported request");
+            return error_response(message_bytes);
+        }
+    }
+
+    Some(outbound)
+}
+
+/// `SystemError` response for a request that cannot be served, so the client is
+/// not left waiting. Frames without a usable header, and responses, get none.
+fn error_response(message_bytes: &[u8]) -> Option<Vec<u8>> {
+    let header = MessageHeader::parse(message_bytes).ok()?;
+
+    match Message::error_response(&header, ResponseCode::SystemError) {
+        Some(response) => Some(response.to_bytes()),
+        None => {
+            log::warn!("No response for command {}, ignoring", String::from_utf8_lossy(&header.cmd));
+            None
+        }
+    }
+}
+
+async fn write_responses(
+    mut writer: OwnedWriteHalf,
+    mut outbound: mpsc::Receiver<Vec<u8>>,
+    write_timeout: Duration,
+    token: CancellationToken,
+) {
+    let timeout = Some(write_timeout);
+
+    while let Some(response) = outbound.recv().await {
+        match write_message(&mut writer, &response, timeout, &token).await {
+            Ok(IoResult::Success(written)) => {
+                let dump = utils::hexdump_frame(&response);
+                log::info!("sent message {} bytes\n\n{}", written, dump);
+                continue;
+            }
+            Ok(IoResult::Closed) => log::error!("Failed to send response, connection closed by peer"),
+            Ok(IoResult::Timeout) => log::error!("Failed to send response, write timed out"),
+            Err(e) => log::error!("write message error: {}", e),
+        }
+        break;
+    }
+
+    // Stop the reader as well; pending responses have nowhere to go
+    token.cancel();
+}
+
 async fn process_verifycvv(
-    request: &VerifyCVVRequest,
-    _kms_client: &KmsClient,
-    shutdown_token: &CancellationToken,
+    request: &VerifyCVVRequestRef<'_>,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
 ) -> VerifyCVVResponse {
 
     let hdr = request.header.hdr;
 
-    let cvka_key_id = String::from_utf8_lossy(&request.cvka)
-        .trim_end_matches('\0')
-        .to_string();
-
-    let cvkb_key_id = String::from_utf8_lossy(&request.cvkb)
-        .trim_end_matches('\0')
-        .to_string();
+    let cvka_key_id = request.cvka_id();
+    let cvkb_key_id = request.cvkb_id();
 
     log::info!("VerifyCVV: CVKA='{}', CVKB='{}'", cvka_key_id, cvkb_key_id);
 
-    let encrypted_cvka = match aws::get_secret_key(&cvka_key_id, shutdown_token).await {
+    let cvv = match load_cvv(cvka_key_id, cvkb_key_id, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(cvv) => cvv,
+        Err(e) => {
+            log::error!("Failed to load CVK pair: {}", e);
+            return VerifyCVVResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let pan     = String::from_utf8_lossy(request.pan);
+    let expdate = String::from_utf8_lossy(request.expdate);
+    let svcode  = String::from_utf8_lossy(request.svcode);
+    let value   = String::from_utf8_lossy(request.cvv);
+
+    match cvv.verify(&pan, &expdate, &svcode, &value) {
+        Ok(true) => {
+            log::info!("VerifyCVV: match");
+            VerifyCVVResponse::success(hdr)
+        }
+        Ok(false) => {
+            log::info!("VerifyCVV: mismatch");
+            VerifyCVVResponse::error(hdr, ResponseCode::CvvMismatch)
+        }
+        Err(e) => {
+            log::error!("Failed to verify CVV: {}", e);
+            VerifyCVVResponse::error(hdr, ResponseCode::SystemError)
+        }
+    }
+}
+
+async fn process_generatecvv(
+    request: &GenerateCVVRequestRef<'_>,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> GenerateCVVResponse {
+
+    let hdr = request.header.hdr;
+
+    let cvka_key_id = request.cvka_id();
+    let cvkb_key_id = request.cvkb_id();
+
+    log::info!("GenerateCVV: CVKA='{}', CVKB='{}'", cvka_key_id, cvkb_key_id);
+
+    let cvv = match load_cvv(cvka_key_id, cvkb_key_id, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(cvv) => cvv,
+        Err(e) => {
+            log::error!("Failed to load CVK pair: {}", e);
+            return GenerateCVVResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let pan     = String::from_utf8_lossy(request.pan);
+    let expdate = String::from_utf8_lossy(request.expdate);
+    let svcode  = String::from_utf8_lossy(request.svcode);
+
+    let value = match cvv.calculate(&pan, &expdate, &svcode) {
+        Ok(value) => value,
+        Err(e) => {
+            log::error!("Failed to calculate CVV: {}", e);
+            return GenerateCVVResponse::error(hdr, ResponseCode::SystemError);
+        }
+    };
+
+    match <[u8; 3]>::try_from(value.as_bytes()) {
+        Ok(value) => {
+            log::info!("GenerateCVV: generated");
+            GenerateCVVResponse::success(hdr, value)
+        }
+        Err(_) => {
+            log::error!("Calculated CVV has unexpected length {}", value.len());
+            GenerateCVVResponse::error(hdr, ResponseCode::SystemError)
+        }
+    }
+}
+
+async fn process_verifypvv(
+    request: &VerifyPvvRequestRef<'_>,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> VerifyPvvResponse {
+
+    let hdr = request.header.hdr;
+
+    let pek_key_id  = request.pek_id();
+    let pvka_key_id = request.pvka_id();
+    let pvkb_key_id = request.pvkb_id();
+
+    log::info!("VerifyPVV: PEK='{}', PVKA='{}', PVKB='{}'", pek_key_id, pvka_key_id, pvkb_key_id);
+
+    let format = match PinBlockFormat::from_code(&String::from_utf8_lossy(request.format)) {
+        Ok(format) => format,
+        Err(e) => {
+            log::warn!("VerifyPVV: {}", e);
+            return VerifyPvvResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    let pek = match load_pin_key(pek_key_id, format, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(pek) => pek,
+        Err(e) => {
+            log::error!("Failed to load PEK: {}", e);
+            return VerifyPvvResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let pvv = match load_pvv(pvka_key_id, pvkb_key_id, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(pvv) => pvv,
+        Err(e) => {
+            log::error!("Failed to load PVK pair: {}", e);
+            return VerifyPvvResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let pin_block = String::from_utf8_lossy(request.pin_block);
+    let pan       = String::from_utf8_lossy(request.pan);
+    let pvki      = String::from_utf8_lossy(request.pvki);
+    let value     = String::from_utf8_lossy(request.pvv);
+
+    // The clear PIN is zeroized on drop and never logged
+    let pin = match pin_block::decrypt_pin_block(&pek, format, &pin_block, &pan) {
+        Ok(pin) => pin,
+        Err(e) => {
+            log::warn!("VerifyPVV: invalid PIN block: {}", e);
+            return VerifyPvvResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    match pvv.verify(&pan, &pvki, &pin, &value) {
+        Ok(true) => {
+            log::info!("VerifyPVV: match");
+            VerifyPvvResponse::success(hdr)
+        }
+        Ok(false) => {
+            log::info!("VerifyPVV: mismatch");
+            VerifyPvvResponse::error(hdr, ResponseCode::PinMismatch)
+        }
+        Err(e) => {
+            log::error!("Failed to verify PVV: {}", e);
+            VerifyPvvResponse::error(hdr, ResponseCode::SystemError)
+        }
+    }
+}
+
+async fn process_generateoffset(
+    request: &GenerateOffsetRequestRef<'_>,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    dectabs: &DecimalizationTables,
+    metrics: &Metrics,
+) -> GenerateOffsetResponse {
+
+    let hdr = request.header.hdr;
+
+    let pek_key_id = request.pek_id();
+    let pvk_key_id = request.pvk_id();
+    let dectab_id  = request.dectab_id();
+
+    log::info!("GenerateOffset: PEK='{}', PVK='{}', DECTAB='{}'", pek_key_id, pvk_key_id, dectab_id);
+
+    let Some(dectab) = dectabs.get(dectab_id) else {
+        log::warn!("GenerateOffset: decimalization table '{}' is not configured", dectab_id);
+        return GenerateOffsetResponse::error(hdr, ResponseCode::DecimalizationTableNotFound);
+    };
+
+    let format = match PinBlockFormat::from_code(&String::from_utf8_lossy(request.format)) {
+        Ok(format) => format,
+        Err(e) => {
+            log::warn!("GenerateOffset: {}", e);
+            return GenerateOffsetResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    let pek = match load_pin_key(pek_key_id, format, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(pek) => pek,
+        Err(e) => {
+            log::error!("Failed to load PEK: {}", e);
+            return GenerateOffsetResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let ibm = match load_pvk(pvk_key_id, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(pvk) => Ibm3624::new(pvk),
+        Err(e) => {
+            log::error!("Failed to load PVK: {}", e);
+            return GenerateOffsetResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let pin_block       = String::from_utf8_lossy(request.pin_block);
+    let pan             = String::from_utf8_lossy(request.pan);
+    let validation_data = String::from_utf8_lossy(request.validation_data);
+
+    let pin = match pin_block::decrypt_pin_block(&pek, format, &pin_block, &pan) {
+        Ok(pin) => pin,
+        Err(e) => {
+            log::warn!("GenerateOffset: invalid PIN block: {}", e);
+            return GenerateOffsetResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    match ibm.offset(&validation_data, dectab, &pin, request.check_length()) {
+        Ok(offset) => {
+            let offset: [u8; OFFSET_SIZE] = match offset.as_bytes().try_into() {
+                Ok(offset) => offset,
+                Err(_) => {
+                    log::error!("Offset has unexpected length {}", offset.len());
+                    return GenerateOffsetResponse::error(hdr, ResponseCode::SystemError);
+                }
+            };
+
+            log::info!("GenerateOffset: success");
+            GenerateOffsetResponse::success(hdr, offset)
+        }
+        Err(e) => {
+            // A PIN shorter than the check length is a property of the PIN block
+            log::warn!("Failed to generate offset: {}", e);
+            GenerateOffsetResponse::error(hdr, ResponseCode::InvalidPinBlock)
+        }
+    }
+}
+
+async fn process_verifyoffset(
+    request: &VerifyOffsetRequestRef<'_>,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    dectabs: &DecimalizationTables,
+    metrics: &Metrics,
+) -> VerifyOffsetResponse {
+
+    let hdr = request.header.hdr;
+
+    let pek_key_id = request.pek_id();
+    let pvk_key_id = request.pvk_id();
+    let dectab_id  = request.dectab_id();
+
+    log::info!("VerifyOffset: PEK='{}', PVK='{}', DECTAB='{}'", pek_key_id, pvk_key_id, dectab_id);
+
+    let Some(dectab) = dectabs.get(dectab_id) else {
+        log::warn!("VerifyOffset: decimalization table '{}' is not configured", dectab_id);
+        return VerifyOffsetResponse::error(hdr, ResponseCode::DecimalizationTableNotFound);
+    };
+
+    let format = match PinBlockFormat::from_code(&String::from_utf8_lossy(request.format)) {
+        Ok(format) => format,
+        Err(e) => {
+            log::warn!("VerifyOffset: {}", e);
+            return VerifyOffsetResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    let pek = match load_pin_key(pek_key_id, format, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(pek) => pek,
+        Err(e) => {
+            log::error!("Failed to load PEK: {}", e);
+            return VerifyOffsetResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let ibm = match load_pvk(pvk_key_id, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(pvk) => Ibm3624::new(pvk),
+        Err(e) => {
+            log::error!("Failed to load PVK: {}", e);
+            return VerifyOffsetResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let pin_block       = String::from_utf8_lossy(request.pin_block);
+    let pan             = String::from_utf8_lossy(request.pan);
+    let validation_data = String::from_utf8_lossy(request.validation_data);
+    let offset          = String::from_utf8_lossy(request.offset);
+
+    let pin = match pin_block::decrypt_pin_block(&pek, format, &pin_block, &pan) {
+        Ok(pin) => pin,
+        Err(e) => {
+            log::warn!("VerifyOffset: invalid PIN block: {}", e);
+            return VerifyOffsetResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    match ibm.verify(&validation_data, dectab, &pin, request.check_length(), &offset) {
+        Ok(true) => {
+            log::info!("VerifyOffset: match");
+            VerifyOffsetResponse::success(hdr)
+        }
+        Ok(false) => {
+            log::info!("VerifyOffset: mismatch");
+            VerifyOffsetResponse::error(hdr, ResponseCode::PinMismatch)
+        }
+        Err(e) => {
+            log::error!("Failed to verify offset: {}", e);
+            VerifyOffsetResponse::error(hdr, ResponseCode::SystemError)
+        }
+    }
+}
+
+async fn process_translatepin(
+    request: &TranslatePinRequestRef<'_>,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> TranslatePinResponse {
+
+    let hdr = request.header.hdr;
+
+    let source_key_id = request.source_key_id();
+    let dest_key_id   = request.dest_key_id();
+
+    let formats = PinBlockFormat::from_code(&String::from_utf8_lossy(request.source_format))
+        .and_then(|source| Ok((source, PinBlockFormat::from_code(&String::from_utf8_lossy(request.dest_format))?)));
+    let (source_format, dest_format) = match formats {
+        Ok(formats) => formats,
+        Err(e) => {
+            log::warn!("TranslatePIN: {}", e);
+            return TranslatePinResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    log::info!("TranslatePIN: source='{}' ({}), dest='{}' ({})",
+        source_key_id, source_format.code(), dest_key_id, dest_format.code());
+
+    // Dropping the PAN binding would let the PIN be replayed with any card
+    if source_format.is_pan_bound() && !dest_format.is_pan_bound() {
+        log::warn!("TranslatePIN: refusing to translate from format {} to {}", source_format.code(), dest_format.code());
+        return TranslatePinResponse::error(hdr, ResponseCode::PinTranslationNotAllowed);
+    }
+
+    // Re-encrypting under the same key would only turn this into a format converter
+    if source_key_id == dest_key_id {
+        log::warn!("TranslatePIN: source and destination key are both '{}'", source_key_id);
+        return TranslatePinResponse::error(hdr, ResponseCode::KeyUsageNotAllowed);
+    }
+
+    let source_key = match load_pin_key(source_key_id, source_format, decryptor, key_cache, keys, secret_client, metrics).await {
         Ok(key) => key,
         Err(e) => {
-            log::error!("Failed to get CVKA: {}", e);
-            return VerifyCVVResponse::error(hdr, *b"99");
+            log::error!("Failed to load source key: {}", e);
+            return TranslatePinResponse::error(hdr, key_error_code(&e));
         }
     };
-    
-    log::debug!("Got encrypted CVKA ({} bytes)", encrypted_cvka.len());
 
-    return VerifyCVVResponse::error(hdr, *b"99");
-}
\ No newline at end of file
+    let dest_key = match load_pin_key(dest_key_id, dest_format, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(key) => key,
+        Err(e) => {
+            log::error!("Failed to load destination key: {}", e);
+            return TranslatePinResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let pin_block = String::from_utf8_lossy(request.pin_block);
+    let pan       = String::from_utf8_lossy(request.pan);
+
+    let pin = match pin_block::decrypt_pin_block(&source_key, source_format, &pin_block, &pan) {
+        Ok(pin) => pin,
+        Err(e) => {
+            log::warn!("TranslatePIN: invalid PIN block: {}", e);
+            return TranslatePinResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    let translated = pin_block::encrypt_pin_block(&dest_key, dest_format, &pin, &pan)
+        .map_err(|e| anyhow!(e))
+        .and_then(|translated| TranslatePinResponse::success(hdr, &translated).map_err(|e| anyhow!(e)));
+
+    match translated {
+        Ok(response) => {
+            log::info!("TranslatePIN: success");
+            response
+        }
+        Err(e) => {
+            log::error!("Failed to translate PIN block: {}", e);
+            TranslatePinResponse::error(hdr, ResponseCode::SystemError)
+        }
+    }
+}
+
+async fn process_dukpttranslatepin(
+    request: &DukptTranslatePinRequestRef<'_>,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> DukptTranslatePinResponse {
+
+    let hdr = request.header.hdr;
+
+    let bdk_id      = request.bdk_id();
+    let dest_key_id = request.dest_key_id();
+
+    let formats = PinBlockFormat::from_code(&String::from_utf8_lossy(request.source_format))
+        .and_then(|source| Ok((source, PinBlockFormat::from_code(&String::from_utf8_lossy(request.dest_format))?)));
+    let (source_format, dest_format) = match formats {
+        Ok(formats) => formats,
+        Err(e) => {
+            log::warn!("DukptTranslatePIN: {}", e);
+            return DukptTranslatePinResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    let ksn_hex = String::from_utf8_lossy(request.ksn);
+    let ksn = match Ksn::from_hex(&ksn_hex) {
+        Ok(ksn) => ksn,
+        Err(e) => {
+            log::warn!("DukptTranslatePIN: {}", e);
+            return DukptTranslatePinResponse::error(hdr, ResponseCode::InvalidKsn);
+        }
+    };
+
+    log::info!("DukptTranslatePIN: bdk='{}', ksn={} ({}), dest='{}' ({})",
+        bdk_id, ksn_hex, source_format.code(), dest_key_id, dest_format.code());
+
+    // Dropping the PAN binding would let the PIN be replayed with any card
+    if source_format.is_pan_bound() && !dest_format.is_pan_bound() {
+        log::warn!("DukptTranslatePIN: refusing to translate from format {} to {}", source_format.code(), dest_format.code());
+        return DukptTranslatePinResponse::error(hdr, ResponseCode::PinTranslationNotAllowed);
+    }
+
+    let source_key = match load_dukpt(bdk_id, &ksn, |bdk_hex| dukpt::pin_key(bdk_hex, &ksn), decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(key) => key,
+        Err(e) => {
+            log::error!("Failed to derive DUKPT PIN key: {}", e);
+            return DukptTranslatePinResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let dest_key = match load_pin_key(dest_key_id, dest_format, decryptor, key_cache, keys, secret_client, metrics).await {
+        Ok(key) => key,
+        Err(e) => {
+            log::error!("Failed to load destination key: {}", e);
+            return DukptTranslatePinResponse::error(hdr, key_error_code(&e));
+        }
+    };
+
+    let pin_block = String::from_utf8_lossy(request.pin_block);
+    let pan       = String::from_utf8_lossy(request.pan);
+
+    // A TDES KSN yields a DES key and an AES KSN an AES key, so a format
+    // that does not match the KSN fails here
+    let pin = match pin_block::decrypt_pin_block(&source_key, source_format, &pin_block, &pan) {
+        Ok(pin) => pin,
+        Err(e) => {
+            log::warn!("DukptTranslatePIN: invalid PIN block: {}", e);
+            return DukptTranslatePinResponse::error(hdr, ResponseCode::InvalidPinBlock);
+        }
+    };
+
+    let translated = pin_block::encrypt_pin_block(&dest_key, dest_format, &pin, &pan)
+        .map_err(|e| anyhow!(e))
+        .and_then(|translated| DukptTranslatePinResponse::success(hdr, &translated).map_err(|e| anyhow!(e)));
+
+    match translated {
+        Ok(response) => {
+            log::info!("DukptTranslatePIN: success");
+            response
+        }
+        Err(e) => {
+            log::error!("Failed to translate PIN block: {}", e);
+            DukptTranslatePinResponse::error(hdr, ResponseCode::SystemError)
+        }
+    }
+}
+
+async fn process_dukptdecrypt(
+    request: &DukptDecryptRequestRef<'_>,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> DukptDecryptResponse {
+
+    let hdr = request.header.hdr;
+
+    let bdk_id = request.bdk_id();
+
+    let ksn_hex = String::from_utf8_lossy(request.ksn);
+    let ksn = match Ksn::from_hex(&ksn_hex) {
+        Ok(ksn) => ksn,
+        Err(e) => {
+            log::warn!("DukptDecrypt: {}", e);
+            return DukptDecryptResponse::error(hdr, ResponseCode::InvalidKsn);
+        }
+    };
+
+    log::info!("DukptDecrypt: bdk='{}', ksn={}, {} bytes", bdk_id, ksn_hex, request.data.len() / 2);
+
+    let data = match hex::decode(request.data) {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("Failed to decode data: {}", e);
+            return DukptDecryptResponse::error(hdr, ResponseCode::SystemError);
+        }
+    };
+
+    let clear = load_dukpt(bdk_id, &ksn, |bdk_hex| dukpt::decrypt_data(bdk_hex, &ksn, &data), decryptor, key_cache, keys, secret_client, metrics).await
+        .and_then(|clear| {
+            let clear_hex = Zeroizing::new(hex::encode_upper(clear.as_slice()));
+            DukptDecryptResponse::success(hdr, &clear_hex).map_err(|e| anyhow!(e))
+        });
+
+    match clear {
+        Ok(response) => {
+            log::info!("DukptDecrypt: success");
+            response
+        }
+        Err(e) => {
+            log::error!("Failed to decrypt DUKPT data: {}", e);
+            DukptDecryptResponse::error(hdr, key_error_code(&e))
+        }
+    }
+}
+
+async fn load_cvv(
+    cvka_key_id: &str,
+    cvkb_key_id: &str,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> Result<Cvv> {
+
+    let cvka = load_key(cvka_key_id, KeyUsage::Cvk, Cipher::Des, decryptor, key_cache, keys, secret_client, metrics).await
+        .context("Failed to load CVKA")?;
+
+    let cvkb = load_key(cvkb_key_id, KeyUsage::Cvk, Cipher::Des, decryptor, key_cache, keys, secret_client, metrics).await
+        .context("Failed to load CVKB")?;
+
+    let cvv = std::str::from_utf8(&cvka).context("CVKA is not valid UTF-8")
+        .and_then(|cvka_hex| {
+            let cvkb_hex = std::str::from_utf8(&cvkb).context("CVKB is not valid UTF-8")?;
+            Cvv::new(cvka_hex.trim(), cvkb_hex.trim()).map_err(|e| anyhow!(e))
+        });
+
+    if cvv.is_err() {
+        // Do not keep serving key material that cannot be used
+        key_cache.evict(cvka_key_id);
+        key_cache.evict(cvkb_key_id);
+    }
+
+    cvv
+}
+
+async fn load_pvv(
+    pvka_key_id: &str,
+    pvkb_key_id: &str,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> Result<Pvv> {
+
+    let pvka = load_key(pvka_key_id, KeyUsage::Pvk, Cipher::Des, decryptor, key_cache, keys, secret_client, metrics).await
+        .context("Failed to load PVKA")?;
+
+    let pvkb = load_key(pvkb_key_id, KeyUsage::Pvk, Cipher::Des, decryptor, key_cache, keys, secret_client, metrics).await
+        .context("Failed to load PVKB")?;
+
+    let pvv = std::str::from_utf8(&pvka).context("PVKA is not valid UTF-8")
+        .and_then(|pvka_hex| {
+            let pvkb_hex = std::str::from_utf8(&pvkb).context("PVKB is not valid UTF-8")?;
+            Pvv::new(pvka_hex.trim(), pvkb_hex.trim()).map_err(|e| anyhow!(e))
+        });
+
+    if pvv.is_err() {
+        key_cache.evict(pvka_key_id);
+        key_cache.evict(pvkb_key_id);
+    }
+
+    pvv
+}
+
+/// Loads a double or triple length PIN verification key.
+async fn load_pvk(
+    key_id: &str,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> Result<DesKey> {
+
+    let key = load_key(key_id, KeyUsage::Pvk, Cipher::Tdes, decryptor, key_cache, keys, secret_client, metrics).await?;
+
+    let des_key = std::str::from_utf8(&key).context("Key is not valid UTF-8")
+        .and_then(|key_hex| DesKey::from_hex(key_hex.trim()).map_err(|e| anyhow!(e)));
+
+    if des_key.is_err() {
+        key_cache.evict(key_id);
+    }
+
+    des_key
+}
+
+/// Loads a PIN encryption key of the type `format` needs.
+async fn load_pin_key(
+    key_id: &str,
+    format: PinBlockFormat,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> Result<PinKey> {
+
+    let cipher = if format == PinBlockFormat::Iso4 { Cipher::Aes } else { Cipher::Tdes };
+    let key = load_key(key_id, KeyUsage::PinEncryption, cipher, decryptor, key_cache, keys, secret_client, metrics).await?;
+
+    let pin_key = std::str::from_utf8(&key).context("Key is not valid UTF-8")
+        .and_then(|key_hex| PinKey::from_hex(format, key_hex.trim()).map_err(|e| anyhow!(e)));
+
+    if pin_key.is_err() {
+        key_cache.evict(key_id);
+    }
+
+    pin_key
+}
+
+/// Loads a hex-encoded BDK of the type `ksn` needs and derives from it with `derive`.
+#[allow(clippy::too_many_arguments)]
+async fn load_dukpt<T>(
+    bdk_key_id: &str,
+    ksn: &Ksn,
+    derive: impl FnOnce(&str) -> std::result::Result<T, String>,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> Result<T> {
+
+    let cipher = if ksn.is_aes() { Cipher::Aes } else { Cipher::Tdes };
+    let bdk = load_key(bdk_key_id, KeyUsage::Bdk, cipher, decryptor, key_cache, keys, secret_client, metrics).await
+        .context("Failed to load BDK")?;
+
+    let derived = std::str::from_utf8(&bdk).context("BDK is not valid UTF-8")
+        .and_then(|bdk_hex| derive(bdk_hex.trim()).map_err(|e| anyhow!(e)));
+
+    if derived.is_err() {
+        key_cache.evict(bdk_key_id);
+    }
+
+    derived
+}
+
+/// Returns the decrypted key, from the cache when possible, if `key_id` is
+/// registered for `usage` with a `cipher` key. Key material whose length
+/// does not match the registered algorithm is refused.
+#[allow(clippy::too_many_arguments)]
+async fn load_key(
+    key_id: &str,
+    usage: KeyUsage,
+    cipher: Cipher,
+    decryptor: &dyn KeyDecryptor,
+    key_cache: &KeyCache,
+    keys: &KeyRegistry,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> Result<Zeroizing<Vec<u8>>> {
+
+    let algorithm = keys.check(key_id, usage, cipher)?;
+
+    let key = match key_cache.get(key_id) {
+        Some(key) => {
+            metrics.cache_lookup("key", true);
+            log::debug!("Key '{}' served from cache", key_id);
+            key
+        }
+        None => {
+            metrics.cache_lookup("key", false);
+            let key = fetch_key(key_id, decryptor, secret_client, metrics).await?;
+            key_cache.insert(key_id, key.clone());
+            key
+        }
+    };
+
+    let hex_len = key.trim_ascii().len();
+    if hex_len != algorithm.key_len() * 2 {
+        key_cache.evict(key_id);
+        return Err(anyhow!("Key '{}' is registered as {} but has {} hex characters", key_id, algorithm, hex_len));
+    }
+
+    Ok(key)
+}
+
+async fn fetch_key(
+    key_id: &str,
+    decryptor: &dyn KeyDecryptor,
+    secret_client: &SecretClient,
+    metrics: &Metrics,
+) -> Result<Zeroizing<Vec<u8>>> {
+
+    let started = Instant::now();
+    let encrypted = secret_client.get_key(key_id).await;
+    metrics.upstream_call("secret_server_get_key", outcome(&encrypted), started.elapsed());
+
+    let encrypted = encrypted.context("Failed to get key from secret server")?;
+    log::debug!("Got encrypted key '{}' ({} bytes)", key_id, encrypted.len());
+
+    let started = Instant::now();
+    let key = decryptor.decrypt(&encrypted).await;
+    metrics.upstream_call("kms_decrypt", outcome(&key), started.elapsed());
+
+    Ok(Zeroizing::new(key.context("Failed to decrypt key")?))
+}
+
+fn outcome<T>(result: &Result<T>) -> &'static str {
+    if result.is_ok() { "ok" } else { "error" }
+}
+
+/// Response code for a failed key load; a key used for the wrong job is the client's error.
+fn key_error_code(e: &anyhow::Error) -> ResponseCode {
+    if e.downcast_ref::<KeyNotPermitted>().is_some() {
+        ResponseCode::KeyUsageNotAllowed
+    } else {
+        ResponseCode::SystemError
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    use async_trait::async_trait;
+    use nitro::message::{
+        DukptDecryptRequest, DukptTranslatePinRequest, GenerateOffsetRequest, GetKeyResponse, TranslatePinRequest,
+        VerifyCVVRequest, VerifyOffsetRequest, VerifyPvvRequest, MSGHDR_LEN_SIZE,
+    };
+
+    use crate::key_registry::{KeyAlgorithm, KeySpec};
+    use crate::secret_client::SecretClientConfig;
+
+    const PAN: &str = "4123456789012345";
+    const PEK: &str = "11111111111111112222222222222222";
+    // ANSI X9.24-1 test BDK and the first KSN of its key set
+    const BDK: &str = "0123456789ABCDEFFEDCBA9876543210";
+    const KSN: &str = "FFFF9876543210E00001";
+
+    /// Every key a test needs is cached, so nothing is ever decrypted.
+    struct CachedKeysOnly;
+
+    #[async_trait]
+    impl KeyDecryptor for CachedKeysOnly {
+        async fn decrypt(&self, _ciphertext: &[u8]) -> Result<Vec<u8>> {
+            Err(anyhow!("key not cached"))
+        }
+    }
+
+    fn cached(keys: &[(&str, &str)]) -> KeyCache {
+        let key_cache = KeyCache::new(Duration::from_secs(60), keys.len());
+        for (key_id, key) in keys {
+            key_cache.insert(key_id, Zeroizing::new(key.as_bytes().to_vec()));
+        }
+        key_cache
+    }
+
+    fn registry() -> KeyRegistry {
+        let spec = |usage, algorithm| KeySpec { usage, algorithm };
+
+        KeyRegistry::from_config(&BTreeMap::from([
+            ("cvk-a".to_string(), spec(KeyUsage::Cvk, KeyAlgorithm::Des)),
+            ("cvk-b".to_string(), spec(KeyUsage::Cvk, KeyAlgorithm::Des)),
+            ("pek".to_string(),   spec(KeyUsage::PinEncryption, KeyAlgorithm::Tdes2)),
+            ("pek-aes".to_string(), spec(KeyUsage::PinEncryption, KeyAlgorithm::Aes128)),
+            ("bdk".to_string(),   spec(KeyUsage::Bdk, KeyAlgorithm::Tdes2)),
+            ("pvk".to_string(),   spec(KeyUsage::Pvk, KeyAlgorithm::Tdes2)),
+            ("pvk-a".to_string(), spec(KeyUsage::Pvk, KeyAlgorithm::Des)),
+            ("pvk-b".to_string(), spec(KeyUsage::Pvk, KeyAlgorithm::Des)),
+        ]))
+    }
+
+    async fn serve(request: &[u8], key_cache: &KeyCache) -> Vec<u8> {
+
+        let secret_client = SecretClient::new(SecretClientConfig {
+            cid: 1,
+            port: 0,
+            pool_size: 1,
+            request_timeout: Duration::from_millis(10),
+            max_frame_size: 1024,
+        }, CancellationToken::new());
+        let dectabs = DecimalizationTables::from_config(&BTreeMap::from([
+            ("dectab".to_string(), "0123456789012345".to_string()),
+        ])).unwrap();
+        let metrics = Metrics::new("nitro-cvv-host-test").unwrap();
+
+        process_request(request, &CachedKeysOnly, key_cache, &registry(), &secret_client, &dectabs, &metrics).await.unwrap()
+    }
+
+    fn pin_block(pin: &str) -> String {
+        let pek = PinKey::from_hex(PinBlockFormat::Iso0, PEK).unwrap();
+        pin_block::encrypt_pin_block(&pek, PinBlockFormat::Iso0, pin, PAN).unwrap()
+    }
+
+    #[tokio::test]
+    async fn verify_pvv_reports_pin_mismatch() {
+        let keys = cached(&[("pek", PEK), ("pvk-a", "0123456789ABCDEF"), ("pvk-b", "FEDCBA9876543210")]);
+
+        for (pin, expected) in [("1234", ResponseCode::Success), ("1235", ResponseCode::PinMismatch)] {
+            let request = VerifyPvvRequest::new(*b"0007", "pek", "pvk-a", "pvk-b", "01", &pin_block(pin), PAN, "1", "1894")
+                .unwrap()
+                .to_bytes();
+
+            let response = VerifyPvvResponse::parse(&serve(&request, &keys).await).unwrap();
+            assert_eq!(response.code(), Some(expected), "PIN {}", pin);
+        }
+    }
+
+    #[tokio::test]
+    async fn generated_offset_verifies() {
+        let keys = cached(&[("pek", PEK), ("pvk", "0123456789ABCDEFFEDCBA9876543210")]);
+
+        let request = GenerateOffsetRequest::new(*b"0008", "pek", "pvk", "dectab", "01", "06", &pin_block("987654"), PAN, PAN)
+            .unwrap()
+            .to_bytes();
+        let response = GenerateOffsetResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.offset_str().as_deref(), Some("925601FFFFFF"));
+
+        for (pin, expected) in [("987654", ResponseCode::Success), ("987655", ResponseCode::PinMismatch)] {
+            let request = VerifyOffsetRequest::new(*b"0009", "pek", "pvk", "dectab", "01", "06", &pin_block(pin), PAN, PAN, "925601FFFFFF")
+                .unwrap()
+                .to_bytes();
+
+            let response = VerifyOffsetResponse::parse(&serve(&request, &keys).await).unwrap();
+            assert_eq!(response.code(), Some(expected), "PIN {}", pin);
+        }
+    }
+
+    #[test]
+    fn unparseable_request_gets_system_error() {
+        let request = VerifyCVVRequest::new(*b"0042", "cvk-a", "cvk-b", "123", "4111111111111111", "2512", "101")
+            .unwrap()
+            .to_bytes();
+
+        // Drop the service code but keep the declared length consistent
+        let mut truncated = request[..request.len() - 3].to_vec();
+        let len = (truncated.len() - MSGHDR_LEN_SIZE) as u16;
+        truncated[..MSGHDR_LEN_SIZE].copy_from_slice(&len.to_be_bytes());
+
+        let response = VerifyCVVResponse::parse(&error_response(&truncated).unwrap()).unwrap();
+        assert_eq!(response.header.hdr, *b"0042");
+        assert_eq!(response.code(), Some(ResponseCode::SystemError));
+    }
+
+    #[test]
+    fn responses_and_bad_headers_get_nothing() {
+        let response = GetKeyResponse::error(*b"0042", ResponseCode::SecretNotFound).to_bytes();
+        assert_eq!(error_response(&response), None);
+        assert_eq!(error_response(b"\x00\x06"), None);
+    }
+
+    #[tokio::test]
+    async fn key_cannot_change_usage() {
+        // A PVK pair that happens to be valid CVK material
+        let keys = cached(&[("pek", PEK), ("pvk-a", "0123456789ABCDEF"), ("pvk-b", "FEDCBA9876543210")]);
+
+        let request = VerifyPvvRequest::new(*b"0010", "pek", "pvk-a", "pvk-b", "01", &pin_block("1234"), PAN, "1", "1894")
+            .unwrap()
+            .to_bytes();
+        let response = VerifyPvvResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::Success));
+
+        let request = VerifyCVVRequest::new(*b"0011", "pvk-a", "pvk-b", "561", "4123456789012345", "8701", "101")
+            .unwrap()
+            .to_bytes();
+        let response = VerifyCVVResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::KeyUsageNotAllowed));
+
+        // Nor may the PEK stand in for a PVK
+        let request = VerifyPvvRequest::new(*b"0012", "pek", "pek", "pvk-b", "01", &pin_block("1234"), PAN, "1", "1894")
+            .unwrap()
+            .to_bytes();
+        let response = VerifyPvvResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::KeyUsageNotAllowed));
+    }
+
+    #[tokio::test]
+    async fn failed_derivation_keeps_usage() {
+        // CVKB is not hex, so building the CVK pair fails and both keys are evicted
+        let keys = cached(&[("cvk-a", "0123456789ABCDEF"), ("cvk-b", "0123456789ABCDEZ")]);
+
+        let request = VerifyCVVRequest::new(*b"0013", "cvk-a", "cvk-b", "561", PAN, "8701", "101")
+            .unwrap()
+            .to_bytes();
+        let response = VerifyCVVResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::SystemError));
+        assert!(keys.get("cvk-a").is_none());
+
+        // The CVK is still refused as a PEK without being fetched again
+        let request = VerifyPvvRequest::new(*b"0014", "cvk-a", "pvk-a", "pvk-b", "01", &pin_block("1234"), PAN, "1", "1894")
+            .unwrap()
+            .to_bytes();
+        let response = VerifyPvvResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::KeyUsageNotAllowed));
+    }
+
+    #[tokio::test]
+    async fn key_material_must_match_registered_algorithm() {
+        // Registered as a double-length PVK but holding a single-length key
+        let keys = cached(&[("pek", PEK), ("pvk", "0123456789ABCDEF")]);
+
+        let request = GenerateOffsetRequest::new(*b"0015", "pek", "pvk", "dectab", "01", "06", &pin_block("987654"), PAN, PAN)
+            .unwrap()
+            .to_bytes();
+        let response = GenerateOffsetResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::SystemError));
+        assert!(keys.get("pvk").is_none());
+
+        // Unregistered keys are refused outright
+        let request = GenerateOffsetRequest::new(*b"0016", "pek", "other", "dectab", "01", "06", &pin_block("987654"), PAN, PAN)
+            .unwrap()
+            .to_bytes();
+        let response = GenerateOffsetResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::KeyUsageNotAllowed));
+    }
+
+    #[tokio::test]
+    async fn pin_keys_are_bound_to_their_algorithm() {
+        let keys = cached(&[("pek", PEK), ("pek-aes", PEK), ("pvk-a", "0123456789ABCDEF"), ("pvk-b", "FEDCBA9876543210")]);
+
+        // The same 32 hex digits registered as TDES do not serve as AES for format 4
+        let request = VerifyPvvRequest::new(*b"0017", "pek", "pvk-a", "pvk-b", "48", &pin_block("1234"), PAN, "1", "1894")
+            .unwrap()
+            .to_bytes();
+        let response = VerifyPvvResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::KeyUsageNotAllowed));
+
+        let cases = [("pek", "pek-aes", "01", "01"), ("pek", "pek", "01", "47")];
+        for (hdr, (source, dest, source_format, dest_format)) in [*b"0018", *b"0019"].into_iter().zip(cases) {
+            let request = TranslatePinRequest::new(hdr, source, dest, source_format, dest_format, &pin_block("1234"), PAN)
+                .unwrap()
+                .to_bytes();
+            let response = TranslatePinResponse::parse(&serve(&request, &keys).await).unwrap();
+            assert_eq!(response.code(), Some(ResponseCode::KeyUsageNotAllowed), "{} to {}", source, dest);
+        }
+    }
+
+    #[tokio::test]
+    async fn translated_pin_decrypts_under_destination_key() {
+        let keys = cached(&[("pek", PEK), ("pek-aes", PEK)]);
+
+        let request = TranslatePinRequest::new(*b"0020", "pek", "pek-aes", "01", "48", &pin_block("1234"), PAN)
+            .unwrap()
+            .to_bytes();
+        let response = TranslatePinResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::Success));
+
+        let translated = response.pin_block_str().unwrap();
+        assert_eq!(translated.len(), 32);
+        let dest_key = PinKey::from_hex(PinBlockFormat::Iso4, PEK).unwrap();
+        let pin = pin_block::decrypt_pin_block(&dest_key, PinBlockFormat::Iso4, &translated, PAN).unwrap();
+        assert_eq!(pin.as_str(), "1234");
+    }
+
+    #[tokio::test]
+    async fn translation_cannot_drop_pan_binding() {
+        let keys = cached(&[("pek", PEK), ("pek-aes", PEK)]);
+
+        // ISO 0 to ISO 1 would give a PIN block usable with any card
+        let request = TranslatePinRequest::new(*b"0021", "pek", "pek-aes", "01", "05", &pin_block("1234"), PAN)
+            .unwrap()
+            .to_bytes();
+        let response = TranslatePinResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::PinTranslationNotAllowed));
+    }
+
+    #[tokio::test]
+    async fn bad_source_pin_block_is_rejected() {
+        let keys = cached(&[("pek", PEK), ("pek-aes", PEK)]);
+
+        // Encrypted under another key, so it does not decrypt to a format 0 block
+        let other_key = PinKey::from_hex(PinBlockFormat::Iso0, "0123456789ABCDEFFEDCBA9876543210").unwrap();
+        let foreign = pin_block::encrypt_pin_block(&other_key, PinBlockFormat::Iso0, "1234", PAN).unwrap();
+
+        let request = TranslatePinRequest::new(*b"0022", "pek", "pek-aes", "01", "48", &foreign, PAN)
+            .unwrap()
+            .to_bytes();
+        let response = TranslatePinResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::InvalidPinBlock));
+        assert_eq!(response.pin_block, None);
+    }
+
+    #[tokio::test]
+    async fn dukpt_rejects_invalid_or_exhausted_ksn() {
+        let keys = cached(&[("bdk", BDK), ("pek", PEK)]);
+
+        // A zero counter, and a counter with more than ten bits set
+        for (hdr, ksn) in [(*b"0023", "FFFF9876543210E00000"), (*b"0024", "FFFF9876543210FFFFFF")] {
+            let request = DukptTranslatePinRequest::new(hdr, "bdk", "pek", "01", "01", ksn, &pin_block("1234"), PAN)
+                .unwrap()
+                .to_bytes();
+            let response = DukptTranslatePinResponse::parse(&serve(&request, &keys).await).unwrap();
+            assert_eq!(response.code(), Some(ResponseCode::InvalidKsn), "G0 with KSN {}", ksn);
+
+            let request = DukptDecryptRequest::new(hdr, "bdk", ksn, "0123456789ABCDEF").unwrap().to_bytes();
+            let response = DukptDecryptResponse::parse(&serve(&request, &keys).await).unwrap();
+            assert_eq!(response.code(), Some(ResponseCode::InvalidKsn), "M2 with KSN {}", ksn);
+        }
+    }
+
+    #[tokio::test]
+    async fn dukpt_translated_pin_verifies_under_destination_key() {
+        let keys = cached(&[("bdk", BDK), ("pek", PEK), ("pvk-a", "0123456789ABCDEF"), ("pvk-b", "FEDCBA9876543210")]);
+
+        let terminal_key = dukpt::pin_key(BDK, &Ksn::from_hex(KSN).unwrap()).unwrap();
+        let terminal_block = pin_block::encrypt_pin_block(&terminal_key, PinBlockFormat::Iso0, "1234", PAN).unwrap();
+
+        let request = DukptTranslatePinRequest::new(*b"0025", "bdk", "pek", "01", "01", KSN, &terminal_block, PAN)
+            .unwrap()
+            .to_bytes();
+        let response = DukptTranslatePinResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::Success));
+
+        // The translated block passes a PVV check under the destination PEK;
+        // the terminal's block does not even decrypt to a valid one there
+        let translated = response.pin_block_str().unwrap();
+        for (pin_block, expected) in [(translated, ResponseCode::Success), (terminal_block, ResponseCode::InvalidPinBlock)] {
+            let request = VerifyPvvRequest::new(*b"0026", "pek", "pvk-a", "pvk-b", "01", &pin_block, PAN, "1", "1894")
+                .unwrap()
+                .to_bytes();
+            let response = VerifyPvvResponse::parse(&serve(&request, &keys).await).unwrap();
+            assert_eq!(response.code(), Some(expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn dukpt_decrypt_returns_terminal_data() {
+        let keys = cached(&[("bdk", BDK)]);
+
+        // CBC with a zero IV under the data key X9.24-1 derives for KSN
+        let data_key = DesKey::Double(hex::decode("448D3F076D8304036A55A3D7E0055A78").unwrap().try_into().unwrap());
+        let clear = b"4111111111111111";
+        let mut previous = [0u8; 8];
+        let mut encrypted = Vec::new();
+        for block in clear.chunks(8) {
+            let mut input = [0u8; 8];
+            for (i, byte) in input.iter_mut().enumerate() {
+                *byte = block[i] ^ previous[i];
+            }
+            previous = data_key.encrypt(&input);
+            encrypted.extend_from_slice(&previous);
+        }
+
+        let request = DukptDecryptRequest::new(*b"0027", "bdk", KSN, &hex::encode_upper(&encrypted)).unwrap().to_bytes();
+        let response = DukptDecryptResponse::parse(&serve(&request, &keys).await).unwrap();
+        assert_eq!(response.code(), Some(ResponseCode::Success));
+        assert_eq!(response.data_str().as_deref().map(String::as_str), Some(hex::encode_upper(clear).as_str()));
+    }
+}
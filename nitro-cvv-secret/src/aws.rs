@@ -1,17 +1,58 @@
 use anyhow::{Result, Context};
+use async_trait::async_trait;
 use aws_config::SdkConfig;
 use aws_sdk_secretsmanager::Client as SecretsManagerClient;
+use aws_sdk_secretsmanager::error::SdkError;
+use aws_sdk_secretsmanager::operation::get_secret_value::GetSecretValueError;
 use aws_sdk_kms::Client as KmsClient;
+use aws_sdk_kms::error::{DisplayErrorContext, ProvideErrorMetadata};
 use aws_sdk_sts::Client as StsClient;
 
 use aws_secretsmanager_caching::SecretsManagerCachingClient;
 
+use std::fmt;
 use std::num::NonZeroUsize;
 use std::time::Duration;
 
+use crate::backend::{KeyEncryptor, SecretStore};
 
-const CACHE_SIZE: NonZeroUsize = unsafe { NonZeroUsize::new_unchecked(1000) };
-const CACHE_TTL: Duration = Duration::from_secs(300);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AwsError {
+    NotFound(String),
+    AccessDenied(String),
+    Other(String),
+}
+
+impl AwsError {
+    fn classify<E>(err: &E) -> Self
+    where
+        E: ProvideErrorMetadata + std::error::Error,
+    {
+        let message = DisplayErrorContext(err).to_string();
+
+        match err.code() {
+            Some("ResourceNotFoundException") | Some("NotFoundException") => {
+                AwsError::NotFound(message)
+            }
+            Some("AccessDeniedException") | Some("AccessDenied") | Some("UnauthorizedException") => {
+                AwsError::AccessDenied(message)
+            }
+            _ => AwsError::Other(message),
+        }
+    }
+}
+
+impl fmt::Display for AwsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AwsError::NotFound(msg) => write!(f, "not found: {}", msg),
+            AwsError::AccessDenied(msg) => write!(f, "access denied: {}", msg),
+            AwsError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for AwsError {}
 
 pub async fn validate_credentials(config: &SdkConfig) -> Result<()> {
     log::info!("Validating AWS credentials...");
@@ -35,13 +76,17 @@ pub async fn validate_credentials(config: &SdkConfig) -> Result<()> {
     }
 }
 
-pub async fn create_secret_client(config: &SdkConfig) -> Result<SecretsManagerCachingClient> {
-    let client = SecretsManagerClient::new(&config);
+pub async fn create_secret_client(
+    config: &SdkConfig,
+    cache_size: NonZeroUsize,
+    cache_ttl: Duration,
+) -> Result<SecretsManagerCachingClient> {
+    let client = SecretsManagerClient::new(config);
 
     let caching_client = SecretsManagerCachingClient::new(
         client,
-        CACHE_SIZE,
-        CACHE_TTL,
+        cache_size,
+        cache_ttl,
         false
     )
     .context("Failed to create secrets caching client")?;
@@ -52,17 +97,20 @@ pub async fn create_secret_client(config: &SdkConfig) -> Result<SecretsManagerCa
 pub async fn fetch_secret(
     secret_client: &SecretsManagerCachingClient,
     secret_name: &str,
-) -> Result<String> {
+) -> Result<String, AwsError> {
     println!("Fetching secret: {}", secret_name);
 
     let secret_value = secret_client
         .get_secret_value(secret_name, None, None, false)
         .await
-        .map_err(|e| anyhow::anyhow!("Failed to retrieve secret '{}': {}", secret_name, e))?;
+        .map_err(|e| match e.downcast_ref::<SdkError<GetSecretValueError>>() {
+            Some(sdk_error) => AwsError::classify(sdk_error),
+            None => AwsError::Other(format!("Failed to retrieve secret '{}': {}", secret_name, e)),
+        })?;
     
     let secret_string = secret_value
         .secret_string
-        .ok_or_else(|| anyhow::anyhow!("Secret '{}' has no string value", secret_name))?;
+        .ok_or_else(|| AwsError::Other(format!("Secret '{}' has no string value", secret_name)))?;
           
     Ok(secret_string)
 }
@@ -71,7 +119,7 @@ pub async fn encrypt_with_kms(
     kms_client: &KmsClient,
     key_id: &str,
     plaintext: &[u8],
-) -> Result<Vec<u8>> {
+) -> Result<Vec<u8>, AwsError> {
     log::debug!("Encrypting {} bytes with KMS key: {}", plaintext.len(), key_id);
  
     let blob = aws_sdk_kms::primitives::Blob::new(plaintext.to_vec());
@@ -82,11 +130,11 @@ pub async fn encrypt_with_kms(
         .plaintext(blob)
         .send()
         .await
-        .map_err(|e| anyhow::anyhow!("KMS Encrypt API error: {}", e))?;
+        .map_err(|e| AwsError::classify(&e))?;
 
     let ciphertext_blob = response
         .ciphertext_blob()
-        .ok_or_else(|| anyhow::anyhow!("KMS response contains no ciphertext"))?;
+        .ok_or_else(|| AwsError::Other("KMS response contains no ciphertext".to_string()))?;
 
     let encrypted_bytes = ciphertext_blob.clone().into_inner();
     
@@ -98,3 +146,17 @@ pub async fn encrypt_with_kms(
     
     Ok(encrypted_bytes)
 }
+
+#[async_trait]
+impl SecretStore for SecretsManagerCachingClient {
+    async fn get_secret(&self, secret_name: &str) -> Result<String, AwsError> {
+        fetch_secret(self, secret_name).await
+    }
+}
+
+#[async_trait]
+impl KeyEncryptor for KmsClient {
+    async fn encrypt(&self, key_id: &str, plaintext: &[u8]) -> Result<Vec<u8>, AwsError> {
+        encrypt_with_kms(self, key_id, plaintext).await
+    }
+}
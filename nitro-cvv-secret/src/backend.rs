@@ -0,0 +1,16 @@
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::aws::AwsError;
+
+/// Looks up the plaintext value of a named secret.
+#[async_trait]
+pub trait SecretStore: Send + Sync {
+    async fn get_secret(&self, secret_name: &str) -> Result<String, AwsError>;
+}
+
+/// Encrypts secret values for the enclave under a KMS key.
+#[async_trait]
+pub trait KeyEncryptor: Send + Sync {
+    async fn encrypt(&self, key_id: &str, plaintext: &[u8]) -> Result<Vec<u8>, AwsError>;
+}
@@ -0,0 +1,64 @@
+use anyhow::{Result, Context, anyhow};
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use nitro_config::LocalConfig;
+use nitro_local::LocalKms;
+
+use crate::aws::AwsError;
+use crate::backend::{KeyEncryptor, SecretStore};
+
+/// Secrets read once from a JSON object of `name -> value`.
+pub struct LocalSecretStore {
+    secrets: HashMap<String, String>,
+}
+
+impl LocalSecretStore {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read secrets file '{}'", path.display()))?;
+
+        let secrets: HashMap<String, String> = serde_json::from_str(&content)
+            .with_context(|| format!("Secrets file '{}' is not a JSON object of strings", path.display()))?;
+
+        log::info!("Loaded {} local secrets from '{}'", secrets.len(), path.display());
+        Ok(Self { secrets })
+    }
+}
+
+#[async_trait]
+impl SecretStore for LocalSecretStore {
+    async fn get_secret(&self, secret_name: &str) -> Result<String, AwsError> {
+        self.secrets
+            .get(secret_name)
+            .cloned()
+            .ok_or_else(|| AwsError::NotFound(format!("Secret '{}' not in local secrets file", secret_name)))
+    }
+}
+
+#[async_trait]
+impl KeyEncryptor for LocalKms {
+    async fn encrypt(&self, key_id: &str, plaintext: &[u8]) -> Result<Vec<u8>, AwsError> {
+        LocalKms::encrypt(self, key_id, plaintext).map_err(|e| AwsError::Other(e.to_string()))
+    }
+}
+
+/// Builds the local store from `local.secrets_file` and the KMS emulator from `local.kms_key`.
+pub fn create_backends(config: &LocalConfig) -> Result<(Arc<dyn SecretStore>, Arc<dyn KeyEncryptor>)> {
+    let path = config.secrets_file
+        .as_deref()
+        .ok_or_else(|| anyhow!("local.secrets_file must be set for the local backend"))?;
+    let store = LocalSecretStore::load(path)?;
+
+    let kms = match &config.kms_key {
+        Some(key) => LocalKms::from_hex(key).context("Invalid local.kms_key")?,
+        None => {
+            log::warn!("local.kms_key not set, using the development master key");
+            LocalKms::development()
+        }
+    };
+
+    Ok((Arc::new(store), Arc::new(kms)))
+}
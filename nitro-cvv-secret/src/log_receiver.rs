@@ -0,0 +1,168 @@
+use anyhow::{Result, Context};
+use std::path::Path;
+
+use tokio::fs::OpenOptions;
+use tokio::io::{self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+use tokio_vsock::{VMADDR_CID_ANY, VsockAddr, VsockListener};
+
+/// Lines queued between connection readers and the output writer.
+const LINE_QUEUE_SIZE: usize = 4096;
+
+/// Longest line forwarded; the rest of a longer line is dropped.
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Accepts log streams from enclaves and appends every line to `output` (stdout when `None`).
+pub async fn run(port: u32, output: Option<&Path>, token: CancellationToken) -> Result<()> {
+    let out: Box<dyn AsyncWrite + Send + Unpin> = match output {
+        Some(path) => Box::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await
+                .with_context(|| format!("failed to open log output '{}'", path.display()))?,
+        ),
+        None => Box::new(io::stdout()),
+    };
+
+    let (lines, queue) = mpsc::channel(LINE_QUEUE_SIZE);
+    let writer = tokio::spawn(write_lines(out, queue));
+
+    let addr     = VsockAddr::new(VMADDR_CID_ANY, port);
+    let listener = VsockListener::bind(addr).context(format!("failed to bind to cid: ANY port: {}", port))?;
+
+    log::info!("receiving logs on cid: ANY port: {}", port);
+    loop {
+        tokio::select! {
+            accept_result = listener.accept() => {
+                match accept_result {
+                    Ok((stream, peer)) => {
+                        log::info!("log stream connected from {}", peer);
+
+                        let lines = lines.clone();
+                        let token = token.child_token();
+                        tokio::spawn(async move {
+                            match read_lines(stream, lines, token).await {
+                                Ok(()) => log::info!("log stream from {} closed", peer),
+                                Err(e) => log::warn!("log stream from {} failed: {}", peer, e),
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        log::error!("error accepting log connection: {}", e);
+                    }
+                }
+            }
+            _ = token.cancelled() => {
+                log::info!("log receiver shutting down ...");
+                break;
+            }
+        }
+    }
+
+    // Readers hold the remaining senders and stop on the same token
+    drop(lines);
+    writer.await.context("log writer task failed")?
+}
+
+async fn read_lines<S: AsyncRead + Unpin>(stream: S, lines: mpsc::Sender<Vec<u8>>, token: CancellationToken) -> Result<()> {
+    let mut reader = BufReader::new(stream);
+
+    loop {
+        let mut line = Vec::new();
+        let read = tokio::select! {
+            read = read_line(&mut reader, &mut line) => read?,
+            _ = token.cancelled() => return Ok(()),
+        };
+
+        if read == 0 {
+            return Ok(());
+        }
+        if !line.ends_with(b"\n") {
+            line.push(b'\n');
+        }
+        if lines.send(line).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Reads one line of at most `MAX_LINE_LEN` bytes and discards the rest of a longer one.
+async fn read_line<R: AsyncBufRead + Unpin>(reader: &mut R, line: &mut Vec<u8>) -> io::Result<usize> {
+    let read = (&mut *reader).take(MAX_LINE_LEN as u64).read_until(b'\n', line).await?;
+    if read < MAX_LINE_LEN || line.ends_with(b"\n") {
+        return Ok(read);
+    }
+
+    let mut dropped = 0;
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            break;
+        }
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                dropped += end;
+                break;
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+                dropped += len;
+            }
+        }
+    }
+
+    log::warn!("truncated log line of {} bytes to {}", read + dropped, MAX_LINE_LEN);
+    Ok(read)
+}
+
+async fn write_lines(mut out: Box<dyn AsyncWrite + Send + Unpin>, mut queue: mpsc::Receiver<Vec<u8>>) -> Result<()> {
+    while let Some(line) = queue.recv().await {
+        out.write_all(&line).await.context("failed to write log line")?;
+
+        // Flush once the burst is written, not per line
+        if queue.is_empty() {
+            out.flush().await.context("failed to flush log output")?;
+        }
+    }
+
+    out.flush().await.context("failed to flush log output")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn received(input: &[u8]) -> Vec<Vec<u8>> {
+        let (lines, mut queue) = mpsc::channel(LINE_QUEUE_SIZE);
+        read_lines(input, lines, CancellationToken::new()).await.unwrap();
+
+        let mut received = Vec::new();
+        while let Some(line) = queue.recv().await {
+            received.push(line);
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn lines_are_forwarded_newline_terminated() {
+        assert_eq!(received(b"first\nsecond").await, vec![b"first\n".to_vec(), b"second\n".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn long_lines_are_truncated() {
+        let mut input = vec![b'a'; MAX_LINE_LEN];
+        input.extend_from_slice(b"dropped\nnext\n");
+        input.extend(vec![b'b'; 3 * MAX_LINE_LEN]);
+
+        let received = received(&input).await;
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[0], [vec![b'a'; MAX_LINE_LEN], b"\n".to_vec()].concat());
+        assert_eq!(received[1], b"next\n");
+        assert_eq!(received[2], [vec![b'b'; MAX_LINE_LEN], b"\n".to_vec()].concat());
+    }
+}
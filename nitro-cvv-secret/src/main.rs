@@ -1,10 +1,13 @@
 
 use anyhow::{Result, Context};
 use anyhow::anyhow;
-use std::env;
+use clap::Parser;
+use std::num::NonZeroUsize;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::signal;
+use tracing::Instrument;
 use tokio_util::sync::CancellationToken;
 use tokio_vsock::{
     VMADDR_CID_ANY,
@@ -13,38 +16,67 @@ use tokio_vsock::{
 };
 
 use aws_config::meta::region::RegionProviderChain;
-use aws_config::BehaviorVersion;
+use aws_config::{BehaviorVersion, Region};
 use aws_sdk_kms::Client as KmsClient;
-use aws_secretsmanager_caching::SecretsManagerCachingClient;
 
-use nitro;
+use nitro_config::{Backend, ReceiveLogsArgs, SecretArgs, SecretCommand, SecretConfig};
+use nitro_metrics::Metrics;
 
 mod aws;
+mod backend;
+#[cfg(feature = "local")]
+mod local;
+mod log_receiver;
 mod session;
 
-const DEFAULT_PORT: u32 = 3000;
-async fn run_server(listen_port: u32, aws_secret_client: Arc<SecretsManagerCachingClient>, aws_kms_client: KmsClient) -> Result<()> {
+use backend::{KeyEncryptor, SecretStore};
 
-    let shutdown_token = CancellationToken::new();
-    let server_token   = shutdown_token.clone();
+const DEFAULT_REGION: &str = "us-east-1";
 
+fn shutdown_on_ctrl_c(token: CancellationToken) {
     tokio::spawn(async move {
         match signal::ctrl_c().await {
             Ok(()) => {
-                println!("");
+                println!();
                 log::info!("shutdown signal received ...");
-                server_token.cancel();
+                token.cancel();
             }
             Err(e) => {
                 log::error!("failed to listen for shutdown signal: {}", e);
             }
-        }    
-    });    
+        }
+    });
+}
+
+async fn run_server(
+    config: &SecretConfig,
+    secret_store: Arc<dyn SecretStore>,
+    encryptor: Arc<dyn KeyEncryptor>,
+    metrics: Arc<Metrics>,
+) -> Result<()> {
+
+    let listen_port    = config.listen_port;
+    let max_frame_size = config.max_frame_size;
+    let write_timeout  = Duration::from_secs(config.write_timeout_secs);
+    let kms_key_id: Arc<str> = Arc::from(config.kms_key_id.as_str());
+
+    let shutdown_token = CancellationToken::new();
+    shutdown_on_ctrl_c(shutdown_token.clone());
+
+    if let Some(metrics_addr) = config.metrics_addr {
+        let metrics = Arc::clone(&metrics);
+        let token   = shutdown_token.clone();
+        tokio::spawn(async move {
+            if let Err(e) = nitro_metrics::serve_http(metrics_addr, metrics, None, token).await {
+                log::error!("metrics server: {:?}", e);
+            }
+        });
+    }
 
     let addr     = VsockAddr::new(VMADDR_CID_ANY, listen_port);
     let listener = VsockListener::bind(addr).context(format!("failed to bind to cid: ANY port: {}", listen_port))?;
 
-    log::info!("listening on cid: ANY port: {}", listen_port);
+    log::info!("listening on cid: ANY port: {} (max frame {} bytes)", listen_port, max_frame_size);
     loop {
         tokio::select! {
             accept_result = listener.accept() => {
@@ -52,22 +84,28 @@ async fn run_server(listen_port: u32, aws_secret_client: Arc<SecretsManagerCachi
                     Ok((client_stream, client_addr)) => {
                         log::info!("accept connection from {}", client_addr);
 
-                        let secret_client  = Arc::clone(&aws_secret_client);
-                        let kms_client     = aws_kms_client.clone();
-                        let handler_token  = shutdown_token.child_token();
+                        let handler_store     = Arc::clone(&secret_store);
+                        let handler_encryptor = Arc::clone(&encryptor);
+                        let handler_kms_key   = Arc::clone(&kms_key_id);
+                        let handler_metrics   = Arc::clone(&metrics);
+                        let handler_token     = shutdown_token.child_token();
 
                         tokio::spawn(async move {
                             if let Err(e) = session::handle_client(
                                 client_stream, 
-                                secret_client,
-                                kms_client,
+                                handler_store,
+                                handler_encryptor,
+                                handler_kms_key,
+                                handler_metrics,
+                                max_frame_size,
+                                write_timeout,
                                 handler_token).await {
                                 log::error!("error handling client from {}: {}", client_addr, e);
                             }
                             else {
                                 log::info!("client {} disconnected cleanly", client_addr);
                             }
-                        });
+                        }.instrument(tracing::info_span!("connection", peer = %client_addr)));
                     },
                     Err(e) => {
                         log::error!("error accepting client connection: {}", e);
@@ -85,49 +123,89 @@ async fn run_server(listen_port: u32, aws_secret_client: Arc<SecretsManagerCachi
     Ok(())
 }
 
+async fn create_backends(config: &SecretConfig) -> Result<(Arc<dyn SecretStore>, Arc<dyn KeyEncryptor>)> {
+    match config.backend {
+        Backend::Aws => {
+            let region_provider = match &config.region {
+                Some(region) => RegionProviderChain::first_try(Region::new(region.clone())),
+                None => RegionProviderChain::default_provider(),
+            }.or_else(DEFAULT_REGION);
+            let sdk_config = aws_config::defaults(BehaviorVersion::latest())
+                .region(region_provider)
+                .load()
+                .await;
+
+            let cache_size = NonZeroUsize::new(config.secret_cache.size)
+                .context("secret_cache.size must be greater than 0")?;
+            let cache_ttl  = Duration::from_secs(config.secret_cache.ttl_secs);
+
+            let secret_client = aws::create_secret_client(&sdk_config, cache_size, cache_ttl).await?;
+            let kms_client    = KmsClient::new(&sdk_config);
+            log::info!("AWS clients initialized");
+
+            log::info!("Validating AWS credentials...");
+            match aws::validate_credentials(&sdk_config).await {
+                Ok(()) => log::info!("AWS credentials validated successfully"),
+                Err(e) => {
+                    log::error!("AWS credential validation failed: {}", e);
+                    return Err(e);
+                }
+            }
+
+            Ok((Arc::new(secret_client), Arc::new(kms_client)))
+        }
+        #[cfg(feature = "local")]
+        Backend::Local => {
+            log::warn!("using local secrets file and KMS emulator, not for production");
+            local::create_backends(&config.local)
+        }
+        #[cfg(not(feature = "local"))]
+        Backend::Local => {
+            Err(anyhow!("local backend requested but built without the `local` feature"))
+        }
+    }
+}
+
+/// Runs the `receive-logs` subcommand; the key server configuration is not loaded.
+async fn receive_logs(args: &SecretArgs, receive: &ReceiveLogsArgs) -> Result<()> {
+    let logging = nitro::LoggingOptions {
+        level: args.common.log_level.unwrap_or(nitro_config::DEFAULT_LOG_LEVEL),
+        format: args.common.log_format.unwrap_or_default(),
+        shipping: None,
+    };
+    let _logging = nitro::init_tracing(&logging).map_err(|e| anyhow!("Failed to initialize logging: {}", e))?;
+
+    let shutdown_token = CancellationToken::new();
+    shutdown_on_ctrl_c(shutdown_token.clone());
+
+    log_receiver::run(receive.port, receive.output.as_deref(), shutdown_token).await
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
 
-    nitro::init_logging().map_err(|e| anyhow!("Failed to initialize logging: {}", e))?;
-
-    log::info!("starting ...");
+    let args = SecretArgs::parse();
 
-    let region_provider = RegionProviderChain::default_provider().or_else("us-east-1");
-    let config = aws_config::defaults(BehaviorVersion::latest())
-        .region(region_provider)
-        .load()
-        .await;
+    if let Some(SecretCommand::ReceiveLogs(receive)) = &args.command {
+        return receive_logs(&args, receive).await;
+    }
 
-    let secret_client = Arc::new(aws::create_secret_client(&config).await?);
-    let kms_client    = KmsClient::new(&config);
-    log::info!("AWS clients initialized");
+    let config = nitro_config::load(&args)?;
 
-    log::info!("Validating AWS credentials...");
-    match aws::validate_credentials(&config).await {
-        Ok(()) => log::info!("AWS credentials validated successfully"),
-        Err(e) => {
-            log::error!("AWS credential validation failed: {}", e);
-            return Err(e);
-        }
+    if args.common.print_config {
+        print!("{}", nitro_config::to_toml(&config)?);
+        return Ok(());
     }
 
-    let listen_port = env::var("SECRET_PORT")
-        .ok()
-        .and_then(|v| {
-            v.parse::<u32>().map_err(|e| {
-                log::warn!(
-                    "failed to parse SECRET_PORT='{}': {}, using default port {}",
-                    v, e, DEFAULT_PORT
-                );
-                e
-            }).ok()
-        })
-        .unwrap_or_else(|| {
-            log::info!("SECRET_PORT not set, using default port {}", DEFAULT_PORT);
-            DEFAULT_PORT
-        });
+    let _logging = nitro::init_tracing(&config.logging()).map_err(|e| anyhow!("Failed to initialize logging: {}", e))?;
+
+    log::info!("starting ...");
+    log::debug!("configuration: {:?}", config);
+
+    let (secret_store, encryptor) = create_backends(&config).await?;
+    let metrics = Arc::new(Metrics::new("secret")?);
 
-    match run_server(listen_port, secret_client, kms_client).await {
+    match run_server(&config, secret_store, encryptor, metrics).await {
         Ok(()) => {
             log::info!("server exited gracefully");
             Ok(())
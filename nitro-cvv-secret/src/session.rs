@@ -1,156 +1,251 @@
 use anyhow::{Result};
 use std::sync::Arc;
+use std::time::Instant;
 
-use tokio_vsock::VsockStream;
+use tokio_vsock::{OwnedWriteHalf, VsockStream};
+use tokio::sync::{Semaphore, mpsc};
 use tokio::time::Duration;
 use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
-use aws_secretsmanager_caching::SecretsManagerCachingClient;
-use aws_sdk_kms::Client as KmsClient;
-
+use nitro_tokio::IoResult;
 use nitro_tokio::message_utils::{read_message, write_message};
 
+use nitro::logging;
 use nitro::utils;
 use nitro::message::{
     Message,
+    MessageHeader,
     GetKeyRequest,
     GetKeyResponse,
+    ResponseCode,
 };
 
-use crate::aws;
+use nitro_metrics::Metrics;
+
+use crate::aws::AwsError;
+use crate::backend::{KeyEncryptor, SecretStore};
 
+const MAX_IN_FLIGHT_REQUESTS: usize = 32;
+
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_client(
-    mut stream: VsockStream,
-    secret_client: Arc<SecretsManagerCachingClient>,
-    kms_client: KmsClient,
+    stream: VsockStream,
+    secret_store: Arc<dyn SecretStore>,
+    encryptor: Arc<dyn KeyEncryptor>,
+    kms_key_id: Arc<str>,
+    metrics: Arc<Metrics>,
+    max_frame_size: usize,
+    write_timeout: Duration,
     shutdown_token: CancellationToken,
 ) -> Result<()> {
     log::info!("Client connected, started");
-    
+    let _connection = metrics.connection_opened();
+
+    let (mut reader, writer) = stream.into_split();
+
+    // Responses are written in completion order; clients match them by hdr
+    let (responses, outbound) = mpsc::channel(MAX_IN_FLIGHT_REQUESTS);
+    let writer_token = shutdown_token.child_token();
+    let writer_task  = tokio::spawn(write_responses(writer, outbound, write_timeout, writer_token.clone()).in_current_span());
+
+    let in_flight = Arc::new(Semaphore::new(MAX_IN_FLIGHT_REQUESTS));
+
     loop {
-        let message_bytes: Vec<u8> = match read_message(&mut stream, None, &shutdown_token).await {
-            Ok(bytes) => bytes,
+        let message_bytes: Vec<u8> = match read_message(&mut reader, max_frame_size, None, &writer_token).await {
+            Ok(IoResult::Success(bytes)) => bytes,
+            Ok(IoResult::Closed) => {
+                log::info!("Connection closed by peer");
+                break;
+            }
+            Ok(IoResult::Timeout) => {
+                log::debug!("Idle timeout waiting for request");
+                continue;
+            }
             Err(e)  => {
                 log::error!("read message error: {}", e);
                 break;
             }
         };
 
-        if message_bytes.is_empty() {
-            log::info!("Connection closed by peer or timeout");
-            break;
-        }
+        let span = logging::request_span(&message_bytes);
+        let started = Instant::now();
+        metrics.request_received(&message_bytes);
 
-        let dump = utils::hexdump_string(&message_bytes);
-        log::info!("recieve message {} bytes\n\n{}", message_bytes.len(), dump);
+        let request = span.in_scope(|| {
+            let dump = utils::hexdump_frame(&message_bytes);
+            log::info!("recieve message {} bytes\n\n{}", message_bytes.len(), dump);
 
-        let message = match Message::parse(&message_bytes) {
-            Ok(msg) => msg,
-            Err(e) => {
-                log::error!("Failed to parse message: {}", e);
+            match Message::parse(&message_bytes) {
+                Ok(Message::GetKeyRequest(request)) => Ok(request),
+                Ok(_) => {
+                    log::warn!("Server received unsupported request");
+                    Err(error_response(&message_bytes))
+                }
+                Err(e) => {
+                    log::error!("Failed to parse message: {}", e);
+                    Err(error_response(&message_bytes))
+                }
+            }
+        });
+
+        let request = match request {
+            Ok(request) => request,
+            Err(response) => {
+                logging::finish_request(&span, response.as_deref(), started);
+                metrics.request_completed(&message_bytes, response.as_deref(), started.elapsed());
+
+                if let Some(response) = response
+                    && responses.send(response).await.is_err() {
+                    log::warn!("Connection closed before response could be sent");
+                    break;
+                }
                 continue;
             }
         };
 
-        match message {
-            Message::GetKeyRequest(request) => {
-                log::info!("Key request for: {}", request.key_id_str());
-
-                let response = process_key_request(&request, &secret_client, &kms_client).await;
-
-                let timeout  = Some(Duration::from_secs(60));
-                let written = write_message(
-                    &mut stream,
-                    &response.to_bytes(),
-                    timeout,
-                    &shutdown_token
-                ).await?;
-                
-                if written == 0 {
-                    log::error!("Failed to send request (connection closed or timeout)");
-                }
-                else {
-                    let dump = utils::hexdump_string(&response.to_bytes());
-                    log::info!("sent message {} bytes\n\n{}", written, dump);
-                }
-            }
-            _ => {
-                log::warn!("Server received unsupport request, ignoring");
+        // Stop reading once the limit is reached until a request completes
+        let permit = Arc::clone(&in_flight).acquire_owned().await?;
+
+        let secret_store = Arc::clone(&secret_store);
+        let encryptor    = Arc::clone(&encryptor);
+        let kms_key_id   = Arc::clone(&kms_key_id);
+        let metrics      = Arc::clone(&metrics);
+        let responses    = responses.clone();
+
+        tokio::spawn(async move {
+            log::info!("Key request for: {} (hdr {})",
+                request.key_id_str(), String::from_utf8_lossy(&request.header.hdr));
+
+            let response = process_key_request(&request, secret_store.as_ref(), encryptor.as_ref(), &kms_key_id, &metrics).await
+                .to_bytes();
+            logging::finish_request(&tracing::Span::current(), Some(&response), started);
+            metrics.request_completed(&message_bytes, Some(&response), started.elapsed());
+
+            if responses.send(response).await.is_err() {
+                log::warn!("Connection closed before response could be sent");
             }
-        }        
+            drop(permit);
+        }.instrument(span));
     }
 
+    // The writer drains outstanding responses once every request task is done
+    drop(responses);
+    writer_task.await?;
+
     log::info!("Connection closed");
     Ok(())
 }
 
+/// `SystemError` response for a request that cannot be served, so the client is
+/// not left waiting. Frames without a usable header, and responses, get none.
+fn error_response(message_bytes: &[u8]) -> Option<Vec<u8>> {
+    let header = MessageHeader::parse(message_bytes).ok()?;
+
+    match Message::error_response(&header, ResponseCode::SystemError) {
+        Some(response) => Some(response.to_bytes()),
+        None => {
+            log::warn!("No response for command {}, ignoring", String::from_utf8_lossy(&header.cmd));
+            None
+        }
+    }
+}
+
+async fn write_responses(
+    mut writer: OwnedWriteHalf,
+    mut outbound: mpsc::Receiver<Vec<u8>>,
+    write_timeout: Duration,
+    token: CancellationToken,
+) {
+    let timeout = Some(write_timeout);
+
+    while let Some(response) = outbound.recv().await {
+        match write_message(&mut writer, &response, timeout, &token).await {
+            Ok(IoResult::Success(written)) => {
+                let dump = utils::hexdump_frame(&response);
+                log::info!("sent message {} bytes\n\n{}", written, dump);
+                continue;
+            }
+            Ok(IoResult::Closed) => log::error!("Failed to send response, connection closed by peer"),
+            Ok(IoResult::Timeout) => log::error!("Failed to send response, write timed out"),
+            Err(e) => log::error!("write message error: {}", e),
+        }
+        break;
+    }
+
+    // Stop the reader as well; pending responses have nowhere to go
+    token.cancel();
+}
+
 async fn process_key_request(
     request: &GetKeyRequest,
-    secrets_client: &SecretsManagerCachingClient,
-    kms_client: &KmsClient,
+    secret_store: &dyn SecretStore,
+    encryptor: &dyn KeyEncryptor,
+    kms_key_id: &str,
+    metrics: &Metrics,
 ) -> GetKeyResponse {
 
     let key_id = request.key_id_str();
     let hdr    = request.header.hdr;
 
-    let kms_key_id = match std::env::var("KMS_KEY_ID") {
-        Ok(id) if !id.is_empty() => id,
-        _ => {
-            log::warn!("KMS_KEY_ID not defined in environment use default nitro-kms-key");
-            "alias/nitro-kms-key".to_string()
-        }
-    };
+    let started = Instant::now();
+    let secret = secret_store.get_secret(&key_id).await;
+    metrics.upstream_call("secrets_manager_get_secret", outcome(&secret), started.elapsed());
 
-    let secret = match aws::fetch_secret(secrets_client, &key_id).await {
+    let secret = match secret {
         Ok(s) => s,
-        Err(e) => {
-            let error_msg = e.to_string();
-            log::error!("Failed to fetch secret '{}': {}", key_id, error_msg);
-
-            if error_msg.contains("ResourceNotFoundException") 
-                || error_msg.contains("not found") {
-                log::warn!("Secret '{}' not found", key_id);
-                return GetKeyResponse::error(hdr, *b"01");
-            } else if error_msg.contains("AccessDenied") 
-                || error_msg.contains("not authorized") {
-                log::warn!("Access denied to secret '{}'", key_id);
-                return GetKeyResponse::error(hdr, *b"97");
-            } else {
-                log::error!("Unknown error fetching secret: {}", error_msg);
-                return GetKeyResponse::error(hdr, *b"99");
-            }
+        Err(AwsError::NotFound(msg)) => {
+            log::warn!("Secret '{}' not found: {}", key_id, msg);
+            return GetKeyResponse::error(hdr, ResponseCode::SecretNotFound);
+        }
+        Err(AwsError::AccessDenied(msg)) => {
+            log::warn!("Access denied to secret '{}': {}", key_id, msg);
+            return GetKeyResponse::error(hdr, ResponseCode::SecretAccessDenied);
+        }
+        Err(AwsError::Other(msg)) => {
+            log::error!("Unknown error fetching secret '{}': {}", key_id, msg);
+            return GetKeyResponse::error(hdr, ResponseCode::SystemError);
         }
     };
     
     log::debug!("Secret fetched successfully ({} bytes)", secret.len());
 
-    let encrypted = match aws::encrypt_with_kms(
-        kms_client,
-        &kms_key_id,
-        secret.as_bytes()
-    ).await {
+    let started = Instant::now();
+    let encrypted = encryptor.encrypt(kms_key_id, secret.as_bytes()).await;
+    metrics.upstream_call("kms_encrypt", outcome(&encrypted), started.elapsed());
+
+    let encrypted = match encrypted {
         Ok(e) => e,
-        Err(e) => {
-            let error_msg = e.to_string();
-            log::error!("Failed to encrypt with KMS: {}", error_msg);
-            
-            // Determine specific error code
-            if error_msg.contains("AccessDenied") 
-                || error_msg.contains("not authorized") {
-                log::warn!("Access denied to KMS key '{}'", kms_key_id);
-                return GetKeyResponse::error(hdr, *b"02");
-            } else if error_msg.contains("NotFoundException")
-                || error_msg.contains("not found") {
-                log::warn!("KMS key '{}' not found", kms_key_id);
-                return GetKeyResponse::error(hdr, *b"03");
-            } else {
-                log::error!("Encryption failed: {}", error_msg);
-                return GetKeyResponse::error(hdr, *b"04");
-            }
+        Err(AwsError::AccessDenied(msg)) => {
+            log::warn!("Access denied to KMS key '{}': {}", kms_key_id, msg);
+            return GetKeyResponse::error(hdr, ResponseCode::KmsAccessDenied);
+        }
+        Err(AwsError::NotFound(msg)) => {
+            log::warn!("KMS key '{}' not found: {}", kms_key_id, msg);
+            return GetKeyResponse::error(hdr, ResponseCode::KmsKeyNotFound);
+        }
+        Err(AwsError::Other(msg)) => {
+            log::error!("Encryption failed: {}", msg);
+            return GetKeyResponse::error(hdr, ResponseCode::EncryptionFailed);
         }
     };
     
     log::info!("Successfully encrypted key '{}' ({} bytes)", key_id, encrypted.len());
 
-    GetKeyResponse::success(hdr, encrypted)
-}
\ No newline at end of file
+    match GetKeyResponse::success(hdr, encrypted) {
+        Ok(response) => response,
+        Err(e) => {
+            log::error!("Failed to build response for key '{}': {}", key_id, e);
+            GetKeyResponse::error(hdr, ResponseCode::SystemError)
+        }
+    }
+}
+
+fn outcome<T>(result: &Result<T, AwsError>) -> &'static str {
+    match result {
+        Ok(_) => "ok",
+        Err(AwsError::NotFound(_)) => "not_found",
+        Err(AwsError::AccessDenied(_)) => "access_denied",
+        Err(AwsError::Other(_)) => "error",
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::{Result, anyhow};
+
+use aes::Aes256;
+use cbc::cipher::{BlockEncryptMut, KeyIvInit};
+use cbc::cipher::block_padding::Pkcs7;
+use rand_core::{OsRng, RngCore};
+use rsa::{Oaep, RsaPublicKey};
+use rsa::pkcs8::DecodePublicKey;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+const OID_DATA: &[u8]           = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x01];
+const OID_ENVELOPED_DATA: &[u8] = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x07, 0x03];
+const OID_RSAES_OAEP: &[u8]     = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x07];
+const OID_MGF1: &[u8]           = &[0x2A, 0x86, 0x48, 0x86, 0xF7, 0x0D, 0x01, 0x01, 0x08];
+const OID_SHA256: &[u8]         = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02, 0x01];
+const OID_AES_256_CBC: &[u8]    = &[0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x01, 0x2A];
+
+const TAG_INTEGER: u8      = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_OID: u8          = 0x06;
+const TAG_SEQUENCE: u8     = 0x30;
+const TAG_SET: u8          = 0x31;
+const TAG_IMPLICIT_0: u8   = 0x80;
+const TAG_CONTEXT_0: u8    = 0xA0;
+const TAG_CONTEXT_1: u8    = 0xA1;
+
+const SUBJECT_KEY_IDENTIFIER_SIZE: usize = 20;
+
+/// Wraps `plaintext` for `recipient_public_key` (DER SubjectPublicKeyInfo) the way KMS
+/// builds `CiphertextForRecipient`: CMS EnvelopedData, RSAES-OAEP-SHA256 key transport
+/// and AES-256-CBC content encryption.
+pub fn seal(recipient_public_key: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let public_key = RsaPublicKey::from_public_key_der(recipient_public_key)
+        .map_err(|e| anyhow!("Recipient public key is not an RSA key: {}", e))?;
+
+    let mut content_key = Zeroizing::new([0u8; 32]);
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(content_key.as_mut_slice());
+    OsRng.fill_bytes(&mut iv);
+
+    let encrypted_content = cbc::Encryptor::<Aes256>::new_from_slices(content_key.as_slice(), &iv)
+        .map_err(|_| anyhow!("Invalid content encryption key"))?
+        .encrypt_padded_vec_mut::<Pkcs7>(plaintext);
+
+    let encrypted_key = public_key
+        .encrypt(&mut OsRng, Oaep::new::<Sha256>(), content_key.as_slice())
+        .map_err(|e| anyhow!("Failed to wrap content key: {}", e))?;
+
+    let key_identifier = &Sha256::digest(recipient_public_key)[..SUBJECT_KEY_IDENTIFIER_SIZE];
+
+    let sha256 = tlv(TAG_SEQUENCE, &tlv(TAG_OID, OID_SHA256));
+    let oaep_params = tlv(TAG_SEQUENCE, &[
+        tlv(TAG_CONTEXT_0, &sha256),
+        tlv(TAG_CONTEXT_1, &tlv(TAG_SEQUENCE, &[tlv(TAG_OID, OID_MGF1), sha256.clone()].concat())),
+    ].concat());
+
+    let recipient_info = tlv(TAG_SEQUENCE, &[
+        tlv(TAG_INTEGER, &[2]),
+        tlv(TAG_IMPLICIT_0, key_identifier),
+        tlv(TAG_SEQUENCE, &[tlv(TAG_OID, OID_RSAES_OAEP), oaep_params].concat()),
+        tlv(TAG_OCTET_STRING, &encrypted_key),
+    ].concat());
+
+    let encrypted_content_info = tlv(TAG_SEQUENCE, &[
+        tlv(TAG_OID, OID_DATA),
+        tlv(TAG_SEQUENCE, &[tlv(TAG_OID, OID_AES_256_CBC), tlv(TAG_OCTET_STRING, &iv)].concat()),
+        tlv(TAG_IMPLICIT_0, &encrypted_content),
+    ].concat());
+
+    let enveloped_data = tlv(TAG_SEQUENCE, &[
+        tlv(TAG_INTEGER, &[2]),
+        tlv(TAG_SET, &recipient_info),
+        encrypted_content_info,
+    ].concat());
+
+    Ok(tlv(TAG_SEQUENCE, &[
+        tlv(TAG_OID, OID_ENVELOPED_DATA),
+        tlv(TAG_CONTEXT_0, &enveloped_data),
+    ].concat()))
+}
+
+/// DER encodes one element with a definite length.
+fn tlv(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(content.len() + 6);
+    result.push(tag);
+
+    match content.len() {
+        len if len < 0x80 => result.push(len as u8),
+        len => {
+            let bytes = (len as u32).to_be_bytes();
+            let skip  = bytes.iter().take_while(|b| **b == 0).count();
+            result.push(0x80 | (bytes.len() - skip) as u8);
+            result.extend_from_slice(&bytes[skip..]);
+        }
+    }
+
+    result.extend_from_slice(content);
+    result
+}
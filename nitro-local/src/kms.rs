@@ -0,0 +1,145 @@
+use anyhow::{Result, Context, anyhow, bail};
+
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::aead::{Aead, AeadCore, Payload};
+use hmac::{Hmac, Mac};
+use rand_core::OsRng;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+use crate::envelope;
+use crate::nsm;
+
+const BLOB_MAGIC: &[u8; 4] = b"LKMS";
+const BLOB_VERSION: u8 = 1;
+const NONCE_SIZE: usize = 12;
+const KEY_DERIVATION_LABEL: &[u8] = b"nitro-local-kms";
+
+/// Master key used when none is configured. Only suitable for local development.
+const DEVELOPMENT_MASTER_KEY: [u8; 32] = *b"nitro-local-kms-development-key!";
+
+/// In-process replacement for the KMS `Encrypt` and `Decrypt` calls.
+///
+/// Ciphertext blobs are AES-256-GCM under a key derived from the master key
+/// and the KMS key id, so the secret server and the enclave only need to share
+/// the master key. `decrypt_for_recipient` accepts the blob only together with
+/// an attestation document signed by the trusted mock NSM certificate, and
+/// returns the plaintext wrapped to the document's public key.
+pub struct LocalKms {
+    master_key: Zeroizing<[u8; 32]>,
+    trusted_certificate: Option<Vec<u8>>,
+}
+
+impl LocalKms {
+    pub fn new(master_key: [u8; 32]) -> Self {
+        Self {
+            master_key: Zeroizing::new(master_key),
+            trusted_certificate: None,
+        }
+    }
+
+    pub fn development() -> Self {
+        Self::new(DEVELOPMENT_MASTER_KEY)
+    }
+
+    pub fn from_hex(master_key_hex: &str) -> Result<Self> {
+        let bytes = Zeroizing::new(hex::decode(master_key_hex.trim()).context("Master key is not valid hex")?);
+        let master_key: [u8; 32] = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| anyhow!("Master key must be 32 bytes, got {}", bytes.len()))?;
+        Ok(Self::new(master_key))
+    }
+
+    /// Pins the certificate that recipient attestation documents must be signed by.
+    pub fn with_trusted_certificate(mut self, certificate: Vec<u8>) -> Self {
+        self.trusted_certificate = Some(certificate);
+        self
+    }
+
+    pub fn encrypt(&self, key_id: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+        if key_id.is_empty() || key_id.len() > u8::MAX as usize {
+            bail!("Invalid key id length: {}", key_id.len());
+        }
+
+        let mut blob = Vec::with_capacity(BLOB_MAGIC.len() + 2 + key_id.len() + NONCE_SIZE + plaintext.len() + 16);
+        blob.extend_from_slice(BLOB_MAGIC);
+        blob.push(BLOB_VERSION);
+        blob.push(key_id.len() as u8);
+        blob.extend_from_slice(key_id.as_bytes());
+
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self.cipher(key_id)?
+            .encrypt(&nonce, Payload { msg: plaintext, aad: &blob })
+            .map_err(|_| anyhow!("Encryption failed"))?;
+
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&ciphertext);
+
+        log::debug!("Local KMS encrypted {} bytes with key '{}'", plaintext.len(), key_id);
+        Ok(blob)
+    }
+
+    pub fn decrypt(&self, ciphertext_blob: &[u8]) -> Result<Vec<u8>> {
+        let (key_id, header_length) = parse_header(ciphertext_blob)?;
+        let (header, rest) = ciphertext_blob.split_at(header_length);
+
+        if rest.len() < NONCE_SIZE {
+            bail!("Ciphertext blob truncated");
+        }
+        let (nonce, ciphertext) = rest.split_at(NONCE_SIZE);
+
+        let plaintext = self.cipher(key_id)?
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+            .map_err(|_| anyhow!("Ciphertext blob failed authentication for key '{}'", key_id))?;
+
+        log::debug!("Local KMS decrypted {} bytes with key '{}'", plaintext.len(), key_id);
+        Ok(plaintext)
+    }
+
+    /// Decrypt on behalf of an enclave, as KMS does for a request carrying `Recipient`.
+    /// Returns the `CiphertextForRecipient` CMS blob rather than the plaintext.
+    pub fn decrypt_for_recipient(&self, ciphertext_blob: &[u8], attestation_document: &[u8]) -> Result<Vec<u8>> {
+        let trusted_certificate = self.trusted_certificate
+            .as_deref()
+            .ok_or_else(|| anyhow!("No trusted NSM certificate configured"))?;
+
+        let document = nsm::verify_attestation_document(attestation_document, trusted_certificate)
+            .context("Recipient attestation rejected")?;
+
+        let public_key = document.public_key
+            .ok_or_else(|| anyhow!("Recipient attestation has no public key"))?;
+
+        let plaintext = Zeroizing::new(self.decrypt(ciphertext_blob)?);
+        envelope::seal(&public_key, &plaintext)
+    }
+
+    fn cipher(&self, key_id: &str) -> Result<Aes256Gcm> {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.master_key.as_slice())
+            .map_err(|e| anyhow!("Invalid master key: {}", e))?;
+        mac.update(KEY_DERIVATION_LABEL);
+        mac.update(key_id.as_bytes());
+
+        let data_key = Zeroizing::new(mac.finalize().into_bytes());
+        Aes256Gcm::new_from_slice(&data_key).map_err(|e| anyhow!("Invalid data key: {}", e))
+    }
+}
+
+fn parse_header(blob: &[u8]) -> Result<(&str, usize)> {
+    const FIXED: usize = BLOB_MAGIC.len() + 2;
+
+    if blob.len() < FIXED || &blob[..BLOB_MAGIC.len()] != BLOB_MAGIC {
+        bail!("Not a local KMS ciphertext blob");
+    }
+    if blob[BLOB_MAGIC.len()] != BLOB_VERSION {
+        bail!("Unsupported ciphertext blob version {}", blob[BLOB_MAGIC.len()]);
+    }
+
+    let key_id_length = blob[BLOB_MAGIC.len() + 1] as usize;
+    let key_id = blob
+        .get(FIXED..FIXED + key_id_length)
+        .ok_or_else(|| anyhow!("Ciphertext blob truncated"))?;
+    let key_id = std::str::from_utf8(key_id).context("Key id is not valid UTF-8")?;
+
+    Ok((key_id, FIXED + key_id_length))
+}
@@ -0,0 +1,6 @@
+pub mod envelope;
+pub mod kms;
+pub mod nsm;
+
+pub use kms::LocalKms;
+pub use nsm::{MockNsm, verify_attestation_document};
@@ -0,0 +1,190 @@
+use anyhow::{Result, Context, anyhow, bail};
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use aws_nitro_enclaves_nsm_api::api::{AttestationDoc, Digest};
+
+use p384::ecdsa::{DerSignature, Signature, SigningKey, VerifyingKey};
+use p384::ecdsa::signature::{Signer, Verifier};
+use rand_core::OsRng;
+use serde_cbor::Value;
+
+use x509_cert::Certificate;
+use x509_cert::builder::{Builder, CertificateBuilder, Profile};
+use x509_cert::der::{Decode, Encode};
+use x509_cert::name::Name;
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::spki::SubjectPublicKeyInfoOwned;
+use x509_cert::time::Validity;
+
+const MODULE_ID: &str = "i-mock-enclave-nsm";
+const CERTIFICATE_SUBJECT: &str = "CN=mock-nsm,O=nitro-local";
+const CERTIFICATE_VALIDITY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// COSE algorithm identifier for ECDSA P-384 with SHA-384, as used by the real NSM
+const COSE_ALG_ES384: i128 = -35;
+const COSE_HEADER_ALG: i128 = 1;
+const COSE_SIGN1_TAG: u64 = 18;
+
+const PCR_COUNT: usize = 16;
+const PCR_LENGTH: usize = 48;
+
+/// Stand-in for the Nitro Secure Module.
+///
+/// Documents have the same COSE_Sign1 / CBOR layout as the real NSM, but are
+/// signed by a P-384 key generated at startup and a matching self-signed
+/// certificate instead of the AWS Nitro PKI. PCRs are all zero.
+pub struct MockNsm {
+    signing_key: SigningKey,
+    certificate: Vec<u8>,
+}
+
+impl MockNsm {
+    pub fn new() -> Result<Self> {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let certificate = self_signed_certificate(&signing_key)?;
+
+        log::debug!("Mock NSM created ({} byte certificate)", certificate.len());
+
+        Ok(Self { signing_key, certificate })
+    }
+
+    /// DER certificate that signs every document; pin it on the verifying side.
+    pub fn certificate(&self) -> &[u8] {
+        &self.certificate
+    }
+
+    pub fn attestation_document(
+        &self,
+        user_data: Option<Vec<u8>>,
+        nonce: Option<Vec<u8>>,
+        public_key: Option<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("System clock is before the Unix epoch")?
+            .as_millis() as u64;
+
+        let pcrs = (0..PCR_COUNT)
+            .map(|index| (index, vec![0u8; PCR_LENGTH]))
+            .collect::<BTreeMap<_, _>>();
+
+        let document = AttestationDoc::new(
+            MODULE_ID.to_string(),
+            Digest::SHA384,
+            timestamp,
+            pcrs,
+            self.certificate.clone(),
+            vec![self.certificate.clone()],
+            user_data,
+            nonce,
+            public_key,
+        );
+
+        let payload   = document.to_binary();
+        let protected = protected_header()?;
+
+        let signature: Signature = self.signing_key.sign(&sig_structure(&protected, &payload)?);
+
+        let cose_sign1 = Value::Array(vec![
+            Value::Bytes(protected),
+            Value::Map(BTreeMap::new()),
+            Value::Bytes(payload),
+            Value::Bytes(signature.to_bytes().to_vec()),
+        ]);
+
+        serde_cbor::to_vec(&cose_sign1).context("Failed to encode COSE_Sign1")
+    }
+}
+
+/// Checks the COSE_Sign1 signature against `trusted_certificate` and returns the decoded document.
+pub fn verify_attestation_document(document: &[u8], trusted_certificate: &[u8]) -> Result<AttestationDoc> {
+    let value: Value = serde_cbor::from_slice(document).context("Attestation document is not CBOR")?;
+
+    let value = match value {
+        Value::Tag(COSE_SIGN1_TAG, inner) => *inner,
+        value => value,
+    };
+
+    let (protected, payload, signature) = match value {
+        Value::Array(items) => match <[Value; 4]>::try_from(items) {
+            Ok([Value::Bytes(protected), Value::Map(_), Value::Bytes(payload), Value::Bytes(signature)]) => {
+                (protected, payload, signature)
+            }
+            _ => bail!("Attestation document is not a COSE_Sign1 structure"),
+        },
+        _ => bail!("Attestation document is not a COSE_Sign1 structure"),
+    };
+
+    if protected != protected_header()? {
+        bail!("Unsupported COSE protected header");
+    }
+
+    let certificate = Certificate::from_der(trusted_certificate)
+        .map_err(|e| anyhow!("Invalid trusted certificate: {}", e))?;
+    let public_key = certificate
+        .tbs_certificate
+        .subject_public_key_info
+        .subject_public_key
+        .raw_bytes();
+
+    let verifying_key = VerifyingKey::from_sec1_bytes(public_key)
+        .map_err(|e| anyhow!("Trusted certificate has no P-384 key: {}", e))?;
+    let signature = Signature::from_slice(&signature)
+        .map_err(|e| anyhow!("Invalid attestation signature: {}", e))?;
+
+    verifying_key
+        .verify(&sig_structure(&protected, &payload)?, &signature)
+        .map_err(|_| anyhow!("Attestation signature does not verify"))?;
+
+    let document = AttestationDoc::from_binary(&payload)
+        .map_err(|e| anyhow!("Invalid attestation payload: {:?}", e))?;
+
+    if document.certificate.as_slice() != trusted_certificate {
+        bail!("Attestation document was issued by an untrusted certificate");
+    }
+
+    Ok(document)
+}
+
+fn protected_header() -> Result<Vec<u8>> {
+    let mut header = BTreeMap::new();
+    header.insert(Value::Integer(COSE_HEADER_ALG), Value::Integer(COSE_ALG_ES384));
+    serde_cbor::to_vec(&Value::Map(header)).context("Failed to encode COSE header")
+}
+
+fn sig_structure(protected: &[u8], payload: &[u8]) -> Result<Vec<u8>> {
+    let structure = Value::Array(vec![
+        Value::Text("Signature1".to_string()),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(Vec::new()),
+        Value::Bytes(payload.to_vec()),
+    ]);
+    serde_cbor::to_vec(&structure).context("Failed to encode COSE Sig_structure")
+}
+
+fn self_signed_certificate(signing_key: &SigningKey) -> Result<Vec<u8>> {
+    let subject  = Name::from_str(CERTIFICATE_SUBJECT).map_err(|e| anyhow!("Invalid subject: {}", e))?;
+    let validity = Validity::from_now(CERTIFICATE_VALIDITY).map_err(|e| anyhow!("Invalid validity: {}", e))?;
+
+    let public_key = SubjectPublicKeyInfoOwned::from_key(*signing_key.verifying_key())
+        .map_err(|e| anyhow!("Failed to encode public key: {}", e))?;
+
+    let builder = CertificateBuilder::new(
+        Profile::Root,
+        SerialNumber::from(1u32),
+        validity,
+        subject,
+        public_key,
+        signing_key,
+    ).map_err(|e| anyhow!("Failed to prepare certificate: {}", e))?;
+
+    let certificate = builder
+        .build::<DerSignature>()
+        .map_err(|e| anyhow!("Failed to sign certificate: {}", e))?;
+
+    certificate.to_der().map_err(|e| anyhow!("Failed to encode certificate: {}", e))
+}
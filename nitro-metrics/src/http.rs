@@ -0,0 +1,192 @@
+use anyhow::{Result, Context, bail};
+use prometheus::IntGauge;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+use tokio_vsock::VsockAddr;
+
+use crate::Metrics;
+use crate::vsock::scrape_vsock;
+
+/// Request heads are tiny; anything larger is not a scrape.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Appends another process's metrics, scraped over vsock, to each response.
+pub struct Relay {
+    addr: VsockAddr,
+    timeout: Duration,
+    up: IntGauge,
+}
+
+impl Relay {
+    /// Registers `enclave_metrics_up` on `metrics`, set to 0 when a scrape fails.
+    pub fn new(metrics: &Metrics, addr: VsockAddr, timeout: Duration) -> Result<Self> {
+        let up = IntGauge::new("enclave_metrics_up", "Whether the last scrape of the enclave's metrics succeeded")?;
+        metrics.registry().register(Box::new(up.clone()))?;
+
+        Ok(Self { addr, timeout, up })
+    }
+
+    async fn scrape(&self) -> Option<String> {
+        match scrape_vsock(self.addr, self.timeout).await {
+            Ok(body) => {
+                self.up.set(1);
+                Some(body)
+            }
+            Err(e) => {
+                log::warn!("enclave metrics unavailable: {:#}", e);
+                self.up.set(0);
+                None
+            }
+        }
+    }
+}
+
+/// Serves `GET /metrics` on `addr` until `token` is cancelled.
+pub async fn serve_http(
+    addr: SocketAddr,
+    metrics: Arc<Metrics>,
+    relay: Option<Relay>,
+    token: CancellationToken,
+) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .context(format!("failed to bind metrics to {}", addr))?;
+    let relay = relay.map(Arc::new);
+
+    log::info!("serving metrics on http://{}/metrics", addr);
+    loop {
+        tokio::select! {
+            accept_result = listener.accept() => {
+                match accept_result {
+                    Ok((stream, peer)) => {
+                        let metrics = Arc::clone(&metrics);
+                        let relay   = relay.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = handle_request(stream, &metrics, relay.as_deref()).await {
+                                log::debug!("metrics request from {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        log::error!("error accepting metrics connection: {}", e);
+                    }
+                }
+            }
+            _ = token.cancelled() => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn handle_request(mut stream: TcpStream, metrics: &Metrics, relay: Option<&Relay>) -> Result<()> {
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+        .await
+        .context("Timeout reading request")??;
+
+    let request_line = head.lines().next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path   = parts.next().unwrap_or_default();
+    let path   = path.split('?').next().unwrap_or_default();
+
+    let response = match (method, path) {
+        ("GET", "/metrics") => {
+            // Scrape first so `enclave_metrics_up` reflects this request
+            let relayed = match relay {
+                Some(relay) => relay.scrape().await,
+                None => None,
+            };
+
+            let mut body = metrics.encode()?;
+            if let Some(relayed) = relayed {
+                body.push_str(&relayed);
+            }
+            response("200 OK", CONTENT_TYPE, &body)
+        }
+        ("GET", _) => response("404 Not Found", "text/plain", "not found\n"),
+        _ => response("405 Method Not Allowed", "text/plain", "method not allowed\n"),
+    };
+
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads up to the blank line ending the request head; the body, if any, is ignored.
+async fn read_head(stream: &mut TcpStream) -> Result<String> {
+    let mut head = Vec::with_capacity(512);
+    let mut buf  = [0u8; 1024];
+
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD {
+            bail!("Request head exceeds {} bytes", MAX_REQUEST_HEAD);
+        }
+
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+fn response(status: &str, content_type: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status, content_type, body.len(), body
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(metrics: &Metrics, relay: Option<&Relay>, request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+
+        client.write_all(request.as_bytes()).await.unwrap();
+        handle_request(server, metrics, relay).await.unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn failed_scrape_sets_enclave_metrics_down() {
+        let metrics = Metrics::new("gateway").unwrap();
+        // No enclave listens here, so every scrape fails
+        let relay = Relay::new(&metrics, VsockAddr::new(u32::MAX - 1, 9), Duration::from_millis(200)).unwrap();
+        relay.up.set(1);
+
+        let response = get(&metrics, Some(&relay), "GET /metrics HTTP/1.1\r\n\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("\nnitro_gateway_enclave_metrics_up 0\n"), "{}", response);
+        assert_eq!(relay.up.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn other_paths_and_methods_are_refused() {
+        let metrics = Metrics::new("gateway").unwrap();
+
+        let response = get(&metrics, None, "GET /metrics?x=1 HTTP/1.1\r\n\r\n").await;
+        assert!(response.contains("nitro_gateway_connections_total 0"), "{}", response);
+        assert!(!response.contains("enclave_metrics_up"));
+
+        assert!(get(&metrics, None, "GET / HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 404 "));
+        assert!(get(&metrics, None, "POST /metrics HTTP/1.1\r\n\r\n").await.starts_with("HTTP/1.1 405 "));
+    }
+}
@@ -0,0 +1,264 @@
+//! Prometheus metrics shared by the nitro binaries.
+//!
+//! Each binary owns one [`Metrics`] whose names are prefixed with
+//! `nitro_<service>_`, so the enclave's metrics can be relayed through a
+//! parent-side `/metrics` endpoint without clashing with the parent's own.
+
+use anyhow::{Result, Context};
+use prometheus::{
+    Encoder,
+    HistogramOpts,
+    HistogramVec,
+    IntCounter,
+    IntCounterVec,
+    IntGauge,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+
+use std::time::Duration;
+
+use nitro::message::{
+    MessageHeader,
+    ResponseCode,
+    CMD_DUKPTDECRYPT_REQUEST,
+    CMD_DUKPTTRANSLATEPIN_REQUEST,
+    CMD_GENERATECVV_REQUEST,
+    CMD_GENERATEOFFSET_REQUEST,
+    CMD_GETKEY_REQUEST,
+    CMD_TRANSLATEPIN_REQUEST,
+    CMD_VERIFYCVV_REQUEST,
+    CMD_VERIFYOFFSET_REQUEST,
+    CMD_VERIFYPVV_REQUEST,
+};
+
+pub mod http;
+pub mod vsock;
+
+pub use http::{Relay, serve_http};
+pub use vsock::{scrape_vsock, serve_vsock};
+
+/// Request commands reported by name; anything else is counted as `other`.
+const KNOWN_COMMANDS: [[u8; 2]; 9] = [
+    CMD_VERIFYCVV_REQUEST,
+    CMD_GENERATECVV_REQUEST,
+    CMD_VERIFYPVV_REQUEST,
+    CMD_GENERATEOFFSET_REQUEST,
+    CMD_VERIFYOFFSET_REQUEST,
+    CMD_TRANSLATEPIN_REQUEST,
+    CMD_DUKPTTRANSLATEPIN_REQUEST,
+    CMD_DUKPTDECRYPT_REQUEST,
+    CMD_GETKEY_REQUEST,
+];
+
+/// Seconds; covers cache hits through slow KMS round trips.
+const LATENCY_BUCKETS: [f64; 12] = [0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    responses: IntCounterVec,
+    request_duration: HistogramVec,
+    connections: IntCounter,
+    active_connections: IntGauge,
+    upstream_duration: HistogramVec,
+    cache_lookups: IntCounterVec,
+}
+
+impl Metrics {
+    /// `service` becomes part of every metric name, e.g. `nitro_host_requests_total`.
+    pub fn new(service: &str) -> Result<Self> {
+        let registry = Registry::new_custom(Some(format!("nitro_{}", service)), None)
+            .context("Failed to create metrics registry")?;
+
+        let requests = IntCounterVec::new(
+            Opts::new("requests_total", "Requests received, by command"),
+            &["cmd"],
+        )?;
+        let responses = IntCounterVec::new(
+            Opts::new("responses_total", "Requests completed, by command and response code (`none` when dropped)"),
+            &["cmd", "code"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new("request_duration_seconds", "Time from receiving a request to its response")
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["cmd"],
+        )?;
+        let connections = IntCounter::new("connections_total", "Connections accepted")?;
+        let active_connections = IntGauge::new("connections_active", "Connections currently open")?;
+        let upstream_duration = HistogramVec::new(
+            HistogramOpts::new("upstream_duration_seconds", "Latency of calls to KMS, Secrets Manager and the secret server")
+                .buckets(LATENCY_BUCKETS.to_vec()),
+            &["operation", "outcome"],
+        )?;
+        let cache_lookups = IntCounterVec::new(
+            Opts::new("cache_lookups_total", "Cache lookups, by cache and result (hit, miss)"),
+            &["cache", "result"],
+        )?;
+
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(responses.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(connections.clone()))?;
+        registry.register(Box::new(active_connections.clone()))?;
+        registry.register(Box::new(upstream_duration.clone()))?;
+        registry.register(Box::new(cache_lookups.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            responses,
+            request_duration,
+            connections,
+            active_connections,
+            upstream_duration,
+            cache_lookups,
+        })
+    }
+
+    /// Counts a request frame as soon as it is read.
+    pub fn request_received(&self, request: &[u8]) {
+        self.requests.with_label_values(&[&command_label(request)]).inc();
+    }
+
+    /// Records the outcome of a request; `response` is the encoded response frame.
+    pub fn request_completed(&self, request: &[u8], response: Option<&[u8]>, elapsed: Duration) {
+        let cmd = command_label(request);
+
+        self.responses.with_label_values(&[&cmd, &response_code_label(response)]).inc();
+        self.request_duration.with_label_values(&[&cmd]).observe(elapsed.as_secs_f64());
+    }
+
+    /// Counts an accepted connection; it stays active until the guard is dropped.
+    pub fn connection_opened(&self) -> ConnectionGuard {
+        self.connections.inc();
+        self.active_connections.inc();
+        ConnectionGuard { active: self.active_connections.clone() }
+    }
+
+    /// `outcome` is a short fixed label such as `ok`, `not_found` or `error`.
+    pub fn upstream_call(&self, operation: &str, outcome: &str, elapsed: Duration) {
+        self.upstream_duration.with_label_values(&[operation, outcome]).observe(elapsed.as_secs_f64());
+    }
+
+    pub fn cache_lookup(&self, cache: &str, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.cache_lookups.with_label_values(&[cache, result]).inc();
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .context("Failed to encode metrics")?;
+
+        String::from_utf8(buffer).context("Metrics are not valid UTF-8")
+    }
+
+    pub(crate) fn registry(&self) -> &Registry {
+        &self.registry
+    }
+}
+
+/// Marks a connection as closed when dropped.
+pub struct ConnectionGuard {
+    active: IntGauge,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.active.dec();
+    }
+}
+
+fn command_label(frame: &[u8]) -> String {
+    match MessageHeader::parse(frame) {
+        Ok(header) if KNOWN_COMMANDS.contains(&header.cmd) => header.cmd_str(),
+        Ok(_) => "other".to_string(),
+        Err(_) => "invalid".to_string(),
+    }
+}
+
+fn response_code_label(response: Option<&[u8]>) -> String {
+    let Some(frame) = response else {
+        return "none".to_string();
+    };
+
+    // Every response payload starts with the 2-byte response code
+    let code = MessageHeader::parse(frame).ok().and_then(|header| {
+        let start = header.header_length();
+        <[u8; 2]>::try_from(frame.get(start..start + 2)?).ok()
+    });
+
+    match code.and_then(|code| ResponseCode::from_bytes(&code)) {
+        Some(code) => String::from_utf8_lossy(&code.as_bytes()).into_owned(),
+        None => "other".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nitro::message::{VerifyCVVRequest, VerifyCVVResponse, VerifyPvvResponse};
+
+    fn request() -> Vec<u8> {
+        VerifyCVVRequest::new(*b"0001", "cvk-a", "cvk-b", "123", "4123456789012345", "8701", "101").unwrap().to_bytes()
+    }
+
+    #[test]
+    fn command_labels() {
+        assert_eq!(command_label(&request()), "CY");
+
+        let mut unknown = request();
+        unknown[6..8].copy_from_slice(b"XX");
+        assert_eq!(command_label(&unknown), "other");
+
+        // Responses are not request commands
+        assert_eq!(command_label(&VerifyCVVResponse::success(*b"0001").to_bytes()), "other");
+
+        assert_eq!(command_label(&[0x00, 0x03, b'0']), "invalid");
+        assert_eq!(command_label(&[]), "invalid");
+    }
+
+    #[test]
+    fn response_code_labels() {
+        assert_eq!(response_code_label(None), "none");
+        assert_eq!(response_code_label(Some(&VerifyCVVResponse::success(*b"0001").to_bytes())), "00");
+        assert_eq!(response_code_label(Some(&VerifyPvvResponse::error(*b"0001", ResponseCode::PinMismatch).to_bytes())), "06");
+
+        let mut unknown = VerifyCVVResponse::success(*b"0001").to_bytes();
+        let code_at = unknown.len() - 2;
+        unknown[code_at..].copy_from_slice(b"ZZ");
+        assert_eq!(response_code_label(Some(&unknown)), "other");
+
+        // Header only, no response code
+        assert_eq!(response_code_label(Some(&[0x00, 0x06, b'0', b'0', b'0', b'1', b'C', b'Z'])), "other");
+        assert_eq!(response_code_label(Some(&[0x00])), "other");
+    }
+
+    #[test]
+    fn encode_uses_service_prefix_and_labels() {
+        let metrics = Metrics::new("test").unwrap();
+        metrics.request_received(&request());
+        metrics.request_completed(&request(), Some(&VerifyCVVResponse::success(*b"0001").to_bytes()), Duration::from_millis(2));
+        metrics.request_completed(&request(), None, Duration::from_millis(2));
+        metrics.upstream_call("kms_decrypt", "ok", Duration::from_millis(20));
+        metrics.cache_lookup("key", true);
+        let guard = metrics.connection_opened();
+
+        let body = metrics.encode().unwrap();
+        assert!(body.contains("nitro_test_requests_total{cmd=\"CY\"} 1"), "{}", body);
+        assert!(body.contains("nitro_test_responses_total{cmd=\"CY\",code=\"00\"} 1"), "{}", body);
+        assert!(body.contains("nitro_test_responses_total{cmd=\"CY\",code=\"none\"} 1"), "{}", body);
+        assert!(body.contains("nitro_test_request_duration_seconds_count{cmd=\"CY\"} 2"), "{}", body);
+        assert!(body.contains("nitro_test_upstream_duration_seconds_count{operation=\"kms_decrypt\",outcome=\"ok\"} 1"), "{}", body);
+        assert!(body.contains("nitro_test_cache_lookups_total{cache=\"key\",result=\"hit\"} 1"), "{}", body);
+        assert!(body.contains("nitro_test_connections_total 1"), "{}", body);
+        assert!(body.contains("nitro_test_connections_active 1"), "{}", body);
+
+        drop(guard);
+        assert!(metrics.encode().unwrap().contains("nitro_test_connections_active 0"));
+    }
+}
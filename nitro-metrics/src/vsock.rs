@@ -0,0 +1,73 @@
+use anyhow::{Result, Context, bail};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_util::sync::CancellationToken;
+use tokio_vsock::{VMADDR_CID_ANY, VsockAddr, VsockListener, VsockStream};
+
+use crate::Metrics;
+
+/// Upper bound on a relayed exposition; the enclave's is a few KiB.
+const MAX_SCRAPE_SIZE: u64 = 1024 * 1024;
+
+/// Writes the current metrics to every connection on `port` and closes it.
+///
+/// Used inside the enclave, which has no network of its own; the parent
+/// scrapes it with [`scrape_vsock`].
+pub async fn serve_vsock(port: u32, metrics: Arc<Metrics>, token: CancellationToken) -> Result<()> {
+    let addr     = VsockAddr::new(VMADDR_CID_ANY, port);
+    let listener = VsockListener::bind(addr).context(format!("failed to bind metrics to cid: ANY port: {}", port))?;
+
+    log::info!("serving metrics on cid: ANY port: {}", port);
+    loop {
+        tokio::select! {
+            accept_result = listener.accept() => {
+                match accept_result {
+                    Ok((mut stream, peer)) => {
+                        let metrics = Arc::clone(&metrics);
+                        tokio::spawn(async move {
+                            if let Err(e) = write_metrics(&mut stream, &metrics).await {
+                                log::warn!("failed to send metrics to {}: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(e) => {
+                        log::error!("error accepting metrics connection: {}", e);
+                    }
+                }
+            }
+            _ = token.cancelled() => break,
+        }
+    }
+
+    Ok(())
+}
+
+async fn write_metrics(stream: &mut VsockStream, metrics: &Metrics) -> Result<()> {
+    let body = metrics.encode()?;
+    stream.write_all(body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Reads one exposition from a [`serve_vsock`] endpoint.
+pub async fn scrape_vsock(addr: VsockAddr, timeout: Duration) -> Result<String> {
+    let scrape = async {
+        let stream = VsockStream::connect(addr).await
+            .context(format!("Failed to connect to metrics on CID {} port {}", addr.cid(), addr.port()))?;
+
+        let mut body = Vec::new();
+        stream.take(MAX_SCRAPE_SIZE + 1).read_to_end(&mut body).await
+            .context("Failed to read metrics")?;
+
+        if body.len() as u64 > MAX_SCRAPE_SIZE {
+            bail!("Metrics exceed {} bytes", MAX_SCRAPE_SIZE);
+        }
+        String::from_utf8(body).context("Metrics are not valid UTF-8")
+    };
+
+    tokio::time::timeout(timeout, scrape)
+        .await
+        .context("Timeout scraping metrics")?
+}
@@ -0,0 +1,84 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use nitro::message::{
+    GetKeyResponse,
+    Message,
+    MessageRef,
+    VerifyCVVRequest,
+    VerifyCVVResponse,
+};
+
+fn verifycvv_frame() -> Vec<u8> {
+    VerifyCVVRequest::new(*b"0001", "test.cvka", "test.cvkb", "123", "4123456789012345", "8701", "101")
+        .expect("valid request")
+        .to_bytes()
+}
+
+fn getkey_response_frame() -> Vec<u8> {
+    GetKeyResponse::success(*b"0001", vec![0xA5; 512])
+        .expect("valid response")
+        .to_bytes()
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+
+    for (name, frame) in [("verifycvv", verifycvv_frame()), ("getkey_response", getkey_response_frame())] {
+        group.bench_function(format!("{}/owned", name), |b| {
+            b.iter(|| Message::parse(black_box(&frame)).unwrap())
+        });
+        group.bench_function(format!("{}/borrowed", name), |b| {
+            b.iter(|| MessageRef::parse(black_box(&frame)).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+
+    let response = VerifyCVVResponse::success(*b"0001");
+    group.bench_function("verifycvv_response/to_bytes", |b| {
+        b.iter(|| black_box(&response).to_bytes())
+    });
+
+    let mut buffer = Vec::with_capacity(64);
+    group.bench_function("verifycvv_response/write_to", |b| {
+        b.iter(|| {
+            buffer.clear();
+            black_box(&response).write_to(&mut buffer);
+        })
+    });
+
+    group.finish();
+}
+
+fn bench_roundtrip(c: &mut Criterion) {
+    let mut group = c.benchmark_group("roundtrip");
+    let frame = verifycvv_frame();
+
+    group.bench_function("verifycvv/owned", |b| {
+        b.iter(|| {
+            let message = Message::parse(black_box(&frame)).unwrap();
+            let Message::VerifyCVVRequest(request) = message else { unreachable!() };
+            VerifyCVVResponse::success(request.header.hdr).to_bytes()
+        })
+    });
+
+    let mut buffer = Vec::with_capacity(64);
+    group.bench_function("verifycvv/borrowed", |b| {
+        b.iter(|| {
+            let message = MessageRef::parse(black_box(&frame)).unwrap();
+            buffer.clear();
+            VerifyCVVResponse::success(message.header().hdr).write_to(&mut buffer);
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_serialize, bench_roundtrip);
+criterion_main!(benches);
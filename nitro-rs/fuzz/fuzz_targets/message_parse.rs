@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use nitro::Message;
+
+// Any input must either fail to parse or round-trip to the exact same frame
+fuzz_target!(|data: &[u8]| {
+    if let Ok(message) = Message::parse(data) {
+        let bytes = message.to_bytes();
+        assert_eq!(bytes, data);
+        assert_eq!(Message::parse(&bytes).as_ref(), Ok(&message));
+    }
+});
@@ -0,0 +1,73 @@
+use std::fmt;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Buffer is shorter than the header or the length it declares
+    TooShort { need: usize, got: usize },
+    /// Command code is not part of the protocol
+    UnknownCommand([u8; 2]),
+    /// Response code is not part of the protocol
+    UnknownResponseCode([u8; 2]),
+    /// Command code does not match the message being parsed
+    UnexpectedCommand { expected: [u8; 2], got: [u8; 2] },
+    /// Field content is malformed (non-digit, invalid UTF-8, ...)
+    BadField { name: &'static str, reason: String },
+    /// Field length is outside the allowed range
+    BadLength { name: &'static str, len: usize },
+    /// Variable length field is missing its ';' terminator
+    MissingDelimiter { name: &'static str },
+    /// Payload ends before the field is complete
+    Truncated { name: &'static str },
+    /// Bytes left over after the last field
+    TrailingBytes { count: usize },
+    /// Payload does not fit the frame length prefix or the configured limit
+    FrameTooLarge { size: usize, max: usize },
+}
+
+impl Error {
+    pub fn bad_field(name: &'static str, reason: impl Into<String>) -> Self {
+        Error::BadField { name, reason: reason.into() }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::TooShort { need, got } => {
+                write!(f, "Buffer too small: need {}, got {}", need, got)
+            }
+            Error::UnknownCommand(cmd) => {
+                write!(f, "Unknown command: {}", String::from_utf8_lossy(cmd))
+            }
+            Error::UnknownResponseCode(code) => {
+                write!(f, "Unknown response code: {}", String::from_utf8_lossy(code))
+            }
+            Error::UnexpectedCommand { expected, got } => {
+                write!(f, "Invalid command: expected {}, got {}",
+                    String::from_utf8_lossy(expected), String::from_utf8_lossy(got))
+            }
+            Error::BadField { name, reason } => {
+                write!(f, "Invalid {}: {}", name, reason)
+            }
+            Error::BadLength { name, len } => {
+                write!(f, "Invalid {} length: {}", name, len)
+            }
+            Error::MissingDelimiter { name } => {
+                write!(f, "{} delimiter ';' not found", name)
+            }
+            Error::Truncated { name } => {
+                write!(f, "Message truncated in {}", name)
+            }
+            Error::TrailingBytes { count } => {
+                write!(f, "Unexpected {} trailing bytes", count)
+            }
+            Error::FrameTooLarge { size, max } => {
+                write!(f, "Frame too large: {} bytes (max {})", size, max)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
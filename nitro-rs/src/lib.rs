@@ -1,9 +1,13 @@
 
 // pub mod socket;
+pub mod error;
 pub mod logging;
 pub mod message;
 pub mod utils; 
 
-pub use logging::init_logging;
-pub use message::{Message, MessageHeader};
+pub use error::{Error, Result};
+#[allow(deprecated)]
+pub use logging::init_logging_with_level;
+pub use logging::{init_logging, init_tracing, LogFormat, LoggingGuard, LoggingOptions, LogShipping, LogSinkStats};
+pub use message::{Message, MessageHeader, MessageRef};
 pub use utils::hexdump;
\ No newline at end of file
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+use std::time::Instant;
+
+use log::LevelFilter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::message::MessageHeader;
+
+mod sink;
+
+pub use sink::{LogSink, LogSinkStats, LogSinkWriter};
+
+/// Modules that are too chatty at the application's level.
+const FILTER_OVERRIDES: &str = "rustls=off,hyper=warn,aws_smithy=warn";
+
+/// Records held while the receiver is slow or unreachable before new ones are dropped.
+pub const DEFAULT_SHIPPING_BUFFER_RECORDS: usize = 8192;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other  => Err(format!("unknown log format '{}', expected 'text' or 'json'", other)),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Where to ship JSON log lines over vsock, in addition to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogShipping {
+    pub cid: u32,
+    pub port: u32,
+    pub buffer_records: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoggingOptions {
+    /// Used when `RUST_LOG` is not set.
+    pub level: LevelFilter,
+    pub format: LogFormat,
+    pub shipping: Option<LogShipping>,
+}
+
+impl Default for LoggingOptions {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            format: LogFormat::Text,
+            shipping: None,
+        }
+    }
+}
+
+/// Flushes shipped log lines when dropped; keep it alive for the life of the process.
+#[must_use]
+pub struct LoggingGuard {
+    shipping: Option<LogSink>,
+}
+
+impl LoggingGuard {
+    /// Sent and dropped record counts, when log shipping is enabled.
+    pub fn shipping_stats(&self) -> Option<LogSinkStats> {
+        self.shipping.as_ref().map(LogSink::stats)
+    }
+}
+
+pub fn init_logging() -> Result<(), Box<dyn std::error::Error>> {
+    // Nothing is shipped, so there is no worker to keep alive
+    init_tracing(&LoggingOptions::default()).map(drop)
+}
+
+#[deprecated(note = "use `init_tracing` with `LoggingOptions { level, .. }`")]
+pub fn init_logging_with_level(level: LevelFilter) -> Result<(), Box<dyn std::error::Error>> {
+    init_tracing(&LoggingOptions { level, ..LoggingOptions::default() }).map(drop)
+}
+
+/// Installs the global `tracing` subscriber; `log` records are forwarded to it.
+///
+/// `RUST_LOG` takes precedence over `options.level` when set.
+pub fn init_tracing(options: &LoggingOptions) -> Result<LoggingGuard, Box<dyn std::error::Error>> {
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) if !directives.is_empty() => EnvFilter::try_new(directives)?,
+        _ => EnvFilter::try_new(format!(
+            "{},{}",
+            options.level.as_str().to_ascii_lowercase(),
+            FILTER_OVERRIDES
+        ))?,
+    };
+
+    let stdout = match options.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_ansi(io::stdout().is_terminal())
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let (shipping, sink) = match options.shipping {
+        Some(target) => {
+            let sink = LogSink::start(target)?;
+            let layer = tracing_subscriber::fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_writer(sink.writer())
+                .boxed();
+            (Some(layer), Some(sink))
+        }
+        None => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(stdout)
+        .with(shipping)
+        .try_init()?;
+
+    Ok(LoggingGuard { shipping: sink })
+}
+
+/// Span for one request frame, carrying its header id and command code.
+///
+/// `response_code` and `latency_ms` are filled in by [`finish_request`].
+pub fn request_span(frame: &[u8]) -> tracing::Span {
+    let (hdr, cmd) = match MessageHeader::parse(frame) {
+        Ok(header) => (
+            String::from_utf8_lossy(&header.hdr).into_owned(),
+            header.cmd_str(),
+        ),
+        Err(_) => (String::from("?"), String::from("?")),
+    };
+
+    tracing::info_span!(
+        "request",
+        hdr = %hdr,
+        cmd = %cmd,
+        response_code = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+    )
+}
+
+/// Records the outcome on `span` and logs completion; `response` is the encoded response frame.
+pub fn finish_request(span: &tracing::Span, response: Option<&[u8]>, started: Instant) {
+    // Every response payload starts with the 2-byte response code
+    let response_code = response
+        .and_then(|frame| {
+            let header = MessageHeader::parse(frame).ok()?;
+            let start = header.header_length();
+            frame.get(start..start + 2)
+        })
+        .map(|code| String::from_utf8_lossy(code).into_owned());
+
+    if let Some(code) = &response_code {
+        span.record("response_code", code.as_str());
+    }
+    let latency_ms = started.elapsed().as_micros() as f64 / 1000.0;
+    span.record("latency_ms", latency_ms);
+
+    span.in_scope(|| match response_code {
+        Some(_) => tracing::info!("request completed"),
+        None => tracing::warn!("request completed without response"),
+    });
+}
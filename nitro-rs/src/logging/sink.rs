@@ -0,0 +1,291 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use nix::sys::socket::{self, sockopt, AddressFamily, SockFlag, SockType, VsockAddr};
+use nix::sys::time::{TimeVal, TimeValLike};
+use tracing_subscriber::fmt::MakeWriter;
+
+use super::LogShipping;
+
+/// Records are written out once this many bytes are queued or `FLUSH_INTERVAL` passes.
+const BATCH_MAX_BYTES: usize = 64 * 1024;
+const FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Pause between connection attempts while the log receiver is unreachable.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A write stalled this long counts as a failed connection.
+const SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long dropping the sink waits for the final flush before leaving the worker behind.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(3);
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+enum Record {
+    Line(Vec<u8>),
+    Shutdown,
+}
+
+#[derive(Default)]
+struct Counters {
+    sent: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Totals since the sink was started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LogSinkStats {
+    pub sent: u64,
+    pub dropped: u64,
+}
+
+/// Ships formatted log records to a receiver on the parent over vsock.
+///
+/// Records are queued in a bounded buffer and written in batches by a
+/// background thread, so logging never blocks on the connection. When the
+/// buffer is full or the receiver is unreachable, records are dropped and
+/// counted; the receiver is told how many were lost once it is back.
+pub struct LogSink {
+    queue: SyncSender<Record>,
+    counters: Arc<Counters>,
+    worker: Option<JoinHandle<()>>,
+    /// Disconnects when the worker exits
+    finished: Receiver<()>,
+}
+
+impl LogSink {
+    pub fn start(target: LogShipping) -> io::Result<Self> {
+        let (queue, records) = mpsc::sync_channel(target.buffer_records.max(1));
+        let counters = Arc::new(Counters::default());
+
+        let (finished_tx, finished) = mpsc::channel();
+
+        let worker_counters = Arc::clone(&counters);
+        let worker = thread::Builder::new()
+            .name("log-sink".to_string())
+            .spawn(move || {
+                let _finished = finished_tx;
+                SinkWorker::new(target, worker_counters).run(records)
+            })?;
+
+        Ok(Self { queue, counters, worker: Some(worker), finished })
+    }
+
+    pub fn writer(&self) -> LogSinkWriter {
+        LogSinkWriter {
+            queue: self.queue.clone(),
+            counters: Arc::clone(&self.counters),
+        }
+    }
+
+    pub fn stats(&self) -> LogSinkStats {
+        LogSinkStats {
+            sent: self.counters.sent.load(Ordering::Relaxed),
+            dropped: self.counters.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Drop for LogSink {
+    /// Flushes queued records, waiting at most `SHUTDOWN_TIMEOUT` in total; a
+    /// worker still busy after that is detached and its records are lost.
+    fn drop(&mut self) {
+        let deadline = Instant::now() + SHUTDOWN_TIMEOUT;
+
+        // The queue may be full behind a slow receiver, so do not block on it
+        loop {
+            match self.queue.try_send(Record::Shutdown) {
+                Ok(()) => break,
+                Err(TrySendError::Full(_)) if Instant::now() < deadline => thread::sleep(SHUTDOWN_POLL_INTERVAL),
+                Err(_) => return,
+            }
+        }
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let finished = self.finished.recv_timeout(remaining) == Err(RecvTimeoutError::Disconnected);
+        if let Some(worker) = self.worker.take().filter(|_| finished) {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// `Write` handle that enqueues each formatted record without blocking.
+#[derive(Clone)]
+pub struct LogSinkWriter {
+    queue: SyncSender<Record>,
+    counters: Arc<Counters>,
+}
+
+impl Write for LogSinkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.queue.try_send(Record::Line(buf.to_vec())) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                self.counters.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        // Logging must never fail the caller
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for LogSinkWriter {
+    type Writer = LogSinkWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+struct SinkWorker {
+    target: LogShipping,
+    counters: Arc<Counters>,
+    stream: Option<File>,
+    retry_at: Instant,
+    batch: Vec<u8>,
+    batch_records: u64,
+    /// Dropped count already reported to the receiver
+    reported_dropped: u64,
+}
+
+impl SinkWorker {
+    fn new(target: LogShipping, counters: Arc<Counters>) -> Self {
+        Self {
+            target,
+            counters,
+            stream: None,
+            retry_at: Instant::now(),
+            batch: Vec::with_capacity(BATCH_MAX_BYTES),
+            batch_records: 0,
+            reported_dropped: 0,
+        }
+    }
+
+    fn run(mut self, records: Receiver<Record>) {
+        loop {
+            let mut shutdown = match records.recv_timeout(FLUSH_INTERVAL) {
+                Ok(record) => self.push(record),
+                Err(RecvTimeoutError::Timeout) => false,
+                Err(RecvTimeoutError::Disconnected) => true,
+            };
+
+            while !shutdown && self.batch.len() < BATCH_MAX_BYTES {
+                shutdown = match records.try_recv() {
+                    Ok(record) => self.push(record),
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => true,
+                };
+            }
+
+            self.send_batch();
+
+            if shutdown {
+                break;
+            }
+        }
+    }
+
+    /// Adds a record to the batch, returning whether it asked the worker to stop.
+    fn push(&mut self, record: Record) -> bool {
+        match record {
+            Record::Line(line) => {
+                self.batch.extend_from_slice(&line);
+                self.batch_records += 1;
+                false
+            }
+            Record::Shutdown => true,
+        }
+    }
+
+    fn send_batch(&mut self) {
+        if self.batch_records == 0 {
+            return;
+        }
+
+        let records = self.batch_records;
+        let mut batch = std::mem::replace(&mut self.batch, Vec::with_capacity(BATCH_MAX_BYTES));
+        self.batch_records = 0;
+
+        let dropped = self.counters.dropped.load(Ordering::Relaxed);
+        let unreported = dropped - self.reported_dropped;
+        if unreported > 0 {
+            batch.splice(0..0, dropped_notice(unreported));
+        }
+
+        let Some(stream) = self.stream() else {
+            self.counters.dropped.fetch_add(records, Ordering::Relaxed);
+            return;
+        };
+
+        match stream.write_all(&batch) {
+            Ok(()) => {
+                self.counters.sent.fetch_add(records, Ordering::Relaxed);
+                self.reported_dropped = dropped;
+            }
+            Err(_) => {
+                self.stream = None;
+                self.retry_at = Instant::now() + RECONNECT_INTERVAL;
+                self.counters.dropped.fetch_add(records, Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn stream(&mut self) -> Option<&mut File> {
+        if self.stream.is_none() && Instant::now() >= self.retry_at {
+            match connect(self.target) {
+                Ok(stream) => self.stream = Some(stream),
+                Err(_) => self.retry_at = Instant::now() + RECONNECT_INTERVAL,
+            }
+        }
+        self.stream.as_mut()
+    }
+}
+
+fn connect(target: LogShipping) -> io::Result<File> {
+    let fd = socket::socket(AddressFamily::Vsock, SockType::Stream, SockFlag::SOCK_CLOEXEC, None)?;
+    socket::connect(fd.as_raw_fd(), &VsockAddr::new(target.cid, target.port))?;
+    socket::setsockopt(&fd, sockopt::SendTimeout, &TimeVal::milliseconds(SEND_TIMEOUT.as_millis() as i64))?;
+    Ok(File::from(fd))
+}
+
+/// JSON line in the shape of the shipped records, so the receiver sees the gap.
+fn dropped_notice(count: u64) -> Vec<u8> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+
+    format!(
+        "{{\"timestamp\":{},\"level\":\"WARN\",\"fields\":{{\"message\":\"dropped {} log records\",\"dropped\":{}}},\"target\":\"nitro::logging::sink\"}}\n",
+        timestamp, count, count
+    ).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_is_bounded_without_a_receiver() {
+        let sink = LogSink::start(LogShipping { cid: 1, port: 0xFFFF_FFF0, buffer_records: 4 }).unwrap();
+
+        let mut writer = sink.writer();
+        for _ in 0..16 {
+            writer.write_all(b"{}\n").unwrap();
+        }
+
+        let started = Instant::now();
+        drop(sink);
+        assert!(started.elapsed() < SHUTDOWN_TIMEOUT + Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,374 @@
+use bytes::BufMut;
+
+use crate::error::{Error, Result};
+
+use crate::message::header::MessageHeader;
+use crate::message::reader::{as_str, FieldReader};
+use super::cmd_cy::VerifyCVVRequest;
+use super::cmd_dc::VerifyPvvRequest;
+use super::command::{CMD_TRANSLATEPIN_REQUEST, CMD_TRANSLATEPIN_RESPONSE, ResponseCode};
+
+
+// Fixed-size fields: source_key(16) + dest_key(16) + source_format(2) + dest_format(2) = 36
+// Plus variable: pin block hex + ';' + pan digits + ';'
+pub const TRANSLATEPIN_FIXED_FIELDS_SIZE: usize = 16 + 16 + 2 + 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranslatePinRequest {
+    pub header: MessageHeader,
+    pub source_key: [u8; 16],
+    pub dest_key:   [u8; 16],
+    pub source_format: [u8; 2],
+    pub dest_format:   [u8; 2],
+    pub pin_block: Vec<u8>,
+    pub pan: Vec<u8>,
+}
+
+impl TranslatePinRequest {
+
+    pub fn new(
+        hdr           : [u8; 4],
+        source_key    : &str,
+        dest_key      : &str,
+        source_format : &str,
+        dest_format   : &str,
+        pin_block     : &str,
+        pan           : &str,
+    ) -> Result<Self> {
+        let source_key    = VerifyPvvRequest::validate_key_id("source_key", source_key)?;
+        let dest_key      = VerifyPvvRequest::validate_key_id("dest_key", dest_key)?;
+        let source_format = VerifyPvvRequest::validate_digits("source_format", source_format)?;
+        let dest_format   = VerifyPvvRequest::validate_digits("dest_format", dest_format)?;
+        VerifyPvvRequest::check_pin_block(pin_block)?;
+        let pan           = VerifyCVVRequest::validate_pan(pan)?;
+
+        let payload_len = TRANSLATEPIN_FIXED_FIELDS_SIZE + pin_block.len() + 1 + pan.len() + 1;
+        let header = MessageHeader::new(hdr, CMD_TRANSLATEPIN_REQUEST, payload_len)?;
+
+        Ok(Self {
+            header,
+            source_key,
+            dest_key,
+            source_format,
+            dest_format,
+            pin_block: pin_block.as_bytes().to_vec(),
+            pan,
+        })
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        TranslatePinRequestRef::parse(buffer).map(TranslatePinRequestRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.source_key);
+        buf.put_slice(&self.dest_key);
+        buf.put_slice(&self.source_format);
+        buf.put_slice(&self.dest_format);
+        buf.put_slice(&self.pin_block);
+        buf.put_u8(b';');
+        buf.put_slice(&self.pan);
+        buf.put_u8(b';');
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a TranslatePin request over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TranslatePinRequestRef<'a> {
+    pub header: MessageHeader,
+    pub source_key: &'a [u8; 16],
+    pub dest_key:   &'a [u8; 16],
+    pub source_format: &'a [u8; 2],
+    pub dest_format:   &'a [u8; 2],
+    pub pin_block: &'a [u8],
+    pub pan: &'a [u8],
+}
+
+impl<'a> TranslatePinRequestRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_TRANSLATEPIN_REQUEST) {
+            return Err(Error::UnexpectedCommand { expected: CMD_TRANSLATEPIN_REQUEST, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let source_key = reader.take_array_ref("source_key")?;
+        VerifyPvvRequest::validate_key_id("source_key", as_str("source_key", source_key)?)?;
+
+        let dest_key = reader.take_array_ref("dest_key")?;
+        VerifyPvvRequest::validate_key_id("dest_key", as_str("dest_key", dest_key)?)?;
+
+        let source_format = reader.take_array_ref("source_format")?;
+        VerifyPvvRequest::validate_digits::<2>("source_format", as_str("source_format", source_format)?)?;
+
+        let dest_format = reader.take_array_ref("dest_format")?;
+        VerifyPvvRequest::validate_digits::<2>("dest_format", as_str("dest_format", dest_format)?)?;
+
+        let pin_block = reader.take_delimited_str("pin_block")?;
+        VerifyPvvRequest::check_pin_block(pin_block)?;
+
+        let pan = reader.take_delimited_str("pan")?;
+        VerifyCVVRequest::check_pan(pan)?;
+
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            source_key,
+            dest_key,
+            source_format,
+            dest_format,
+            pin_block: pin_block.as_bytes(),
+            pan: pan.as_bytes(),
+        })
+    }
+
+    pub fn source_key_id(&self) -> &'a str {
+        as_str("source_key", self.source_key).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn dest_key_id(&self) -> &'a str {
+        as_str("dest_key", self.dest_key).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(self.source_key);
+        buf.put_slice(self.dest_key);
+        buf.put_slice(self.source_format);
+        buf.put_slice(self.dest_format);
+        buf.put_slice(self.pin_block);
+        buf.put_u8(b';');
+        buf.put_slice(self.pan);
+        buf.put_u8(b';');
+    }
+
+    pub fn into_owned(self) -> TranslatePinRequest {
+        TranslatePinRequest {
+            header: self.header,
+            source_key: *self.source_key,
+            dest_key: *self.dest_key,
+            source_format: *self.source_format,
+            dest_format: *self.dest_format,
+            pin_block: self.pin_block.to_vec(),
+            pan: self.pan.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranslatePinResponse {
+    pub header: MessageHeader,
+    pub response_code: [u8; 2],
+    pub pin_block: Option<Vec<u8>>,
+}
+
+impl TranslatePinResponse {
+    pub fn success(hdr: [u8; 4], pin_block: &str) -> Result<Self> {
+        VerifyPvvRequest::check_pin_block(pin_block)?;
+
+        let data_length = 2 + pin_block.len() as u16;
+        let header = MessageHeader::fixed(hdr, CMD_TRANSLATEPIN_RESPONSE, data_length);
+
+        Ok(Self {
+            header,
+            response_code: ResponseCode::Success.as_bytes(),
+            pin_block: Some(pin_block.as_bytes().to_vec()),
+        })
+    }
+
+    pub fn error(hdr: [u8; 4], error_code: ResponseCode) -> Self {
+        let data_length = 2;
+        let header = MessageHeader::fixed(hdr, CMD_TRANSLATEPIN_RESPONSE, data_length);
+
+        Self {
+            header,
+            response_code: error_code.as_bytes(),
+            pin_block: None,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.response_code == ResponseCode::Success.as_bytes()
+    }
+
+    pub fn response_code_str(&self) -> String {
+        String::from_utf8_lossy(&self.response_code).to_string()
+    }
+
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
+    pub fn pin_block_str(&self) -> Option<String> {
+        self.pin_block.as_ref().map(|pin_block| String::from_utf8_lossy(pin_block).to_string())
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        TranslatePinResponseRef::parse(buffer).map(TranslatePinResponseRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+
+        if let Some(ref pin_block) = self.pin_block {
+            buf.put_slice(pin_block);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a TranslatePin response over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TranslatePinResponseRef<'a> {
+    pub header: MessageHeader,
+    pub response_code: [u8; 2],
+    pub pin_block: Option<&'a [u8]>,
+}
+
+impl<'a> TranslatePinResponseRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_TRANSLATEPIN_RESPONSE) {
+            return Err(Error::UnexpectedCommand { expected: CMD_TRANSLATEPIN_RESPONSE, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let response_code = reader.take_array("response_code")?;
+
+        let pin_block = match reader.take_rest() {
+            []        => None,
+            pin_block => {
+                VerifyPvvRequest::check_pin_block(as_str("pin_block", pin_block)?)?;
+                Some(pin_block)
+            }
+        };
+
+        Ok(Self {
+            header,
+            response_code,
+            pin_block,
+        })
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.response_code == ResponseCode::Success.as_bytes()
+    }
+
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+
+        if let Some(pin_block) = self.pin_block {
+            buf.put_slice(pin_block);
+        }
+    }
+
+    pub fn into_owned(self) -> TranslatePinResponse {
+        TranslatePinResponse {
+            header: self.header,
+            response_code: self.response_code,
+            pin_block: self.pin_block.map(<[u8]>::to_vec),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::message::testing::{relabel, relabeled_truncations, truncations, with_declared_len};
+
+    fn request() -> Vec<u8> {
+        TranslatePinRequest::new(*b"0001", "zpk-a", "zpk-b", "01", "48", "0412AC89ABCDEF67", "4111111111111111")
+            .unwrap()
+            .to_bytes()
+    }
+
+    #[test]
+    fn request_round_trips() {
+        let frame = request();
+        assert_eq!(TranslatePinRequest::parse(&frame).unwrap().to_bytes(), frame);
+
+        let view = TranslatePinRequestRef::parse(&frame).unwrap();
+        assert_eq!((view.source_key_id(), view.dest_key_id()), ("zpk-a", "zpk-b"));
+        assert_eq!((view.source_format, view.dest_format), (b"01", b"48"));
+    }
+
+    #[test]
+    fn request_truncated_at_every_offset_is_rejected() {
+        let frame = request();
+        for cut in truncations(&frame) {
+            assert!(TranslatePinRequestRef::parse(cut).is_err(), "accepted {} of {} bytes", cut.len(), frame.len());
+            assert!(Message::parse(cut).is_err());
+        }
+        for cut in relabeled_truncations(&frame) {
+            assert!(TranslatePinRequestRef::parse(&cut).is_err(), "accepted relabeled {} of {} bytes", cut.len(), frame.len());
+        }
+    }
+
+    #[test]
+    fn request_with_trailing_bytes_is_rejected() {
+        let mut frame = request();
+        frame.push(b'0');
+        assert_eq!(TranslatePinRequestRef::parse(&frame), Err(Error::TrailingBytes { count: 1 }));
+        assert_eq!(TranslatePinRequestRef::parse(&relabel(frame)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn request_with_wrong_declared_len_is_rejected() {
+        let frame = request();
+        assert!(matches!(TranslatePinRequestRef::parse(&with_declared_len(&frame, 1)), Err(Error::TooShort { .. })));
+        assert_eq!(TranslatePinRequestRef::parse(&with_declared_len(&frame, -1)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn response_truncated_at_every_offset_is_rejected() {
+        let frame = TranslatePinResponse::success(*b"0001", "0412AC89ABCDEF67").unwrap().to_bytes();
+        assert_eq!(TranslatePinResponse::parse(&frame).unwrap().pin_block_str().as_deref(), Some("0412AC89ABCDEF67"));
+
+        for cut in truncations(&frame) {
+            assert!(TranslatePinResponseRef::parse(cut).is_err());
+        }
+
+        // A bare response code is a valid error response; a partial PIN block is not
+        let header_len = TranslatePinResponse::error(*b"0001", ResponseCode::SystemError).header.header_length();
+        for cut in relabeled_truncations(&frame) {
+            let parsed = TranslatePinResponseRef::parse(&cut);
+            assert_eq!(parsed.is_ok(), cut.len() == header_len + 2, "relabeled {} bytes", cut.len());
+        }
+
+        let mut long = frame.clone();
+        long.push(b'0');
+        assert!(TranslatePinResponseRef::parse(&relabel(long)).is_err());
+    }
+
+    #[test]
+    fn response_carries_aes_pin_block() {
+        let pin_block = "44123456789ABCDEF0123456789ABCDE";
+        let frame = TranslatePinResponse::success(*b"0001", pin_block).unwrap().to_bytes();
+        assert_eq!(TranslatePinResponse::parse(&frame).unwrap().pin_block_str().as_deref(), Some(pin_block));
+    }
+}
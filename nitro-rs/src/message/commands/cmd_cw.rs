@@ -0,0 +1,293 @@
+use bytes::BufMut;
+
+use crate::error::{Error, Result};
+
+use crate::message::header::MessageHeader;
+use crate::message::reader::{as_str, FieldReader};
+use super::cmd_cy::VerifyCVVRequest;
+use super::command::{CMD_GENERATECVV_REQUEST, CMD_GENERATECVV_RESPONSE, ResponseCode};
+
+
+// Fixed-size fields: cvka(16) + cvkb(16) + expdate(4) + svcode(3) = 39
+// Plus variable: pan digits + ';'
+pub const GENERATECVV_FIXED_FIELDS_SIZE: usize = 16 + 16 + 4 + 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerateCVVRequest {
+    pub header: MessageHeader,
+    pub cvka: [u8; 16],
+    pub cvkb: [u8; 16],
+    pub pan: Vec<u8>,
+    pub expdate: [u8; 4],
+    pub svcode: [u8; 3]
+}
+
+impl GenerateCVVRequest {
+
+    pub fn new(
+        hdr     : [u8; 4],
+        cvka    : &str,
+        cvkb    : &str,
+        pan     : &str,
+        expdate : &str,
+        svcode  : &str,
+    ) -> Result<Self> {
+        let cvka    = VerifyCVVRequest::validate_cvka(cvka)?;
+        let cvkb    = VerifyCVVRequest::validate_cvkb(cvkb)?;
+        let pan     = VerifyCVVRequest::validate_pan(pan)?;
+        let expdate = VerifyCVVRequest::validate_expdate(expdate)?;
+        let svcode  = VerifyCVVRequest::validate_svcode(svcode)?;
+
+        let payload_len = GENERATECVV_FIXED_FIELDS_SIZE + pan.len() + 1;
+        let header = MessageHeader::new(hdr, CMD_GENERATECVV_REQUEST, payload_len)?;
+
+        Ok(Self { header, cvka, cvkb, pan, expdate, svcode })
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        GenerateCVVRequestRef::parse(buffer).map(GenerateCVVRequestRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.cvka);
+        buf.put_slice(&self.cvkb);
+        buf.put_slice(&self.pan); // raw digits
+        buf.put_u8(b';');         // terminator
+        buf.put_slice(&self.expdate);
+        buf.put_slice(&self.svcode);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a GenerateCVV request over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenerateCVVRequestRef<'a> {
+    pub header: MessageHeader,
+    pub cvka: &'a [u8; 16],
+    pub cvkb: &'a [u8; 16],
+    pub pan: &'a [u8],
+    pub expdate: &'a [u8; 4],
+    pub svcode: &'a [u8; 3]
+}
+
+impl<'a> GenerateCVVRequestRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_GENERATECVV_REQUEST) {
+            return Err(Error::UnexpectedCommand { expected: CMD_GENERATECVV_REQUEST, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let cvka = reader.take_array_ref("cvka")?;
+        VerifyCVVRequest::validate_cvka(as_str("cvka", cvka)?)?;
+
+        let cvkb = reader.take_array_ref("cvkb")?;
+        VerifyCVVRequest::validate_cvkb(as_str("cvkb", cvkb)?)?;
+
+        let pan = reader.take_delimited_str("pan")?;
+        VerifyCVVRequest::check_pan(pan)?;
+
+        let expdate = reader.take_array_ref("expdate")?;
+        VerifyCVVRequest::validate_expdate(as_str("expdate", expdate)?)?;
+
+        let svcode = reader.take_array_ref("svcode")?;
+        VerifyCVVRequest::validate_svcode(as_str("svcode", svcode)?)?;
+
+        reader.finish()?;
+
+        Ok(Self { header, cvka, cvkb, pan: pan.as_bytes(), expdate, svcode })
+    }
+
+    pub fn cvka_id(&self) -> &'a str {
+        as_str("cvka", self.cvka).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn cvkb_id(&self) -> &'a str {
+        as_str("cvkb", self.cvkb).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(self.cvka);
+        buf.put_slice(self.cvkb);
+        buf.put_slice(self.pan);
+        buf.put_u8(b';');
+        buf.put_slice(self.expdate);
+        buf.put_slice(self.svcode);
+    }
+
+    pub fn into_owned(self) -> GenerateCVVRequest {
+        GenerateCVVRequest {
+            header: self.header,
+            cvka: *self.cvka,
+            cvkb: *self.cvkb,
+            pan: self.pan.to_vec(),
+            expdate: *self.expdate,
+            svcode: *self.svcode,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenerateCVVResponse {
+    pub header: MessageHeader,
+    pub response_code: [u8; 2],
+    pub cvv: Option<[u8; 3]>,
+}
+
+impl GenerateCVVResponse {
+    pub fn success(hdr: [u8; 4], cvv: [u8; 3]) -> Self {
+        let data_length = 2 + cvv.len() as u16;
+        let header = MessageHeader::fixed(hdr, CMD_GENERATECVV_RESPONSE, data_length);
+
+        Self {
+            header,
+            response_code: ResponseCode::Success.as_bytes(),
+            cvv: Some(cvv),
+        }
+    }
+
+    pub fn error(hdr: [u8; 4], error_code: ResponseCode) -> Self {
+        let data_length = 2;
+        let header = MessageHeader::fixed(hdr, CMD_GENERATECVV_RESPONSE, data_length);
+
+        Self {
+            header,
+            response_code: error_code.as_bytes(),
+            cvv: None,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.response_code == ResponseCode::Success.as_bytes()
+    }
+
+    pub fn response_code_str(&self) -> String {
+        String::from_utf8_lossy(&self.response_code).to_string()
+    }
+
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
+    pub fn cvv_str(&self) -> Option<String> {
+        self.cvv.map(|cvv| String::from_utf8_lossy(&cvv).to_string())
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_GENERATECVV_RESPONSE) {
+            return Err(Error::UnexpectedCommand { expected: CMD_GENERATECVV_RESPONSE, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let response_code = reader.take_array("response_code")?;
+
+        let cvv = if reader.remaining() > 0 {
+            Some(reader.take_array("cvv")?)
+        } else {
+            None
+        };
+
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            response_code,
+            cvv,
+        })
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+
+        if let Some(ref cvv) = self.cvv {
+            buf.put_slice(cvv);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::message::testing::{relabel, relabeled_truncations, truncations, with_declared_len};
+
+    fn request() -> Vec<u8> {
+        GenerateCVVRequest::new(*b"0001", "cvka", "cvkb", "4111111111111111", "2512", "101")
+            .unwrap()
+            .to_bytes()
+    }
+
+    #[test]
+    fn request_round_trips() {
+        let frame = request();
+        assert_eq!(GenerateCVVRequest::parse(&frame).unwrap().to_bytes(), frame);
+    }
+
+    #[test]
+    fn request_truncated_at_every_offset_is_rejected() {
+        let frame = request();
+        for cut in truncations(&frame) {
+            assert!(GenerateCVVRequestRef::parse(cut).is_err(), "accepted {} of {} bytes", cut.len(), frame.len());
+            assert!(Message::parse(cut).is_err());
+        }
+        for cut in relabeled_truncations(&frame) {
+            assert!(GenerateCVVRequestRef::parse(&cut).is_err(), "accepted relabeled {} of {} bytes", cut.len(), frame.len());
+        }
+    }
+
+    #[test]
+    fn request_with_trailing_bytes_is_rejected() {
+        let mut frame = request();
+        frame.push(b'0');
+        assert_eq!(GenerateCVVRequestRef::parse(&frame), Err(Error::TrailingBytes { count: 1 }));
+        assert_eq!(GenerateCVVRequestRef::parse(&relabel(frame)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn request_with_wrong_declared_len_is_rejected() {
+        let frame = request();
+        assert!(matches!(GenerateCVVRequestRef::parse(&with_declared_len(&frame, 1)), Err(Error::TooShort { .. })));
+        assert_eq!(GenerateCVVRequestRef::parse(&with_declared_len(&frame, -1)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn response_truncated_at_every_offset_is_rejected() {
+        let frame = GenerateCVVResponse::success(*b"0001", *b"123").to_bytes();
+        assert_eq!(GenerateCVVResponse::parse(&frame).unwrap().cvv_str().as_deref(), Some("123"));
+
+        for cut in truncations(&frame) {
+            assert!(GenerateCVVResponse::parse(cut).is_err());
+        }
+
+        // A bare response code is a valid error response; a partial CVV is not
+        let header_len = GenerateCVVResponse::error(*b"0001", ResponseCode::SystemError).header.header_length();
+        for cut in relabeled_truncations(&frame) {
+            let parsed = GenerateCVVResponse::parse(&cut);
+            assert_eq!(parsed.is_ok(), cut.len() == header_len + 2, "relabeled {} bytes", cut.len());
+        }
+
+        let mut long = frame.clone();
+        long.push(b'0');
+        assert_eq!(GenerateCVVResponse::parse(&relabel(long)), Err(Error::TrailingBytes { count: 1 }));
+    }
+}
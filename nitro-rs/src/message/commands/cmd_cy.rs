@@ -1,7 +1,10 @@
-use anyhow::{Result, bail, Context};
+use bytes::BufMut;
 
-use crate::message::header::{MessageHeader, MSGHDR_FMT_SIZE, MSGHDR_LEN_SIZE};
-use super::command::{CMD_VERIFYCVV_REQUEST, CMD_VERIFYCVV_RESPONSE, RESPONSE_SUCCESS};
+use crate::error::{Error, Result};
+
+use crate::message::header::MessageHeader;
+use crate::message::reader::{as_str, FieldReader};
+use super::command::{CMD_VERIFYCVV_REQUEST, CMD_VERIFYCVV_RESPONSE, ResponseCode};
 
 
 // Fixed-size fields: cvka(16) + cvkb(16) + cvv(3) + expdate(4) + svcode(3) = 42
@@ -21,66 +24,71 @@ pub struct VerifyCVVRequest {
 
 impl VerifyCVVRequest {
 
-    fn validate_cvka(cvka: &str) -> Result<[u8; 16]> {
+    pub(super) fn validate_cvka(cvka: &str) -> Result<[u8; 16]> {
         if cvka.is_empty() || cvka.len() > 16 {
-            bail!("CVKA must be 1-16 bytes, got {}", cvka.len());
+            return Err(Error::BadLength { name: "cvka", len: cvka.len() });
         }
         let mut buf = [0u8; 16];
         buf[..cvka.len()].copy_from_slice(cvka.as_bytes());
         Ok(buf)
     }
 
-    fn validate_cvkb(cvkb: &str) -> Result<[u8; 16]> {
+    pub(super) fn validate_cvkb(cvkb: &str) -> Result<[u8; 16]> {
         if cvkb.is_empty() || cvkb.len() > 16 {
-            bail!("CVKB must be 1-16 bytes, got {}", cvkb.len());
+            return Err(Error::BadLength { name: "cvkb", len: cvkb.len() });
         }
         let mut buf = [0u8; 16];
         buf[..cvkb.len()].copy_from_slice(cvkb.as_bytes());
         Ok(buf)
     }
 
-    fn validate_cvv(cvv: &str) -> Result<[u8; 3]> {
+    pub(super) fn validate_cvv(cvv: &str) -> Result<[u8; 3]> {
         if cvv.is_empty() || cvv.len() > 3 {
-            bail!("CVV must be 1-3 digits, got {}", cvv.len());
+            return Err(Error::BadLength { name: "cvv", len: cvv.len() });
         }
         if !cvv.chars().all(|c| c.is_ascii_digit()) {
-            bail!("CVV must contain digits only, got: {}", cvv);
+            return Err(Error::bad_field("cvv", "must contain digits only"));
         }
         let mut buf = [0u8; 3];
         buf[..cvv.len()].copy_from_slice(cvv.as_bytes());
         Ok(buf)
     }
 
-    fn validate_pan(pan: &str) -> Result<Vec<u8>> {
+    pub(super) fn check_pan(pan: &str) -> Result<()> {
         if pan.is_empty() || pan.len() > 19 {
-            bail!("PAN must be 1-19 digits, got {}", pan.len());
+            return Err(Error::BadLength { name: "pan", len: pan.len() });
         }
         if !pan.chars().all(|c| c.is_ascii_digit()) {
-            bail!("PAN must contain digits only, got: {}", pan);
+            return Err(Error::bad_field("pan", "must contain digits only"));
         }
+        Ok(())
+    }
+
+    pub(super) fn validate_pan(pan: &str) -> Result<Vec<u8>> {
+        Self::check_pan(pan)?;
         Ok(pan.as_bytes().to_vec())
     }
 
-    fn validate_expdate(expdate: &str) -> Result<[u8; 4]> {
+    pub(super) fn validate_expdate(expdate: &str) -> Result<[u8; 4]> {
         if expdate.len() != 4 {
-            bail!("Expdate must be exactly 4 bytes (YYMM), got {}", expdate.len());
+            return Err(Error::BadLength { name: "expdate", len: expdate.len() });
         }
         if !expdate.chars().all(|c| c.is_ascii_digit()) {
-            bail!("Expdate must contain digits only, got: {}", expdate);
+            return Err(Error::bad_field("expdate", "must contain digits only"));
         }
         expdate.as_bytes().try_into()
-            .context("Failed to convert expdate to [u8; 4]")
+            .map_err(|_| Error::BadLength { name: "expdate", len: expdate.len() })
     }
 
-    fn validate_svcode(svcode: &str) -> Result<[u8; 3]> {
+    pub(super) fn validate_svcode(svcode: &str) -> Result<[u8; 3]> {
         if svcode.len() != 3 {
-            bail!("Service code must be exactly 3 bytes, got {}", svcode.len());
+            return Err(Error::BadLength { name: "svcode", len: svcode.len() });
         }
         if !svcode.chars().all(|c| c.is_ascii_digit()) {
-            bail!("Service code must contain digits only, got: {}", svcode);
+            return Err(Error::bad_field("svcode", "must contain digits only"));
         }
         svcode.as_bytes().try_into()
-            .context("Failed to convert svcode to [u8; 3]")
+            .map_err(|_| Error::BadLength { name: "svcode", len: svcode.len() })
     }
 
     pub fn new(
@@ -100,73 +108,111 @@ impl VerifyCVVRequest {
         let svcode  = Self::validate_svcode(svcode)?;
 
         let payload_len = VERIFYCVV_FIXED_FIELDS_SIZE + pan.len() + 1;
-        let header = MessageHeader::new(hdr, CMD_VERIFYCVV_REQUEST, payload_len);
+        let header = MessageHeader::new(hdr, CMD_VERIFYCVV_REQUEST, payload_len)?;
 
         Ok(Self { header, cvka, cvkb, cvv, pan, expdate, svcode })
     }
 
     pub fn parse(buffer: &[u8]) -> Result<Self> {
-        let header = MessageHeader::parse(buffer)
-            .context("Failed to parse message header")?;
+        VerifyCVVRequestRef::parse(buffer).map(VerifyCVVRequestRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.cvka);
+        buf.put_slice(&self.cvkb);
+        buf.put_slice(&self.cvv);
+        buf.put_slice(&self.pan); // raw digits
+        buf.put_u8(b';');         // terminator
+        buf.put_slice(&self.expdate);
+        buf.put_slice(&self.svcode);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a VerifyCVV request over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyCVVRequestRef<'a> {
+    pub header: MessageHeader,
+    pub cvka: &'a [u8; 16],
+    pub cvkb: &'a [u8; 16],
+    pub cvv:  &'a [u8; 3],
+    pub pan: &'a [u8],
+    pub expdate: &'a [u8; 4],
+    pub svcode: &'a [u8; 3]
+}
+
+impl<'a> VerifyCVVRequestRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+        let header = MessageHeader::parse(buffer)?;
 
         if !header.is_cmd(&CMD_VERIFYCVV_REQUEST) {
-            bail!("Invalid command: expected VerifyCVV, got {}", header.cmd_str());
+            return Err(Error::UnexpectedCommand { expected: CMD_VERIFYCVV_REQUEST, got: header.cmd });
         }
 
-        let mut offset = MSGHDR_FMT_SIZE;
+        let mut reader = FieldReader::new(header.payload(buffer)?);
 
-        let cvka = Self::validate_cvka(
-            std::str::from_utf8(&buffer[offset..offset + 16]).context("Invalid UTF-8 in cvka")?
-        )?;
-        offset += 16;
+        let cvka = reader.take_array_ref("cvka")?;
+        VerifyCVVRequest::validate_cvka(as_str("cvka", cvka)?)?;
 
-        let cvkb = Self::validate_cvkb(
-            std::str::from_utf8(&buffer[offset..offset + 16]).context("Invalid UTF-8 in cvkb")?
-        )?;
-        offset += 16;
+        let cvkb = reader.take_array_ref("cvkb")?;
+        VerifyCVVRequest::validate_cvkb(as_str("cvkb", cvkb)?)?;
 
-        let cvv = Self::validate_cvv(
-            std::str::from_utf8(&buffer[offset..offset + 3]).context("Invalid UTF-8 in cvv")?
-        )?;
-        offset += 3;
+        let cvv = reader.take_array_ref("cvv")?;
+        VerifyCVVRequest::validate_cvv(as_str("cvv", cvv)?)?;
 
-        let pan_start = offset;
-        let pan_end = buffer[pan_start..].iter().position(|&b| b == b';')
-            .map(|pos| pan_start + pos)
-            .context("PAN delimiter ';' not found")?;
-        let pan = Self::validate_pan(
-            std::str::from_utf8(&buffer[pan_start..pan_end]).context("Invalid UTF-8 in pan")?
-        )?;
-        offset = pan_end + 1;
+        let pan = reader.take_delimited_str("pan")?;
+        VerifyCVVRequest::check_pan(pan)?;
 
-        let expdate = Self::validate_expdate(
-            std::str::from_utf8(&buffer[offset..offset + 4]).context("Invalid UTF-8 in expdate")?
-        )?;
-        offset += 4;
+        let expdate = reader.take_array_ref("expdate")?;
+        VerifyCVVRequest::validate_expdate(as_str("expdate", expdate)?)?;
 
-        let svcode = Self::validate_svcode(
-            std::str::from_utf8(&buffer[offset..offset + 3]).context("Invalid UTF-8 in svcode")?
-        )?;
+        let svcode = reader.take_array_ref("svcode")?;
+        VerifyCVVRequest::validate_svcode(as_str("svcode", svcode)?)?;
 
-        Ok(Self { header, cvka, cvkb, cvv, pan, expdate, svcode })
+        reader.finish()?;
+
+        Ok(Self { header, cvka, cvkb, cvv, pan: pan.as_bytes(), expdate, svcode })
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let total = MSGHDR_LEN_SIZE + VERIFYCVV_FIXED_FIELDS_SIZE + self.pan.len() + 1;
-        let mut result = Vec::with_capacity(total);
-        result.extend_from_slice(&self.header.to_bytes());
-        result.extend_from_slice(&self.cvka);
-        result.extend_from_slice(&self.cvkb);
-        result.extend_from_slice(&self.cvv);
-        result.extend_from_slice(&self.pan); // raw digits
-        result.push(b';');                   // terminator
-        result.extend_from_slice(&self.expdate);
-        result.extend_from_slice(&self.svcode);
-        result
+    pub fn cvka_id(&self) -> &'a str {
+        as_str("cvka", self.cvka).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn cvkb_id(&self) -> &'a str {
+        as_str("cvkb", self.cvkb).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(self.cvka);
+        buf.put_slice(self.cvkb);
+        buf.put_slice(self.cvv);
+        buf.put_slice(self.pan);
+        buf.put_u8(b';');
+        buf.put_slice(self.expdate);
+        buf.put_slice(self.svcode);
+    }
+
+    pub fn into_owned(self) -> VerifyCVVRequest {
+        VerifyCVVRequest {
+            header: self.header,
+            cvka: *self.cvka,
+            cvkb: *self.cvkb,
+            cvv: *self.cvv,
+            pan: self.pan.to_vec(),
+            expdate: *self.expdate,
+            svcode: *self.svcode,
+        }
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct VerifyCVVResponse {
     pub header: MessageHeader,
     pub response_code: [u8; 2],
@@ -175,54 +221,49 @@ pub struct VerifyCVVResponse {
 impl VerifyCVVResponse {
     pub fn success(hdr: [u8; 4]) -> Self {
         let data_length = 2;
-        let header = MessageHeader::new(hdr, CMD_VERIFYCVV_RESPONSE, data_length);
+        let header = MessageHeader::fixed(hdr, CMD_VERIFYCVV_RESPONSE, data_length);
         
         Self {
             header,
-            response_code: RESPONSE_SUCCESS,
+            response_code: ResponseCode::Success.as_bytes(),
         }
     }
 
-    pub fn error(hdr: [u8; 4], error_code: [u8; 2]) -> Self {
+    pub fn error(hdr: [u8; 4], error_code: ResponseCode) -> Self {
         let data_length = 2;
-        let header = MessageHeader::new(hdr, CMD_VERIFYCVV_RESPONSE, data_length);
+        let header = MessageHeader::fixed(hdr, CMD_VERIFYCVV_RESPONSE, data_length);
         
         Self {
             header,
-            response_code: error_code
+            response_code: error_code.as_bytes()
         }
     }
 
     pub fn is_success(&self) -> bool {
-        self.response_code == RESPONSE_SUCCESS
+        self.response_code == ResponseCode::Success.as_bytes()
     }
 
     pub fn response_code_str(&self) -> String {
         String::from_utf8_lossy(&self.response_code).to_string()
     }
 
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
     pub fn parse(buffer: &[u8]) -> Result<Self> {
 
-        let header = MessageHeader::parse(buffer)
-            .context("Failed to parse message header")?;
+        let header = MessageHeader::parse(buffer)?;
 
         if !header.is_cmd(&CMD_VERIFYCVV_RESPONSE) {
-            bail!("Invalid command for KeyResponse: expected Z1, got {}", header.cmd_str());
+            return Err(Error::UnexpectedCommand { expected: CMD_VERIFYCVV_RESPONSE, got: header.cmd });
         }
 
-        let expected_total = MSGHDR_LEN_SIZE + header.len as usize;
-        if buffer.len() < expected_total {
-            bail!("Buffer too small: need {}, got {}", expected_total, buffer.len());
-        }
-        
-        let data_length = header.data_length();
-        if data_length < 2 {
-            bail!("Data too small for response code: need at least 2 bytes, got {}", data_length
-            );
-        }
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let response_code = reader.take_array("response_code")?;
 
-        let mut response_code = [0u8; 2];
-        response_code.copy_from_slice(&buffer[MSGHDR_FMT_SIZE..MSGHDR_FMT_SIZE + 2]);
+        reader.finish()?;
         
         Ok(Self {
             header,
@@ -230,12 +271,78 @@ impl VerifyCVVResponse {
         })
     }
 
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::with_capacity(MSGHDR_LEN_SIZE + self.header.len as usize);
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::message::testing::{relabel, relabeled_truncations, truncations, with_declared_len};
+
+    fn request() -> Vec<u8> {
+        VerifyCVVRequest::new(*b"0001", "cvka", "cvkb", "123", "4111111111111111", "2512", "101")
+            .unwrap()
+            .to_bytes()
+    }
 
-        result.extend_from_slice(&self.header.to_bytes());
-        result.extend_from_slice(&self.response_code);
+    #[test]
+    fn request_round_trips() {
+        let frame = request();
+        let parsed = VerifyCVVRequest::parse(&frame).unwrap();
+        assert_eq!(parsed.to_bytes(), frame);
+        assert_eq!(VerifyCVVRequestRef::parse(&frame).unwrap().cvka_id(), "cvka");
+    }
 
-        result
+    #[test]
+    fn request_truncated_at_every_offset_is_rejected() {
+        let frame = request();
+        for cut in truncations(&frame) {
+            assert!(VerifyCVVRequestRef::parse(cut).is_err(), "accepted {} of {} bytes", cut.len(), frame.len());
+            assert!(Message::parse(cut).is_err());
+        }
+        for cut in relabeled_truncations(&frame) {
+            assert!(VerifyCVVRequestRef::parse(&cut).is_err(), "accepted relabeled {} of {} bytes", cut.len(), frame.len());
+        }
+    }
+
+    #[test]
+    fn request_with_trailing_bytes_is_rejected() {
+        let mut frame = request();
+        frame.push(b'0');
+        assert_eq!(VerifyCVVRequestRef::parse(&frame), Err(Error::TrailingBytes { count: 1 }));
+        assert_eq!(VerifyCVVRequestRef::parse(&relabel(frame)), Err(Error::TrailingBytes { count: 1 }));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn request_with_wrong_declared_len_is_rejected() {
+        let frame = request();
+        assert!(matches!(VerifyCVVRequestRef::parse(&with_declared_len(&frame, 1)), Err(Error::TooShort { .. })));
+        assert_eq!(VerifyCVVRequestRef::parse(&with_declared_len(&frame, -1)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn response_truncated_at_every_offset_is_rejected() {
+        let frame = VerifyCVVResponse::error(*b"0001", ResponseCode::CvvMismatch).to_bytes();
+        assert_eq!(VerifyCVVResponse::parse(&frame).unwrap().code(), Some(ResponseCode::CvvMismatch));
+
+        for cut in truncations(&frame) {
+            assert!(VerifyCVVResponse::parse(cut).is_err());
+        }
+        for cut in relabeled_truncations(&frame) {
+            assert!(VerifyCVVResponse::parse(&cut).is_err());
+        }
+
+        let mut long = frame.clone();
+        long.push(b'0');
+        assert_eq!(VerifyCVVResponse::parse(&relabel(long)), Err(Error::TrailingBytes { count: 1 }));
+    }
+}
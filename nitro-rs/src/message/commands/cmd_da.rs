@@ -0,0 +1,407 @@
+use bytes::BufMut;
+
+use crate::error::{Error, Result};
+
+use crate::message::header::MessageHeader;
+use crate::message::reader::{as_str, FieldReader};
+use super::cmd_cy::VerifyCVVRequest;
+use super::cmd_dc::VerifyPvvRequest;
+use super::command::{CMD_VERIFYOFFSET_REQUEST, CMD_VERIFYOFFSET_RESPONSE, ResponseCode};
+
+
+// Fixed-size fields: pek(16) + pvk(16) + dectab(16) + format(2) + check_length(2) + offset(12) = 64
+// Plus variable: pin block hex + ';' + pan digits + ';' + validation data + ';'
+pub const VERIFYOFFSET_FIXED_FIELDS_SIZE: usize = 16 + 16 + 16 + 2 + 2 + OFFSET_SIZE;
+
+/// Offsets are left aligned and padded with `F` to this width.
+pub const OFFSET_SIZE: usize = 12;
+
+const CHECK_LENGTH_MIN: u8 = 4;
+const CHECK_LENGTH_MAX: u8 = 12;
+const VALIDATION_DATA_MAX: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyOffsetRequest {
+    pub header: MessageHeader,
+    pub pek:    [u8; 16],
+    pub pvk:    [u8; 16],
+    pub dectab: [u8; 16],
+    pub format: [u8; 2],
+    pub check_length: [u8; 2],
+    pub pin_block: Vec<u8>,
+    pub pan: Vec<u8>,
+    pub validation_data: Vec<u8>,
+    pub offset: [u8; OFFSET_SIZE],
+}
+
+impl VerifyOffsetRequest {
+
+    /// Number of leftmost PIN digits covered by the offset, `04` to `12`.
+    pub(super) fn validate_check_length(check_length: &str) -> Result<[u8; 2]> {
+        let digits = VerifyPvvRequest::validate_digits::<2>("check_length", check_length)?;
+        let value = (digits[0] - b'0') * 10 + (digits[1] - b'0');
+        if !(CHECK_LENGTH_MIN..=CHECK_LENGTH_MAX).contains(&value) {
+            return Err(Error::bad_field("check_length", format!("must be between {:02} and {:02}", CHECK_LENGTH_MIN, CHECK_LENGTH_MAX)));
+        }
+        Ok(digits)
+    }
+
+    /// Hex digits encrypted under the PVK, usually account digits; padded with `F` to 16.
+    pub(super) fn check_validation_data(validation_data: &str) -> Result<()> {
+        if validation_data.is_empty() || validation_data.len() > VALIDATION_DATA_MAX {
+            return Err(Error::BadLength { name: "validation_data", len: validation_data.len() });
+        }
+        if !validation_data.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::bad_field("validation_data", "must contain hex digits only"));
+        }
+        Ok(())
+    }
+
+    /// Offset digits followed by `F` padding.
+    pub(super) fn validate_offset(offset: &str) -> Result<[u8; OFFSET_SIZE]> {
+        if offset.len() != OFFSET_SIZE {
+            return Err(Error::BadLength { name: "offset", len: offset.len() });
+        }
+        let digits = offset.trim_end_matches(['F', 'f']);
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(Error::bad_field("offset", "must be digits padded with F"));
+        }
+        offset.as_bytes().try_into()
+            .map_err(|_| Error::BadLength { name: "offset", len: offset.len() })
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        hdr             : [u8; 4],
+        pek             : &str,
+        pvk             : &str,
+        dectab          : &str,
+        format          : &str,
+        check_length    : &str,
+        pin_block       : &str,
+        pan             : &str,
+        validation_data : &str,
+        offset          : &str,
+    ) -> Result<Self> {
+        let pek          = VerifyPvvRequest::validate_key_id("pek", pek)?;
+        let pvk          = VerifyPvvRequest::validate_key_id("pvk", pvk)?;
+        let dectab       = VerifyPvvRequest::validate_key_id("dectab", dectab)?;
+        let format       = VerifyPvvRequest::validate_digits("format", format)?;
+        let check_length = Self::validate_check_length(check_length)?;
+        VerifyPvvRequest::check_pin_block(pin_block)?;
+        let pan          = VerifyCVVRequest::validate_pan(pan)?;
+        Self::check_validation_data(validation_data)?;
+        let offset       = Self::validate_offset(offset)?;
+
+        let payload_len = VERIFYOFFSET_FIXED_FIELDS_SIZE
+            + pin_block.len() + 1 + pan.len() + 1 + validation_data.len() + 1;
+        let header = MessageHeader::new(hdr, CMD_VERIFYOFFSET_REQUEST, payload_len)?;
+
+        Ok(Self {
+            header,
+            pek,
+            pvk,
+            dectab,
+            format,
+            check_length,
+            pin_block: pin_block.as_bytes().to_vec(),
+            pan,
+            validation_data: validation_data.as_bytes().to_vec(),
+            offset,
+        })
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        VerifyOffsetRequestRef::parse(buffer).map(VerifyOffsetRequestRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.pek);
+        buf.put_slice(&self.pvk);
+        buf.put_slice(&self.dectab);
+        buf.put_slice(&self.format);
+        buf.put_slice(&self.check_length);
+        buf.put_slice(&self.pin_block);
+        buf.put_u8(b';');
+        buf.put_slice(&self.pan);
+        buf.put_u8(b';');
+        buf.put_slice(&self.validation_data);
+        buf.put_u8(b';');
+        buf.put_slice(&self.offset);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a VerifyOffset request over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyOffsetRequestRef<'a> {
+    pub header: MessageHeader,
+    pub pek:    &'a [u8; 16],
+    pub pvk:    &'a [u8; 16],
+    pub dectab: &'a [u8; 16],
+    pub format: &'a [u8; 2],
+    pub check_length: &'a [u8; 2],
+    pub pin_block: &'a [u8],
+    pub pan: &'a [u8],
+    pub validation_data: &'a [u8],
+    pub offset: &'a [u8; OFFSET_SIZE],
+}
+
+impl<'a> VerifyOffsetRequestRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_VERIFYOFFSET_REQUEST) {
+            return Err(Error::UnexpectedCommand { expected: CMD_VERIFYOFFSET_REQUEST, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let pek = reader.take_array_ref("pek")?;
+        VerifyPvvRequest::validate_key_id("pek", as_str("pek", pek)?)?;
+
+        let pvk = reader.take_array_ref("pvk")?;
+        VerifyPvvRequest::validate_key_id("pvk", as_str("pvk", pvk)?)?;
+
+        let dectab = reader.take_array_ref("dectab")?;
+        VerifyPvvRequest::validate_key_id("dectab", as_str("dectab", dectab)?)?;
+
+        let format = reader.take_array_ref("format")?;
+        VerifyPvvRequest::validate_digits::<2>("format", as_str("format", format)?)?;
+
+        let check_length = reader.take_array_ref("check_length")?;
+        VerifyOffsetRequest::validate_check_length(as_str("check_length", check_length)?)?;
+
+        let pin_block = reader.take_delimited_str("pin_block")?;
+        VerifyPvvRequest::check_pin_block(pin_block)?;
+
+        let pan = reader.take_delimited_str("pan")?;
+        VerifyCVVRequest::check_pan(pan)?;
+
+        let validation_data = reader.take_delimited_str("validation_data")?;
+        VerifyOffsetRequest::check_validation_data(validation_data)?;
+
+        let offset = reader.take_array_ref("offset")?;
+        VerifyOffsetRequest::validate_offset(as_str("offset", offset)?)?;
+
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            pek,
+            pvk,
+            dectab,
+            format,
+            check_length,
+            pin_block: pin_block.as_bytes(),
+            pan: pan.as_bytes(),
+            validation_data: validation_data.as_bytes(),
+            offset,
+        })
+    }
+
+    pub fn pek_id(&self) -> &'a str {
+        as_str("pek", self.pek).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn pvk_id(&self) -> &'a str {
+        as_str("pvk", self.pvk).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn dectab_id(&self) -> &'a str {
+        as_str("dectab", self.dectab).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn check_length(&self) -> usize {
+        as_str("check_length", self.check_length).ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(self.pek);
+        buf.put_slice(self.pvk);
+        buf.put_slice(self.dectab);
+        buf.put_slice(self.format);
+        buf.put_slice(self.check_length);
+        buf.put_slice(self.pin_block);
+        buf.put_u8(b';');
+        buf.put_slice(self.pan);
+        buf.put_u8(b';');
+        buf.put_slice(self.validation_data);
+        buf.put_u8(b';');
+        buf.put_slice(self.offset);
+    }
+
+    pub fn into_owned(self) -> VerifyOffsetRequest {
+        VerifyOffsetRequest {
+            header: self.header,
+            pek: *self.pek,
+            pvk: *self.pvk,
+            dectab: *self.dectab,
+            format: *self.format,
+            check_length: *self.check_length,
+            pin_block: self.pin_block.to_vec(),
+            pan: self.pan.to_vec(),
+            validation_data: self.validation_data.to_vec(),
+            offset: *self.offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyOffsetResponse {
+    pub header: MessageHeader,
+    pub response_code: [u8; 2],
+}
+
+impl VerifyOffsetResponse {
+    pub fn success(hdr: [u8; 4]) -> Self {
+        Self::error(hdr, ResponseCode::Success)
+    }
+
+    pub fn error(hdr: [u8; 4], error_code: ResponseCode) -> Self {
+        let data_length = 2;
+        let header = MessageHeader::fixed(hdr, CMD_VERIFYOFFSET_RESPONSE, data_length);
+
+        Self {
+            header,
+            response_code: error_code.as_bytes(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.response_code == ResponseCode::Success.as_bytes()
+    }
+
+    pub fn response_code_str(&self) -> String {
+        String::from_utf8_lossy(&self.response_code).to_string()
+    }
+
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_VERIFYOFFSET_RESPONSE) {
+            return Err(Error::UnexpectedCommand { expected: CMD_VERIFYOFFSET_RESPONSE, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let response_code = reader.take_array("response_code")?;
+
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            response_code
+        })
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::message::testing::{relabel, relabeled_truncations, truncations, with_declared_len};
+
+    fn request() -> Vec<u8> {
+        VerifyOffsetRequest::new(
+            *b"0001", "pek", "pvk", "dectab", "01", "06", "0412AC89ABCDEF67", "4111111111111111", "41111111111F", "9256FFFFFFFF",
+        )
+        .unwrap()
+        .to_bytes()
+    }
+
+    #[test]
+    fn request_round_trips() {
+        let frame = request();
+        assert_eq!(VerifyOffsetRequest::parse(&frame).unwrap().to_bytes(), frame);
+
+        let view = VerifyOffsetRequestRef::parse(&frame).unwrap();
+        assert_eq!((view.pek_id(), view.pvk_id(), view.dectab_id()), ("pek", "pvk", "dectab"));
+        assert_eq!(view.offset, b"9256FFFFFFFF");
+    }
+
+    #[test]
+    fn request_truncated_at_every_offset_is_rejected() {
+        let frame = request();
+        for cut in truncations(&frame) {
+            assert!(VerifyOffsetRequestRef::parse(cut).is_err(), "accepted {} of {} bytes", cut.len(), frame.len());
+            assert!(Message::parse(cut).is_err());
+        }
+        for cut in relabeled_truncations(&frame) {
+            assert!(VerifyOffsetRequestRef::parse(&cut).is_err(), "accepted relabeled {} of {} bytes", cut.len(), frame.len());
+        }
+    }
+
+    #[test]
+    fn request_with_trailing_bytes_is_rejected() {
+        let mut frame = request();
+        frame.push(b'F');
+        assert_eq!(VerifyOffsetRequestRef::parse(&frame), Err(Error::TrailingBytes { count: 1 }));
+        assert_eq!(VerifyOffsetRequestRef::parse(&relabel(frame)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn request_with_wrong_declared_len_is_rejected() {
+        let frame = request();
+        assert!(matches!(VerifyOffsetRequestRef::parse(&with_declared_len(&frame, 1)), Err(Error::TooShort { .. })));
+        assert_eq!(VerifyOffsetRequestRef::parse(&with_declared_len(&frame, -1)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn request_with_bad_offset_is_rejected() {
+        // Digits must come before the padding, and only digits and F are allowed
+        for offset in ["F9256FFFFFFF", "92A6FFFFFFFF", "9256FFFFFFF"] {
+            let result = VerifyOffsetRequest::new(
+                *b"0001", "pek", "pvk", "dectab", "01", "06", "0412AC89ABCDEF67", "4111111111111111", "41111111111F", offset,
+            );
+            assert!(result.is_err(), "accepted offset {}", offset);
+        }
+
+        let mut frame = request();
+        let at = frame.len() - OFFSET_SIZE;
+        frame[at] = b'F';
+        assert!(VerifyOffsetRequestRef::parse(&frame).is_err());
+    }
+
+    #[test]
+    fn response_truncated_at_every_offset_is_rejected() {
+        let frame = VerifyOffsetResponse::error(*b"0001", ResponseCode::PinMismatch).to_bytes();
+        assert_eq!(VerifyOffsetResponse::parse(&frame).unwrap().code(), Some(ResponseCode::PinMismatch));
+
+        for cut in truncations(&frame) {
+            assert!(VerifyOffsetResponse::parse(cut).is_err());
+        }
+        for cut in relabeled_truncations(&frame) {
+            assert!(VerifyOffsetResponse::parse(&cut).is_err());
+        }
+
+        let mut long = frame.clone();
+        long.push(b'0');
+        assert_eq!(VerifyOffsetResponse::parse(&relabel(long)), Err(Error::TrailingBytes { count: 1 }));
+    }
+}
@@ -0,0 +1,377 @@
+use bytes::BufMut;
+
+use crate::error::{Error, Result};
+
+use crate::message::header::MessageHeader;
+use crate::message::reader::{as_str, FieldReader};
+use super::cmd_cy::VerifyCVVRequest;
+use super::command::{CMD_VERIFYPVV_REQUEST, CMD_VERIFYPVV_RESPONSE, ResponseCode};
+
+
+// Fixed-size fields: pek(16) + pvka(16) + pvkb(16) + format(2) + pvki(1) + pvv(4) = 55
+// Plus variable: pin block hex + ';' + pan digits + ';'
+pub const VERIFYPVV_FIXED_FIELDS_SIZE: usize = 16 + 16 + 16 + 2 + 1 + 4;
+
+/// Hex digits in a DES PIN block; AES blocks (ISO format 4) take twice as many.
+pub const PIN_BLOCK_DES_HEX_LEN: usize = 16;
+pub const PIN_BLOCK_AES_HEX_LEN: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyPvvRequest {
+    pub header: MessageHeader,
+    pub pek:  [u8; 16],
+    pub pvka: [u8; 16],
+    pub pvkb: [u8; 16],
+    pub format: [u8; 2],
+    pub pin_block: Vec<u8>,
+    pub pan: Vec<u8>,
+    pub pvki: [u8; 1],
+    pub pvv:  [u8; 4],
+}
+
+impl VerifyPvvRequest {
+
+    /// Key references are names of up to 16 bytes, zero padded on the wire.
+    pub(super) fn validate_key_id(name: &'static str, key_id: &str) -> Result<[u8; 16]> {
+        if key_id.is_empty() || key_id.len() > 16 {
+            return Err(Error::BadLength { name, len: key_id.len() });
+        }
+        let mut buf = [0u8; 16];
+        buf[..key_id.len()].copy_from_slice(key_id.as_bytes());
+        Ok(buf)
+    }
+
+    pub(super) fn validate_digits<const N: usize>(name: &'static str, value: &str) -> Result<[u8; N]> {
+        if value.len() != N {
+            return Err(Error::BadLength { name, len: value.len() });
+        }
+        if !value.chars().all(|c| c.is_ascii_digit()) {
+            return Err(Error::bad_field(name, "must contain digits only"));
+        }
+        value.as_bytes().try_into()
+            .map_err(|_| Error::BadLength { name, len: value.len() })
+    }
+
+    pub(super) fn check_pin_block(pin_block: &str) -> Result<()> {
+        if pin_block.len() != PIN_BLOCK_DES_HEX_LEN && pin_block.len() != PIN_BLOCK_AES_HEX_LEN {
+            return Err(Error::BadLength { name: "pin_block", len: pin_block.len() });
+        }
+        if !pin_block.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::bad_field("pin_block", "must contain hex digits only"));
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        hdr       : [u8; 4],
+        pek       : &str,
+        pvka      : &str,
+        pvkb      : &str,
+        format    : &str,
+        pin_block : &str,
+        pan       : &str,
+        pvki      : &str,
+        pvv       : &str,
+    ) -> Result<Self> {
+        let pek    = Self::validate_key_id("pek", pek)?;
+        let pvka   = Self::validate_key_id("pvka", pvka)?;
+        let pvkb   = Self::validate_key_id("pvkb", pvkb)?;
+        let format = Self::validate_digits("format", format)?;
+        Self::check_pin_block(pin_block)?;
+        let pan    = VerifyCVVRequest::validate_pan(pan)?;
+        let pvki   = Self::validate_digits("pvki", pvki)?;
+        let pvv    = Self::validate_digits("pvv", pvv)?;
+
+        let payload_len = VERIFYPVV_FIXED_FIELDS_SIZE + pin_block.len() + 1 + pan.len() + 1;
+        let header = MessageHeader::new(hdr, CMD_VERIFYPVV_REQUEST, payload_len)?;
+
+        Ok(Self { header, pek, pvka, pvkb, format, pin_block: pin_block.as_bytes().to_vec(), pan, pvki, pvv })
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        VerifyPvvRequestRef::parse(buffer).map(VerifyPvvRequestRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.pek);
+        buf.put_slice(&self.pvka);
+        buf.put_slice(&self.pvkb);
+        buf.put_slice(&self.format);
+        buf.put_slice(&self.pin_block);
+        buf.put_u8(b';');
+        buf.put_slice(&self.pan);
+        buf.put_u8(b';');
+        buf.put_slice(&self.pvki);
+        buf.put_slice(&self.pvv);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a VerifyPVV request over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyPvvRequestRef<'a> {
+    pub header: MessageHeader,
+    pub pek:  &'a [u8; 16],
+    pub pvka: &'a [u8; 16],
+    pub pvkb: &'a [u8; 16],
+    pub format: &'a [u8; 2],
+    pub pin_block: &'a [u8],
+    pub pan: &'a [u8],
+    pub pvki: &'a [u8; 1],
+    pub pvv:  &'a [u8; 4],
+}
+
+impl<'a> VerifyPvvRequestRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_VERIFYPVV_REQUEST) {
+            return Err(Error::UnexpectedCommand { expected: CMD_VERIFYPVV_REQUEST, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let pek = reader.take_array_ref("pek")?;
+        VerifyPvvRequest::validate_key_id("pek", as_str("pek", pek)?)?;
+
+        let pvka = reader.take_array_ref("pvka")?;
+        VerifyPvvRequest::validate_key_id("pvka", as_str("pvka", pvka)?)?;
+
+        let pvkb = reader.take_array_ref("pvkb")?;
+        VerifyPvvRequest::validate_key_id("pvkb", as_str("pvkb", pvkb)?)?;
+
+        let format = reader.take_array_ref("format")?;
+        VerifyPvvRequest::validate_digits::<2>("format", as_str("format", format)?)?;
+
+        let pin_block = reader.take_delimited_str("pin_block")?;
+        VerifyPvvRequest::check_pin_block(pin_block)?;
+
+        let pan = reader.take_delimited_str("pan")?;
+        VerifyCVVRequest::check_pan(pan)?;
+
+        let pvki = reader.take_array_ref("pvki")?;
+        VerifyPvvRequest::validate_digits::<1>("pvki", as_str("pvki", pvki)?)?;
+
+        let pvv = reader.take_array_ref("pvv")?;
+        VerifyPvvRequest::validate_digits::<4>("pvv", as_str("pvv", pvv)?)?;
+
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            pek,
+            pvka,
+            pvkb,
+            format,
+            pin_block: pin_block.as_bytes(),
+            pan: pan.as_bytes(),
+            pvki,
+            pvv,
+        })
+    }
+
+    pub fn pek_id(&self) -> &'a str {
+        as_str("pek", self.pek).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn pvka_id(&self) -> &'a str {
+        as_str("pvka", self.pvka).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn pvkb_id(&self) -> &'a str {
+        as_str("pvkb", self.pvkb).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(self.pek);
+        buf.put_slice(self.pvka);
+        buf.put_slice(self.pvkb);
+        buf.put_slice(self.format);
+        buf.put_slice(self.pin_block);
+        buf.put_u8(b';');
+        buf.put_slice(self.pan);
+        buf.put_u8(b';');
+        buf.put_slice(self.pvki);
+        buf.put_slice(self.pvv);
+    }
+
+    pub fn into_owned(self) -> VerifyPvvRequest {
+        VerifyPvvRequest {
+            header: self.header,
+            pek: *self.pek,
+            pvka: *self.pvka,
+            pvkb: *self.pvkb,
+            format: *self.format,
+            pin_block: self.pin_block.to_vec(),
+            pan: self.pan.to_vec(),
+            pvki: *self.pvki,
+            pvv: *self.pvv,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyPvvResponse {
+    pub header: MessageHeader,
+    pub response_code: [u8; 2],
+}
+
+impl VerifyPvvResponse {
+    pub fn success(hdr: [u8; 4]) -> Self {
+        Self::error(hdr, ResponseCode::Success)
+    }
+
+    pub fn error(hdr: [u8; 4], error_code: ResponseCode) -> Self {
+        let data_length = 2;
+        let header = MessageHeader::fixed(hdr, CMD_VERIFYPVV_RESPONSE, data_length);
+
+        Self {
+            header,
+            response_code: error_code.as_bytes(),
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.response_code == ResponseCode::Success.as_bytes()
+    }
+
+    pub fn response_code_str(&self) -> String {
+        String::from_utf8_lossy(&self.response_code).to_string()
+    }
+
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_VERIFYPVV_RESPONSE) {
+            return Err(Error::UnexpectedCommand { expected: CMD_VERIFYPVV_RESPONSE, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let response_code = reader.take_array("response_code")?;
+
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            response_code
+        })
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::message::testing::{relabel, relabeled_truncations, truncations, with_declared_len};
+
+    fn request() -> Vec<u8> {
+        VerifyPvvRequest::new(*b"0001", "pek", "pvka", "pvkb", "01", "0412AC89ABCDEF67", "4111111111111111", "1", "1894")
+            .unwrap()
+            .to_bytes()
+    }
+
+    #[test]
+    fn request_round_trips() {
+        let frame = request();
+        let parsed = VerifyPvvRequest::parse(&frame).unwrap();
+        assert_eq!(parsed.to_bytes(), frame);
+
+        let view = VerifyPvvRequestRef::parse(&frame).unwrap();
+        assert_eq!((view.pek_id(), view.pvka_id(), view.pvkb_id()), ("pek", "pvka", "pvkb"));
+        assert_eq!(view.pin_block, b"0412AC89ABCDEF67");
+        assert_eq!(view.pvv, b"1894");
+    }
+
+    #[test]
+    fn request_accepts_aes_pin_block() {
+        let frame = VerifyPvvRequest::new(*b"0001", "pek", "pvka", "pvkb", "48", &"A".repeat(PIN_BLOCK_AES_HEX_LEN), "4111111111111111", "1", "1894")
+            .unwrap()
+            .to_bytes();
+        assert_eq!(VerifyPvvRequest::parse(&frame).unwrap().to_bytes(), frame);
+    }
+
+    #[test]
+    fn request_truncated_at_every_offset_is_rejected() {
+        let frame = request();
+        for cut in truncations(&frame) {
+            assert!(VerifyPvvRequestRef::parse(cut).is_err(), "accepted {} of {} bytes", cut.len(), frame.len());
+            assert!(Message::parse(cut).is_err());
+        }
+        for cut in relabeled_truncations(&frame) {
+            assert!(VerifyPvvRequestRef::parse(&cut).is_err(), "accepted relabeled {} of {} bytes", cut.len(), frame.len());
+        }
+    }
+
+    #[test]
+    fn request_with_trailing_bytes_is_rejected() {
+        let mut frame = request();
+        frame.push(b'0');
+        assert_eq!(VerifyPvvRequestRef::parse(&frame), Err(Error::TrailingBytes { count: 1 }));
+        assert_eq!(VerifyPvvRequestRef::parse(&relabel(frame)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn request_with_wrong_declared_len_is_rejected() {
+        let frame = request();
+        assert!(matches!(VerifyPvvRequestRef::parse(&with_declared_len(&frame, 1)), Err(Error::TooShort { .. })));
+        assert_eq!(VerifyPvvRequestRef::parse(&with_declared_len(&frame, -1)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn request_with_bad_pin_block_is_rejected() {
+        let frame = request();
+        let at = frame.windows(16).position(|window| window == b"0412AC89ABCDEF67").unwrap();
+
+        let mut bad_digit = frame.clone();
+        bad_digit[at] = b'G';
+        assert!(VerifyPvvRequestRef::parse(&bad_digit).is_err());
+
+        // A 17-digit block: the delimiter moves one byte further
+        let mut long = frame[..at + 16].to_vec();
+        long.push(b'0');
+        long.extend_from_slice(&frame[at + 16..]);
+        assert_eq!(VerifyPvvRequestRef::parse(&relabel(long)), Err(Error::BadLength { name: "pin_block", len: 17 }));
+    }
+
+    #[test]
+    fn response_truncated_at_every_offset_is_rejected() {
+        let frame = VerifyPvvResponse::error(*b"0001", ResponseCode::PinMismatch).to_bytes();
+        assert_eq!(VerifyPvvResponse::parse(&frame).unwrap().code(), Some(ResponseCode::PinMismatch));
+
+        for cut in truncations(&frame) {
+            assert!(VerifyPvvResponse::parse(cut).is_err());
+        }
+        for cut in relabeled_truncations(&frame) {
+            assert!(VerifyPvvResponse::parse(&cut).is_err());
+        }
+
+        let mut long = frame.clone();
+        long.push(b'0');
+        assert_eq!(VerifyPvvResponse::parse(&relabel(long)), Err(Error::TrailingBytes { count: 1 }));
+    }
+}
@@ -0,0 +1,378 @@
+use bytes::BufMut;
+
+use crate::error::{Error, Result};
+
+use crate::message::header::MessageHeader;
+use crate::message::reader::{as_str, FieldReader};
+use super::cmd_cy::VerifyCVVRequest;
+use super::cmd_da::{VerifyOffsetRequest, OFFSET_SIZE};
+use super::cmd_dc::VerifyPvvRequest;
+use super::command::{CMD_GENERATEOFFSET_REQUEST, CMD_GENERATEOFFSET_RESPONSE, ResponseCode};
+
+
+// Fixed-size fields: pek(16) + pvk(16) + dectab(16) + format(2) + check_length(2) = 52
+// Plus variable: pin block hex + ';' + pan digits + ';' + validation data + ';'
+pub const GENERATEOFFSET_FIXED_FIELDS_SIZE: usize = 16 + 16 + 16 + 2 + 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GenerateOffsetRequest {
+    pub header: MessageHeader,
+    pub pek:    [u8; 16],
+    pub pvk:    [u8; 16],
+    pub dectab: [u8; 16],
+    pub format: [u8; 2],
+    pub check_length: [u8; 2],
+    pub pin_block: Vec<u8>,
+    pub pan: Vec<u8>,
+    pub validation_data: Vec<u8>,
+}
+
+impl GenerateOffsetRequest {
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        hdr             : [u8; 4],
+        pek             : &str,
+        pvk             : &str,
+        dectab          : &str,
+        format          : &str,
+        check_length    : &str,
+        pin_block       : &str,
+        pan             : &str,
+        validation_data : &str,
+    ) -> Result<Self> {
+        let pek          = VerifyPvvRequest::validate_key_id("pek", pek)?;
+        let pvk          = VerifyPvvRequest::validate_key_id("pvk", pvk)?;
+        let dectab       = VerifyPvvRequest::validate_key_id("dectab", dectab)?;
+        let format       = VerifyPvvRequest::validate_digits("format", format)?;
+        let check_length = VerifyOffsetRequest::validate_check_length(check_length)?;
+        VerifyPvvRequest::check_pin_block(pin_block)?;
+        let pan          = VerifyCVVRequest::validate_pan(pan)?;
+        VerifyOffsetRequest::check_validation_data(validation_data)?;
+
+        let payload_len = GENERATEOFFSET_FIXED_FIELDS_SIZE
+            + pin_block.len() + 1 + pan.len() + 1 + validation_data.len() + 1;
+        let header = MessageHeader::new(hdr, CMD_GENERATEOFFSET_REQUEST, payload_len)?;
+
+        Ok(Self {
+            header,
+            pek,
+            pvk,
+            dectab,
+            format,
+            check_length,
+            pin_block: pin_block.as_bytes().to_vec(),
+            pan,
+            validation_data: validation_data.as_bytes().to_vec(),
+        })
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        GenerateOffsetRequestRef::parse(buffer).map(GenerateOffsetRequestRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.pek);
+        buf.put_slice(&self.pvk);
+        buf.put_slice(&self.dectab);
+        buf.put_slice(&self.format);
+        buf.put_slice(&self.check_length);
+        buf.put_slice(&self.pin_block);
+        buf.put_u8(b';');
+        buf.put_slice(&self.pan);
+        buf.put_u8(b';');
+        buf.put_slice(&self.validation_data);
+        buf.put_u8(b';');
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a GenerateOffset request over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenerateOffsetRequestRef<'a> {
+    pub header: MessageHeader,
+    pub pek:    &'a [u8; 16],
+    pub pvk:    &'a [u8; 16],
+    pub dectab: &'a [u8; 16],
+    pub format: &'a [u8; 2],
+    pub check_length: &'a [u8; 2],
+    pub pin_block: &'a [u8],
+    pub pan: &'a [u8],
+    pub validation_data: &'a [u8],
+}
+
+impl<'a> GenerateOffsetRequestRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_GENERATEOFFSET_REQUEST) {
+            return Err(Error::UnexpectedCommand { expected: CMD_GENERATEOFFSET_REQUEST, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let pek = reader.take_array_ref("pek")?;
+        VerifyPvvRequest::validate_key_id("pek", as_str("pek", pek)?)?;
+
+        let pvk = reader.take_array_ref("pvk")?;
+        VerifyPvvRequest::validate_key_id("pvk", as_str("pvk", pvk)?)?;
+
+        let dectab = reader.take_array_ref("dectab")?;
+        VerifyPvvRequest::validate_key_id("dectab", as_str("dectab", dectab)?)?;
+
+        let format = reader.take_array_ref("format")?;
+        VerifyPvvRequest::validate_digits::<2>("format", as_str("format", format)?)?;
+
+        let check_length = reader.take_array_ref("check_length")?;
+        VerifyOffsetRequest::validate_check_length(as_str("check_length", check_length)?)?;
+
+        let pin_block = reader.take_delimited_str("pin_block")?;
+        VerifyPvvRequest::check_pin_block(pin_block)?;
+
+        let pan = reader.take_delimited_str("pan")?;
+        VerifyCVVRequest::check_pan(pan)?;
+
+        let validation_data = reader.take_delimited_str("validation_data")?;
+        VerifyOffsetRequest::check_validation_data(validation_data)?;
+
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            pek,
+            pvk,
+            dectab,
+            format,
+            check_length,
+            pin_block: pin_block.as_bytes(),
+            pan: pan.as_bytes(),
+            validation_data: validation_data.as_bytes(),
+        })
+    }
+
+    pub fn pek_id(&self) -> &'a str {
+        as_str("pek", self.pek).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn pvk_id(&self) -> &'a str {
+        as_str("pvk", self.pvk).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn dectab_id(&self) -> &'a str {
+        as_str("dectab", self.dectab).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn check_length(&self) -> usize {
+        as_str("check_length", self.check_length).ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(self.pek);
+        buf.put_slice(self.pvk);
+        buf.put_slice(self.dectab);
+        buf.put_slice(self.format);
+        buf.put_slice(self.check_length);
+        buf.put_slice(self.pin_block);
+        buf.put_u8(b';');
+        buf.put_slice(self.pan);
+        buf.put_u8(b';');
+        buf.put_slice(self.validation_data);
+        buf.put_u8(b';');
+    }
+
+    pub fn into_owned(self) -> GenerateOffsetRequest {
+        GenerateOffsetRequest {
+            header: self.header,
+            pek: *self.pek,
+            pvk: *self.pvk,
+            dectab: *self.dectab,
+            format: *self.format,
+            check_length: *self.check_length,
+            pin_block: self.pin_block.to_vec(),
+            pan: self.pan.to_vec(),
+            validation_data: self.validation_data.to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GenerateOffsetResponse {
+    pub header: MessageHeader,
+    pub response_code: [u8; 2],
+    pub offset: Option<[u8; OFFSET_SIZE]>,
+}
+
+impl GenerateOffsetResponse {
+    pub fn success(hdr: [u8; 4], offset: [u8; OFFSET_SIZE]) -> Self {
+        let data_length = 2 + offset.len() as u16;
+        let header = MessageHeader::fixed(hdr, CMD_GENERATEOFFSET_RESPONSE, data_length);
+
+        Self {
+            header,
+            response_code: ResponseCode::Success.as_bytes(),
+            offset: Some(offset),
+        }
+    }
+
+    pub fn error(hdr: [u8; 4], error_code: ResponseCode) -> Self {
+        let data_length = 2;
+        let header = MessageHeader::fixed(hdr, CMD_GENERATEOFFSET_RESPONSE, data_length);
+
+        Self {
+            header,
+            response_code: error_code.as_bytes(),
+            offset: None,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.response_code == ResponseCode::Success.as_bytes()
+    }
+
+    pub fn response_code_str(&self) -> String {
+        String::from_utf8_lossy(&self.response_code).to_string()
+    }
+
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
+    pub fn offset_str(&self) -> Option<String> {
+        self.offset.map(|offset| String::from_utf8_lossy(&offset).to_string())
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_GENERATEOFFSET_RESPONSE) {
+            return Err(Error::UnexpectedCommand { expected: CMD_GENERATEOFFSET_RESPONSE, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let response_code = reader.take_array("response_code")?;
+
+        let offset = if reader.remaining() > 0 {
+            Some(reader.take_array("offset")?)
+        } else {
+            None
+        };
+
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            response_code,
+            offset,
+        })
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+
+        if let Some(ref offset) = self.offset {
+            buf.put_slice(offset);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::message::testing::{relabel, relabeled_truncations, truncations, with_declared_len};
+
+    fn request() -> Vec<u8> {
+        GenerateOffsetRequest::new(*b"0001", "pek", "pvk", "dectab", "01", "06", "0412AC89ABCDEF67", "4111111111111111", "41111111111F")
+            .unwrap()
+            .to_bytes()
+    }
+
+    #[test]
+    fn request_round_trips() {
+        let frame = request();
+        assert_eq!(GenerateOffsetRequest::parse(&frame).unwrap().to_bytes(), frame);
+
+        let view = GenerateOffsetRequestRef::parse(&frame).unwrap();
+        assert_eq!((view.pek_id(), view.pvk_id(), view.dectab_id()), ("pek", "pvk", "dectab"));
+        assert_eq!(view.validation_data, b"41111111111F");
+    }
+
+    #[test]
+    fn request_truncated_at_every_offset_is_rejected() {
+        let frame = request();
+        for cut in truncations(&frame) {
+            assert!(GenerateOffsetRequestRef::parse(cut).is_err(), "accepted {} of {} bytes", cut.len(), frame.len());
+            assert!(Message::parse(cut).is_err());
+        }
+        for cut in relabeled_truncations(&frame) {
+            assert!(GenerateOffsetRequestRef::parse(&cut).is_err(), "accepted relabeled {} of {} bytes", cut.len(), frame.len());
+        }
+    }
+
+    #[test]
+    fn request_with_trailing_bytes_is_rejected() {
+        let mut frame = request();
+        frame.push(b'0');
+        assert_eq!(GenerateOffsetRequestRef::parse(&frame), Err(Error::TrailingBytes { count: 1 }));
+        assert_eq!(GenerateOffsetRequestRef::parse(&relabel(frame)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn request_with_wrong_declared_len_is_rejected() {
+        let frame = request();
+        assert!(matches!(GenerateOffsetRequestRef::parse(&with_declared_len(&frame, 1)), Err(Error::TooShort { .. })));
+        assert_eq!(GenerateOffsetRequestRef::parse(&with_declared_len(&frame, -1)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn request_with_bad_check_length_is_rejected() {
+        for check_length in ["03", "13", "0A"] {
+            assert!(GenerateOffsetRequest::new(*b"0001", "pek", "pvk", "dectab", "01", check_length, "0412AC89ABCDEF67", "4111111111111111", "4111")
+                .is_err());
+        }
+
+        let mut frame = request();
+        let at = MessageHeader::parse(&frame).unwrap().header_length() + 16 * 3 + 2;
+        frame[at..at + 2].copy_from_slice(b"13");
+        assert!(GenerateOffsetRequestRef::parse(&frame).is_err());
+    }
+
+    #[test]
+    fn response_truncated_at_every_offset_is_rejected() {
+        let frame = GenerateOffsetResponse::success(*b"0001", *b"9256FFFFFFFF").to_bytes();
+        assert_eq!(GenerateOffsetResponse::parse(&frame).unwrap().offset_str().as_deref(), Some("9256FFFFFFFF"));
+
+        for cut in truncations(&frame) {
+            assert!(GenerateOffsetResponse::parse(cut).is_err());
+        }
+
+        // A bare response code is a valid error response; a partial offset is not
+        let header_len = GenerateOffsetResponse::error(*b"0001", ResponseCode::SystemError).header.header_length();
+        for cut in relabeled_truncations(&frame) {
+            let parsed = GenerateOffsetResponse::parse(&cut);
+            assert_eq!(parsed.is_ok(), cut.len() == header_len + 2, "relabeled {} bytes", cut.len());
+        }
+
+        let mut long = frame.clone();
+        long.push(b'F');
+        assert_eq!(GenerateOffsetResponse::parse(&relabel(long)), Err(Error::TrailingBytes { count: 1 }));
+    }
+}
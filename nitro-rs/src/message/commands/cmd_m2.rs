@@ -0,0 +1,328 @@
+use std::fmt;
+
+use bytes::BufMut;
+use zeroize::Zeroizing;
+
+use crate::error::{Error, Result};
+
+use crate::message::header::MessageHeader;
+use crate::message::reader::{as_str, FieldReader};
+use super::cmd_dc::VerifyPvvRequest;
+use super::cmd_g0::{DukptTranslatePinRequest, KSN_TDES_HEX_LEN};
+use super::command::{CMD_DUKPTDECRYPT_REQUEST, CMD_DUKPTDECRYPT_RESPONSE, ResponseCode};
+
+
+// Fixed-size fields: bdk(16)
+// Plus variable: ksn hex + ';' + data hex + ';'
+pub const DUKPTDECRYPT_FIXED_FIELDS_SIZE: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DukptDecryptRequest {
+    pub header: MessageHeader,
+    pub bdk: [u8; 16],
+    pub ksn: Vec<u8>,
+    pub data: Vec<u8>,
+}
+
+impl DukptDecryptRequest {
+
+    /// Hex ciphertext in whole blocks: 8 bytes for TDES DUKPT, 16 for AES.
+    pub(super) fn check_data(ksn: &str, data: &str) -> Result<()> {
+        let block_hex_len = if ksn.len() == KSN_TDES_HEX_LEN { 16 } else { 32 };
+
+        if data.is_empty() || !data.len().is_multiple_of(block_hex_len) {
+            return Err(Error::BadLength { name: "data", len: data.len() });
+        }
+        if !data.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::bad_field("data", "must contain hex digits only"));
+        }
+        Ok(())
+    }
+
+    pub fn new(hdr: [u8; 4], bdk: &str, ksn: &str, data: &str) -> Result<Self> {
+        let bdk = VerifyPvvRequest::validate_key_id("bdk", bdk)?;
+        DukptTranslatePinRequest::check_ksn(ksn)?;
+        Self::check_data(ksn, data)?;
+
+        let payload_len = DUKPTDECRYPT_FIXED_FIELDS_SIZE + ksn.len() + 1 + data.len() + 1;
+        let header = MessageHeader::new(hdr, CMD_DUKPTDECRYPT_REQUEST, payload_len)?;
+
+        Ok(Self {
+            header,
+            bdk,
+            ksn: ksn.as_bytes().to_vec(),
+            data: data.as_bytes().to_vec(),
+        })
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        DukptDecryptRequestRef::parse(buffer).map(DukptDecryptRequestRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.bdk);
+        buf.put_slice(&self.ksn);
+        buf.put_u8(b';');
+        buf.put_slice(&self.data);
+        buf.put_u8(b';');
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a DUKPT DecryptData request over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DukptDecryptRequestRef<'a> {
+    pub header: MessageHeader,
+    pub bdk: &'a [u8; 16],
+    pub ksn: &'a [u8],
+    pub data: &'a [u8],
+}
+
+impl<'a> DukptDecryptRequestRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_DUKPTDECRYPT_REQUEST) {
+            return Err(Error::UnexpectedCommand { expected: CMD_DUKPTDECRYPT_REQUEST, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let bdk = reader.take_array_ref("bdk")?;
+        VerifyPvvRequest::validate_key_id("bdk", as_str("bdk", bdk)?)?;
+
+        let ksn = reader.take_delimited_str("ksn")?;
+        DukptTranslatePinRequest::check_ksn(ksn)?;
+
+        let data = reader.take_delimited_str("data")?;
+        DukptDecryptRequest::check_data(ksn, data)?;
+
+        reader.finish()?;
+
+        Ok(Self {
+            header,
+            bdk,
+            ksn: ksn.as_bytes(),
+            data: data.as_bytes(),
+        })
+    }
+
+    pub fn bdk_id(&self) -> &'a str {
+        as_str("bdk", self.bdk).unwrap_or_default().trim_end_matches('\0')
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(self.bdk);
+        buf.put_slice(self.ksn);
+        buf.put_u8(b';');
+        buf.put_slice(self.data);
+        buf.put_u8(b';');
+    }
+
+    pub fn into_owned(self) -> DukptDecryptRequest {
+        DukptDecryptRequest {
+            header: self.header,
+            bdk: *self.bdk,
+            ksn: self.ksn.to_vec(),
+            data: self.data.to_vec(),
+        }
+    }
+}
+
+/// Clear data is wiped on drop and left out of `Debug` output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct DukptDecryptResponse {
+    pub header: MessageHeader,
+    pub response_code: [u8; 2],
+    pub data: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl DukptDecryptResponse {
+    pub fn success(hdr: [u8; 4], data: &str) -> Result<Self> {
+        Self::check_clear_data(data)?;
+
+        let data_length = 2 + data.len();
+        let header = MessageHeader::new(hdr, CMD_DUKPTDECRYPT_RESPONSE, data_length)?;
+
+        Ok(Self {
+            header,
+            response_code: ResponseCode::Success.as_bytes(),
+            data: Some(Zeroizing::new(data.as_bytes().to_vec())),
+        })
+    }
+
+    pub fn error(hdr: [u8; 4], error_code: ResponseCode) -> Self {
+        let data_length = 2;
+        let header = MessageHeader::fixed(hdr, CMD_DUKPTDECRYPT_RESPONSE, data_length);
+
+        Self {
+            header,
+            response_code: error_code.as_bytes(),
+            data: None,
+        }
+    }
+
+    fn check_clear_data(data: &str) -> Result<()> {
+        if data.is_empty() || !data.len().is_multiple_of(2) {
+            return Err(Error::BadLength { name: "data", len: data.len() });
+        }
+        if !data.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::bad_field("data", "must contain hex digits only"));
+        }
+        Ok(())
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.response_code == ResponseCode::Success.as_bytes()
+    }
+
+    pub fn response_code_str(&self) -> String {
+        String::from_utf8_lossy(&self.response_code).to_string()
+    }
+
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
+    pub fn data_str(&self) -> Option<Zeroizing<String>> {
+        self.data.as_ref().map(|data| Zeroizing::new(String::from_utf8_lossy(data).to_string()))
+    }
+
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        DukptDecryptResponseRef::parse(buffer).map(DukptDecryptResponseRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+
+        if let Some(ref data) = self.data {
+            buf.put_slice(data);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+impl fmt::Debug for DukptDecryptResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DukptDecryptResponse")
+            .field("header", &self.header)
+            .field("response_code", &self.response_code)
+            .field("data", &redacted(self.data.as_deref().map(Vec::as_slice)))
+            .finish()
+    }
+}
+
+/// Borrowed view of a DUKPT DecryptData response over a received frame.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DukptDecryptResponseRef<'a> {
+    pub header: MessageHeader,
+    pub response_code: [u8; 2],
+    pub data: Option<&'a [u8]>,
+}
+
+impl<'a> DukptDecryptResponseRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+
+        let header = MessageHeader::parse(buffer)?;
+
+        if !header.is_cmd(&CMD_DUKPTDECRYPT_RESPONSE) {
+            return Err(Error::UnexpectedCommand { expected: CMD_DUKPTDECRYPT_RESPONSE, got: header.cmd });
+        }
+
+        let mut reader = FieldReader::new(header.payload(buffer)?);
+
+        let response_code = reader.take_array("response_code")?;
+
+        let data = match reader.take_rest() {
+            []   => None,
+            data => {
+                DukptDecryptResponse::check_clear_data(as_str("data", data)?)?;
+                Some(data)
+            }
+        };
+
+        Ok(Self {
+            header,
+            response_code,
+            data,
+        })
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.response_code == ResponseCode::Success.as_bytes()
+    }
+
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+
+        if let Some(data) = self.data {
+            buf.put_slice(data);
+        }
+    }
+
+    pub fn into_owned(self) -> DukptDecryptResponse {
+        DukptDecryptResponse {
+            header: self.header,
+            response_code: self.response_code,
+            data: self.data.map(|data| Zeroizing::new(data.to_vec())),
+        }
+    }
+}
+
+impl fmt::Debug for DukptDecryptResponseRef<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DukptDecryptResponseRef")
+            .field("header", &self.header)
+            .field("response_code", &self.response_code)
+            .field("data", &redacted(self.data))
+            .finish()
+    }
+}
+
+fn redacted(data: Option<&[u8]>) -> Option<String> {
+    data.map(|data| format!("<{} bytes redacted>", data.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR_DATA: &str = "34313131313131313131313131313131";
+
+    #[test]
+    fn response_round_trips() {
+        let frame = DukptDecryptResponse::success(*b"0001", CLEAR_DATA).unwrap().to_bytes();
+        let parsed = DukptDecryptResponse::parse(&frame).unwrap();
+        assert_eq!(parsed.to_bytes(), frame);
+        assert_eq!(parsed.data_str().as_deref().map(String::as_str), Some(CLEAR_DATA));
+    }
+
+    #[test]
+    fn debug_leaves_out_clear_data() {
+        let response = DukptDecryptResponse::success(*b"0001", CLEAR_DATA).unwrap();
+        let frame = response.to_bytes();
+
+        for debug in [format!("{:?}", response), format!("{:?}", DukptDecryptResponseRef::parse(&frame).unwrap())] {
+            assert!(!debug.contains(CLEAR_DATA), "{}", debug);
+            assert!(debug.contains("<32 bytes redacted>"), "{}", debug);
+        }
+    }
+}
@@ -1,7 +1,10 @@
-use anyhow::{Result, bail, Context};
+use bytes::BufMut;
 
-use crate::message::header::{MessageHeader, MSGHDR_FMT_SIZE, MSGHDR_LEN_SIZE};
-use super::command::{CMD_GETKEY_REQUEST, CMD_GETKEY_RESPONSE, RESPONSE_SUCCESS};
+use crate::error::{Error, Result};
+
+use crate::message::header::MessageHeader;
+use crate::message::reader::FieldReader;
+use super::command::{CMD_GETKEY_REQUEST, CMD_GETKEY_RESPONSE, ResponseCode};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct GetKeyRequest {
@@ -11,7 +14,10 @@ pub struct GetKeyRequest {
 
 impl GetKeyRequest {
     pub fn new(hdr: [u8; 4], key_id: Vec<u8>) -> Result<Self> {
-        let header = MessageHeader::new(hdr, CMD_GETKEY_REQUEST, key_id.len());
+        if key_id.is_empty() {
+            return Err(Error::BadLength { name: "key_id", len: 0 });
+        }
+        let header = MessageHeader::new(hdr, CMD_GETKEY_REQUEST, key_id.len())?;
         Ok(Self { header, key_id })
     }
 
@@ -20,29 +26,56 @@ impl GetKeyRequest {
     }
 
     pub fn parse(buffer: &[u8]) -> Result<Self> {
-        let header = MessageHeader::parse(buffer)
-            .context("Failed to parse message header")?;
+        GetKeyRequestRef::parse(buffer).map(GetKeyRequestRef::into_owned)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.key_id);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a GetKey request over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetKeyRequestRef<'a> {
+    pub header: MessageHeader,
+    pub key_id: &'a [u8],
+}
+
+impl<'a> GetKeyRequestRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+        let header = MessageHeader::parse(buffer)?;
 
         if !header.is_cmd(&CMD_GETKEY_REQUEST) {
-            bail!("Invalid command for KeyRequest: expected Z0, got {}", header.cmd_str());
+            return Err(Error::UnexpectedCommand { expected: CMD_GETKEY_REQUEST, got: header.cmd });
         }
 
-        let expected_total = MSGHDR_LEN_SIZE + header.len as usize;
-        if buffer.len() < expected_total {
-            bail!("Buffer too small: need {}, got {}", expected_total, buffer.len());
-        }
+        let mut reader = FieldReader::new(header.payload(buffer)?);
 
-        let data_length = header.data_length();
-        let key_id = buffer[MSGHDR_FMT_SIZE..MSGHDR_FMT_SIZE + data_length].to_vec();
+        let key_id = reader.take_rest();
+        if key_id.is_empty() {
+            return Err(Error::BadLength { name: "key_id", len: 0 });
+        }
         
         Ok(Self { header, key_id })
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::with_capacity(MSGHDR_LEN_SIZE + self.header.len as usize);
-        result.extend_from_slice(&self.header.to_bytes());
-        result.extend_from_slice(&self.key_id);
-        result
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(self.key_id);
+    }
+
+    pub fn into_owned(self) -> GetKeyRequest {
+        GetKeyRequest {
+            header: self.header,
+            key_id: self.key_id.to_vec(),
+        }
     }
 }
 
@@ -54,65 +87,84 @@ pub struct GetKeyResponse {
 }
 
 impl GetKeyResponse {
-    pub fn success(hdr: [u8; 4], encrypted_key: Vec<u8>) -> Self {
+    pub fn success(hdr: [u8; 4], encrypted_key: Vec<u8>) -> Result<Self> {
         let data_length = 2 + encrypted_key.len();
-        let header = MessageHeader::new(hdr, CMD_GETKEY_RESPONSE, data_length);
+        let header = MessageHeader::new(hdr, CMD_GETKEY_RESPONSE, data_length)?;
         
-        Self {
+        Ok(Self {
             header,
-            response_code: RESPONSE_SUCCESS,
+            response_code: ResponseCode::Success.as_bytes(),
             encrypted_key: Some(encrypted_key),
-        }
+        })
     }
 
-    pub fn error(hdr: [u8; 4], error_code: [u8; 2]) -> Self {
+    pub fn error(hdr: [u8; 4], error_code: ResponseCode) -> Self {
         let data_length = 2;
-        let header = MessageHeader::new(hdr, CMD_GETKEY_RESPONSE, data_length);
+        let header = MessageHeader::fixed(hdr, CMD_GETKEY_RESPONSE, data_length);
         
         Self {
             header,
-            response_code: error_code,
+            response_code: error_code.as_bytes(),
             encrypted_key: None,
         }
     }
 
     pub fn is_success(&self) -> bool {
-        self.response_code == RESPONSE_SUCCESS
+        self.response_code == ResponseCode::Success.as_bytes()
     }
 
     pub fn response_code_str(&self) -> String {
         String::from_utf8_lossy(&self.response_code).to_string()
     }
 
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
     pub fn parse(buffer: &[u8]) -> Result<Self> {
+        GetKeyResponseRef::parse(buffer).map(GetKeyResponseRef::into_owned)
+    }
 
-        let header = MessageHeader::parse(buffer)
-            .context("Failed to parse message header")?;
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+        
+        if let Some(ref key) = self.encrypted_key {
+            buf.put_slice(key);
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header.frame_length());
+        self.write_to(&mut result);
+        result
+    }
+}
+
+/// Borrowed view of a GetKey response over a received frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GetKeyResponseRef<'a> {
+    pub header: MessageHeader,
+    pub response_code: [u8; 2],
+    pub encrypted_key: Option<&'a [u8]>,
+}
+
+impl<'a> GetKeyResponseRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+
+        let header = MessageHeader::parse(buffer)?;
 
         if !header.is_cmd(&CMD_GETKEY_RESPONSE) {
-            bail!("Invalid command for KeyResponse: expected Z1, got {}", header.cmd_str());
+            return Err(Error::UnexpectedCommand { expected: CMD_GETKEY_RESPONSE, got: header.cmd });
         }
 
-        let expected_total = MSGHDR_LEN_SIZE + header.len as usize;
-        if buffer.len() < expected_total {
-            bail!("Buffer too small: need {}, got {}", expected_total, buffer.len());
-        }
-        
-        let data_length = header.data_length();
-        if data_length < 2 {
-            bail!("Data too small for response code: need at least 2 bytes, got {}", data_length
-            );
-        }
+        let mut reader = FieldReader::new(header.payload(buffer)?);
 
-        let mut response_code = [0u8; 2];
-        response_code.copy_from_slice(&buffer[MSGHDR_FMT_SIZE..MSGHDR_FMT_SIZE + 2]);
+        let response_code = reader.take_array("response_code")?;
 
-        let encrypted_key = if data_length > 2 {
-            let key_start = MSGHDR_FMT_SIZE + 2;
-            let key_end = key_start + (data_length - 2);
-            Some(buffer[key_start..key_end].to_vec())
-        } else {
-            None
+        let encrypted_key = match reader.take_rest() {
+            []  => None,
+            key => Some(key),
         };
         
         Ok(Self {
@@ -122,15 +174,81 @@ impl GetKeyResponse {
         })
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::with_capacity(MSGHDR_LEN_SIZE + self.header.len as usize);
-        result.extend_from_slice(&self.header.to_bytes());
-        result.extend_from_slice(&self.response_code);
-        
-        if let Some(ref key) = self.encrypted_key {
-            result.extend_from_slice(key);
+    pub fn is_success(&self) -> bool {
+        self.response_code == ResponseCode::Success.as_bytes()
+    }
+
+    pub fn code(&self) -> Option<ResponseCode> {
+        ResponseCode::from_bytes(&self.response_code)
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        self.header.write_to(buf);
+        buf.put_slice(&self.response_code);
+
+        if let Some(key) = self.encrypted_key {
+            buf.put_slice(key);
         }
-        
-        result
     }
-}
\ No newline at end of file
+
+    pub fn into_owned(self) -> GetKeyResponse {
+        GetKeyResponse {
+            header: self.header,
+            response_code: self.response_code,
+            encrypted_key: self.encrypted_key.map(<[u8]>::to_vec),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::Message;
+    use crate::message::header::MSGHDR_FMT_SIZE;
+    use crate::message::testing::{relabeled_truncations, truncations, with_declared_len};
+
+    #[test]
+    fn request_truncated_at_every_offset_is_rejected() {
+        let frame = GetKeyRequest::new(*b"0001", b"cvk-visa-a".to_vec()).unwrap().to_bytes();
+        assert_eq!(GetKeyRequest::parse(&frame).unwrap().key_id_str(), "cvk-visa-a");
+
+        for cut in truncations(&frame) {
+            assert!(GetKeyRequestRef::parse(cut).is_err());
+            assert!(Message::parse(cut).is_err());
+        }
+
+        // The key id runs to the end of the frame, so only an empty one is invalid
+        for cut in relabeled_truncations(&frame) {
+            assert_eq!(GetKeyRequestRef::parse(&cut).is_ok(), cut.len() > MSGHDR_FMT_SIZE);
+        }
+    }
+
+    #[test]
+    fn request_with_wrong_declared_len_is_rejected() {
+        let frame = GetKeyRequest::new(*b"0001", b"cvk-visa-a".to_vec()).unwrap().to_bytes();
+        assert!(matches!(GetKeyRequestRef::parse(&with_declared_len(&frame, 1)), Err(Error::TooShort { .. })));
+        assert_eq!(GetKeyRequestRef::parse(&with_declared_len(&frame, -1)), Err(Error::TrailingBytes { count: 1 }));
+    }
+
+    #[test]
+    fn response_truncated_at_every_offset_is_rejected() {
+        let frame = GetKeyResponse::success(*b"0001", vec![0xA5; 32]).unwrap().to_bytes();
+        assert_eq!(GetKeyResponse::parse(&frame).unwrap().encrypted_key, Some(vec![0xA5; 32]));
+
+        for cut in truncations(&frame) {
+            assert!(GetKeyResponseRef::parse(cut).is_err());
+        }
+
+        // Anything from the response code on is a valid, possibly shorter, response
+        for cut in relabeled_truncations(&frame) {
+            assert_eq!(GetKeyResponseRef::parse(&cut).is_ok(), cut.len() >= MSGHDR_FMT_SIZE + 2);
+        }
+    }
+
+    #[test]
+    fn response_with_wrong_declared_len_is_rejected() {
+        let frame = GetKeyResponse::error(*b"0001", ResponseCode::SecretNotFound).to_bytes();
+        assert!(matches!(GetKeyResponseRef::parse(&with_declared_len(&frame, 1)), Err(Error::TooShort { .. })));
+        assert_eq!(GetKeyResponseRef::parse(&with_declared_len(&frame, -1)), Err(Error::TrailingBytes { count: 1 }));
+    }
+}
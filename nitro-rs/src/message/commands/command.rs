@@ -1,12 +1,143 @@
+use crate::error::{Error, Result};
+
 
 /// VERIFY CVV
 pub const CMD_VERIFYCVV_REQUEST:  [u8; 2] = *b"CY";
 pub const CMD_VERIFYCVV_RESPONSE: [u8; 2] = *b"CZ";
 
+/// GENERATE CVV
+pub const CMD_GENERATECVV_REQUEST:  [u8; 2] = *b"CW";
+pub const CMD_GENERATECVV_RESPONSE: [u8; 2] = *b"CX";
+
+/// VERIFY PIN (Visa PVV)
+pub const CMD_VERIFYPVV_REQUEST:  [u8; 2] = *b"DC";
+pub const CMD_VERIFYPVV_RESPONSE: [u8; 2] = *b"DD";
+
+/// GENERATE PIN OFFSET (IBM 3624)
+pub const CMD_GENERATEOFFSET_REQUEST:  [u8; 2] = *b"DE";
+pub const CMD_GENERATEOFFSET_RESPONSE: [u8; 2] = *b"DF";
+
+/// VERIFY PIN (IBM 3624 offset)
+pub const CMD_VERIFYOFFSET_REQUEST:  [u8; 2] = *b"DA";
+pub const CMD_VERIFYOFFSET_RESPONSE: [u8; 2] = *b"DB";
+
+/// TRANSLATE PIN
+pub const CMD_TRANSLATEPIN_REQUEST:  [u8; 2] = *b"CC";
+pub const CMD_TRANSLATEPIN_RESPONSE: [u8; 2] = *b"CD";
+
+/// TRANSLATE PIN (DUKPT source)
+pub const CMD_DUKPTTRANSLATEPIN_REQUEST:  [u8; 2] = *b"G0";
+pub const CMD_DUKPTTRANSLATEPIN_RESPONSE: [u8; 2] = *b"G1";
+
+/// DECRYPT DATA (DUKPT)
+pub const CMD_DUKPTDECRYPT_REQUEST:  [u8; 2] = *b"M2";
+pub const CMD_DUKPTDECRYPT_RESPONSE: [u8; 2] = *b"M3";
+
 /// GET KEY
 pub const CMD_GETKEY_REQUEST:  [u8; 2] = *b"Z0";
 pub const CMD_GETKEY_RESPONSE: [u8; 2] = *b"Z1";
 
 /// RESPONSE CODE
-pub const RESPONSE_SUCCESS: [u8; 2] = *b"00";
-pub const RESPONSE_ERROR_NOT_FOUND: [u8; 2] = *b"01";
\ No newline at end of file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResponseCode {
+    Success,
+    SecretNotFound,
+    KmsAccessDenied,
+    KmsKeyNotFound,
+    EncryptionFailed,
+    CvvMismatch,
+    PinMismatch,
+    InvalidPinBlock,
+    DecimalizationTableNotFound,
+    PinTranslationNotAllowed,
+    InvalidKsn,
+    KeyUsageNotAllowed,
+    SecretAccessDenied,
+    SystemError,
+}
+
+impl ResponseCode {
+    pub const ALL: [ResponseCode; 14] = [
+        ResponseCode::Success,
+        ResponseCode::SecretNotFound,
+        ResponseCode::KmsAccessDenied,
+        ResponseCode::KmsKeyNotFound,
+        ResponseCode::EncryptionFailed,
+        ResponseCode::CvvMismatch,
+        ResponseCode::PinMismatch,
+        ResponseCode::InvalidPinBlock,
+        ResponseCode::DecimalizationTableNotFound,
+        ResponseCode::PinTranslationNotAllowed,
+        ResponseCode::InvalidKsn,
+        ResponseCode::KeyUsageNotAllowed,
+        ResponseCode::SecretAccessDenied,
+        ResponseCode::SystemError,
+    ];
+
+    pub const fn as_bytes(&self) -> [u8; 2] {
+        match self {
+            ResponseCode::Success                     => *b"00",
+            ResponseCode::SecretNotFound              => *b"01",
+            ResponseCode::KmsAccessDenied             => *b"02",
+            ResponseCode::KmsKeyNotFound              => *b"03",
+            ResponseCode::EncryptionFailed            => *b"04",
+            ResponseCode::CvvMismatch                 => *b"05",
+            ResponseCode::PinMismatch                 => *b"06",
+            ResponseCode::InvalidPinBlock             => *b"07",
+            ResponseCode::DecimalizationTableNotFound => *b"08",
+            ResponseCode::PinTranslationNotAllowed    => *b"09",
+            ResponseCode::InvalidKsn                  => *b"10",
+            ResponseCode::KeyUsageNotAllowed          => *b"11",
+            ResponseCode::SecretAccessDenied          => *b"97",
+            ResponseCode::SystemError                 => *b"99",
+        }
+    }
+
+    pub fn from_bytes(code: &[u8; 2]) -> Option<Self> {
+        Self::ALL.into_iter().find(|c| &c.as_bytes() == code)
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            ResponseCode::Success                     => "Success",
+            ResponseCode::SecretNotFound              => "Secret not found",
+            ResponseCode::KmsAccessDenied             => "Access denied to KMS key",
+            ResponseCode::KmsKeyNotFound              => "KMS key not found",
+            ResponseCode::EncryptionFailed            => "KMS encryption failed",
+            ResponseCode::CvvMismatch                 => "CVV mismatch",
+            ResponseCode::PinMismatch                 => "PIN verification failed",
+            ResponseCode::InvalidPinBlock             => "PIN block invalid or format unsupported",
+            ResponseCode::DecimalizationTableNotFound => "Decimalization table not configured",
+            ResponseCode::PinTranslationNotAllowed    => "PIN block format conversion not allowed",
+            ResponseCode::InvalidKsn                  => "Key serial number invalid or counter exhausted",
+            ResponseCode::KeyUsageNotAllowed          => "Key not permitted for this operation",
+            ResponseCode::SecretAccessDenied          => "Access denied to secret",
+            ResponseCode::SystemError                 => "System error",
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        *self == ResponseCode::Success
+    }
+}
+
+impl From<ResponseCode> for [u8; 2] {
+    fn from(code: ResponseCode) -> Self {
+        code.as_bytes()
+    }
+}
+
+impl TryFrom<[u8; 2]> for ResponseCode {
+    type Error = Error;
+
+    fn try_from(code: [u8; 2]) -> Result<Self> {
+        Self::from_bytes(&code).ok_or(Error::UnknownResponseCode(code))
+    }
+}
+
+impl std::fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let code = self.as_bytes();
+        write!(f, "{} ({})", String::from_utf8_lossy(&code), self.description())
+    }
+}
\ No newline at end of file
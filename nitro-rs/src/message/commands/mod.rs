@@ -1,64 +1,387 @@
 
+mod cmd_cc;
+mod cmd_cw;
 mod cmd_cy;
+mod cmd_da;
+mod cmd_dc;
+mod cmd_de;
+mod cmd_g0;
+mod cmd_m2;
 mod cmd_z0;
 
 pub mod command;
 
 pub use command::*;
-pub use cmd_cy::{VerifyCVVRequest, VerifyCVVResponse};
-pub use cmd_z0::{GetKeyRequest, GetKeyResponse};
+pub use cmd_cc::{TranslatePinRequest, TranslatePinRequestRef, TranslatePinResponse, TranslatePinResponseRef};
+pub use cmd_cw::{GenerateCVVRequest, GenerateCVVRequestRef, GenerateCVVResponse};
+pub use cmd_cy::{VerifyCVVRequest, VerifyCVVRequestRef, VerifyCVVResponse};
+pub use cmd_da::{VerifyOffsetRequest, VerifyOffsetRequestRef, VerifyOffsetResponse, OFFSET_SIZE};
+pub use cmd_dc::{VerifyPvvRequest, VerifyPvvRequestRef, VerifyPvvResponse, PIN_BLOCK_AES_HEX_LEN, PIN_BLOCK_DES_HEX_LEN};
+pub use cmd_de::{GenerateOffsetRequest, GenerateOffsetRequestRef, GenerateOffsetResponse};
+pub use cmd_g0::{DukptTranslatePinRequest, DukptTranslatePinRequestRef, DukptTranslatePinResponse, DukptTranslatePinResponseRef, KSN_AES_HEX_LEN, KSN_TDES_HEX_LEN};
+pub use cmd_m2::{DukptDecryptRequest, DukptDecryptRequestRef, DukptDecryptResponse, DukptDecryptResponseRef};
+pub use cmd_z0::{GetKeyRequest, GetKeyRequestRef, GetKeyResponse, GetKeyResponseRef};
 
 
-use anyhow::{Result, bail, Context};
+use bytes::BufMut;
+
+use crate::error::{Error, Result};
 use crate::message::header::MessageHeader;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Message {
     VerifyCVVRequest(VerifyCVVRequest),
     VerifyCVVResponse(VerifyCVVResponse),
+    GenerateCVVRequest(GenerateCVVRequest),
+    GenerateCVVResponse(GenerateCVVResponse),
+    VerifyPvvRequest(VerifyPvvRequest),
+    VerifyPvvResponse(VerifyPvvResponse),
+    GenerateOffsetRequest(GenerateOffsetRequest),
+    GenerateOffsetResponse(GenerateOffsetResponse),
+    VerifyOffsetRequest(VerifyOffsetRequest),
+    VerifyOffsetResponse(VerifyOffsetResponse),
+    TranslatePinRequest(TranslatePinRequest),
+    TranslatePinResponse(TranslatePinResponse),
+    DukptTranslatePinRequest(DukptTranslatePinRequest),
+    DukptTranslatePinResponse(DukptTranslatePinResponse),
+    DukptDecryptRequest(DukptDecryptRequest),
+    DukptDecryptResponse(DukptDecryptResponse),
     GetKeyRequest(GetKeyRequest),
     GetKeyResponse(GetKeyResponse),
 }
 
 impl Message {
     pub fn parse(buffer: &[u8]) -> Result<Self> {
-        let header = MessageHeader::parse(buffer)
-            .context("Failed to parse message header")?;
+        let header = MessageHeader::parse(buffer)?;
 
-        match &header.cmd {
-            &CMD_VERIFYCVV_REQUEST => {
+        match header.cmd {
+            CMD_VERIFYCVV_REQUEST => {
                 Ok(Message::VerifyCVVRequest(VerifyCVVRequest::parse(buffer)?))
             }
-            &CMD_VERIFYCVV_RESPONSE => {
+            CMD_VERIFYCVV_RESPONSE => {
                 Ok(Message::VerifyCVVResponse(VerifyCVVResponse::parse(buffer)?))
             }
-            &CMD_GETKEY_REQUEST => {
+            CMD_GENERATECVV_REQUEST => {
+                Ok(Message::GenerateCVVRequest(GenerateCVVRequest::parse(buffer)?))
+            }
+            CMD_GENERATECVV_RESPONSE => {
+                Ok(Message::GenerateCVVResponse(GenerateCVVResponse::parse(buffer)?))
+            }
+            CMD_VERIFYPVV_REQUEST => {
+                Ok(Message::VerifyPvvRequest(VerifyPvvRequest::parse(buffer)?))
+            }
+            CMD_VERIFYPVV_RESPONSE => {
+                Ok(Message::VerifyPvvResponse(VerifyPvvResponse::parse(buffer)?))
+            }
+            CMD_GENERATEOFFSET_REQUEST => {
+                Ok(Message::GenerateOffsetRequest(GenerateOffsetRequest::parse(buffer)?))
+            }
+            CMD_GENERATEOFFSET_RESPONSE => {
+                Ok(Message::GenerateOffsetResponse(GenerateOffsetResponse::parse(buffer)?))
+            }
+            CMD_VERIFYOFFSET_REQUEST => {
+                Ok(Message::VerifyOffsetRequest(VerifyOffsetRequest::parse(buffer)?))
+            }
+            CMD_VERIFYOFFSET_RESPONSE => {
+                Ok(Message::VerifyOffsetResponse(VerifyOffsetResponse::parse(buffer)?))
+            }
+            CMD_TRANSLATEPIN_REQUEST => {
+                Ok(Message::TranslatePinRequest(TranslatePinRequest::parse(buffer)?))
+            }
+            CMD_TRANSLATEPIN_RESPONSE => {
+                Ok(Message::TranslatePinResponse(TranslatePinResponse::parse(buffer)?))
+            }
+            CMD_DUKPTTRANSLATEPIN_REQUEST => {
+                Ok(Message::DukptTranslatePinRequest(DukptTranslatePinRequest::parse(buffer)?))
+            }
+            CMD_DUKPTTRANSLATEPIN_RESPONSE => {
+                Ok(Message::DukptTranslatePinResponse(DukptTranslatePinResponse::parse(buffer)?))
+            }
+            CMD_DUKPTDECRYPT_REQUEST => {
+                Ok(Message::DukptDecryptRequest(DukptDecryptRequest::parse(buffer)?))
+            }
+            CMD_DUKPTDECRYPT_RESPONSE => {
+                Ok(Message::DukptDecryptResponse(DukptDecryptResponse::parse(buffer)?))
+            }
+            CMD_GETKEY_REQUEST => {
                 Ok(Message::GetKeyRequest(GetKeyRequest::parse(buffer)?))
             }
-            &CMD_GETKEY_RESPONSE => {
+            CMD_GETKEY_RESPONSE => {
                 Ok(Message::GetKeyResponse(GetKeyResponse::parse(buffer)?))
             }
             _ => {
-                bail!("Unknown command: {}", header.cmd_str());
+                Err(Error::UnknownCommand(header.cmd))
             }
         }
     }
 
+    /// Error response answering the request `header` belongs to, or `None` when
+    /// its command is not a request. Lets a server reply to a frame whose header
+    /// parsed but whose payload did not.
+    pub fn error_response(header: &MessageHeader, code: ResponseCode) -> Option<Self> {
+        let hdr = header.hdr;
+
+        match header.cmd {
+            CMD_VERIFYCVV_REQUEST         => Some(Message::VerifyCVVResponse(VerifyCVVResponse::error(hdr, code))),
+            CMD_GENERATECVV_REQUEST       => Some(Message::GenerateCVVResponse(GenerateCVVResponse::error(hdr, code))),
+            CMD_VERIFYPVV_REQUEST         => Some(Message::VerifyPvvResponse(VerifyPvvResponse::error(hdr, code))),
+            CMD_GENERATEOFFSET_REQUEST    => Some(Message::GenerateOffsetResponse(GenerateOffsetResponse::error(hdr, code))),
+            CMD_VERIFYOFFSET_REQUEST      => Some(Message::VerifyOffsetResponse(VerifyOffsetResponse::error(hdr, code))),
+            CMD_TRANSLATEPIN_REQUEST      => Some(Message::TranslatePinResponse(TranslatePinResponse::error(hdr, code))),
+            CMD_DUKPTTRANSLATEPIN_REQUEST => Some(Message::DukptTranslatePinResponse(DukptTranslatePinResponse::error(hdr, code))),
+            CMD_DUKPTDECRYPT_REQUEST      => Some(Message::DukptDecryptResponse(DukptDecryptResponse::error(hdr, code))),
+            CMD_GETKEY_REQUEST            => Some(Message::GetKeyResponse(GetKeyResponse::error(hdr, code))),
+            _ => None,
+        }
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        match self {
+            Message::VerifyCVVRequest(req)          => req.write_to(buf),
+            Message::VerifyCVVResponse(res)         => res.write_to(buf),
+            Message::GenerateCVVRequest(req)        => req.write_to(buf),
+            Message::GenerateCVVResponse(res)       => res.write_to(buf),
+            Message::VerifyPvvRequest(req)          => req.write_to(buf),
+            Message::VerifyPvvResponse(res)         => res.write_to(buf),
+            Message::GenerateOffsetRequest(req)     => req.write_to(buf),
+            Message::GenerateOffsetResponse(res)    => res.write_to(buf),
+            Message::VerifyOffsetRequest(req)       => req.write_to(buf),
+            Message::VerifyOffsetResponse(res)      => res.write_to(buf),
+            Message::TranslatePinRequest(req)       => req.write_to(buf),
+            Message::TranslatePinResponse(res)      => res.write_to(buf),
+            Message::DukptTranslatePinRequest(req)  => req.write_to(buf),
+            Message::DukptTranslatePinResponse(res) => res.write_to(buf),
+            Message::DukptDecryptRequest(req)       => req.write_to(buf),
+            Message::DukptDecryptResponse(res)      => res.write_to(buf),
+            Message::GetKeyRequest(req)             => req.write_to(buf),
+            Message::GetKeyResponse(resp)           => resp.write_to(buf),
+        }
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         match self {
             Message::VerifyCVVRequest(req)  => req.to_bytes(),
             Message::VerifyCVVResponse(res) => res.to_bytes(),
+            Message::GenerateCVVRequest(req)  => req.to_bytes(),
+            Message::GenerateCVVResponse(res) => res.to_bytes(),
+            Message::VerifyPvvRequest(req)    => req.to_bytes(),
+            Message::VerifyPvvResponse(res)   => res.to_bytes(),
+            Message::GenerateOffsetRequest(req) => req.to_bytes(),
+            Message::GenerateOffsetResponse(res) => res.to_bytes(),
+            Message::VerifyOffsetRequest(req) => req.to_bytes(),
+            Message::VerifyOffsetResponse(res) => res.to_bytes(),
+            Message::TranslatePinRequest(req) => req.to_bytes(),
+            Message::TranslatePinResponse(res) => res.to_bytes(),
+            Message::DukptTranslatePinRequest(req) => req.to_bytes(),
+            Message::DukptTranslatePinResponse(res) => res.to_bytes(),
+            Message::DukptDecryptRequest(req) => req.to_bytes(),
+            Message::DukptDecryptResponse(res) => res.to_bytes(),
             Message::GetKeyRequest(req)     => req.to_bytes(),
             Message::GetKeyResponse(resp)   => resp.to_bytes(),
         }
     }
 
+    pub fn header(&self) -> &MessageHeader {
+        match self {
+            Message::VerifyCVVRequest(req)          => &req.header,
+            Message::VerifyCVVResponse(res)         => &res.header,
+            Message::GenerateCVVRequest(req)        => &req.header,
+            Message::GenerateCVVResponse(res)       => &res.header,
+            Message::VerifyPvvRequest(req)          => &req.header,
+            Message::VerifyPvvResponse(res)         => &res.header,
+            Message::GenerateOffsetRequest(req)     => &req.header,
+            Message::GenerateOffsetResponse(res)    => &res.header,
+            Message::VerifyOffsetRequest(req)       => &req.header,
+            Message::VerifyOffsetResponse(res)      => &res.header,
+            Message::TranslatePinRequest(req)       => &req.header,
+            Message::TranslatePinResponse(res)      => &res.header,
+            Message::DukptTranslatePinRequest(req)  => &req.header,
+            Message::DukptTranslatePinResponse(res) => &res.header,
+            Message::DukptDecryptRequest(req)       => &req.header,
+            Message::DukptDecryptResponse(res)      => &res.header,
+            Message::GetKeyRequest(req)             => &req.header,
+            Message::GetKeyResponse(resp)           => &resp.header,
+        }
+    }
+
     pub fn cmd(&self) -> String {
         match self {
             Message::VerifyCVVRequest(req)  => req.header.cmd_str(),
             Message::VerifyCVVResponse(res) => res.header.cmd_str(),
+            Message::GenerateCVVRequest(req)  => req.header.cmd_str(),
+            Message::GenerateCVVResponse(res) => res.header.cmd_str(),
+            Message::VerifyPvvRequest(req)    => req.header.cmd_str(),
+            Message::VerifyPvvResponse(res)   => res.header.cmd_str(),
+            Message::GenerateOffsetRequest(req) => req.header.cmd_str(),
+            Message::GenerateOffsetResponse(res) => res.header.cmd_str(),
+            Message::VerifyOffsetRequest(req) => req.header.cmd_str(),
+            Message::VerifyOffsetResponse(res) => res.header.cmd_str(),
+            Message::TranslatePinRequest(req) => req.header.cmd_str(),
+            Message::TranslatePinResponse(res) => res.header.cmd_str(),
+            Message::DukptTranslatePinRequest(req) => req.header.cmd_str(),
+            Message::DukptTranslatePinResponse(res) => res.header.cmd_str(),
+            Message::DukptDecryptRequest(req) => req.header.cmd_str(),
+            Message::DukptDecryptResponse(res) => res.header.cmd_str(),
             Message::GetKeyRequest(req)     => req.header.cmd_str(),
             Message::GetKeyResponse(resp)   => resp.header.cmd_str(),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Borrowed counterpart of [`Message`]; variable length fields point into the frame buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageRef<'a> {
+    VerifyCVVRequest(VerifyCVVRequestRef<'a>),
+    VerifyCVVResponse(VerifyCVVResponse),
+    GenerateCVVRequest(GenerateCVVRequestRef<'a>),
+    GenerateCVVResponse(GenerateCVVResponse),
+    VerifyPvvRequest(VerifyPvvRequestRef<'a>),
+    VerifyPvvResponse(VerifyPvvResponse),
+    GenerateOffsetRequest(GenerateOffsetRequestRef<'a>),
+    GenerateOffsetResponse(GenerateOffsetResponse),
+    VerifyOffsetRequest(VerifyOffsetRequestRef<'a>),
+    VerifyOffsetResponse(VerifyOffsetResponse),
+    TranslatePinRequest(TranslatePinRequestRef<'a>),
+    TranslatePinResponse(TranslatePinResponseRef<'a>),
+    DukptTranslatePinRequest(DukptTranslatePinRequestRef<'a>),
+    DukptTranslatePinResponse(DukptTranslatePinResponseRef<'a>),
+    DukptDecryptRequest(DukptDecryptRequestRef<'a>),
+    DukptDecryptResponse(DukptDecryptResponseRef<'a>),
+    GetKeyRequest(GetKeyRequestRef<'a>),
+    GetKeyResponse(GetKeyResponseRef<'a>),
+}
+
+impl<'a> MessageRef<'a> {
+    pub fn parse(buffer: &'a [u8]) -> Result<Self> {
+        let header = MessageHeader::parse(buffer)?;
+
+        match header.cmd {
+            CMD_VERIFYCVV_REQUEST => {
+                Ok(MessageRef::VerifyCVVRequest(VerifyCVVRequestRef::parse(buffer)?))
+            }
+            CMD_VERIFYCVV_RESPONSE => {
+                Ok(MessageRef::VerifyCVVResponse(VerifyCVVResponse::parse(buffer)?))
+            }
+            CMD_GENERATECVV_REQUEST => {
+                Ok(MessageRef::GenerateCVVRequest(GenerateCVVRequestRef::parse(buffer)?))
+            }
+            CMD_GENERATECVV_RESPONSE => {
+                Ok(MessageRef::GenerateCVVResponse(GenerateCVVResponse::parse(buffer)?))
+            }
+            CMD_VERIFYPVV_REQUEST => {
+                Ok(MessageRef::VerifyPvvRequest(VerifyPvvRequestRef::parse(buffer)?))
+            }
+            CMD_VERIFYPVV_RESPONSE => {
+                Ok(MessageRef::VerifyPvvResponse(VerifyPvvResponse::parse(buffer)?))
+            }
+            CMD_GENERATEOFFSET_REQUEST => {
+                Ok(MessageRef::GenerateOffsetRequest(GenerateOffsetRequestRef::parse(buffer)?))
+            }
+            CMD_GENERATEOFFSET_RESPONSE => {
+                Ok(MessageRef::GenerateOffsetResponse(GenerateOffsetResponse::parse(buffer)?))
+            }
+            CMD_VERIFYOFFSET_REQUEST => {
+                Ok(MessageRef::VerifyOffsetRequest(VerifyOffsetRequestRef::parse(buffer)?))
+            }
+            CMD_VERIFYOFFSET_RESPONSE => {
+                Ok(MessageRef::VerifyOffsetResponse(VerifyOffsetResponse::parse(buffer)?))
+            }
+            CMD_TRANSLATEPIN_REQUEST => {
+                Ok(MessageRef::TranslatePinRequest(TranslatePinRequestRef::parse(buffer)?))
+            }
+            CMD_TRANSLATEPIN_RESPONSE => {
+                Ok(MessageRef::TranslatePinResponse(TranslatePinResponseRef::parse(buffer)?))
+            }
+            CMD_DUKPTTRANSLATEPIN_REQUEST => {
+                Ok(MessageRef::DukptTranslatePinRequest(DukptTranslatePinRequestRef::parse(buffer)?))
+            }
+            CMD_DUKPTTRANSLATEPIN_RESPONSE => {
+                Ok(MessageRef::DukptTranslatePinResponse(DukptTranslatePinResponseRef::parse(buffer)?))
+            }
+            CMD_DUKPTDECRYPT_REQUEST => {
+                Ok(MessageRef::DukptDecryptRequest(DukptDecryptRequestRef::parse(buffer)?))
+            }
+            CMD_DUKPTDECRYPT_RESPONSE => {
+                Ok(MessageRef::DukptDecryptResponse(DukptDecryptResponseRef::parse(buffer)?))
+            }
+            CMD_GETKEY_REQUEST => {
+                Ok(MessageRef::GetKeyRequest(GetKeyRequestRef::parse(buffer)?))
+            }
+            CMD_GETKEY_RESPONSE => {
+                Ok(MessageRef::GetKeyResponse(GetKeyResponseRef::parse(buffer)?))
+            }
+            _ => {
+                Err(Error::UnknownCommand(header.cmd))
+            }
+        }
+    }
+
+    pub fn header(&self) -> &MessageHeader {
+        match self {
+            MessageRef::VerifyCVVRequest(req)          => &req.header,
+            MessageRef::VerifyCVVResponse(res)         => &res.header,
+            MessageRef::GenerateCVVRequest(req)        => &req.header,
+            MessageRef::GenerateCVVResponse(res)       => &res.header,
+            MessageRef::VerifyPvvRequest(req)          => &req.header,
+            MessageRef::VerifyPvvResponse(res)         => &res.header,
+            MessageRef::GenerateOffsetRequest(req)     => &req.header,
+            MessageRef::GenerateOffsetResponse(res)    => &res.header,
+            MessageRef::VerifyOffsetRequest(req)       => &req.header,
+            MessageRef::VerifyOffsetResponse(res)      => &res.header,
+            MessageRef::TranslatePinRequest(req)       => &req.header,
+            MessageRef::TranslatePinResponse(res)      => &res.header,
+            MessageRef::DukptTranslatePinRequest(req)  => &req.header,
+            MessageRef::DukptTranslatePinResponse(res) => &res.header,
+            MessageRef::DukptDecryptRequest(req)       => &req.header,
+            MessageRef::DukptDecryptResponse(res)      => &res.header,
+            MessageRef::GetKeyRequest(req)             => &req.header,
+            MessageRef::GetKeyResponse(resp)           => &resp.header,
+        }
+    }
+
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        match self {
+            MessageRef::VerifyCVVRequest(req)          => req.write_to(buf),
+            MessageRef::VerifyCVVResponse(res)         => res.write_to(buf),
+            MessageRef::GenerateCVVRequest(req)        => req.write_to(buf),
+            MessageRef::GenerateCVVResponse(res)       => res.write_to(buf),
+            MessageRef::VerifyPvvRequest(req)          => req.write_to(buf),
+            MessageRef::VerifyPvvResponse(res)         => res.write_to(buf),
+            MessageRef::GenerateOffsetRequest(req)     => req.write_to(buf),
+            MessageRef::GenerateOffsetResponse(res)    => res.write_to(buf),
+            MessageRef::VerifyOffsetRequest(req)       => req.write_to(buf),
+            MessageRef::VerifyOffsetResponse(res)      => res.write_to(buf),
+            MessageRef::TranslatePinRequest(req)       => req.write_to(buf),
+            MessageRef::TranslatePinResponse(res)      => res.write_to(buf),
+            MessageRef::DukptTranslatePinRequest(req)  => req.write_to(buf),
+            MessageRef::DukptTranslatePinResponse(res) => res.write_to(buf),
+            MessageRef::DukptDecryptRequest(req)       => req.write_to(buf),
+            MessageRef::DukptDecryptResponse(res)      => res.write_to(buf),
+            MessageRef::GetKeyRequest(req)             => req.write_to(buf),
+            MessageRef::GetKeyResponse(resp)           => resp.write_to(buf),
+        }
+    }
+
+    pub fn into_owned(self) -> Message {
+        match self {
+            MessageRef::VerifyCVVRequest(req)          => Message::VerifyCVVRequest(req.into_owned()),
+            MessageRef::VerifyCVVResponse(res)         => Message::VerifyCVVResponse(res),
+            MessageRef::GenerateCVVRequest(req)        => Message::GenerateCVVRequest(req.into_owned()),
+            MessageRef::GenerateCVVResponse(res)       => Message::GenerateCVVResponse(res),
+            MessageRef::VerifyPvvRequest(req)          => Message::VerifyPvvRequest(req.into_owned()),
+            MessageRef::VerifyPvvResponse(res)         => Message::VerifyPvvResponse(res),
+            MessageRef::GenerateOffsetRequest(req)     => Message::GenerateOffsetRequest(req.into_owned()),
+            MessageRef::GenerateOffsetResponse(res)    => Message::GenerateOffsetResponse(res),
+            MessageRef::VerifyOffsetRequest(req)       => Message::VerifyOffsetRequest(req.into_owned()),
+            MessageRef::VerifyOffsetResponse(res)      => Message::VerifyOffsetResponse(res),
+            MessageRef::TranslatePinRequest(req)       => Message::TranslatePinRequest(req.into_owned()),
+            MessageRef::TranslatePinResponse(res)      => Message::TranslatePinResponse(res.into_owned()),
+            MessageRef::DukptTranslatePinRequest(req)  => Message::DukptTranslatePinRequest(req.into_owned()),
+            MessageRef::DukptTranslatePinResponse(res) => Message::DukptTranslatePinResponse(res.into_owned()),
+            MessageRef::DukptDecryptRequest(req)       => Message::DukptDecryptRequest(req.into_owned()),
+            MessageRef::DukptDecryptResponse(res)      => Message::DukptDecryptResponse(res.into_owned()),
+            MessageRef::GetKeyRequest(req)             => Message::GetKeyRequest(req.into_owned()),
+            MessageRef::GetKeyResponse(resp)           => Message::GetKeyResponse(resp.into_owned()),
+        }
+    }
+}
@@ -1,5 +1,7 @@
 
-use anyhow::{Result, bail};
+use bytes::BufMut;
+
+use crate::error::{Error, Result};
 
 
 pub const MSGHDR_LEN_SIZE: usize = 2;
@@ -7,16 +9,38 @@ pub const MSGHDR_HDR_SIZE: usize = 4;
 pub const MSGHDR_CMD_SIZE: usize = 2;
 pub const MSGHDR_FMT_SIZE: usize = MSGHDR_LEN_SIZE + MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// A 2-byte length of `0xFFFF` announces an extended frame: a 4-byte
+/// big-endian length follows the marker, then `hdr` and `cmd` as usual.
+pub const MSGHDR_EXT_MARKER: u16 = u16::MAX;
+pub const MSGHDR_EXT_LEN_SIZE: usize = 4;
+pub const MSGHDR_EXT_FMT_SIZE: usize = MSGHDR_FMT_SIZE + MSGHDR_EXT_LEN_SIZE;
+
+/// Largest `len` that fits the standard 2-byte prefix
+pub const MSGHDR_MAX_STD_LEN: usize = MSGHDR_EXT_MARKER as usize - 1;
+/// Largest `len` that fits the extended 4-byte prefix
+pub const MSGHDR_MAX_LEN: usize = u32::MAX as usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MessageHeader {
-    pub len: u16,
+    pub len: u32,
     pub hdr: [u8; 4],
     pub cmd: [u8; 2],
 }
 
 impl MessageHeader {
-    pub fn new(hdr: [u8; 4], cmd: [u8; 2], data_length: usize) -> Self {
-        let len = (MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE + data_length) as u16;
+    /// Fails with `FrameTooLarge` when the payload cannot be described by the length prefix.
+    pub fn new(hdr: [u8; 4], cmd: [u8; 2], data_length: usize) -> Result<Self> {
+        let len = data_length
+            .checked_add(MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE)
+            .filter(|len| *len <= MSGHDR_MAX_LEN)
+            .ok_or(Error::FrameTooLarge { size: data_length, max: MSGHDR_MAX_LEN })?;
+
+        Ok(Self { len: len as u32, hdr, cmd })
+    }
+
+    /// Header for a payload whose size is bounded by the message type.
+    pub(crate) fn fixed(hdr: [u8; 4], cmd: [u8; 2], data_length: u16) -> Self {
+        let len = (MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE) as u32 + data_length as u32;
         Self { len, hdr, cmd }
     }
 
@@ -29,31 +53,161 @@ impl MessageHeader {
     }
 
     pub fn data_length(&self) -> usize {
-        self.len as usize - MSGHDR_HDR_SIZE - MSGHDR_CMD_SIZE
+        (self.len as usize).saturating_sub(MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE)
+    }
+
+    pub fn is_extended(&self) -> bool {
+        self.len as usize > MSGHDR_MAX_STD_LEN
+    }
+
+    /// Bytes taken by the length prefix, including the extended length when present.
+    pub fn prefix_length(&self) -> usize {
+        if self.is_extended() {
+            MSGHDR_LEN_SIZE + MSGHDR_EXT_LEN_SIZE
+        } else {
+            MSGHDR_LEN_SIZE
+        }
+    }
+
+    pub fn header_length(&self) -> usize {
+        self.prefix_length() + MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE
+    }
+
+    pub fn frame_length(&self) -> usize {
+        self.prefix_length() + self.len as usize
+    }
+
+    /// Returns the payload after the header, requiring the buffer to hold exactly one frame.
+    pub fn payload<'a>(&self, buffer: &'a [u8]) -> Result<&'a [u8]> {
+        let frame_length = self.frame_length();
+        if buffer.len() < frame_length {
+            return Err(Error::TooShort { need: frame_length, got: buffer.len() });
+        }
+        if buffer.len() > frame_length {
+            return Err(Error::TrailingBytes { count: buffer.len() - frame_length });
+        }
+        Ok(&buffer[self.header_length()..frame_length])
+    }
+
+    /// Decodes the length prefix at the start of `buffer`, returning the prefix size
+    /// and the declared `len`, or `None` until enough bytes are available.
+    pub fn peek_length(buffer: &[u8]) -> Option<(usize, usize)> {
+        if buffer.len() < MSGHDR_LEN_SIZE {
+            return None;
+        }
+        match u16::from_be_bytes([buffer[0], buffer[1]]) {
+            MSGHDR_EXT_MARKER => {
+                let prefix = MSGHDR_LEN_SIZE + MSGHDR_EXT_LEN_SIZE;
+                if buffer.len() < prefix {
+                    return None;
+                }
+                let len = u32::from_be_bytes([buffer[2], buffer[3], buffer[4], buffer[5]]);
+                Some((prefix, len as usize))
+            }
+            len => Some((MSGHDR_LEN_SIZE, len as usize)),
+        }
     }
 
     pub fn parse(buffer: &[u8]) -> Result<Self> {
         if buffer.len() < MSGHDR_FMT_SIZE {
-            bail!("Buffer too small for header: need {}, got {}", MSGHDR_FMT_SIZE, buffer.len());
+            return Err(Error::TooShort { need: MSGHDR_FMT_SIZE, got: buffer.len() });
         }
         
-        let len = u16::from_be_bytes([buffer[0], buffer[1]]);
+        let (len, offset) = match u16::from_be_bytes([buffer[0], buffer[1]]) {
+            MSGHDR_EXT_MARKER => {
+                if buffer.len() < MSGHDR_EXT_FMT_SIZE {
+                    return Err(Error::TooShort { need: MSGHDR_EXT_FMT_SIZE, got: buffer.len() });
+                }
+                let len = u32::from_be_bytes([buffer[2], buffer[3], buffer[4], buffer[5]]);
+                // Extended prefix is only valid for lengths the standard prefix cannot carry
+                if (len as usize) <= MSGHDR_MAX_STD_LEN {
+                    return Err(Error::BadLength { name: "len", len: len as usize });
+                }
+                (len, MSGHDR_LEN_SIZE + MSGHDR_EXT_LEN_SIZE)
+            }
+            len => (len as u32, MSGHDR_LEN_SIZE),
+        };
+        if (len as usize) < MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE {
+            return Err(Error::BadLength { name: "len", len: len as usize });
+        }
         
         let mut hdr = [0u8; 4];
-        hdr.copy_from_slice(&buffer[2..6]);
+        hdr.copy_from_slice(&buffer[offset..offset + MSGHDR_HDR_SIZE]);
         
         let mut cmd = [0u8; 2];
-        cmd.copy_from_slice(&buffer[6..8]);
+        cmd.copy_from_slice(&buffer[offset + MSGHDR_HDR_SIZE..offset + MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE]);
         
         Ok(Self { len, hdr, cmd })
     }
 
-    pub fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::with_capacity(MSGHDR_FMT_SIZE);
+    pub fn write_to(&self, buf: &mut impl BufMut) {
+        if self.is_extended() {
+            buf.put_u16(MSGHDR_EXT_MARKER);
+            buf.put_u32(self.len);
+        } else {
+            buf.put_u16(self.len as u16);
+        }
+        buf.put_slice(&self.hdr);
+        buf.put_slice(&self.cmd);
+    }
 
-        result.extend_from_slice(&self.len.to_be_bytes());
-        result.extend_from_slice(&self.hdr);
-        result.extend_from_slice(&self.cmd);
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.header_length());
+        self.write_to(&mut result);
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER_SIZE: usize = MSGHDR_HDR_SIZE + MSGHDR_CMD_SIZE;
+
+    #[test]
+    fn new_rejects_payloads_the_prefix_cannot_describe() {
+        let header = MessageHeader::new(*b"0001", *b"Z1", MSGHDR_MAX_LEN - HEADER_SIZE).unwrap();
+        assert_eq!(header.len as usize, MSGHDR_MAX_LEN);
+
+        assert_eq!(
+            MessageHeader::new(*b"0001", *b"Z1", MSGHDR_MAX_LEN - HEADER_SIZE + 1),
+            Err(Error::FrameTooLarge { size: MSGHDR_MAX_LEN - HEADER_SIZE + 1, max: MSGHDR_MAX_LEN })
+        );
+        assert!(matches!(MessageHeader::new(*b"0001", *b"Z1", usize::MAX), Err(Error::FrameTooLarge { .. })));
+    }
+
+    #[test]
+    fn extended_prefix_starts_above_the_standard_maximum() {
+        let standard = MessageHeader::new(*b"0001", *b"Z1", MSGHDR_MAX_STD_LEN - HEADER_SIZE).unwrap();
+        assert!(!standard.is_extended());
+        assert_eq!(standard.to_bytes()[..2], (MSGHDR_MAX_STD_LEN as u16).to_be_bytes());
+
+        let extended = MessageHeader::new(*b"0001", *b"Z1", MSGHDR_MAX_STD_LEN - HEADER_SIZE + 1).unwrap();
+        assert!(extended.is_extended());
+        assert_eq!(extended.prefix_length(), MSGHDR_LEN_SIZE + MSGHDR_EXT_LEN_SIZE);
+
+        let bytes = extended.to_bytes();
+        assert_eq!(bytes[..2], MSGHDR_EXT_MARKER.to_be_bytes());
+        assert_eq!(bytes[2..6], (MSGHDR_MAX_STD_LEN as u32 + 1).to_be_bytes());
+        assert_eq!(MessageHeader::parse(&bytes).unwrap(), extended);
+    }
+
+    #[test]
+    fn parse_rejects_non_canonical_extended_length() {
+        for len in [HEADER_SIZE as u32, MSGHDR_MAX_STD_LEN as u32] {
+            let mut bytes = MSGHDR_EXT_MARKER.to_be_bytes().to_vec();
+            bytes.extend_from_slice(&len.to_be_bytes());
+            bytes.extend_from_slice(b"0001Z1");
+
+            assert_eq!(MessageHeader::parse(&bytes), Err(Error::BadLength { name: "len", len: len as usize }));
+        }
+    }
+
+    #[test]
+    fn parse_rejects_length_shorter_than_the_header() {
+        let mut bytes = (HEADER_SIZE as u16 - 1).to_be_bytes().to_vec();
+        bytes.extend_from_slice(b"0001Z1");
+
+        assert_eq!(MessageHeader::parse(&bytes), Err(Error::BadLength { name: "len", len: HEADER_SIZE - 1 }));
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::header::MSGHDR_HDR_SIZE;
+
+/// Number of distinct ids: `hdr` carries four ASCII digits.
+pub const HEADER_ID_COUNT: u32 = 10_000;
+
+struct State {
+    next: u32,
+    in_flight: HashSet<[u8; MSGHDR_HDR_SIZE]>,
+}
+
+/// Hands out `hdr` values for requests sharing a connection.
+///
+/// Ids are four ASCII digits assigned round-robin, skipping any still held
+/// by an outstanding [`HeaderId`]. Dropping the `HeaderId` frees its value,
+/// so keep it alive until the matching response has arrived.
+#[derive(Clone)]
+pub struct HeaderIdAllocator {
+    state: Arc<Mutex<State>>,
+}
+
+impl HeaderIdAllocator {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State { next: 0, in_flight: HashSet::new() })),
+        }
+    }
+
+    /// Returns `None` when every id is in flight.
+    pub fn allocate(&self) -> Option<HeaderId> {
+        let mut state = self.lock();
+
+        for _ in 0..HEADER_ID_COUNT {
+            let id = state.next;
+            state.next = (state.next + 1) % HEADER_ID_COUNT;
+
+            let hdr = format_id(id);
+            if state.in_flight.insert(hdr) {
+                return Some(HeaderId { hdr, state: Arc::clone(&self.state) });
+            }
+        }
+
+        None
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.lock().in_flight.len()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        lock(&self.state)
+    }
+}
+
+impl Default for HeaderIdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An allocated `hdr`, released back to its allocator on drop.
+pub struct HeaderId {
+    hdr: [u8; MSGHDR_HDR_SIZE],
+    state: Arc<Mutex<State>>,
+}
+
+impl HeaderId {
+    pub fn hdr(&self) -> [u8; MSGHDR_HDR_SIZE] {
+        self.hdr
+    }
+}
+
+impl fmt::Debug for HeaderId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HeaderId({})", String::from_utf8_lossy(&self.hdr))
+    }
+}
+
+impl Drop for HeaderId {
+    fn drop(&mut self) {
+        lock(&self.state).in_flight.remove(&self.hdr);
+    }
+}
+
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    // The set is never left half-updated, so a poisoned lock is still usable
+    state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn format_id(id: u32) -> [u8; MSGHDR_HDR_SIZE] {
+    let mut hdr = [0u8; MSGHDR_HDR_SIZE];
+    hdr.copy_from_slice(format!("{:04}", id).as_bytes());
+    hdr
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_are_four_digits_assigned_in_order() {
+        let allocator = HeaderIdAllocator::new();
+        let first  = allocator.allocate().unwrap();
+        let second = allocator.allocate().unwrap();
+
+        assert_eq!(&first.hdr(), b"0000");
+        assert_eq!(&second.hdr(), b"0001");
+        assert_eq!(allocator.in_flight(), 2);
+    }
+
+    #[test]
+    fn dropping_an_id_releases_it() {
+        let allocator = HeaderIdAllocator::new();
+        let id = allocator.allocate().unwrap();
+        assert_eq!(allocator.in_flight(), 1);
+
+        drop(id);
+        assert_eq!(allocator.in_flight(), 0);
+
+        // Clones share the same set of ids
+        let clone = allocator.clone();
+        let _held = clone.allocate().unwrap();
+        assert_eq!(allocator.in_flight(), 1);
+    }
+
+    #[test]
+    fn ids_wrap_around_after_9999() {
+        let allocator = HeaderIdAllocator::new();
+        for _ in 0..HEADER_ID_COUNT - 1 {
+            allocator.allocate().unwrap();
+        }
+
+        assert_eq!(&allocator.allocate().unwrap().hdr(), b"9999");
+        assert_eq!(&allocator.allocate().unwrap().hdr(), b"0000");
+    }
+
+    #[test]
+    fn ids_in_flight_are_skipped() {
+        let allocator = HeaderIdAllocator::new();
+        let held: Vec<HeaderId> = (0..3).map(|_| allocator.allocate().unwrap()).collect();
+
+        // Go round once more; 0000..0002 are still held
+        for _ in 3..HEADER_ID_COUNT {
+            allocator.allocate().unwrap();
+        }
+
+        assert_eq!(&allocator.allocate().unwrap().hdr(), b"0003");
+        assert_eq!(held.len(), 3);
+    }
+
+    #[test]
+    fn allocation_fails_when_every_id_is_in_flight() {
+        let allocator = HeaderIdAllocator::new();
+        let mut held: Vec<HeaderId> = (0..HEADER_ID_COUNT).map(|_| allocator.allocate().unwrap()).collect();
+
+        assert!(allocator.allocate().is_none());
+        assert_eq!(allocator.in_flight(), HEADER_ID_COUNT as usize);
+
+        let freed = held.remove(1234);
+        let hdr = freed.hdr();
+        drop(freed);
+        assert_eq!(allocator.allocate().unwrap().hdr(), hdr);
+    }
+}
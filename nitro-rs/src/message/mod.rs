@@ -1,21 +1,86 @@
 pub mod header;
+pub mod header_id;
 pub mod commands;
+mod reader;
+#[cfg(test)]
+mod testing;
 
 
-pub use header::{MessageHeader, MSGHDR_LEN_SIZE, MSGHDR_HDR_SIZE, MSGHDR_CMD_SIZE, MSGHDR_FMT_SIZE};
+pub use header::{
+    MessageHeader,
+    MSGHDR_LEN_SIZE,
+    MSGHDR_HDR_SIZE,
+    MSGHDR_CMD_SIZE,
+    MSGHDR_FMT_SIZE,
+    MSGHDR_EXT_MARKER,
+    MSGHDR_EXT_LEN_SIZE,
+    MSGHDR_EXT_FMT_SIZE,
+    MSGHDR_MAX_STD_LEN,
+    MSGHDR_MAX_LEN,
+};
+
+pub use header_id::{HeaderId, HeaderIdAllocator, HEADER_ID_COUNT};
 
 pub use commands::{
     Message, 
+    MessageRef,
     VerifyCVVRequest,
+    VerifyCVVRequestRef,
     VerifyCVVResponse,
+    GenerateCVVRequest,
+    GenerateCVVRequestRef,
+    GenerateCVVResponse,
+    VerifyPvvRequest,
+    VerifyPvvRequestRef,
+    VerifyPvvResponse,
+    PIN_BLOCK_DES_HEX_LEN,
+    PIN_BLOCK_AES_HEX_LEN,
+    GenerateOffsetRequest,
+    GenerateOffsetRequestRef,
+    GenerateOffsetResponse,
+    VerifyOffsetRequest,
+    VerifyOffsetRequestRef,
+    VerifyOffsetResponse,
+    OFFSET_SIZE,
+    TranslatePinRequest,
+    TranslatePinRequestRef,
+    TranslatePinResponse,
+    TranslatePinResponseRef,
+    DukptTranslatePinRequest,
+    DukptTranslatePinRequestRef,
+    DukptTranslatePinResponse,
+    DukptTranslatePinResponseRef,
+    KSN_TDES_HEX_LEN,
+    KSN_AES_HEX_LEN,
+    DukptDecryptRequest,
+    DukptDecryptRequestRef,
+    DukptDecryptResponse,
+    DukptDecryptResponseRef,
     GetKeyRequest, 
+    GetKeyRequestRef,
     GetKeyResponse,
+    GetKeyResponseRef,
 };
 
 // Re-export command constants
 pub use commands::command::{
     CMD_VERIFYCVV_REQUEST,
     CMD_VERIFYCVV_RESPONSE,
+    CMD_GENERATECVV_REQUEST,
+    CMD_GENERATECVV_RESPONSE,
+    CMD_VERIFYPVV_REQUEST,
+    CMD_VERIFYPVV_RESPONSE,
+    CMD_GENERATEOFFSET_REQUEST,
+    CMD_GENERATEOFFSET_RESPONSE,
+    CMD_VERIFYOFFSET_REQUEST,
+    CMD_VERIFYOFFSET_RESPONSE,
+    CMD_TRANSLATEPIN_REQUEST,
+    CMD_TRANSLATEPIN_RESPONSE,
+    CMD_DUKPTTRANSLATEPIN_REQUEST,
+    CMD_DUKPTTRANSLATEPIN_RESPONSE,
+    CMD_DUKPTDECRYPT_REQUEST,
+    CMD_DUKPTDECRYPT_RESPONSE,
     CMD_GETKEY_REQUEST, 
-    CMD_GETKEY_RESPONSE
+    CMD_GETKEY_RESPONSE,
+    ResponseCode,
 };
@@ -0,0 +1,104 @@
+use crate::error::{Error, Result};
+
+pub(crate) fn as_str<'a>(name: &'static str, bytes: &'a [u8]) -> Result<&'a str> {
+    std::str::from_utf8(bytes).map_err(|_| Error::bad_field(name, "invalid UTF-8"))
+}
+
+/// Sequential, bounds-checked access to the payload of a single frame.
+pub(crate) struct FieldReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> FieldReader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    pub(crate) fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    pub(crate) fn take(&mut self, name: &'static str, len: usize) -> Result<&'a [u8]> {
+        if self.remaining() < len {
+            return Err(Error::Truncated { name });
+        }
+        let field = &self.data[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(field)
+    }
+
+    pub(crate) fn take_array<const N: usize>(&mut self, name: &'static str) -> Result<[u8; N]> {
+        Ok(*self.take_array_ref(name)?)
+    }
+
+    pub(crate) fn take_array_ref<const N: usize>(&mut self, name: &'static str) -> Result<&'a [u8; N]> {
+        let field = self.take(name, N)?;
+        field.try_into().map_err(|_| Error::Truncated { name })
+    }
+
+    /// Takes bytes up to the next ';' and consumes the delimiter.
+    pub(crate) fn take_delimited_str(&mut self, name: &'static str) -> Result<&'a str> {
+        let rest = &self.data[self.offset..];
+        let end = rest.iter().position(|&b| b == b';')
+            .ok_or(Error::MissingDelimiter { name })?;
+        self.offset += end + 1;
+        as_str(name, &rest[..end])
+    }
+
+    pub(crate) fn take_rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.offset..];
+        self.offset = self.data.len();
+        rest
+    }
+
+    pub(crate) fn finish(self) -> Result<()> {
+        match self.remaining() {
+            0 => Ok(()),
+            count => Err(Error::TrailingBytes { count }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_past_end_is_truncated() {
+        let data = b"0123456789";
+        for len in data.len() + 1..data.len() + 4 {
+            let mut reader = FieldReader::new(data);
+            assert_eq!(reader.take("field", len), Err(Error::Truncated { name: "field" }));
+        }
+    }
+
+    #[test]
+    fn take_array_ref_at_every_cut_is_truncated() {
+        let data = [b'1'; 16];
+        for end in 0..data.len() {
+            let mut reader = FieldReader::new(&data[..end]);
+            assert_eq!(reader.take_array_ref::<16>("key"), Err(Error::Truncated { name: "key" }));
+        }
+    }
+
+    #[test]
+    fn take_delimited_str_without_delimiter() {
+        let data = b"4111111111111111;";
+        for end in 0..data.len() {
+            let mut reader = FieldReader::new(&data[..end]);
+            assert_eq!(reader.take_delimited_str("pan"), Err(Error::MissingDelimiter { name: "pan" }));
+        }
+
+        let mut reader = FieldReader::new(data);
+        assert_eq!(reader.take_delimited_str("pan"), Ok("4111111111111111"));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn finish_rejects_trailing_bytes() {
+        let mut reader = FieldReader::new(b"00XYZ");
+        assert_eq!(reader.take_array::<2>("response_code"), Ok(*b"00"));
+        assert_eq!(reader.finish(), Err(Error::TrailingBytes { count: 3 }));
+    }
+}
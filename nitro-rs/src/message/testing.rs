@@ -0,0 +1,29 @@
+//! Frame surgery shared by the message unit tests.
+
+use super::header::{MSGHDR_FMT_SIZE, MSGHDR_LEN_SIZE};
+
+/// Every proper prefix of `frame`; the length prefix still declares the whole frame.
+pub(crate) fn truncations(frame: &[u8]) -> impl Iterator<Item = &[u8]> {
+    (0..frame.len()).map(move |end| &frame[..end])
+}
+
+/// Every proper prefix of `frame` that still holds a header, with the length
+/// prefix rewritten to match so parsing reaches the field that was cut.
+pub(crate) fn relabeled_truncations(frame: &[u8]) -> impl Iterator<Item = Vec<u8>> + '_ {
+    (MSGHDR_FMT_SIZE..frame.len()).map(move |end| relabel(frame[..end].to_vec()))
+}
+
+/// `frame` with its standard 2-byte length prefix set to the bytes that follow it.
+pub(crate) fn relabel(mut frame: Vec<u8>) -> Vec<u8> {
+    let len = (frame.len() - MSGHDR_LEN_SIZE) as u16;
+    frame[..MSGHDR_LEN_SIZE].copy_from_slice(&len.to_be_bytes());
+    frame
+}
+
+/// `frame` with its declared length shifted by `delta` and the bytes left as they are.
+pub(crate) fn with_declared_len(frame: &[u8], delta: i32) -> Vec<u8> {
+    let mut frame = frame.to_vec();
+    let len = u16::from_be_bytes([frame[0], frame[1]]) as i32 + delta;
+    frame[..MSGHDR_LEN_SIZE].copy_from_slice(&(len as u16).to_be_bytes());
+    frame
+}
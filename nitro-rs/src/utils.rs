@@ -1,3 +1,7 @@
+use std::ops::Range;
+
+use crate::message::{MessageHeader, MessageRef, MSGHDR_LEN_SIZE};
+
 
 pub fn hexdump(bytes: &[u8]) {
     hexdump_with_options(bytes, 16, true)
@@ -57,7 +61,7 @@ pub fn hexdump_string(bytes: &[u8]) -> String {
 
         for (j, byte) in chunk.iter().enumerate() {
             result.push_str(&format!("{:02x}", byte));
-            if 16 == 16 && j == 7 {
+            if j == 7 {
                 result.push(' ');
             }
             result.push(' ');
@@ -84,3 +88,270 @@ pub fn hexdump_string(bytes: &[u8]) -> String {
     
     result
 }
+
+/// Byte written over masked fields.
+pub const MASK_BYTE: u8 = b'*';
+
+const PAN_CLEAR_PREFIX: usize = 6;
+const PAN_CLEAR_SUFFIX: usize = 4;
+
+/// Copy of a wire frame with cardholder and key data masked.
+///
+/// The PAN keeps its first six and last four digits, CVVs, PIN data and key
+/// material are masked completely. Frames that do not parse as a known message have
+/// their whole payload masked.
+pub fn redact_frame(frame: &[u8]) -> Vec<u8> {
+    let mut redacted = frame.to_vec();
+
+    let message = match MessageRef::parse(frame) {
+        Ok(message) => message,
+        Err(_) => {
+            let payload_start = MessageHeader::parse(frame)
+                .map(|header| header.header_length())
+                .unwrap_or(MSGHDR_LEN_SIZE);
+            mask(&mut redacted, payload_start.min(frame.len())..frame.len());
+            return redacted;
+        }
+    };
+
+    match message {
+        MessageRef::VerifyCVVRequest(request) => {
+            mask(&mut redacted, field_range(frame, request.cvv));
+            mask_pan(&mut redacted, field_range(frame, request.pan));
+        }
+        MessageRef::GenerateCVVRequest(request) => {
+            mask_pan(&mut redacted, field_range(frame, request.pan));
+        }
+        MessageRef::VerifyPvvRequest(request) => {
+            mask(&mut redacted, field_range(frame, request.pin_block));
+            mask_pan(&mut redacted, field_range(frame, request.pan));
+            mask(&mut redacted, field_range(frame, request.pvv));
+        }
+        MessageRef::GenerateOffsetRequest(request) => {
+            mask(&mut redacted, field_range(frame, request.pin_block));
+            mask_pan(&mut redacted, field_range(frame, request.pan));
+            mask(&mut redacted, field_range(frame, request.validation_data));
+        }
+        MessageRef::VerifyOffsetRequest(request) => {
+            mask(&mut redacted, field_range(frame, request.pin_block));
+            mask_pan(&mut redacted, field_range(frame, request.pan));
+            mask(&mut redacted, field_range(frame, request.validation_data));
+            mask(&mut redacted, field_range(frame, request.offset));
+        }
+        MessageRef::TranslatePinRequest(request) => {
+            mask(&mut redacted, field_range(frame, request.pin_block));
+            mask_pan(&mut redacted, field_range(frame, request.pan));
+        }
+        MessageRef::DukptTranslatePinRequest(request) => {
+            mask(&mut redacted, field_range(frame, request.pin_block));
+            mask_pan(&mut redacted, field_range(frame, request.pan));
+        }
+        MessageRef::DukptDecryptRequest(request) => {
+            mask(&mut redacted, field_range(frame, request.data));
+        }
+        MessageRef::GenerateCVVResponse(response) => {
+            // The generated CVV follows the response code
+            let cvv_start = response.header.header_length() + response.response_code.len();
+            mask(&mut redacted, cvv_start.min(frame.len())..frame.len());
+        }
+        MessageRef::GenerateOffsetResponse(response) => {
+            let offset_start = response.header.header_length() + response.response_code.len();
+            mask(&mut redacted, offset_start.min(frame.len())..frame.len());
+        }
+        MessageRef::TranslatePinResponse(response) => {
+            if let Some(pin_block) = response.pin_block {
+                mask(&mut redacted, field_range(frame, pin_block));
+            }
+        }
+        MessageRef::DukptTranslatePinResponse(response) => {
+            if let Some(pin_block) = response.pin_block {
+                mask(&mut redacted, field_range(frame, pin_block));
+            }
+        }
+        MessageRef::DukptDecryptResponse(response) => {
+            if let Some(data) = response.data {
+                mask(&mut redacted, field_range(frame, data));
+            }
+        }
+        MessageRef::GetKeyResponse(response) => {
+            if let Some(key) = response.encrypted_key {
+                mask(&mut redacted, field_range(frame, key));
+            }
+        }
+        MessageRef::VerifyCVVResponse(_)
+        | MessageRef::VerifyPvvResponse(_)
+        | MessageRef::VerifyOffsetResponse(_)
+        | MessageRef::GetKeyRequest(_) => {}
+    }
+
+    redacted
+}
+
+/// Hexdump of `frame` with sensitive fields masked, see [`redact_frame`].
+pub fn hexdump_redacted(frame: &[u8]) -> String {
+    hexdump_string(&redact_frame(frame))
+}
+
+/// Hexdump for logging wire frames: redacted unless built with `raw-dump`.
+#[cfg(not(feature = "raw-dump"))]
+pub fn hexdump_frame(frame: &[u8]) -> String {
+    hexdump_redacted(frame)
+}
+
+/// Hexdump for logging wire frames: redacted unless built with `raw-dump`.
+#[cfg(feature = "raw-dump")]
+pub fn hexdump_frame(frame: &[u8]) -> String {
+    hexdump_string(frame)
+}
+
+/// Masks all but the first six and last four digits, or everything for short PANs.
+pub fn mask_pan(buf: &mut [u8], range: Range<usize>) {
+    if range.len() <= PAN_CLEAR_PREFIX + PAN_CLEAR_SUFFIX {
+        mask(buf, range);
+    } else {
+        mask(buf, range.start + PAN_CLEAR_PREFIX..range.end - PAN_CLEAR_SUFFIX);
+    }
+}
+
+fn mask(buf: &mut [u8], range: Range<usize>) {
+    if let Some(bytes) = buf.get_mut(range) {
+        bytes.fill(MASK_BYTE);
+    }
+}
+
+/// Position of a field borrowed from `frame`.
+fn field_range(frame: &[u8], field: &[u8]) -> Range<usize> {
+    let start = field.as_ptr() as usize - frame.as_ptr() as usize;
+    start..start + field.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::*;
+
+    const PAN: &str = "4123456789012345";
+    const PAN_REDACTED: &str = "412345******2345";
+
+    fn position(frame: &[u8], field: &str) -> usize {
+        let matches: Vec<usize> = frame.windows(field.len())
+            .enumerate()
+            .filter(|(_, window)| *window == field.as_bytes())
+            .map(|(at, _)| at)
+            .collect();
+        assert_eq!(matches.len(), 1, "{} must occur once in the frame", field);
+        matches[0]
+    }
+
+    /// Checks that exactly `secrets` are masked and `PAN`, if present, keeps its ends.
+    fn assert_redacted(frame: &[u8], secrets: &[&str]) {
+        let mut expected = frame.to_vec();
+        for secret in secrets {
+            let at = position(frame, secret);
+            expected[at..at + secret.len()].fill(MASK_BYTE);
+        }
+        if frame.windows(PAN.len()).any(|window| window == PAN.as_bytes()) {
+            let at = position(frame, PAN);
+            expected[at..at + PAN.len()].copy_from_slice(PAN_REDACTED.as_bytes());
+        }
+
+        assert_eq!(String::from_utf8_lossy(&redact_frame(frame)), String::from_utf8_lossy(&expected));
+    }
+
+    #[test]
+    fn cvv_requests() {
+        let frame = VerifyCVVRequest::new(*b"0001", "cvk-a", "cvk-b", "738", PAN, "2512", "101").unwrap().to_bytes();
+        assert_redacted(&frame, &["738"]);
+
+        let frame = GenerateCVVRequest::new(*b"0001", "cvk-a", "cvk-b", PAN, "2512", "101").unwrap().to_bytes();
+        assert_redacted(&frame, &[]);
+    }
+
+    #[test]
+    fn pin_verification_requests() {
+        let pin_block = "A1B2C3D4E5F60718";
+
+        let frame = VerifyPvvRequest::new(*b"0001", "pek", "pvk-a", "pvk-b", "01", pin_block, PAN, "1", "9327").unwrap().to_bytes();
+        assert_redacted(&frame, &[pin_block, "9327"]);
+
+        let frame = GenerateOffsetRequest::new(*b"0001", "pek", "pvk", "dectab", "01", "04", pin_block, PAN, "55667788AB").unwrap().to_bytes();
+        assert_redacted(&frame, &[pin_block, "55667788AB"]);
+
+        let frame = VerifyOffsetRequest::new(*b"0001", "pek", "pvk", "dectab", "01", "04", pin_block, PAN, "55667788AB", "9256FFFFFFFF")
+            .unwrap()
+            .to_bytes();
+        assert_redacted(&frame, &[pin_block, "55667788AB", "9256FFFFFFFF"]);
+    }
+
+    #[test]
+    fn pin_translation_requests() {
+        let pin_block = "A1B2C3D4E5F60718";
+
+        let frame = TranslatePinRequest::new(*b"0001", "zpk-a", "zpk-b", "01", "01", pin_block, PAN).unwrap().to_bytes();
+        assert_redacted(&frame, &[pin_block]);
+
+        let frame = DukptTranslatePinRequest::new(*b"0001", "bdk", "zpk", "01", "01", "FFFF9876543210E00001", pin_block, PAN)
+            .unwrap()
+            .to_bytes();
+        assert_redacted(&frame, &[pin_block]);
+
+        let frame = DukptDecryptRequest::new(*b"0001", "bdk", "FFFF9876543210E00001", "0A1B2C3D4E5F6A7B8C9D0E1F2A3B4C5D").unwrap().to_bytes();
+        assert_redacted(&frame, &["0A1B2C3D4E5F6A7B8C9D0E1F2A3B4C5D"]);
+    }
+
+    #[test]
+    fn responses_with_sensitive_data() {
+        assert_redacted(&GenerateCVVResponse::success(*b"0001", *b"738").to_bytes(), &["738"]);
+        assert_redacted(&GenerateOffsetResponse::success(*b"0001", *b"9256FFFFFFFF").to_bytes(), &["9256FFFFFFFF"]);
+        assert_redacted(&TranslatePinResponse::success(*b"0001", "A1B2C3D4E5F60718").unwrap().to_bytes(), &["A1B2C3D4E5F60718"]);
+        assert_redacted(&DukptTranslatePinResponse::success(*b"0001", "A1B2C3D4E5F60718").unwrap().to_bytes(), &["A1B2C3D4E5F60718"]);
+        assert_redacted(&DukptDecryptResponse::success(*b"0001", "0A1B2C3D4E5F6A7B").unwrap().to_bytes(), &["0A1B2C3D4E5F6A7B"]);
+        assert_redacted(&GetKeyResponse::success(*b"0001", b"wrapped-key-bytes".to_vec()).unwrap().to_bytes(), &["wrapped-key-bytes"]);
+    }
+
+    #[test]
+    fn messages_without_sensitive_data_are_unchanged() {
+        let frames = [
+            VerifyCVVResponse::success(*b"0001").to_bytes(),
+            VerifyPvvResponse::error(*b"0001", ResponseCode::PinMismatch).to_bytes(),
+            VerifyOffsetResponse::success(*b"0001").to_bytes(),
+            GetKeyRequest::new(*b"0001", b"cvk-visa-a".to_vec()).unwrap().to_bytes(),
+            GenerateCVVResponse::error(*b"0001", ResponseCode::SystemError).to_bytes(),
+            TranslatePinResponse::error(*b"0001", ResponseCode::InvalidPinBlock).to_bytes(),
+            DukptDecryptResponse::error(*b"0001", ResponseCode::InvalidKsn).to_bytes(),
+            GetKeyResponse::error(*b"0001", ResponseCode::SecretNotFound).to_bytes(),
+        ];
+
+        for frame in frames {
+            assert_redacted(&frame, &[]);
+        }
+    }
+
+    #[test]
+    fn short_pan_is_masked_completely() {
+        let mut buffer = *b"4123456789";
+        mask_pan(&mut buffer, 0..10);
+        assert_eq!(&buffer, b"**********");
+    }
+
+    #[test]
+    fn unparseable_frames_mask_the_whole_payload() {
+        // Valid header, unknown command
+        let mut frame = VerifyCVVRequest::new(*b"0001", "cvk-a", "cvk-b", "738", PAN, "2512", "101").unwrap().to_bytes();
+        frame[6..8].copy_from_slice(b"XX");
+        let redacted = redact_frame(&frame);
+        assert_eq!(redacted[..8], frame[..8]);
+        assert!(redacted[8..].iter().all(|&byte| byte == MASK_BYTE));
+
+        // Truncated known command
+        let frame = VerifyCVVRequest::new(*b"0001", "cvk-a", "cvk-b", "738", PAN, "2512", "101").unwrap().to_bytes();
+        let cut = &frame[..frame.len() - 1];
+        let redacted = redact_frame(cut);
+        assert_eq!(redacted[..8], cut[..8]);
+        assert!(redacted[8..].iter().all(|&byte| byte == MASK_BYTE));
+
+        // Not even a header: everything after the length prefix
+        let redacted = redact_frame(&[0x00, 0x03, b'0', b'1', b'2']);
+        assert_eq!(redacted, [0x00, 0x03, MASK_BYTE, MASK_BYTE, MASK_BYTE]);
+    }
+}
@@ -0,0 +1,246 @@
+use std::fmt;
+use std::io;
+
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use nitro::{Message, MessageHeader};
+use nitro::message::{MSGHDR_LEN_SIZE, MSGHDR_MAX_STD_LEN};
+
+/// Default limit on the length after the prefix; larger frames need an explicit
+/// `max_frame_size` on both ends of the connection.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = u16::MAX as usize;
+
+#[derive(Debug)]
+pub enum CodecError {
+    Io(io::Error),
+    FrameTooLarge { size: usize, max: usize },
+    Protocol(nitro::Error),
+}
+
+impl fmt::Display for CodecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodecError::Io(e) => write!(f, "I/O error: {}", e),
+            CodecError::FrameTooLarge { size, max } => {
+                write!(f, "Frame too large: {} bytes (max {})", size, max)
+            }
+            CodecError::Protocol(e) => write!(f, "Protocol error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CodecError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CodecError::Io(e) => Some(e),
+            CodecError::Protocol(e) => Some(e),
+            CodecError::FrameTooLarge { .. } => None,
+        }
+    }
+}
+
+impl From<io::Error> for CodecError {
+    fn from(e: io::Error) -> Self {
+        CodecError::Io(e)
+    }
+}
+
+impl From<nitro::Error> for CodecError {
+    fn from(e: nitro::Error) -> Self {
+        CodecError::Protocol(e)
+    }
+}
+
+/// Length-prefixed frame codec producing [`nitro::Message`] values.
+///
+/// A frame that fails to parse is consumed from the buffer before the
+/// error is returned, but `Framed` ends the stream after any decode error.
+#[derive(Debug, Clone, Copy)]
+pub struct NitroCodec {
+    max_frame_size: usize,
+}
+
+impl NitroCodec {
+    pub fn new() -> Self {
+        Self { max_frame_size: DEFAULT_MAX_FRAME_SIZE }
+    }
+
+    /// `max_frame_size` is the largest payload accepted after the length prefix.
+    pub fn with_max_frame_size(max_frame_size: usize) -> Self {
+        Self { max_frame_size }
+    }
+
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+}
+
+impl Default for NitroCodec {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Decoder for NitroCodec {
+    type Item  = Message;
+    type Error = CodecError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, CodecError> {
+        let Some((prefix_length, length)) = MessageHeader::peek_length(src) else {
+            return Ok(None);
+        };
+
+        if length > self.max_frame_size {
+            return Err(CodecError::FrameTooLarge { size: length, max: self.max_frame_size });
+        }
+        if prefix_length > MSGHDR_LEN_SIZE && length <= MSGHDR_MAX_STD_LEN {
+            return Err(CodecError::Protocol(nitro::Error::BadLength { name: "len", len: length }));
+        }
+
+        let frame_length = prefix_length + length;
+        if src.len() < frame_length {
+            src.reserve(frame_length - src.len());
+            return Ok(None);
+        }
+
+        let frame = src.split_to(frame_length);
+        log::trace!("Decoded frame of {} bytes ({} left in buffer)", frame.len(), src.remaining());
+
+        Ok(Some(Message::parse(&frame)?))
+    }
+}
+
+impl Encoder<&Message> for NitroCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, message: &Message, dst: &mut BytesMut) -> Result<(), CodecError> {
+        let length = message.header().len as usize;
+        if length > self.max_frame_size {
+            return Err(CodecError::FrameTooLarge { size: length, max: self.max_frame_size });
+        }
+
+        message.write_to(dst);
+        Ok(())
+    }
+}
+
+impl Encoder<Message> for NitroCodec {
+    type Error = CodecError;
+
+    fn encode(&mut self, message: Message, dst: &mut BytesMut) -> Result<(), CodecError> {
+        self.encode(&message, dst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nitro::message::{GetKeyRequest, GetKeyResponse, VerifyCVVRequest};
+
+    fn request() -> Message {
+        Message::VerifyCVVRequest(VerifyCVVRequest::new(*b"0001", "cvk-a", "cvk-b", "123", "4123456789012345", "8701", "101").unwrap())
+    }
+
+    fn response() -> Message {
+        Message::GetKeyResponse(GetKeyResponse::success(*b"0002", vec![0xA5; 48]).unwrap())
+    }
+
+    #[test]
+    fn encode_then_decode_round_trips() {
+        let mut codec = NitroCodec::new();
+        let mut buffer = BytesMut::new();
+
+        codec.encode(&request(), &mut buffer).unwrap();
+        assert_eq!(buffer.as_ref(), request().to_bytes());
+
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(request()));
+        assert!(buffer.is_empty());
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+    }
+
+    #[test]
+    fn partial_frames_wait_for_more_bytes() {
+        let mut codec = NitroCodec::new();
+        let frame = request().to_bytes();
+        let mut buffer = BytesMut::new();
+
+        // One byte at a time, covering a partial length prefix, header and body
+        for &byte in &frame[..frame.len() - 1] {
+            buffer.extend_from_slice(&[byte]);
+            assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+        }
+        assert_eq!(buffer.len(), frame.len() - 1);
+
+        buffer.extend_from_slice(&frame[frame.len() - 1..]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(request()));
+    }
+
+    #[test]
+    fn several_frames_in_one_buffer_decode_in_order() {
+        let mut codec = NitroCodec::new();
+        let mut buffer = BytesMut::new();
+        codec.encode(request(), &mut buffer).unwrap();
+        codec.encode(response(), &mut buffer).unwrap();
+        let next_frame = GetKeyRequest::new(*b"0003", b"key".to_vec()).unwrap().to_bytes();
+        buffer.extend_from_slice(&next_frame[..5]);
+
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(request()));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(response()));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+        assert_eq!(buffer.as_ref(), &next_frame[..5]);
+    }
+
+    #[test]
+    fn frames_over_the_limit_are_rejected() {
+        let frame = response().to_bytes();
+        let length = frame.len() - 2;
+        let mut codec = NitroCodec::with_max_frame_size(length - 1);
+
+        // Rejected from the length prefix alone, before the body arrives
+        let mut buffer = BytesMut::from(&frame[..2]);
+        assert!(matches!(
+            codec.decode(&mut buffer),
+            Err(CodecError::FrameTooLarge { size, max }) if size == length && max == length - 1
+        ));
+
+        let mut buffer = BytesMut::new();
+        assert!(matches!(codec.encode(&response(), &mut buffer), Err(CodecError::FrameTooLarge { .. })));
+        assert!(buffer.is_empty());
+
+        let mut codec = NitroCodec::with_max_frame_size(length);
+        let mut buffer = BytesMut::from(frame.as_slice());
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(response()));
+    }
+
+    #[test]
+    fn extended_frames_round_trip() {
+        let message = Message::GetKeyResponse(GetKeyResponse::success(*b"0001", vec![0xA5; MSGHDR_MAX_STD_LEN]).unwrap());
+        let mut codec = NitroCodec::with_max_frame_size(128 * 1024);
+        let mut buffer = BytesMut::new();
+
+        codec.encode(&message, &mut buffer).unwrap();
+        assert_eq!(buffer[..2], [0xFF, 0xFF]);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(message));
+    }
+
+    #[test]
+    fn non_canonical_extended_length_is_rejected() {
+        let mut buffer = BytesMut::from(&[0xFF, 0xFF, 0x00, 0x00, 0x00, 0x06][..]);
+        buffer.extend_from_slice(b"0001Z0");
+
+        assert!(matches!(NitroCodec::new().decode(&mut buffer), Err(CodecError::Protocol(nitro::Error::BadLength { .. }))));
+    }
+
+    #[test]
+    fn unparseable_frame_is_consumed() {
+        let mut frame = request().to_bytes();
+        frame[6..8].copy_from_slice(b"XX");
+        let mut buffer = BytesMut::from(frame.as_slice());
+        buffer.extend_from_slice(&response().to_bytes());
+
+        let mut codec = NitroCodec::new();
+        assert!(matches!(codec.decode(&mut buffer), Err(CodecError::Protocol(_))));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(response()));
+    }
+}
@@ -1,10 +1,13 @@
-pub enum IoResult {
-    Success(usize),
+/// Outcome of a read or write that did not fail outright.
+/// `T` is the byte count for raw I/O and the frame for message reads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IoResult<T = usize> {
+    Success(T),
     Closed,
     Timeout,
 }
 
-impl IoResult {
+impl<T> IoResult<T> {
     pub fn is_success(&self) -> bool {
         matches!(self, IoResult::Success(_))
     }
@@ -16,7 +19,25 @@ impl IoResult {
     pub fn is_timeout(&self) -> bool {
         matches!(self, IoResult::Timeout)
     }
-    
+
+    pub fn success(self) -> Option<T> {
+        if let IoResult::Success(value) = self {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> IoResult<U> {
+        match self {
+            IoResult::Success(value) => IoResult::Success(f(value)),
+            IoResult::Closed => IoResult::Closed,
+            IoResult::Timeout => IoResult::Timeout,
+        }
+    }
+}
+
+impl IoResult<usize> {
     pub fn bytes(&self) -> Option<usize> {
         if let IoResult::Success(n) = self {
             Some(*n)
@@ -26,7 +47,7 @@ impl IoResult {
     }
 }
 
-impl std::fmt::Display for IoResult {
+impl std::fmt::Display for IoResult<usize> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             IoResult::Success(n) => write!(f, "Success({} bytes)", n),
@@ -34,4 +55,14 @@ impl std::fmt::Display for IoResult {
             IoResult::Timeout => write!(f, "Timeout"),
         }
     }
-}
\ No newline at end of file
+}
+
+impl std::fmt::Display for IoResult<Vec<u8>> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IoResult::Success(frame) => write!(f, "Success({} bytes)", frame.len()),
+            IoResult::Closed => write!(f, "Closed"),
+            IoResult::Timeout => write!(f, "Timeout"),
+        }
+    }
+}
@@ -1,7 +1,9 @@
+pub mod codec;
 mod io_result;
 mod io_utils;
 pub mod message_utils;
 
 pub use io_result::IoResult;
 pub use io_utils::{read, write};
-pub use message_utils::{read_message, write_message};
\ No newline at end of file
+pub use message_utils::{read_message, read_message_into, write_message};
+pub use codec::{CodecError, NitroCodec, DEFAULT_MAX_FRAME_SIZE};
\ No newline at end of file
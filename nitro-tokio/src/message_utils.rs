@@ -5,68 +5,111 @@ use tokio::time::Duration;
 use tokio_util::sync::CancellationToken;
 
 
+use nitro::message::{MSGHDR_EXT_MARKER, MSGHDR_EXT_LEN_SIZE, MSGHDR_LEN_SIZE, MSGHDR_MAX_STD_LEN};
+
 use crate::io_result::IoResult;
 use crate::io_utils;
 
+/// Reads one frame. `Timeout` is only returned while waiting for a new frame;
+/// a timeout part way through a frame leaves the stream unusable and is an error.
+/// Frames declaring more than `max_frame_size` bytes after the prefix are rejected.
 pub async fn read_message<S>(
     stream: &mut S,
+    max_frame_size: usize,
+    timeout: Option<Duration>,
+    shutdown_token: &CancellationToken
+) -> Result<IoResult<Vec<u8>>>
+where 
+    S: AsyncRead + Unpin
+{
+    let mut buffer = Vec::new();
+    let result = read_message_into(stream, &mut buffer, max_frame_size, timeout, shutdown_token).await?;
+    Ok(result.map(|_| buffer))
+}
+
+/// Reads one frame into `buffer`, reusing its capacity across calls.
+/// On success returns the frame length; `buffer` is left empty on `Closed` or `Timeout`.
+pub async fn read_message_into<S>(
+    stream: &mut S,
+    buffer: &mut Vec<u8>,
+    max_frame_size: usize,
     timeout: Option<Duration>,
     shutdown_token: &CancellationToken
-) -> Result<Vec<u8>>
+) -> Result<IoResult>
 where 
     S: AsyncRead + Unpin
 {
-    const HEADER_LENGTH: usize = 2;
-    let mut header = [0u8; HEADER_LENGTH];
+    let mut header = [0u8; MSGHDR_LEN_SIZE + MSGHDR_EXT_LEN_SIZE];
+    let mut header_length = MSGHDR_LEN_SIZE;
+
+    buffer.clear();
     
     let mut context: &str = "read_header";
-    match io_utils::read(stream, &mut header, HEADER_LENGTH, timeout, shutdown_token, context).await? {
+    match io_utils::read(stream, &mut header, MSGHDR_LEN_SIZE, timeout, shutdown_token, context).await? {
         IoResult::Success(_) => {
         }
         IoResult::Closed => {
             log::debug!("Connection closed while reading header in {}", context);
-            return Ok(Vec::new());
+            return Ok(IoResult::Closed);
         }
         IoResult::Timeout => {
             log::warn!("Timeout reading header in {}", context);
-            return Ok(Vec::new());
+            return Ok(IoResult::Timeout);
         }
     }
     
-    let length = u16::from_be_bytes([header[0], header[1]]) as usize;
+    let mut length = u16::from_be_bytes([header[0], header[1]]) as usize;
+
+    if length == MSGHDR_EXT_MARKER as usize {
+        context = "read_ext_header";
+        match io_utils::read(stream, &mut header[MSGHDR_LEN_SIZE..], MSGHDR_EXT_LEN_SIZE, timeout, shutdown_token, context).await? {
+            IoResult::Success(_) => {
+            }
+            IoResult::Closed => {
+                log::warn!("Connection closed while reading extended header in {}", context);
+                return Ok(IoResult::Closed);
+            }
+            IoResult::Timeout => {
+                anyhow::bail!("Timeout reading extended header in {}, stream out of sync", context);
+            }
+        }
+        header_length += MSGHDR_EXT_LEN_SIZE;
+        length = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+
+        // Lengths the standard prefix can carry must use it
+        if length <= MSGHDR_MAX_STD_LEN {
+            anyhow::bail!("Non-canonical extended length in {}: {} bytes", context, length);
+        }
+    }
     
     log::debug!("Header received in {}: length={} bytes", context, length);
     
-    // Validate length
-    const MAX_MESSAGE_SIZE: usize = 10 * 1024 * 1024;  // 10 MB
-    if length > MAX_MESSAGE_SIZE {
-        anyhow::bail!("Message too large in {}: {} bytes (max {})", context, length, MAX_MESSAGE_SIZE);
+    if length > max_frame_size {
+        anyhow::bail!("Message too large in {}: {} bytes (max {})", context, length, max_frame_size);
     }
 
     context = "read_data";
 
-    let mut data = vec![0u8; length];    
-    match io_utils::read(stream, &mut data, length, timeout, shutdown_token, context).await? {
+    // Header + data, body read in place after the length prefix
+    buffer.extend_from_slice(&header[..header_length]);
+    buffer.resize(header_length + length, 0);
+    match io_utils::read(stream, &mut buffer[header_length..], length, timeout, shutdown_token, context).await? {
         IoResult::Success(_) => {
         }
         IoResult::Closed => {
             log::warn!("Connection closed while reading body in {}", context);
-            return Ok(Vec::new());
+            buffer.clear();
+            return Ok(IoResult::Closed);
         }
         IoResult::Timeout => {
-            log::warn!("Timeout reading body in {}", context);
-            return Ok(Vec::new());
+            buffer.clear();
+            anyhow::bail!("Timeout reading body in {} ({} bytes expected), stream out of sync", context, length);
         }
     }
     
-    log::debug!("Body received in {}: {} bytes", context, data.len());
+    log::debug!("Body received in {}: {} bytes", context, length);
     
-    // Return header + data
-    let mut result = Vec::with_capacity(2 + length);
-    result.extend_from_slice(&header);  // 2 bytes header
-    result.extend_from_slice(&data);    // N bytes data
-    
-    Ok(result)
+    Ok(IoResult::Success(buffer.len()))
 }
 
 pub async fn write_message<S>(
@@ -74,22 +117,98 @@ pub async fn write_message<S>(
     buffer: &[u8],
     timeout: Option<Duration>,
     shutdown_token: &CancellationToken
-) -> Result<usize>
+) -> Result<IoResult>
 where 
     S: AsyncWrite + Unpin
 {
-    let context: &str = "read_header";
-    match io_utils::write(stream, buffer, timeout, shutdown_token, context).await? {
-        IoResult::Success(written) => {
-            return Ok(written)
+    let context: &str = "write_message";
+    let result = io_utils::write(stream, buffer, timeout, shutdown_token, context).await?;
+    match result {
+        IoResult::Success(_) => {
         }
         IoResult::Closed => {
-            log::warn!("Connection closed while writing data {}", context);
-            return Ok(0);
+            log::warn!("Connection closed while writing data in {}", context);
         }
         IoResult::Timeout => {
-            log::warn!("Timeout reading body in {}", context);
-            return Ok(0);
+            log::warn!("Timeout writing data in {}", context);
         }
-    }    
-}
\ No newline at end of file
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nitro::message::{GetKeyResponse, Message, MessageHeader};
+
+    const MAX_FRAME_SIZE: usize = 128 * 1024;
+
+    async fn read(bytes: &[u8], max_frame_size: usize) -> Result<IoResult<Vec<u8>>> {
+        let mut stream = bytes;
+        read_message(&mut stream, max_frame_size, None, &CancellationToken::new()).await
+    }
+
+    fn extended_frame() -> Vec<u8> {
+        GetKeyResponse::success(*b"0001", vec![0xA5; MSGHDR_MAX_STD_LEN]).unwrap().to_bytes()
+    }
+
+    #[tokio::test]
+    async fn reads_standard_frame() {
+        let frame = GetKeyResponse::success(*b"0001", vec![0xA5; 32]).unwrap().to_bytes();
+        assert_eq!(read(&frame, MAX_FRAME_SIZE).await.unwrap(), IoResult::Success(frame));
+    }
+
+    #[tokio::test]
+    async fn reads_extended_frame() {
+        let frame = extended_frame();
+        assert_eq!(u16::from_be_bytes([frame[0], frame[1]]), MSGHDR_EXT_MARKER);
+
+        let IoResult::Success(read_frame) = read(&frame, MAX_FRAME_SIZE).await.unwrap() else {
+            panic!("extended frame not read");
+        };
+        assert_eq!(read_frame, frame);
+        assert!(MessageHeader::parse(&read_frame).unwrap().is_extended());
+        assert!(matches!(Message::parse(&read_frame).unwrap(), Message::GetKeyResponse(_)));
+    }
+
+    #[tokio::test]
+    async fn rejects_non_canonical_extended_length() {
+        for length in [0, 6, MSGHDR_MAX_STD_LEN] {
+            let mut frame = vec![0xFF, 0xFF];
+            frame.extend_from_slice(&(length as u32).to_be_bytes());
+            frame.extend_from_slice(b"0001Z1");
+            frame.resize(MSGHDR_LEN_SIZE + MSGHDR_EXT_LEN_SIZE + length, 0);
+
+            let error = read(&frame, MAX_FRAME_SIZE).await.unwrap_err();
+            assert!(error.to_string().contains("Non-canonical extended length"), "{}", error);
+        }
+    }
+
+    #[tokio::test]
+    async fn enforces_each_listeners_frame_limit() {
+        let frame = extended_frame();
+        let length = frame.len() - MSGHDR_LEN_SIZE - MSGHDR_EXT_LEN_SIZE;
+
+        assert!(read(&frame, length).await.unwrap().is_success());
+
+        let error = read(&frame, length - 1).await.unwrap_err();
+        assert!(error.to_string().contains("Message too large"), "{}", error);
+
+        // Refused from the prefix alone, before any of the body is buffered
+        let error = read(&frame[..MSGHDR_LEN_SIZE + MSGHDR_EXT_LEN_SIZE], length - 1).await.unwrap_err();
+        assert!(error.to_string().contains("Message too large"), "{}", error);
+
+        let standard = GetKeyResponse::success(*b"0001", vec![0xA5; 32]).unwrap().to_bytes();
+        assert!(read(&standard, 16).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn closed_stream_mid_frame_is_reported() {
+        assert_eq!(read(&[], MAX_FRAME_SIZE).await.unwrap(), IoResult::Closed);
+
+        let frame = extended_frame();
+        for cut in [1, 4, frame.len() - 1] {
+            assert_eq!(read(&frame[..cut], MAX_FRAME_SIZE).await.unwrap(), IoResult::Closed);
+        }
+    }
+}